@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use common::protocol::Direction;
+use crate::html::{self, Table};
+use crate::plot::{self, ChartOptions, OverlayRun};
+use crate::replot::{self, Samples};
+use crate::results::RunResult;
+use crate::stats::Summary;
+use crate::units::Units;
+
+/// A row of the statistics table: the metric, its statistics if any run has it, and how a value
+/// is shown.
+type Row<'a> = (String, Option<Summary>, &'a dyn Fn(f64) -> String);
+
+/// Options of the `aggregate` subcommand.
+#[derive(Clone, clap::Args)]
+pub struct AggregateArgs {
+    /// Run summaries written with `--json`, e.g. a week of runs from one site.
+    #[arg(value_name = "SUMMARY", required = true)]
+    summaries: Vec<PathBuf>,
+
+    /// Metrics CSV the runs wrote, e.g. `download_metrics.csv`, whose samples are overlaid in the
+    /// charts and pooled into the statistics; may be given more than once.
+    #[arg(long, value_name = "FILE")]
+    metrics: Vec<PathBuf>,
+
+    /// File the HTML report is written to; the overlay chart goes beside it as a PNG of the same
+    /// name.
+    #[arg(long, value_name = "FILE", default_value = "aggregate.html")]
+    output: PathBuf,
+
+    /// Inline this local copy of plotly.js into the report, so it can be viewed offline, instead
+    /// of loading it from the CDN.
+    #[arg(long, value_name = "FILE")]
+    plotly_js: Option<PathBuf>,
+
+    /// Show data rates in decimal (Mbit/s) or binary (Mibit/s) units.
+    #[arg(long, value_name = "UNITS", value_enum, default_value_t = Units::Si)]
+    units: Units,
+
+    /// Write the report and the chart into this directory, creating it if needed.
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    chart: ChartOptions,
+}
+
+/// Combines saved runs into statistics over all of them, a chart overlaying their samples, and
+/// one HTML report.
+///
+/// # Returns
+/// - The process exit code.
+pub fn run(args: &AggregateArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    crate::output::create_dir(args.output_dir.as_deref())?;
+    let output = |name: &Path| args.output_dir.as_ref().map_or_else(|| name.to_path_buf(), |dir| dir.join(name));
+
+    let mut results = Vec::new();
+    for path in &args.summaries {
+        let result = RunResult::read_json(path).map_err(|e| format!("{} is not a run summary: {}", path.display(), e))?;
+        results.push(result);
+    }
+    results.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+
+    let mut samples = Vec::new();
+    for path in &args.metrics {
+        samples.extend(replot::read_runs(path)?);
+    }
+    // Files written with --per-run-files have no run IDs; their runs are matched by start time
+    let samples: Vec<Option<&Samples>> = results
+        .iter()
+        .map(|result| {
+            let started_at = humantime::parse_rfc3339(&result.started_at).ok();
+            samples.iter().find(|samples| match (&samples.run_id, started_at) {
+                (Some(run_id), _) => run_id == &result.run_id,
+                (None, Some(started_at)) => samples.started_at.duration_since(started_at).unwrap_or_else(|e| e.duration()) < Duration::from_secs(1),
+                (None, None) => false,
+            })
+        })
+        .collect();
+    let unmatched = samples.iter().filter(|samples| samples.is_none()).count();
+    if !args.metrics.is_empty() && unmatched > 0 {
+        eprintln!("{} of {} runs have no samples in the metrics files and are left out of the charts", unmatched, results.len());
+    }
+
+    let units = args.units;
+    let mut tables = Vec::new();
+    for direction in [Direction::Download, Direction::Upload] {
+        let runs: Vec<(&RunResult, Option<&Samples>)> =
+            results.iter().zip(samples.iter().copied()).filter(|(result, _)| result.direction == direction).collect();
+        if runs.is_empty() {
+            continue;
+        }
+        let of_runs = |value: fn(&RunResult) -> Option<f64>| Summary::of(&runs.iter().filter_map(|(result, _)| value(result)).collect::<Vec<_>>());
+        let pooled = |values: fn(&Samples) -> &[f64]| Summary::of(&runs.iter().flat_map(|(_, samples)| samples.map_or(&[][..], values)).copied().collect::<Vec<_>>());
+        let rate_unit = units.rate_unit();
+        let rate = |value: f64| format!("{:.2}", units.rate_value(value));
+        let ms = |value: f64| format!("{:.3}", value * 1000.0);
+        let percent = |value: f64| format!("{:.2}", value);
+        let rows: Vec<Row> = vec![
+            (format!("Data rate per run ({})", rate_unit), of_runs(|result| result.throughput_bps), &rate),
+            ("Transfer time per run (ms)".to_string(), of_runs(|result| result.latency_seconds), &ms),
+            ("Loss per run (%)".to_string(), of_runs(|result| Some(result.loss_percent)), &percent),
+            ("RTT per run (ms)".to_string(), of_runs(|result| result.rtt_seconds), &ms),
+            (format!("Data rate per sample ({})", rate_unit), pooled(|samples| &samples.data_rates), &rate),
+            ("Transfer time per sample (ms)".to_string(), pooled(|samples| &samples.latencies), &ms),
+        ];
+
+        let name = match direction {
+            Direction::Download => "Download",
+            Direction::Upload => "Upload",
+        };
+        let heading = format!("{} runs: {}", name, runs.len());
+        println!("{}:", heading);
+        println!("  {:<32} {:>8} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}", "", "Count", "Mean", "Median", "Std Dev", "Min", "p95", "Max");
+        let mut table_rows = Vec::new();
+        for (name, summary, format) in rows {
+            if let Some(summary) = summary {
+                let values = [summary.mean, summary.median, summary.stddev, summary.min, summary.p95, summary.max].map(format);
+                println!(
+                    "  {:<32} {:>8} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}",
+                    name, summary.samples, values[0], values[1], values[2], values[3], values[4], values[5]
+                );
+                table_rows.push(vec![name, summary.samples.to_string()].into_iter().chain(values).collect());
+            }
+        }
+        tables.push(Table {
+            heading,
+            header: ["Metric", "Count", "Mean", "Median", "Std Dev", "Min", "p95", "Max"].map(String::from).to_vec(),
+            rows: table_rows,
+        });
+    }
+
+    tables.push(Table {
+        heading: "Runs".to_string(),
+        header: ["Started (UTC)", "Run ID", "Server", "Direction", "Data Rate", "Transfer Time (ms)", "Loss (%)", "RTT (ms)", "Samples"]
+            .map(String::from)
+            .to_vec(),
+        rows: results
+            .iter()
+            .zip(&samples)
+            .map(|(result, samples)| {
+                vec![
+                    result.started_at.clone(),
+                    result.run_id.clone(),
+                    result.server.clone(),
+                    result.direction.to_string(),
+                    units.rate_or_na(result.throughput_bps),
+                    result.latency_seconds.map(|latency| format!("{:.3}", latency * 1000.0)).unwrap_or_default(),
+                    format!("{:.2}", result.loss_percent),
+                    result.rtt_seconds.map(|rtt| format!("{:.3}", rtt * 1000.0)).unwrap_or_default(),
+                    samples.map(|samples| samples.latencies.len().to_string()).unwrap_or_default(),
+                ]
+            })
+            .collect(),
+    });
+
+    let names: Vec<String> = results.iter().map(|result| format!("{} {}", result.started_at, result.direction)).collect();
+    let overlay: Vec<OverlayRun> = names
+        .iter()
+        .zip(&samples)
+        .filter_map(|(name, samples)| samples.filter(|samples| !samples.latencies.is_empty()).map(|samples| (name, samples)))
+        .map(|(name, samples)| OverlayRun { name, offsets: &samples.offsets, latencies: &samples.latencies, data_rates: &samples.data_rates })
+        .collect();
+
+    let report_path = output(&args.output);
+    let theme = args.chart.theme(units);
+    if !overlay.is_empty() {
+        plot::plot_overlay(&report_path.with_extension("png"), &overlay, &theme)?;
+    }
+    let servers: Vec<&str> = results.iter().fold(Vec::new(), |mut servers, result| {
+        if !servers.contains(&result.server.as_str()) {
+            servers.push(&result.server);
+        }
+        servers
+    });
+    let intro = format!(
+        "{} runs against {} from {} to {}.",
+        results.len(),
+        servers.join(", "),
+        results[0].started_at,
+        results[results.len() - 1].started_at
+    );
+    html::write_aggregate(&report_path, &format!("Bandwidth tests: {} runs", results.len()), &intro, &tables, &overlay, &theme, args.plotly_js.as_deref())?;
+    Ok(0)
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+use serde::Serialize;
+use crate::results::RunResult;
+
+/// How a scheduled job's runs are judged against its own recent runs.
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    /// Standard deviations from the moving average beyond which a metric is anomalous.
+    pub sigmas: f64,
+    /// Weight of the newest run in the moving average and variance, between 0 and 1.
+    pub alpha: f64,
+    /// Runs to learn from before any is judged.
+    pub warmup: usize,
+}
+
+/// Exponentially weighted moving average and variance of one metric.
+#[derive(Clone, Copy, Debug, Default)]
+struct Ewma {
+    mean: f64,
+    variance: f64,
+    runs: usize,
+}
+
+impl Ewma {
+    fn update(&mut self, value: f64, alpha: f64) {
+        if self.runs == 0 {
+            self.mean = value;
+        } else {
+            let difference = value - self.mean;
+            let increment = alpha * difference;
+            self.mean += increment;
+            self.variance = (1.0 - alpha) * (self.variance + difference * increment);
+        }
+        self.runs += 1;
+    }
+
+    /// How many standard deviations `value` is from the average. The deviation is at least 5% of
+    /// the average, so a metric that barely varied so far does not flag the slightest change.
+    fn sigmas(&self, value: f64) -> Option<f64> {
+        let deviation = self.variance.sqrt().max(self.mean.abs() * 0.05);
+        if deviation > 0.0 {
+            Some((value - self.mean) / deviation)
+        } else if value == self.mean {
+            Some(0.0)
+        } else {
+            None
+        }
+    }
+}
+
+/// A metric of a run that deviated from the job's moving average.
+#[derive(Clone, Debug, Serialize)]
+pub struct Anomaly {
+    /// Metric name, as in threshold violations.
+    pub metric: &'static str,
+    pub measured: f64,
+    /// Moving average of the earlier runs.
+    pub expected: f64,
+    /// Standard deviations from the average, negative below it; absent for a metric that had
+    /// never varied before.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sigmas: Option<f64>,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} was {:.5}, expected {:.5}", self.metric, self.measured, self.expected)?;
+        match self.sigmas {
+            Some(sigmas) => write!(f, " ({:+.1} sigma)", sigmas),
+            None => write!(f, " (it never varied before)"),
+        }
+    }
+}
+
+/// The moving averages of a scheduled job's metrics, learned from its completed runs.
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    settings: Settings,
+    throughput: Ewma,
+    latency: Ewma,
+    loss: Ewma,
+}
+
+impl Monitor {
+    pub fn new(settings: Settings) -> Self {
+        Monitor { settings, throughput: Ewma::default(), latency: Ewma::default(), loss: Ewma::default() }
+    }
+
+    /// Returns the metrics of `result` that deviate from the averages by more than the configured
+    /// number of standard deviations, once the warmup runs have been learned.
+    pub fn check(&self, result: &RunResult) -> Vec<Anomaly> {
+        if self.throughput.runs < self.settings.warmup {
+            return Vec::new();
+        }
+        self.metrics(result)
+            .into_iter()
+            .filter_map(|(metric, ewma, measured)| {
+                let sigmas = ewma.sigmas(measured);
+                let anomalous = sigmas.is_none_or(|sigmas| sigmas.abs() > self.settings.sigmas);
+                anomalous.then_some(Anomaly { metric, measured, expected: ewma.mean, sigmas })
+            })
+            .collect()
+    }
+
+    /// Learns from a completed run.
+    pub fn update(&mut self, result: &RunResult) {
+        let alpha = self.settings.alpha;
+        if let Some(throughput) = result.throughput_bps {
+            self.throughput.update(throughput, alpha);
+        }
+        if let Some(latency) = result.latency_seconds {
+            self.latency.update(latency, alpha);
+        }
+        self.loss.update(result.loss_percent, alpha);
+    }
+
+    /// The metrics `result` has, with their averages. A run that received no data has no data
+    /// rate, and one that received no chunk no latency.
+    fn metrics(&self, result: &RunResult) -> Vec<(&'static str, &Ewma, f64)> {
+        vec![
+            ("throughput (bps)", &self.throughput, result.throughput_bps),
+            ("latency (s)", &self.latency, result.latency_seconds),
+            ("loss (%)", &self.loss, Some(result.loss_percent)),
+        ]
+        .into_iter()
+        .filter_map(|(metric, ewma, value)| Some((metric, ewma, value?)))
+        .collect()
+    }
+}
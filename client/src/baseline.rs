@@ -0,0 +1,70 @@
+use crate::results::RunResult;
+
+/// Change of one metric between a baseline run and the current run.
+pub struct Delta {
+    pub metric: &'static str,
+    pub baseline: f64,
+    pub current: f64,
+    /// Relative change from the baseline, in percent.
+    pub change_percent: f64,
+    /// True if the metric moved in the bad direction by more than the tolerance.
+    pub regression: bool,
+}
+
+/// Calculates the percentage change from `baseline` to `current`.
+///
+/// # Returns
+/// - The relative change in percent, or 0 if both values are zero.
+fn percent_change(baseline: f64, current: f64) -> f64 {
+    if baseline == 0.0 {
+        if current == 0.0 { 0.0 } else { f64::INFINITY.copysign(current) }
+    } else {
+        (current - baseline) / baseline.abs() * 100.0
+    }
+}
+
+/// Compares the current run against a stored baseline.
+///
+/// Throughput regresses when it drops, latency and loss regress when they rise. Loss is compared
+/// in absolute percentage points, since a relative change from a zero-loss baseline is meaningless.
+/// Throughput is only compared if the baseline received data, and a current run that received none
+/// counts as 0 bps. Latency is only compared if a chunk arrived in both runs.
+///
+/// # Arguments
+/// - `baseline`: The stored reference result.
+/// - `current`: The result of this run.
+/// - `tolerance_percent`: How far a metric may move in the bad direction before it is flagged.
+pub fn compare(baseline: &RunResult, current: &RunResult, tolerance_percent: f64) -> Vec<Delta> {
+    let loss_change = current.loss_percent - baseline.loss_percent;
+
+    let mut deltas = Vec::new();
+    if let Some(baseline) = baseline.throughput_bps {
+        let current = current.throughput_bps.unwrap_or_default();
+        let throughput_change = percent_change(baseline, current);
+        deltas.push(Delta {
+            metric: "Throughput (bps)",
+            baseline,
+            current,
+            change_percent: throughput_change,
+            regression: throughput_change < -tolerance_percent,
+        });
+    }
+    if let (Some(baseline), Some(current)) = (baseline.latency_seconds, current.latency_seconds) {
+        let latency_change = percent_change(baseline, current);
+        deltas.push(Delta {
+            metric: "Latency (s)",
+            baseline,
+            current,
+            change_percent: latency_change,
+            regression: latency_change > tolerance_percent,
+        });
+    }
+    deltas.push(Delta {
+        metric: "Loss (%)",
+        baseline: baseline.loss_percent,
+        current: current.loss_percent,
+        change_percent: loss_change,
+        regression: loss_change > tolerance_percent,
+    });
+    deltas
+}
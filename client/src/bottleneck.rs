@@ -0,0 +1,171 @@
+use std::io;
+use std::net::TcpStream;
+use std::num::NonZeroUsize;
+use std::thread;
+use std::time::{Duration, Instant};
+use common::protocol::Direction;
+use common::tcp_info::TcpInfo;
+use crate::results::Bottleneck;
+
+/// Share of the available cores above which the process is considered CPU-bound.
+pub const CPU_BOUND_FRACTION: f64 = 0.85;
+/// Share of the run spent writing to disk above which the transfer is considered disk-bound.
+pub const DISK_BOUND_FRACTION: f64 = 0.5;
+/// Average fill of the socket buffer above which the queue is considered backed up.
+const QUEUE_BACKED_UP_FRACTION: f64 = 0.5;
+/// Share of the kernel's busy time above which a sender-side limit is reported.
+pub const SENDER_LIMITED_FRACTION: f64 = 0.5;
+
+/// Measures what limited a transfer while it runs: the CPU time used, and how full the socket
+/// queue on our side was each time a chunk completed.
+pub struct Sampler {
+    started: Instant,
+    cpu_at_start: Option<Duration>,
+    queue_samples: usize,
+    queue_sum: f64,
+}
+
+impl Sampler {
+    pub fn start() -> Self {
+        Sampler { started: Instant::now(), cpu_at_start: process_cpu_time().ok(), queue_samples: 0, queue_sum: 0.0 }
+    }
+
+    /// Records how many bytes are queued on our side of `stream`: received but not yet read for a
+    /// download, written but not yet sent for an upload.
+    pub fn sample_queue(&mut self, stream: &TcpStream, direction: Direction) {
+        if let Ok(queued) = queued_bytes(stream, direction) {
+            self.queue_samples += 1;
+            self.queue_sum += queued as f64;
+        }
+    }
+
+    /// Attributes the transfer to its most likely bottleneck.
+    ///
+    /// # Arguments
+    /// - `direction`: Which way the data flowed.
+    /// - `bytes`: Payload transferred; without any there is nothing to attribute.
+    /// - `socket_buffer`: Size of the socket buffer the queue was sampled from, in bytes.
+    /// - `disk_time`: Time spent writing received data to disk, if it was written.
+    /// - `tcp_info`: The kernel's view of the connection at the end of the run, if available.
+    pub fn finish(
+        self,
+        direction: Direction,
+        bytes: u64,
+        socket_buffer: u64,
+        disk_time: Option<Duration>,
+        tcp_info: Option<&TcpInfo>,
+    ) -> Bottleneck {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let cpu_percent = match (self.cpu_at_start, process_cpu_time()) {
+            (Some(start), Ok(end)) if elapsed > 0.0 => Some((end - start).as_secs_f64() / elapsed * 100.0),
+            _ => None,
+        };
+        // The data moves on more than one thread in an upload, so the whole process is measured
+        // against every core it could have used
+        let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let disk_percent = disk_time.filter(|_| elapsed > 0.0).map(|time| time.as_secs_f64() / elapsed * 100.0);
+        let avg_queue_bytes = if self.queue_samples > 0 { Some(self.queue_sum / self.queue_samples as f64) } else { None };
+        let queue_fill = avg_queue_bytes.filter(|_| socket_buffer > 0).map(|queued| queued / socket_buffer as f64);
+
+        let path = match direction {
+            Direction::Download => "receive path",
+            Direction::Upload => "send path",
+        };
+        let mut bottleneck = Bottleneck {
+            verdict: String::new(),
+            cpu_percent,
+            cores: Some(cores),
+            disk_percent,
+            avg_queue_bytes,
+            socket_buffer_bytes: socket_buffer,
+        };
+        bottleneck.verdict = if bytes == 0 {
+            "undetermined: no data was transferred".to_string()
+        } else if bottleneck.cpu_bound() {
+            format!("{} was CPU-bound (the client kept its {} cores {:.0}% busy)", path, cores, bottleneck.cpu_load_percent().unwrap_or_default())
+        } else if let Some(disk) = disk_percent.filter(|disk| *disk >= DISK_BOUND_FRACTION * 100.0) {
+            format!("{} was disk-bound (writing to disk took {:.0}% of the run)", path, disk)
+        } else if let Some(limit) = sender_limit(direction, tcp_info) {
+            limit
+        } else {
+            match (direction, queue_fill) {
+                (Direction::Download, Some(fill)) if fill >= QUEUE_BACKED_UP_FRACTION => format!(
+                    "receive path could not keep up: the socket queue was {:.0}% full on average, so data arrived faster than it was read",
+                    fill * 100.0
+                ),
+                (Direction::Download, Some(fill)) => format!(
+                    "network-bound: the socket queue was {:.0}% full on average, so data was read as fast as it arrived",
+                    fill * 100.0
+                ),
+                (Direction::Upload, Some(fill)) if fill >= QUEUE_BACKED_UP_FRACTION => format!(
+                    "network-bound: the send queue was {:.0}% full on average, so data waited for the network",
+                    fill * 100.0
+                ),
+                (Direction::Upload, Some(fill)) => format!(
+                    "send path could not keep up: the send queue was {:.0}% full on average, so the network waited for data",
+                    fill * 100.0
+                ),
+                (_, None) => "undetermined: the socket queue could not be sampled on this platform".to_string(),
+            }
+        };
+        bottleneck
+    }
+}
+
+/// Reports a limit the kernel tracked for our side as sender, when it dominated the busy time.
+fn sender_limit(direction: Direction, tcp_info: Option<&TcpInfo>) -> Option<String> {
+    let info = tcp_info.filter(|info| direction == Direction::Upload && info.busy_time > 0)?;
+    let busy = info.busy_time as f64;
+    if info.rwnd_limited as f64 / busy >= SENDER_LIMITED_FRACTION {
+        Some(format!(
+            "limited by the server's receive window for {:.0}% of the time data was in flight",
+            info.rwnd_limited as f64 / busy * 100.0
+        ))
+    } else if info.sndbuf_limited as f64 / busy >= SENDER_LIMITED_FRACTION {
+        Some(format!(
+            "limited by the client's send buffer for {:.0}% of the time data was in flight; a larger SO_SNDBUF may help",
+            info.sndbuf_limited as f64 / busy * 100.0
+        ))
+    } else {
+        None
+    }
+}
+
+/// CPU time (user and system) the process has used so far, across all threads.
+#[cfg(unix)]
+fn process_cpu_time() -> io::Result<Duration> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let time = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+    Ok(time(usage.ru_utime) + time(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn process_cpu_time() -> io::Result<Duration> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "CPU time sampling is not supported on this platform"))
+}
+
+/// Bytes queued on our side of `stream`: received but not yet read for a download, written but
+/// not yet acknowledged for an upload.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn queued_bytes(stream: &TcpStream, direction: Direction) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    // SIOCINQ and SIOCOUTQ share their values with FIONREAD and TIOCOUTQ
+    let request = match direction {
+        Direction::Download => libc::FIONREAD,
+        Direction::Upload => libc::TIOCOUTQ,
+    };
+    let mut queued: libc::c_int = 0;
+    if unsafe { libc::ioctl(stream.as_raw_fd(), request, &mut queued) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(queued.max(0) as usize)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub fn queued_bytes(_stream: &TcpStream, _direction: Direction) -> io::Result<usize> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Socket queue sampling is only supported on Linux"))
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+/// Arrival times of a single chunk.
+pub struct ChunkTiming {
+    /// When the first byte of the chunk arrived.
+    pub first_byte: Instant,
+    /// When the last byte of the chunk arrived.
+    pub end: Instant,
+    /// Size of the chunk in bytes.
+    pub bytes: usize,
+}
+
+/// Throughput and latency recovery of one burst of chunks.
+#[derive(Serialize, Deserialize)]
+pub struct BurstStats {
+    /// Number of chunks received in the burst.
+    pub chunks: usize,
+    /// Effective data rate from the first to the last byte of the burst, in bits per second.
+    pub throughput_bps: f64,
+    /// Download time of the first chunk after the idle gap, in seconds.
+    pub first_chunk_latency_seconds: f64,
+    /// Median download time of the remaining chunks of the burst, in seconds, if there were any.
+    pub steady_latency_seconds: Option<f64>,
+}
+
+impl BurstStats {
+    /// How much slower the first chunk after an idle gap was than the rest of the burst.
+    ///
+    /// # Returns
+    /// - The ratio of first-chunk to steady-state latency, or `None` for single-chunk bursts.
+    pub fn recovery_ratio(&self) -> Option<f64> {
+        self.steady_latency_seconds.map(|steady| self.first_chunk_latency_seconds / steady)
+    }
+}
+
+/// Groups consecutive chunks into bursts, starting a new burst whenever no data arrived for longer
+/// than `gap_threshold`.
+///
+/// # Arguments
+/// - `chunks`: Chunk timings in arrival order.
+/// - `gap_threshold`: Idle time between two chunks that marks the start of a new burst.
+///
+/// # Returns
+/// - The statistics of every burst in order.
+pub fn split_bursts(chunks: &[ChunkTiming], gap_threshold: Duration) -> Vec<BurstStats> {
+    let mut bursts = Vec::new();
+    let mut start = 0;
+
+    for i in 1..=chunks.len() {
+        let is_boundary = i == chunks.len() || chunks[i].first_byte.duration_since(chunks[i - 1].end) > gap_threshold;
+        if is_boundary {
+            bursts.push(burst_stats(&chunks[start..i]));
+            start = i;
+        }
+    }
+
+    bursts
+}
+
+fn burst_stats(chunks: &[ChunkTiming]) -> BurstStats {
+    let bytes: usize = chunks.iter().map(|c| c.bytes).sum();
+    let duration = chunks[chunks.len() - 1].end.duration_since(chunks[0].first_byte).as_secs_f64();
+    let latency = |c: &ChunkTiming| c.end.duration_since(c.first_byte).as_secs_f64();
+
+    let mut steady: Vec<f64> = chunks[1..].iter().map(latency).collect();
+    steady.sort_by(|a, b| a.total_cmp(b));
+    let steady_latency_seconds = if steady.is_empty() { None } else { Some(steady[steady.len() / 2]) };
+
+    BurstStats {
+        chunks: chunks.len(),
+        throughput_bps: bytes as f64 * 8.0 / duration,
+        first_chunk_latency_seconds: latency(&chunks[0]),
+        steady_latency_seconds,
+    }
+}
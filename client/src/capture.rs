@@ -0,0 +1,361 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use crate::results::{CaptureSummary, SynOptions};
+
+/// pcap link type of packets that start with their IPv4 or IPv6 header.
+const LINKTYPE_RAW: u32 = 101;
+
+/// Captures the packets of the test connection from a separate thread into a pcap file.
+///
+/// The packets are read from an AF_PACKET socket on every interface, so the capture sees exactly
+/// what the kernel sends and receives, including retransmissions and the handshake. A classic BPF
+/// filter attached to the socket lets through only TCP packets to or from the server's address
+/// and port, so other traffic is not even copied to the capture thread. Requires Linux and root
+/// or CAP_NET_RAW.
+pub struct Capture {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<io::Result<CaptureSummary>>>,
+}
+
+impl Capture {
+    /// Starts capturing the packets exchanged with `remote` into a new pcap file at `path`.
+    ///
+    /// # Arguments
+    /// - `snaplen`: Bytes kept of every packet; headers are enough for most analysis.
+    /// - `max_bytes`: Size the file may grow to; packets beyond it are counted but not written.
+    pub fn start(path: &Path, remote: SocketAddr, snaplen: u32, max_bytes: u64) -> io::Result<Self> {
+        let capturer = sys::Capturer::open(remote)?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_file_header(&mut writer, snaplen)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let file = path.display().to_string();
+        let handle = thread::spawn(move || {
+            let mut summary = CaptureSummary { file, snaplen, ..CaptureSummary::default() };
+            let mut buffer = vec![0u8; snaplen as usize];
+            let mut written = 24;
+            // Packets still queued when the test ends are drained before the thread exits
+            while let Some(packet) = capturer.next(&mut buffer, &stopped)? {
+                summary.packets += 1;
+                // The handshake comes first, so the options are known long before the file fills up
+                if summary.syn_ack.is_none() {
+                    match syn_options(&buffer[..packet.captured]) {
+                        Some((false, options)) if summary.syn.is_none() => summary.syn = Some(options),
+                        Some((true, options)) => summary.syn_ack = Some(options),
+                        _ => {}
+                    }
+                }
+                let record = 16 + packet.captured as u64;
+                if written + record > max_bytes {
+                    summary.limit_reached = true;
+                    continue;
+                }
+                write_record(&mut writer, &packet, &buffer[..packet.captured])?;
+                written += record;
+                summary.packets_written += 1;
+            }
+            writer.flush()?;
+            summary.file_bytes = written;
+            summary.dropped = capturer.dropped();
+            Ok(summary)
+        });
+        Ok(Capture { path: path.to_path_buf(), stop, handle: Some(handle) })
+    }
+
+    /// Stops capturing once the packets already received are written, and returns what was captured.
+    pub fn finish(mut self) -> io::Result<CaptureSummary> {
+        self.stop.store(true, Ordering::Relaxed);
+        let handle = self.handle.take().ok_or_else(|| io::Error::other("capture already finished"))?;
+        let summary = handle.join().map_err(|_| io::Error::other(format!("capture of {} panicked", self.path.display())))??;
+        Ok(summary)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A packet received by the capture socket; its first `captured` bytes are in the buffer.
+struct Packet {
+    seconds: u32,
+    microseconds: u32,
+    captured: usize,
+    /// Length of the packet on the wire, which exceeds `captured` if it was cut at the snap length.
+    length: usize,
+}
+
+/// Reads the TCP options of a SYN or SYN-ACK, given the packet from its IP header on.
+///
+/// # Returns
+/// - Whether the packet is the SYN-ACK, and its options; `None` for other packets, and for
+///   handshake packets cut off at the snap length before the end of their options.
+fn syn_options(packet: &[u8]) -> Option<(bool, SynOptions)> {
+    let tcp = match packet.first()? >> 4 {
+        4 if packet.get(9) == Some(&6) => packet.get(usize::from(packet[0] & 0x0f) * 4..)?,
+        // Extension headers are not followed; the handshake does not carry any
+        6 if packet.get(6) == Some(&6) => packet.get(40..)?,
+        _ => return None,
+    };
+    let flags = *tcp.get(13)?;
+    if flags & 0x02 == 0 {
+        return None;
+    }
+    let header = usize::from(tcp.get(12)? >> 4) * 4;
+    let mut options = tcp.get(20..header)?;
+    let mut syn = SynOptions::default();
+    while let Some(&kind) = options.first() {
+        match kind {
+            0 => break,
+            1 => {
+                options = &options[1..];
+                continue;
+            }
+            _ => {}
+        }
+        let length = usize::from(*options.get(1)?);
+        let value = options.get(2..length.max(2))?;
+        match (kind, value.len()) {
+            (2, 2) => syn.mss = Some(u16::from_be_bytes([value[0], value[1]])),
+            (3, 1) => syn.window_scale = Some(value[0]),
+            (4, 0) => syn.sack_permitted = true,
+            (8, 8) => syn.timestamps = true,
+            _ => {}
+        }
+        options = &options[length.max(2)..];
+    }
+    Some((flags & 0x10 != 0, syn))
+}
+
+fn write_file_header(writer: &mut impl Write, snaplen: u32) -> io::Result<()> {
+    // Magic, version 2.4, UTC offset and timestamp accuracy (both unused), snap length, link type
+    writer.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&4u16.to_le_bytes())?;
+    writer.write_all(&[0; 8])?;
+    writer.write_all(&snaplen.to_le_bytes())?;
+    writer.write_all(&LINKTYPE_RAW.to_le_bytes())
+}
+
+fn write_record(writer: &mut impl Write, packet: &Packet, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&packet.seconds.to_le_bytes())?;
+    writer.write_all(&packet.microseconds.to_le_bytes())?;
+    writer.write_all(&(packet.captured as u32).to_le_bytes())?;
+    writer.write_all(&(packet.length as u32).to_le_bytes())?;
+    writer.write_all(data)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem::{self, MaybeUninit};
+    use std::net::{IpAddr, SocketAddr};
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use socket2::{Domain, Protocol, Socket, Type};
+    use super::Packet;
+
+    /// Reports the kernel receive time of the last packet read from a socket.
+    const SIOCGSTAMP: libc::c_ulong = 0x8906;
+    /// How often a capture waiting for packets checks whether it should stop.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+    /// Kernel queue for packets not yet read, absorbing bursts the capture thread falls behind on.
+    const RECEIVE_BUFFER: usize = 8 << 20;
+
+    pub struct Capturer {
+        socket: Socket,
+        /// Index of the loopback interface, on which every packet is seen twice.
+        loopback: u32,
+    }
+
+    impl Capturer {
+        pub fn open(remote: SocketAddr) -> io::Result<Self> {
+            // Cooked (SOCK_DGRAM) packets start at the network header whatever the link layer is
+            let all = i32::from((libc::ETH_P_ALL as u16).to_be());
+            let socket = Socket::new(Domain::PACKET, Type::DGRAM, Some(Protocol::from(all))).map_err(|e| {
+                if e.kind() == io::ErrorKind::PermissionDenied {
+                    io::Error::new(e.kind(), format!("packet capture requires root or CAP_NET_RAW ({})", e))
+                } else {
+                    e
+                }
+            })?;
+            socket.attach_filter(&filter(remote))?;
+            socket.set_recv_buffer_size(RECEIVE_BUFFER)?;
+            socket.set_read_timeout(Some(POLL_INTERVAL))?;
+            let loopback = unsafe { libc::if_nametoindex(b"lo\0".as_ptr() as *const libc::c_char) };
+            Ok(Capturer { socket, loopback })
+        }
+
+        /// Waits for the next packet, copying up to the buffer's length of it; returns `None` once
+        /// `stop` is set and no packets are left.
+        pub fn next(&self, buffer: &mut [u8], stop: &AtomicBool) -> io::Result<Option<Packet>> {
+            loop {
+                // With MSG_TRUNC the length of the whole packet is returned, not just what was copied
+                let uninit = unsafe { &mut *(buffer as *mut [u8] as *mut [MaybeUninit<u8>]) };
+                let (length, address) = match self.socket.recv_from_with_flags(uninit, libc::MSG_TRUNC) {
+                    Ok(received) => received,
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {
+                        if stop.load(Ordering::Relaxed) {
+                            return Ok(None);
+                        }
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                let link = unsafe { &*(address.as_ptr() as *const libc::sockaddr_ll) };
+                // Loopback packets are seen once leaving and once arriving; like tcpdump, keep the latter
+                if link.sll_pkttype == libc::PACKET_OUTGOING && link.sll_ifindex as u32 == self.loopback {
+                    continue;
+                }
+                let mut stamp = libc::timeval { tv_sec: 0, tv_usec: 0 };
+                if unsafe { libc::ioctl(self.socket.as_raw_fd(), SIOCGSTAMP as _, &mut stamp) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                return Ok(Some(Packet {
+                    seconds: stamp.tv_sec as u32,
+                    microseconds: stamp.tv_usec as u32,
+                    captured: length.min(buffer.len()),
+                    length,
+                }));
+            }
+        }
+
+        /// Packets the kernel dropped because the capture thread fell behind.
+        pub fn dropped(&self) -> u64 {
+            let mut stats = libc::tpacket_stats { tp_packets: 0, tp_drops: 0 };
+            let mut length = mem::size_of::<libc::tpacket_stats>() as libc::socklen_t;
+            let status = unsafe {
+                libc::getsockopt(
+                    self.socket.as_raw_fd(),
+                    libc::SOL_PACKET,
+                    libc::PACKET_STATISTICS,
+                    &mut stats as *mut _ as *mut libc::c_void,
+                    &mut length,
+                )
+            };
+            if status == 0 {
+                u64::from(stats.tp_drops)
+            } else {
+                0
+            }
+        }
+    }
+
+    /// An instruction of the filter under construction, jumping to labels resolved once it is complete.
+    struct Instruction {
+        code: u32,
+        jump_true: Option<&'static str>,
+        jump_false: Option<&'static str>,
+        k: u32,
+    }
+
+    fn op(program: &mut Vec<Instruction>, code: u32, k: u32, jump_true: Option<&'static str>, jump_false: Option<&'static str>) {
+        program.push(Instruction { code, jump_true, jump_false, k });
+    }
+
+    /// Builds a classic BPF program accepting the TCP packets to or from `remote`, e.g. for
+    /// 192.0.2.1:7878 the equivalent of `tcp and ((src host 192.0.2.1 and src port 7878) or
+    /// (dst host 192.0.2.1 and dst port 7878))`.
+    ///
+    /// Packets start at the IP header. IPv4 fragments after the first, which carry no TCP header,
+    /// are rejected; IPv6 packets are expected to have no extension headers, as TCP traffic does.
+    fn filter(remote: SocketAddr) -> Vec<libc::sock_filter> {
+        let mut program: Vec<Instruction> = Vec::new();
+        let mut labels: Vec<(&'static str, usize)> = Vec::new();
+        let load_word = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let equal = libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K;
+
+        // IP version and TCP as the protocol
+        op(&mut program, libc::BPF_LD | libc::BPF_B | libc::BPF_ABS, 0, None, None);
+        op(&mut program, libc::BPF_ALU | libc::BPF_AND | libc::BPF_K, 0xf0, None, None);
+        let (version, protocol_offset, source, destination, words, port): (u32, u32, u32, u32, Vec<u32>, u32) = match remote.ip() {
+            IpAddr::V4(address) => (0x40, 9, 12, 16, vec![u32::from(address)], 0),
+            IpAddr::V6(address) => {
+                let octets = address.octets();
+                let words = octets.chunks(4).map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]])).collect();
+                (0x60, 6, 8, 24, words, 40)
+            }
+        };
+        op(&mut program, equal, version, None, Some("reject"));
+        op(&mut program, libc::BPF_LD | libc::BPF_B | libc::BPF_ABS, protocol_offset, None, None);
+        op(&mut program, equal, libc::IPPROTO_TCP as u32, None, Some("reject"));
+        // Ports are read at X plus an offset: past the IPv4 header's variable length, or the fixed IPv6 header
+        let load_port = if version == 0x40 {
+            op(&mut program, libc::BPF_LD | libc::BPF_H | libc::BPF_ABS, 6, None, None);
+            op(&mut program, libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K, 0x1fff, Some("reject"), None);
+            op(&mut program, libc::BPF_LDX | libc::BPF_B | libc::BPF_MSH, 0, None, None);
+            libc::BPF_LD | libc::BPF_H | libc::BPF_IND
+        } else {
+            libc::BPF_LD | libc::BPF_H | libc::BPF_ABS
+        };
+
+        // Server as the source: its port is the source port. Both ends may have the same address
+        // (over loopback), so a packet that does not match this way is tried the other way round
+        for (i, word) in words.iter().enumerate() {
+            op(&mut program, load_word, source + 4 * i as u32, None, None);
+            op(&mut program, equal, *word, None, Some("destination"));
+        }
+        op(&mut program, load_port, port, None, None);
+        op(&mut program, equal, u32::from(remote.port()), Some("accept"), None);
+        // Server as the destination: its port is the destination port
+        labels.push(("destination", program.len()));
+        for (i, word) in words.iter().enumerate() {
+            op(&mut program, load_word, destination + 4 * i as u32, None, None);
+            op(&mut program, equal, *word, None, Some("reject"));
+        }
+        op(&mut program, load_port, port + 2, None, None);
+        op(&mut program, equal, u32::from(remote.port()), None, Some("reject"));
+        // The whole packet is accepted; the capture only copies the snap length of it
+        labels.push(("accept", program.len()));
+        op(&mut program, libc::BPF_RET | libc::BPF_K, u32::MAX, None, None);
+        labels.push(("reject", program.len()));
+        op(&mut program, libc::BPF_RET | libc::BPF_K, 0, None, None);
+
+        let target = |label: &str| labels.iter().find(|(name, _)| *name == label).map(|(_, index)| *index).unwrap_or_default();
+        program
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| {
+                let offset = |label: Option<&str>| label.map_or(0, |label| (target(label) - index - 1) as u8);
+                libc::sock_filter {
+                    code: instruction.code as u16,
+                    jt: offset(instruction.jump_true),
+                    jf: offset(instruction.jump_false),
+                    k: instruction.k,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicBool;
+    use super::Packet;
+
+    pub struct Capturer;
+
+    impl Capturer {
+        pub fn open(_remote: SocketAddr) -> io::Result<Self> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "packet capture is only supported on Linux"))
+        }
+
+        pub fn next(&self, _buffer: &mut [u8], _stop: &AtomicBool) -> io::Result<Option<Packet>> {
+            Ok(None)
+        }
+
+        pub fn dropped(&self) -> u64 {
+            0
+        }
+    }
+}
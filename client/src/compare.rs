@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+use crate::baseline;
+use crate::results::RunResult;
+use crate::units::Units;
+
+/// Options of the `compare` subcommand.
+#[derive(Clone, clap::Args)]
+pub struct CompareArgs {
+    /// Run summaries written with `--json`; every one after the first is compared with the first.
+    #[arg(value_name = "SUMMARY", required = true, num_args = 2..)]
+    summaries: Vec<PathBuf>,
+
+    /// Percentage a metric may worsen relative to the first summary before it counts as a
+    /// regression.
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0, value_parser = crate::non_negative)]
+    tolerance: f64,
+
+    /// Show data rates in decimal (Mbit/s) or binary (Mibit/s) units.
+    #[arg(long, value_name = "UNITS", value_enum, default_value_t = Units::Si)]
+    units: Units,
+}
+
+/// Shows saved runs side by side and how each one moved against the first, as a run compares
+/// itself with `--baseline`.
+///
+/// # Returns
+/// - The process exit code: 0, or `EXIT_THRESHOLD_FAILURE` if any run regressed against the first.
+pub fn run(args: &CompareArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut results = Vec::new();
+    for path in &args.summaries {
+        let result = RunResult::read_json(path).map_err(|e| format!("{} is not a run summary: {}", path.display(), e))?;
+        results.push((path.display().to_string(), result));
+    }
+
+    let width = results.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    println!(
+        "{:<width$}  {:<28} {:<24} {:<8} {:>20} {:>15} {:>8}",
+        "Summary",
+        "Started (UTC)",
+        "Server",
+        "Dir",
+        "Data Rate",
+        "Transfer Time",
+        "Loss",
+        width = width
+    );
+    for (name, result) in &results {
+        println!(
+            "{:<width$}  {:<28} {:<24} {:<8} {:>20} {:>15} {:>6.2} %",
+            name,
+            result.started_at,
+            result.server,
+            result.direction,
+            args.units.rate_or_na(result.throughput_bps),
+            result.latency_seconds.map_or("n/a".to_string(), |latency| format!("{:.3} ms", latency * 1000.0)),
+            result.loss_percent,
+            width = width
+        );
+    }
+
+    let (first_name, first) = &results[0];
+    let mut regressed = false;
+    for (name, result) in &results[1..] {
+        println!("{} against {} (tolerance {:.1}%):", name, first_name, args.tolerance);
+        for delta in baseline::compare(first, result, args.tolerance) {
+            println!(
+                "  {}: {:.5} -> {:.5} ({:+.2}%){}",
+                delta.metric,
+                delta.baseline,
+                delta.current,
+                delta.change_percent,
+                if delta.regression { "  REGRESSION" } else { "" }
+            );
+            regressed |= delta.regression;
+        }
+    }
+    Ok(if regressed { crate::EXIT_THRESHOLD_FAILURE } else { 0 })
+}
@@ -0,0 +1,345 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
+use common::sockopt::{self, Keepalive};
+use crate::proxy::Proxy;
+use crate::results::HappyEyeballs;
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// How long an attempt gets before the next address is tried alongside it (RFC 8305's Connection
+/// Attempt Delay).
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+/// How long the outcome of the other family's attempt is waited for once the race is reported.
+const LOSER_WAIT: Duration = Duration::from_secs(1);
+
+/// IP version to connect over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+impl Family {
+    pub fn of(address: SocketAddr) -> Self {
+        if address.is_ipv4() { Family::Ipv4 } else { Family::Ipv6 }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if *self == Family::Ipv4 { "IPv4" } else { "IPv6" })
+    }
+}
+
+/// Options applied to the data connection before it is established.
+pub struct ConnectOptions {
+    /// Local address to send from, selecting the uplink on multi-homed hosts.
+    pub bind: Option<IpAddr>,
+    /// Network interface to bind to with SO_BINDTODEVICE (Linux only).
+    pub interface: Option<String>,
+    /// DSCP code point for the packets the client sends (the server marks its own data separately).
+    pub dscp: Option<u8>,
+    /// TTL (IPv4) or hop limit (IPv6) for the packets the client sends.
+    pub ttl: Option<u8>,
+    /// Proxy to tunnel the connection through instead of connecting to the server directly.
+    pub proxy: Option<Proxy>,
+    /// Give up on a TCP handshake that has not completed after this long, instead of waiting for the
+    /// OS to give up (minutes on most systems).
+    pub connect_timeout: Option<Duration>,
+    /// Read and write timeout of the connected socket, covering the proxy handshake.
+    pub io_timeout: Option<Duration>,
+    /// TCP keepalive settings, if keepalive is enabled.
+    pub keepalive: Option<Keepalive>,
+    /// Receive and send buffer size; must be set before connecting to affect the window scale.
+    pub socket_buffer: Option<usize>,
+    /// Disable Nagle's algorithm.
+    pub nodelay: bool,
+    /// Send the first write in the SYN with TCP Fast Open; `connect` then returns before the handshake.
+    pub fast_open: bool,
+    /// Only connect over this IP version, instead of racing both when the name has addresses of each.
+    pub family: Option<Family>,
+}
+
+/// How long the stages of establishing a connection took.
+pub struct ConnectTimings {
+    /// Resolving the server address, or the proxy address when connecting through one.
+    pub resolve: Duration,
+    /// The TCP handshake, with the proxy when connecting through one.
+    pub connect: Duration,
+    /// Having the proxy open the tunnel to the server, if connecting through one.
+    pub proxy: Option<Duration>,
+    /// Address connected to, the proxy's when tunneling. With Fast Open the socket only reports its
+    /// peer once the handshake is over, after the first write.
+    pub peer: SocketAddr,
+    /// The race between IPv6 and IPv4, if the name had addresses of both.
+    pub race: Option<Race>,
+}
+
+/// A won Happy Eyeballs race, with the attempts still running that it is compared against.
+pub struct Race {
+    winner: SocketAddr,
+    handshake: Duration,
+    /// Connection attempts started before the winner connected.
+    attempts: usize,
+    /// Attempts that failed before the winner connected.
+    failures: Vec<(SocketAddr, io::Error)>,
+    /// Outcomes of the attempts of the other family, which keep running after the race is decided.
+    losers: Receiver<Attempt>,
+}
+
+/// The outcome of one connection attempt and how long it took.
+type Attempt = (SocketAddr, Duration, io::Result<Socket>);
+
+/// Resolves `server` to the address the test connects to.
+///
+/// When a bind address is given, only server addresses of the same IP family are considered.
+///
+/// # Arguments
+/// - `server`: Server address as `host:port`.
+/// - `bind`: Local address the connection will be made from, if any.
+/// - `family`: IP version the connection is limited to, if any.
+pub fn resolve(server: &str, bind: Option<IpAddr>, family: Option<Family>) -> io::Result<SocketAddr> {
+    Ok(resolve_all(server, bind, family)?[0])
+}
+
+/// Resolves `server` to every address the test may connect to, in the resolver's order, keeping
+/// only those of the bind address's family and of `family`.
+fn resolve_all(server: &str, bind: Option<IpAddr>, family: Option<Family>) -> io::Result<Vec<SocketAddr>> {
+    let addresses: Vec<SocketAddr> = server
+        .to_socket_addrs()?
+        .filter(|addr| bind.is_none_or(|bind| bind.is_ipv4() == addr.is_ipv4()))
+        .filter(|addr| family.is_none_or(|family| family == Family::of(*addr)))
+        .collect();
+    if addresses.is_empty() {
+        let family = family.map(|family| format!(" {}", family)).unwrap_or_default();
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No usable{} address found for {}", family, server)));
+    }
+    Ok(addresses)
+}
+
+/// Resolves `server` and connects to it with the given options, through the proxy if one is set.
+///
+/// When the name has both IPv6 and IPv4 addresses, they are raced as RFC 8305 describes (Happy
+/// Eyeballs): the addresses are tried alternating between the families, IPv6 first, each 250 ms
+/// after the one before unless that one failed sooner, and the first to connect is used. With TCP
+/// Fast Open the handshake completes after the first write, so only the first address is tried.
+///
+/// # Arguments
+/// - `server`: Server address as `host:port`.
+/// - `options`: Socket options to apply before connecting.
+///
+/// # Returns
+/// - The connected stream, and how long resolving and connecting took.
+pub fn connect(server: &str, options: &ConnectOptions) -> io::Result<(TcpStream, ConnectTimings)> {
+    let started = Instant::now();
+    let addresses = resolve_all(options.proxy.as_ref().map_or(server, |proxy| &proxy.address), options.bind, options.family)?;
+    let resolve = started.elapsed();
+
+    let connect_started = Instant::now();
+    let dual_stack = addresses.iter().any(|addr| addr.is_ipv4()) && addresses.iter().any(|addr| addr.is_ipv6());
+    let (socket, addr, race) = if dual_stack && !options.fast_open {
+        let race = race(addresses, options).map_err(|(addr, e)| connect_error(server, addr, options, e))?;
+        (race.0, race.1.winner, Some(race.1))
+    } else {
+        let addr = addresses[0];
+        let socket = attempt(open(addr, options)?, addr, options.connect_timeout).map_err(|e| connect_error(server, addr, options, e))?;
+        (socket, addr, None)
+    };
+    let connect = connect_started.elapsed();
+
+    let mut stream: TcpStream = socket.into();
+    stream.set_read_timeout(options.io_timeout)?;
+    stream.set_write_timeout(options.io_timeout)?;
+    let proxy = match &options.proxy {
+        Some(proxy) => {
+            let tunnel_started = Instant::now();
+            proxy.tunnel(&mut stream, server)?;
+            Some(tunnel_started.elapsed())
+        }
+        None => None,
+    };
+
+    Ok((stream, ConnectTimings { resolve, connect, proxy, peer: addr, race }))
+}
+
+/// Creates a socket for connecting to `addr`, with the options applied.
+fn open(addr: SocketAddr, options: &ConnectOptions) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if let Some(interface) = &options.interface {
+        bind_to_interface(&socket, interface)?;
+    }
+    if let Some(bind) = options.bind {
+        socket.bind(&SocketAddr::new(bind, 0).into())?;
+    }
+    if let Some(dscp) = options.dscp {
+        sockopt::set_dscp(&socket, dscp)?;
+    }
+    if let Some(ttl) = options.ttl {
+        sockopt::set_ttl(&socket, ttl)?;
+    }
+    if let Some(keepalive) = &options.keepalive {
+        sockopt::set_keepalive(&socket, keepalive)?;
+    }
+    if let Some(size) = options.socket_buffer {
+        socket.set_recv_buffer_size(size)?;
+        socket.set_send_buffer_size(size)?;
+    }
+    socket.set_nodelay(options.nodelay)?;
+    if options.fast_open {
+        sockopt::set_fast_open_connect(&socket)?;
+    }
+    Ok(socket)
+}
+
+/// Connects `socket` to `addr`, giving up after `timeout` if there is one.
+fn attempt(socket: Socket, addr: SocketAddr, timeout: Option<Duration>) -> io::Result<Socket> {
+    match timeout {
+        Some(timeout) => socket.connect_timeout(&addr.into(), timeout)?,
+        None => socket.connect(&addr.into())?,
+    }
+    Ok(socket)
+}
+
+/// Races connections to `addresses`, which hold addresses of both families.
+///
+/// Once an attempt wins, the first address of the other family is still tried if it was not yet,
+/// so the race can tell how far behind that family was. Attempts that connect after the winner are
+/// closed at once.
+///
+/// # Returns
+/// - The winning socket and the race, or the last address tried and its error if none connected.
+fn race(addresses: Vec<SocketAddr>, options: &ConnectOptions) -> Result<(Socket, Race), (SocketAddr, io::Error)> {
+    // Alternate between the families, starting with IPv6 (RFC 8305, section 4)
+    let (mut v6, mut v4): (Vec<SocketAddr>, Vec<SocketAddr>) = addresses.into_iter().partition(SocketAddr::is_ipv6);
+    let mut order = Vec::new();
+    while !v6.is_empty() || !v4.is_empty() {
+        for family in [&mut v6, &mut v4] {
+            if !family.is_empty() {
+                order.push(family.remove(0));
+            }
+        }
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let start = |addr: SocketAddr| -> io::Result<()> {
+        let socket = open(addr, options)?;
+        let sender = sender.clone();
+        let timeout = options.connect_timeout;
+        thread::spawn(move || {
+            let started = Instant::now();
+            let connected = attempt(socket, addr, timeout);
+            // The receiver is gone once the race is reported; the socket is closed either way
+            let _ = sender.send((addr, started.elapsed(), connected));
+        });
+        Ok(())
+    };
+
+    let mut next = 0;
+    let mut pending = 0;
+    let mut next_at = Instant::now();
+    let mut failures = Vec::new();
+    let (winner, handshake, socket) = loop {
+        let now = Instant::now();
+        if next < order.len() && (pending == 0 || now >= next_at) {
+            let addr = order[next];
+            next += 1;
+            match start(addr) {
+                Ok(()) => pending += 1,
+                Err(e) => failures.push((addr, e)),
+            }
+            next_at = now + ATTEMPT_DELAY;
+            continue;
+        }
+        if pending == 0 {
+            return Err(failures.pop().unwrap_or_else(|| (order[0], io::Error::new(io::ErrorKind::NotFound, "no address to connect to"))));
+        }
+        let wait = if next < order.len() { next_at.saturating_duration_since(now) } else { Duration::from_secs(3600) };
+        match receiver.recv_timeout(wait) {
+            Ok((addr, handshake, Ok(socket))) => break (addr, handshake, socket),
+            Ok((addr, _, Err(e))) => {
+                pending -= 1;
+                failures.push((addr, e));
+                // A failed attempt hands over to the next address at once
+                next_at = Instant::now();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => unreachable!("the race holds a sender"),
+        }
+    };
+
+    let attempts = next;
+    let other_family = |addr: &&SocketAddr| Family::of(**addr) != Family::of(winner);
+    if !order[..next].iter().any(|addr| other_family(&addr)) {
+        if let Some(&addr) = order.iter().find(other_family) {
+            // Only the outcome is of interest; an attempt that fails to start shows up as none
+            let _ = start(addr);
+        }
+    }
+    Ok((socket, Race { winner, handshake, attempts, failures, losers: receiver }))
+}
+
+impl Race {
+    /// Reports the race, waiting up to a second for the other family's first attempt to finish if
+    /// it still has not.
+    pub fn report(self) -> HappyEyeballs {
+        let family = Family::of(self.winner);
+        let deadline = Instant::now() + LOSER_WAIT;
+        let mut other = self.failures.into_iter().find(|(addr, _)| Family::of(*addr) != family).map(|(addr, e)| (addr, Duration::ZERO, Some(e)));
+        while other.is_none() {
+            match self.losers.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((addr, handshake, connected)) if Family::of(addr) != family => other = Some((addr, handshake, connected.err())),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        let handshake_seconds = self.handshake.as_secs_f64();
+        HappyEyeballs {
+            winner: self.winner,
+            handshake_seconds,
+            attempts: self.attempts,
+            other: other.as_ref().map(|(addr, _, _)| *addr),
+            other_handshake_seconds: other.as_ref().filter(|(_, _, error)| error.is_none()).map(|(_, handshake, _)| handshake.as_secs_f64()),
+            margin_seconds: other
+                .as_ref()
+                .filter(|(_, _, error)| error.is_none())
+                .map(|(_, handshake, _)| handshake.as_secs_f64() - handshake_seconds),
+            other_error: other.and_then(|(_, _, error)| error).map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Explains a failed connection attempt with what is most likely wrong.
+fn connect_error(server: &str, addr: SocketAddr, options: &ConnectOptions, e: io::Error) -> io::Error {
+    let target = match &options.proxy {
+        Some(proxy) => format!("proxy {} ({})", proxy, addr),
+        None => format!("{} ({})", server, addr),
+    };
+    let hint = match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => match options.connect_timeout {
+            Some(timeout) => format!(
+                "no answer within {:.1}s; the host may be down or a firewall may be dropping the connection",
+                timeout.as_secs_f64()
+            ),
+            None => "no answer; the host may be down or a firewall may be dropping the connection".to_string(),
+        },
+        io::ErrorKind::ConnectionRefused => "connection refused; is it running and listening on that port?".to_string(),
+        _ => e.to_string(),
+    };
+    io::Error::new(e.kind(), format!("Failed to connect to {}: {}", target, hint))
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_to_interface(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes())).map_err(|e| {
+        io::Error::new(e.kind(), format!("Failed to bind to interface {}: {}", interface, e))
+    })
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_to_interface(_socket: &Socket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Binding to an interface is only supported on Linux"))
+}
@@ -0,0 +1,416 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use common::protocol;
+use common::sockopt::{self, Keepalive};
+use csv::Writer;
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use crate::plot::{self, Series};
+use crate::results::RunResult;
+use crate::output;
+use crate::servers;
+use crate::stats::Summary;
+use crate::{Args, EXIT_TEST_ERROR, EXIT_THRESHOLD_FAILURE};
+
+/// Keepalive on the control connection, so a coordinator waiting out a long test notices an agent
+/// host that went away.
+const CONTROL_KEEPALIVE: Keepalive =
+    Keepalive { idle: Some(Duration::from_secs(30)), interval: Some(Duration::from_secs(10)), count: Some(3) };
+
+/// A test a coordinator asks an agent to run, sent as one JSON line.
+#[derive(Serialize, Deserialize)]
+struct Job {
+    /// Wait this long after receiving the job before starting the test.
+    start_in_ms: u64,
+    /// Client options of the test, as on the command line.
+    args: Vec<String>,
+}
+
+/// An agent's answer to a job, sent as one JSON line once the test is over.
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    /// Exit code the test would have exited with as a standalone client.
+    pub exit_code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<RunResult>,
+    /// Why the test produced no result, or ended with a test error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of one agent in a coordinated test.
+#[derive(Serialize, Deserialize)]
+pub struct AgentRun {
+    pub agent: String,
+    pub server: String,
+    pub exit_code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<RunResult>,
+}
+
+impl AgentRun {
+    fn new(agent: String, server: String, report: Result<Report, Box<dyn std::error::Error>>) -> Self {
+        match report {
+            Ok(report) => AgentRun { agent, server, exit_code: report.exit_code, error: report.error, result: report.result },
+            Err(e) => AgentRun { agent, server, exit_code: EXIT_TEST_ERROR, error: Some(e.to_string()), result: None },
+        }
+    }
+}
+
+/// Results of all agents of a coordinated test, and what they add up to.
+#[derive(Serialize, Deserialize)]
+pub struct CoordinatedReport {
+    pub agents: usize,
+    /// Agents that returned a result, whether or not it met the thresholds.
+    pub completed: usize,
+    /// Sum of the effective data rates of all agents, the load the servers carried together.
+    pub total_throughput_bps: f64,
+    /// Effective data rates of the agents that completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_agent_throughput: Option<Summary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_agent_latency: Option<Summary>,
+    pub runs: Vec<AgentRun>,
+}
+
+/// Results of every pair of nodes of a mesh test.
+#[derive(Serialize, Deserialize)]
+pub struct MeshReport {
+    pub nodes: Vec<String>,
+    /// One run per ordered pair of nodes, the source node's agent testing against the destination's server.
+    pub runs: Vec<AgentRun>,
+}
+
+/// Accepts jobs from coordinators on `address` and runs them one at a time, forever.
+///
+/// Every job runs like a standalone client with the job's options, writing its output files in the
+/// agent's working directory, and its summary is sent back to the coordinator.
+pub fn serve(address: SocketAddr) -> i32 {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen for coordinators on {}: {}", address, e);
+            return EXIT_TEST_ERROR;
+        }
+    };
+    println!("Agent waiting for jobs on {}", address);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept a coordinator: {}", e);
+                continue;
+            }
+        };
+        let coordinator = stream.peer_addr().map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+        if let Err(e) = run_job(stream) {
+            eprintln!("Job from {} failed: {}", coordinator, e);
+        }
+    }
+    0
+}
+
+fn run_job(mut stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+    sockopt::set_keepalive(&SockRef::from(&stream), &CONTROL_KEEPALIVE)?;
+    let job: Job = serde_json::from_str(&protocol::read_line(&mut stream)?)?;
+    let received = Instant::now();
+    println!("Job received, starting in {} ms: {}", job.start_in_ms, job.args.join(" "));
+
+    let report = match parse_test(job.args) {
+        Ok(args) => {
+            thread::sleep(Duration::from_millis(job.start_in_ms).saturating_sub(received.elapsed()));
+            run_test(args)
+        }
+        Err(error) => Report { exit_code: EXIT_TEST_ERROR, result: None, error: Some(error) },
+    };
+    println!("Job finished with exit code {}", report.exit_code);
+    writeln!(stream, "{}", serde_json::to_string(&report)?)?;
+    Ok(())
+}
+
+/// Parses the options of a test run on someone else's behalf, which must be a single test rather
+/// than another long-running mode.
+pub fn parse_test(options: Vec<String>) -> Result<Args, String> {
+    match Args::parse_arguments(iter::once("client".to_string()).chain(options)) {
+        Ok(args) if args.agent.is_none() && args.coordinate.is_none() && args.mesh.is_none() && args.schedule.is_none() => Ok(args),
+        Ok(_) => Err("only single tests can be run as jobs, not agents, coordinators, meshes, or schedules".to_string()),
+        // Only the first line of clap's message; the rest is usage help for a terminal
+        Err(e) => Err(e.to_string().lines().next().unwrap_or_default().to_string()),
+    }
+}
+
+/// Runs a test like a standalone client would, and returns its exit code and run summary.
+pub fn run_test(mut args: Args) -> Report {
+    // The summary comes back through a file, exactly as --json would write it
+    let summary = env::temp_dir().join(format!("bwtest-agent-{}.json", process::id()));
+    let _ = fs::remove_file(&summary);
+    args.json = Some(summary.clone());
+    if let Err(e) = output::prepare(&mut args) {
+        return Report { exit_code: EXIT_TEST_ERROR, result: None, error: Some(e) };
+    }
+    let outcome = crate::select_server(&mut args).and_then(|_| crate::run(&args));
+    let result = RunResult::read_json(&summary).ok();
+    let _ = fs::remove_file(&summary);
+    match outcome {
+        Ok(exit_code) => Report { exit_code, result, error: None },
+        Err(e) => Report { exit_code: EXIT_TEST_ERROR, result, error: Some(e.to_string()) },
+    }
+}
+
+/// Starts the test given by `args.agent_args` on every agent listed in `args.coordinate` at the same
+/// moment, waits for all of them, and writes the combined report.
+///
+/// Each line of the agent list names an agent, optionally followed by the server it tests against;
+/// agents without one test against `--server`.
+///
+/// # Returns
+/// - The process exit code: `EXIT_TEST_ERROR` if any agent failed to run its test,
+///   `EXIT_THRESHOLD_FAILURE` if any missed a threshold, 0 otherwise.
+pub fn coordinate(args: &Args, list: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut targets = Vec::new();
+    for line in servers::load(list)? {
+        let mut fields = line.split_whitespace();
+        let agent = fields.next().unwrap_or_default().to_string();
+        let server = fields.next().map_or_else(|| args.server.clone(), str::to_string);
+        targets.push((agent, server));
+    }
+    let start_delay = Duration::from_secs_f64(args.start_delay);
+    let connect_timeout = crate::timeout(args.connect_timeout).unwrap_or(Duration::from_secs(10));
+
+    // Every agent is connected before any is told to start, so connecting does not skew the start
+    println!("Connecting to {} agents...", targets.len());
+    let connections: Vec<io::Result<TcpStream>> = targets.iter().map(|(agent, _)| connect(agent, connect_timeout)).collect();
+    let deadline = Instant::now() + start_delay;
+    let handles: Vec<_> = targets
+        .iter()
+        .zip(connections)
+        .map(|((agent, server), connection)| {
+            let job_args: Vec<String> = vec!["--server".to_string(), server.clone()].into_iter().chain(args.agent_args.iter().cloned()).collect();
+            let (agent, server) = (agent.clone(), server.clone());
+            thread::spawn(move || {
+                let report = connection.map_err(|e| e.into()).and_then(|stream| dispatch(stream, deadline, job_args));
+                AgentRun::new(agent, server, report)
+            })
+        })
+        .collect();
+    println!("Starting the test on all agents in {:.1}s", start_delay.as_secs_f64());
+    let runs: Vec<AgentRun> = handles
+        .into_iter()
+        .map(|handle| handle.join().map_err(|_| "agent thread panicked"))
+        .collect::<Result<_, _>>()?;
+
+    let report = aggregate(runs);
+    print_report(&report);
+    let csv = output::path(args, None, "coordinator_report.csv");
+    write_csv(&report, &csv)?;
+    let json = output::path(args, None, args.json.as_deref().unwrap_or(Path::new("coordinator_report.json")));
+    serde_json::to_writer_pretty(fs::File::create(&json)?, &report)?;
+    println!("Coordinated report saved to {} and {}", csv.display(), json.display());
+
+    Ok(exit_code(&report.runs))
+}
+
+/// Measures every pair of nodes listed in `list`, one pair at a time: each node's agent runs the
+/// test given by `args.agent_args` against every other node's server, and the results are written
+/// as source-by-destination matrices.
+///
+/// Each line of the node list names a node's agent followed by its server.
+///
+/// # Returns
+/// - The process exit code, as for `coordinate`.
+pub fn mesh(args: &Args, list: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut nodes = Vec::new();
+    for line in servers::load(list)? {
+        match line.split_whitespace().collect::<Vec<&str>>()[..] {
+            [agent, server] => nodes.push((agent.to_string(), server.to_string())),
+            _ => return Err(format!("'{}' is not an AGENT:PORT SERVER:PORT node", line).into()),
+        }
+    }
+    if nodes.len() < 2 {
+        return Err("a mesh needs at least two nodes".into());
+    }
+    let connect_timeout = crate::timeout(args.connect_timeout).unwrap_or(Duration::from_secs(10));
+
+    // Pairs run one after another, so no test competes with another for a node's links
+    let mut runs = Vec::new();
+    for (source, (agent, _)) in nodes.iter().enumerate() {
+        for (destination, (_, server)) in nodes.iter().enumerate() {
+            if source == destination {
+                continue;
+            }
+            println!("Testing {} -> {}...", agent, server);
+            let job_args = vec!["--server".to_string(), server.clone()].into_iter().chain(args.agent_args.iter().cloned()).collect();
+            let report = connect(agent, connect_timeout).map_err(|e| e.into()).and_then(|stream| dispatch(stream, Instant::now(), job_args));
+            let run = AgentRun::new(agent.clone(), server.clone(), report);
+            if let Some(error) = &run.error {
+                eprintln!("{} -> {} failed: {}", agent, server, error);
+            }
+            runs.push(run);
+        }
+    }
+
+    let names: Vec<String> = nodes.iter().map(|(agent, _)| agent.clone()).collect();
+    let matrix = |value: fn(&RunResult) -> Option<f64>| -> Vec<Vec<Option<f64>>> {
+        nodes
+            .iter()
+            .map(|(agent, _)| {
+                nodes
+                    .iter()
+                    .map(|(_, server)| {
+                        let run = runs.iter().find(|run| &run.agent == agent && &run.server == server)?;
+                        run.result.as_ref().and_then(value)
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+    let throughput = matrix(|result| result.throughput_bps);
+    let latency = matrix(|result| result.latency_seconds);
+    let rate_title = format!("Effective Data Rate ({})", args.units.rate_unit());
+    print_matrix(&rate_title, &names, &throughput, 1.0 / args.units.mega());
+    print_matrix("Avg Latency (ms)", &names, &latency, 1e3);
+
+    let (throughput_csv, latency_csv) = (output::path(args, None, "mesh_throughput.csv"), output::path(args, None, "mesh_latency.csv"));
+    write_matrix(&names, &throughput, &throughput_csv)?;
+    write_matrix(&names, &latency, &latency_csv)?;
+    let theme = crate::chart_theme(args);
+    let rate = |bps: f64| format!("{:.1}", args.units.rate_value(bps));
+    plot::plot_heatmap(&output::path(args, None, "mesh_throughput.png"), &rate_title, &names, &throughput, rate, Series::DataRate, &theme)?;
+    let ms = |seconds: f64| format!("{:.3}", seconds * 1e3);
+    plot::plot_heatmap(&output::path(args, None, "mesh_latency.png"), "Avg Latency (ms)", &names, &latency, ms, Series::Latency, &theme)?;
+    let code = exit_code(&runs);
+    let json = output::path(args, None, args.json.as_deref().unwrap_or(Path::new("mesh_report.json")));
+    serde_json::to_writer_pretty(fs::File::create(&json)?, &MeshReport { nodes: names, runs })?;
+    println!("Mesh matrices saved to {} and {}, all results to {}", throughput_csv.display(), latency_csv.display(), json.display());
+
+    Ok(code)
+}
+
+/// Exit code of a test made of several runs: the worst of them.
+fn exit_code(runs: &[AgentRun]) -> i32 {
+    let worst = runs.iter().map(|run| run.exit_code).max().unwrap_or(0);
+    if worst >= EXIT_TEST_ERROR {
+        EXIT_TEST_ERROR
+    } else if worst > 0 {
+        EXIT_THRESHOLD_FAILURE
+    } else {
+        0
+    }
+}
+
+fn connect(agent: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let address = crate::connection::resolve(agent, None, None)?;
+    let stream = TcpStream::connect_timeout(&address, timeout)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to connect to agent {}: {}", agent, e)))?;
+    sockopt::set_keepalive(&SockRef::from(&stream), &CONTROL_KEEPALIVE)?;
+    Ok(stream)
+}
+
+/// Sends a job that starts at `deadline`, and waits for the agent's report.
+fn dispatch(mut stream: TcpStream, deadline: Instant, args: Vec<String>) -> Result<Report, Box<dyn std::error::Error>> {
+    let job = Job { start_in_ms: deadline.saturating_duration_since(Instant::now()).as_millis() as u64, args };
+    writeln!(stream, "{}", serde_json::to_string(&job)?)?;
+    let line = protocol::read_line(&mut stream).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => io::Error::new(e.kind(), "the agent closed the connection without a report"),
+        _ => e,
+    })?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+fn aggregate(runs: Vec<AgentRun>) -> CoordinatedReport {
+    let results: Vec<&RunResult> = runs.iter().filter_map(|run| run.result.as_ref()).collect();
+    let throughputs: Vec<f64> = results.iter().filter_map(|result| result.throughput_bps).collect();
+    let latencies: Vec<f64> = results.iter().filter_map(|result| result.latency_seconds).collect();
+    CoordinatedReport {
+        agents: runs.len(),
+        completed: results.len(),
+        total_throughput_bps: throughputs.iter().fold(0.0, |total, throughput| total + throughput),
+        per_agent_throughput: Summary::of(&throughputs),
+        per_agent_latency: Summary::of(&latencies),
+        runs,
+    }
+}
+
+fn print_report(report: &CoordinatedReport) {
+    println!("=== Coordinated report ===");
+    for run in &report.runs {
+        match (&run.result, &run.error) {
+            (Some(result), _) => println!(
+                "{:<24} -> {:<24} {:>24} {:>14} {:>7.2} %  exit {}",
+                run.agent,
+                run.server,
+                result.throughput_bps.map_or("n/a".to_string(), |throughput| format!("{:.2} bps", throughput)),
+                result.latency_seconds.map_or("n/a".to_string(), |latency| format!("{:.5} s", latency)),
+                result.loss_percent,
+                run.exit_code
+            ),
+            (None, error) => println!("{:<24} -> {:<24} failed: {}", run.agent, run.server, error.as_deref().unwrap_or("no result")),
+        }
+    }
+    println!("Agents completed: {} of {}", report.completed, report.agents);
+    println!("Total Effective Data Rate: {:.2} bps", report.total_throughput_bps);
+    if let Some(throughput) = &report.per_agent_throughput {
+        println!(
+            "Per-agent Effective Data Rate: min {:.2}, median {:.2}, max {:.2} bps",
+            throughput.min, throughput.median, throughput.max
+        );
+    }
+}
+
+fn write_csv(report: &CoordinatedReport, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut wtr = Writer::from_path(path)?;
+    wtr.write_record(["Agent", "Server", "Exit Code", "Effective Data Rate (bps)", "Avg Latency (s)", "Loss (%)", "Error"])?;
+    for run in &report.runs {
+        let metric = |value: fn(&RunResult) -> Option<f64>| run.result.as_ref().and_then(value).map(|value| value.to_string()).unwrap_or_default();
+        wtr.write_record(&[
+            run.agent.clone(),
+            run.server.clone(),
+            run.exit_code.to_string(),
+            metric(|result| result.throughput_bps),
+            metric(|result| result.latency_seconds),
+            metric(|result| Some(result.loss_percent)),
+            run.error.clone().unwrap_or_default(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Prints a matrix with sources as rows and destinations as columns, scaling every value by `scale`.
+fn print_matrix(title: &str, nodes: &[String], values: &[Vec<Option<f64>>], scale: f64) {
+    println!("=== {} ===", title);
+    print!("{:<24}", "Source \\ Destination");
+    for node in nodes {
+        print!(" {:>22}", node);
+    }
+    println!();
+    for (node, row) in nodes.iter().zip(values) {
+        print!("{:<24}", node);
+        for value in row {
+            match value {
+                Some(value) => print!(" {:>22.3}", value * scale),
+                None => print!(" {:>22}", "-"),
+            }
+        }
+        println!();
+    }
+}
+
+fn write_matrix(nodes: &[String], values: &[Vec<Option<f64>>], path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut wtr = Writer::from_path(path)?;
+    wtr.write_record(iter::once("Source \\ Destination").chain(nodes.iter().map(String::as_str)))?;
+    for (node, row) in nodes.iter().zip(values) {
+        wtr.write_record(iter::once(node.clone()).chain(row.iter().map(|value| value.map(|value| value.to_string()).unwrap_or_default())))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
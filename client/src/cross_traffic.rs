@@ -0,0 +1,166 @@
+use std::net::{IpAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use common::protocol::Direction;
+use crate::bottleneck;
+use crate::netdev::{self, Counters};
+use crate::results::{CrossTraffic, CrossTrafficInterval};
+use crate::wire::IoCounters;
+
+/// Other traffic below this rate is background chatter (ARP, NTP, mDNS) and never flags an
+/// interval, however little the test itself moved.
+const MIN_OTHER_BYTES_PER_SECOND: f64 = 12_500.0;
+
+/// What the interface and the test moved in one sampling interval.
+pub struct Interval {
+    /// Start and end of the interval, in seconds from the start of the run.
+    pub start: f64,
+    pub end: f64,
+    /// Bytes in either direction that neither the test nor the estimated headers account for.
+    pub other_bytes: u64,
+    /// Bytes the test put on or took off the wire, in either direction.
+    pub test_bytes: u64,
+}
+
+/// Samples the interface counters and the test's own socket counters on a background thread, so
+/// traffic from other applications can be told apart from the test's, interval by interval.
+pub struct Background {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Vec<Interval>>>,
+}
+
+impl Background {
+    /// Starts sampling `interface` every `interval` until `finish` is called, timing the intervals
+    /// from `run_start`. Intervals whose counters cannot be read are skipped.
+    ///
+    /// # Arguments
+    /// - `local`: Local address of the test connection, which sets the header size per packet.
+    /// - `stream`: The test connection, whose unread or unacknowledged bytes are still on the
+    ///   wire's side of the socket counters. After a reconnect its queue is no longer counted.
+    /// - `io`: The test connection's byte counters.
+    pub fn start(interface: String, local: IpAddr, stream: TcpStream, direction: Direction, io: Arc<IoCounters>, interval: Duration, run_start: Instant) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let header_bytes = netdev::header_bytes_per_packet(local);
+            // Bytes the test has moved through the wire: what the socket counted, corrected by
+            // what is still queued in the kernel
+            let test_bytes = || {
+                let queued = bottleneck::queued_bytes(&stream, direction).unwrap_or(0) as u64;
+                match direction {
+                    Direction::Download => (io.bytes_read() + queued, io.bytes_written()),
+                    Direction::Upload => (io.bytes_read(), io.bytes_written().saturating_sub(queued)),
+                }
+            };
+            let mut intervals = Vec::new();
+            let mut previous = netdev::read(&interface).ok().map(|counters| (Instant::now(), counters, test_bytes()));
+            while !stopped.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let now = Instant::now();
+                let counters = match netdev::read(&interface) {
+                    Ok(counters) => counters,
+                    Err(_) => continue,
+                };
+                let test = test_bytes();
+                if let Some((then, earlier, earlier_test)) = previous {
+                    let delta = counters.since(&earlier);
+                    let (read, written) = (test.0.saturating_sub(earlier_test.0), test.1.saturating_sub(earlier_test.1));
+                    intervals.push(Interval {
+                        start: (then - run_start).as_secs_f64(),
+                        end: (now - run_start).as_secs_f64(),
+                        other_bytes: other_bytes(&delta, read, written, header_bytes, local.is_loopback()),
+                        test_bytes: read + written,
+                    });
+                }
+                previous = Some((now, counters, test));
+            }
+            intervals
+        });
+        Background { stop, handle: Some(handle) }
+    }
+
+    /// Stops sampling and waits for the thread, which finishes the current interval first.
+    pub fn finish(mut self) -> Vec<Interval> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.take().and_then(|handle| handle.join().ok()).unwrap_or_default()
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Bytes the interface counted beyond the test's own and a TCP header for every packet. Headers of
+/// other traffic are subtracted too, which errs towards reporting less of it.
+///
+/// # Arguments
+/// - `loopback`: Whether the test runs over the loopback interface, which counts every packet as
+///   both sent and received.
+fn other_bytes(delta: &Counters, read: u64, written: u64, header_bytes: u64, loopback: bool) -> u64 {
+    let (received, sent) = if loopback { (read + written, read + written) } else { (read, written) };
+    let other_received = delta.rx_bytes.saturating_sub(delta.rx_packets * header_bytes + received);
+    let other_sent = delta.tx_bytes.saturating_sub(delta.tx_packets * header_bytes + sent);
+    other_received + other_sent
+}
+
+/// Summarizes the intervals, flagging those in which other traffic reached `threshold_percent` of
+/// the test's own.
+pub fn summarize(interface: &str, intervals: &[Interval], interval: Duration, threshold_percent: f64) -> CrossTraffic {
+    let flagged = intervals
+        .iter()
+        .filter(|sample| {
+            let seconds = sample.end - sample.start;
+            seconds > 0.0
+                && sample.other_bytes as f64 / seconds >= MIN_OTHER_BYTES_PER_SECOND
+                && sample.other_bytes as f64 >= sample.test_bytes as f64 * threshold_percent / 100.0
+        })
+        .map(|sample| CrossTrafficInterval {
+            start_offset_seconds: sample.start,
+            end_offset_seconds: sample.end,
+            other_bytes: sample.other_bytes,
+            other_bps: sample.other_bytes as f64 * 8.0 / (sample.end - sample.start),
+            test_bytes: sample.test_bytes,
+        })
+        .collect();
+    CrossTraffic {
+        interface: interface.to_string(),
+        interval_seconds: interval.as_secs_f64(),
+        threshold_percent,
+        intervals: intervals.len(),
+        other_bytes: intervals.iter().map(|sample| sample.other_bytes).sum(),
+        flagged,
+        excluded_samples: None,
+    }
+}
+
+/// Returns whether each sample overlaps a flagged interval. A sample covers the time from the end
+/// of the previous sample (or the start of the run) to its own end.
+///
+/// # Arguments
+/// - `offsets`: When each sample completed, in seconds from the start of the run.
+pub fn affected_samples(summary: &CrossTraffic, offsets: &[f64]) -> Vec<bool> {
+    offsets
+        .iter()
+        .enumerate()
+        .map(|(i, &end)| {
+            let start = if i == 0 { 0.0 } else { offsets[i - 1] };
+            summary.flagged.iter().any(|flagged| start < flagged.end_offset_seconds && end > flagged.start_offset_seconds)
+        })
+        .collect()
+}
+
+/// Groups the affected samples into runs of consecutive samples, as first and last index.
+pub fn spans(affected: &[bool]) -> Vec<(usize, usize)> {
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in affected.iter().enumerate().filter(|(_, &affected)| affected) {
+        match spans.last_mut() {
+            Some(span) if span.1 + 1 == i => span.1 = i,
+            _ => spans.push((i, i)),
+        }
+    }
+    spans
+}
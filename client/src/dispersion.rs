@@ -0,0 +1,215 @@
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use common::echo::{TrainPacket, TrainRequest, TrainStamp};
+use common::mmsg::{self, Batch};
+use common::protocol::Direction;
+use crate::echo;
+use crate::results::{Confidence, DispersionEstimate, DispersionRate, UdpOffload};
+use crate::stats;
+use crate::units::Units;
+
+/// How long to wait for the last packet or stamp of a train before counting it as incomplete.
+const TRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How the packet trains of an estimate are sent.
+pub struct Settings {
+    pub trains: usize,
+    /// Packets per train; a train of two is a packet pair.
+    pub packets: u16,
+    /// UDP payload of each packet, in bytes.
+    pub packet_len: usize,
+    /// Pause between trains, which keeps the probing light on a busy path.
+    pub interval: Duration,
+    /// Send upload trains with UDP GSO and receive download trains with GRO.
+    pub offload: bool,
+}
+
+/// Estimates the bandwidth of `direction` by sending trains through the server's echo service at
+/// `target`. Downloads are timed as the client receives the server's trains, uploads by the
+/// arrival stamps the server returns for the client's trains, so only one clock times each train.
+pub fn estimate(target: SocketAddr, bind: Option<IpAddr>, direction: Direction, settings: &Settings) -> io::Result<DispersionEstimate> {
+    let socket = echo::connect(target, bind)?;
+    // Without kernel timestamps, download trains are timed when the receive call returns
+    let _ = mmsg::enable_timestamps(&socket);
+    let mut offload = settings.offload.then(|| UdpOffload {
+        gso: direction == Direction::Upload,
+        gro: direction == Direction::Download && enable_gro(&socket),
+        coalesced_packets: 0,
+    });
+    let mut train_rates = Vec::with_capacity(settings.trains);
+    let mut pair_rates = Vec::with_capacity(settings.trains);
+    for train in 0..settings.trains {
+        let arrivals = match direction {
+            Direction::Download => download_train(&socket, train as u32, settings, offload.as_mut())?,
+            Direction::Upload => upload_train(&socket, train as u32, settings, offload.as_mut())?,
+        };
+        if let Some(arrivals) = arrivals {
+            let bits = settings.packet_len as f64 * 8.0;
+            let spread = arrivals[arrivals.len() - 1] - arrivals[0];
+            let pair_spread = arrivals[1] - arrivals[0];
+            // Packets that arrived together were not spread by the path, and give no rate
+            if spread > 0.0 && pair_spread > 0.0 {
+                train_rates.push(bits * (arrivals.len() - 1) as f64 / spread);
+                pair_rates.push(bits / pair_spread);
+            }
+        }
+        if train + 1 < settings.trains {
+            thread::sleep(settings.interval);
+        }
+    }
+
+    let rate = rate(&train_rates, &pair_rates, settings.trains);
+    Ok(DispersionEstimate {
+        direction,
+        trains_sent: settings.trains,
+        trains_used: train_rates.len(),
+        packets_per_train: settings.packets,
+        packet_bytes: settings.packet_len,
+        rate,
+        offload,
+    })
+}
+
+/// Enables GRO on the socket of a download estimate, reporting whether it could be.
+fn enable_gro(socket: &UdpSocket) -> bool {
+    match mmsg::enable_gro(socket) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("UDP GRO unavailable, continuing without it: {}", e);
+            false
+        }
+    }
+}
+
+/// Summarizes the rates of the usable trains.
+fn rate(train_rates: &[f64], pair_rates: &[f64], trains_sent: usize) -> Option<DispersionRate> {
+    let (ci_low_bps, ci_high_bps) = stats::bootstrap_median_ci(train_rates, 0.95, 10_000)?;
+    let median = |rates: &[f64]| {
+        let mut sorted = rates.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        stats::percentile(&sorted, 50.0)
+    };
+    let available_bps = median(train_rates);
+    // A wide interval means the trains disagreed; many lost trains mean the survivors may not be
+    // typical of the path
+    let relative_width = (ci_high_bps - ci_low_bps) / available_bps;
+    let used = train_rates.len() as f64 / trains_sent as f64;
+    let confidence = if train_rates.len() >= 10 && relative_width <= 0.1 && used >= 0.9 {
+        Confidence::High
+    } else if train_rates.len() >= 5 && relative_width <= 0.3 && used >= 0.5 {
+        Confidence::Medium
+    } else {
+        Confidence::Low
+    };
+    Some(DispersionRate { available_bps, ci_low_bps, ci_high_bps, pair_capacity_bps: median(pair_rates), confidence })
+}
+
+/// Requests a train from the server and times the arrival of its packets.
+///
+/// # Returns
+/// - The arrival time of every packet in seconds, in train order, or `None` if any was lost.
+fn download_train(socket: &UdpSocket, train: u32, settings: &Settings, offload: Option<&mut UdpOffload>) -> io::Result<Option<Vec<f64>>> {
+    let request = TrainRequest { train, packets: settings.packets, len: settings.packet_len as u16 };
+    let sent = Instant::now();
+    socket.send(&request.encode())?;
+    collect(socket, sent, settings.packets, offload, |packet, arrival| {
+        let received = TrainPacket::decode(packet).filter(|packet| packet.train == train)?;
+        Some((received.index, arrival))
+    })
+}
+
+/// Sends a train to the server back-to-back and collects the arrival stamps it returns.
+///
+/// # Returns
+/// - The server's arrival time of every packet in seconds, in train order, or `None` if any
+///   packet or stamp was lost.
+fn upload_train(socket: &UdpSocket, train: u32, settings: &Settings, mut offload: Option<&mut UdpOffload>) -> io::Result<Option<Vec<f64>>> {
+    let packets: Vec<Vec<u8>> = (0..settings.packets).map(|index| TrainPacket { train, index, packets: settings.packets }.encode(settings.packet_len)).collect();
+    let sent = Instant::now();
+    match offload.as_deref_mut().filter(|offload| offload.gso) {
+        Some(offload) => {
+            if let Err(e) = mmsg::send_segmented(socket, &packets, None) {
+                eprintln!("UDP GSO unavailable, continuing without it: {}", e);
+                offload.gso = false;
+                mmsg::send_all(socket, &packets, None)?;
+            }
+        }
+        None => mmsg::send_all(socket, &packets, None)?,
+    }
+    collect(socket, sent, settings.packets, offload, |packet, _| {
+        let stamp = TrainStamp::decode(packet).filter(|stamp| stamp.train == train)?;
+        Some((stamp.index, stamp.received_ns as f64 / 1e9))
+    })
+}
+
+/// Receives until every packet of a train is timed or `TRAIN_TIMEOUT` has passed since `sent`.
+/// Packets already queued are taken in one call, so a fast train does not outrun the receiver.
+///
+/// # Arguments
+/// - `offload`: Counts the packets GRO coalesced, if offload is in use.
+/// - `time`: Returns the index and time of a packet of the train, or `None` for anything else,
+///   e.g. a late packet of an earlier train. It is given the packet and its arrival in seconds
+///   after `sent`.
+fn collect(
+    socket: &UdpSocket,
+    sent: Instant,
+    packets: u16,
+    mut offload: Option<&mut UdpOffload>,
+    mut time: impl FnMut(&[u8], f64) -> Option<(u16, f64)>,
+) -> io::Result<Option<Vec<f64>>> {
+    let mut arrivals = vec![None; usize::from(packets)];
+    let mut missing = arrivals.len();
+    let gro = offload.as_ref().is_some_and(|offload| offload.gro);
+    let mut batch = Batch::new(arrivals.len(), if gro { mmsg::MAX_GRO_LEN } else { mmsg::MAX_DATAGRAM_LEN });
+    // The kernel stamps arrivals on the wall clock
+    let sent_at = SystemTime::now() - sent.elapsed();
+    while missing > 0 {
+        let remaining = match TRAIN_TIMEOUT.checked_sub(sent.elapsed()) {
+            Some(remaining) if !remaining.is_zero() => remaining,
+            _ => return Ok(None),
+        };
+        socket.set_read_timeout(Some(remaining))?;
+        match batch.recv(socket) {
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let returned = sent.elapsed().as_secs_f64();
+        for (packet, datagram) in batch.iter() {
+            let arrival = datagram.received.and_then(|received| received.duration_since(sent_at).ok()).map_or(returned, |offset| offset.as_secs_f64());
+            if let Some((index, at)) = time(packet, arrival) {
+                if let Some(arrival @ None) = arrivals.get_mut(usize::from(index)) {
+                    *arrival = Some(at);
+                    missing -= 1;
+                    if let Some(offload) = offload.as_deref_mut().filter(|_| datagram.is_coalesced()) {
+                        offload.coalesced_packets += 1;
+                    }
+                }
+            }
+        }
+    }
+    Ok(arrivals.into_iter().collect())
+}
+
+/// Prints the estimate of one direction.
+pub fn print(estimate: &DispersionEstimate, units: Units) {
+    match &estimate.rate {
+        Some(rate) => println!(
+            "Dispersion ({}): {} available, 95% CI [{:.2}, {:.2}], packet-pair capacity {}, {} confidence ({} of {} trains)",
+            estimate.direction,
+            units.rate(rate.available_bps),
+            units.rate_value(rate.ci_low_bps),
+            units.rate_value(rate.ci_high_bps),
+            units.rate(rate.pair_capacity_bps),
+            rate.confidence,
+            estimate.trains_used,
+            estimate.trains_sent
+        ),
+        None => println!("Dispersion ({}): none of {} trains arrived complete and spread out", estimate.direction, estimate.trains_sent),
+    }
+    if let Some(offload) = &estimate.offload {
+        println!("UDP offload ({}): {}", estimate.direction, offload);
+    }
+}
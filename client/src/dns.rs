@@ -0,0 +1,102 @@
+use std::net::IpAddr;
+use std::time::Instant;
+use crate::results::DnsLookup;
+
+/// Record types looked up, A for IPv4 addresses and AAAA for IPv6.
+const RECORD_TYPES: [&str; 2] = ["A", "AAAA"];
+
+/// Returns the host part of a `host:port` server address if it is a name rather than an IP literal.
+pub fn hostname(server: &str) -> Option<&str> {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.parse::<IpAddr>().is_ok() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+/// Resolves `host` once per record type through the system resolver and times each lookup.
+///
+/// The lookups go through `getaddrinfo` like the connection itself, so they include the resolver
+/// configuration, caches, and search domains the test would otherwise hide.
+pub fn time_lookups(host: &str) -> Vec<DnsLookup> {
+    RECORD_TYPES.iter().map(|&record_type| lookup(host, record_type)).collect()
+}
+
+#[cfg(unix)]
+fn lookup(host: &str, record_type: &str) -> DnsLookup {
+    use std::ffi::{CStr, CString};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::{mem, ptr};
+
+    let mut result = DnsLookup { record_type: record_type.to_string(), seconds: 0.0, addresses: Vec::new(), error: None };
+    let name = match CString::new(host) {
+        Ok(name) => name,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+
+    let mut hints: libc::addrinfo = unsafe { mem::zeroed() };
+    hints.ai_family = if record_type == "A" { libc::AF_INET } else { libc::AF_INET6 };
+    hints.ai_socktype = libc::SOCK_STREAM;
+    let mut list = ptr::null_mut();
+    let started = Instant::now();
+    let status = unsafe { libc::getaddrinfo(name.as_ptr(), ptr::null(), &hints, &mut list) };
+    result.seconds = started.elapsed().as_secs_f64();
+
+    if status != 0 {
+        let message = unsafe { CStr::from_ptr(libc::gai_strerror(status)) };
+        result.error = Some(message.to_string_lossy().into_owned());
+        return result;
+    }
+    let mut entry = list;
+    while !entry.is_null() {
+        unsafe {
+            let info = &*entry;
+            let address = match info.ai_family {
+                libc::AF_INET => {
+                    let addr = &*(info.ai_addr as *const libc::sockaddr_in);
+                    Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))))
+                }
+                libc::AF_INET6 => {
+                    let addr = &*(info.ai_addr as *const libc::sockaddr_in6);
+                    Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+                }
+                _ => None,
+            };
+            if let Some(address) = address.filter(|address| !result.addresses.contains(address)) {
+                result.addresses.push(address);
+            }
+            entry = info.ai_next;
+        }
+    }
+    unsafe { libc::freeaddrinfo(list) };
+
+    result
+}
+
+/// Resolves every address of `host` through `ToSocketAddrs`, which cannot ask for one family, and
+/// keeps those of `record_type`.
+#[cfg(not(unix))]
+fn lookup(host: &str, record_type: &str) -> DnsLookup {
+    use std::net::ToSocketAddrs;
+
+    let mut result = DnsLookup { record_type: record_type.to_string(), seconds: 0.0, addresses: Vec::new(), error: None };
+    let started = Instant::now();
+    let resolved = (host, 0).to_socket_addrs();
+    result.seconds = started.elapsed().as_secs_f64();
+    match resolved {
+        Ok(addresses) => {
+            for address in addresses.map(|address| address.ip()).filter(|address| address.is_ipv4() == (record_type == "A")) {
+                if !result.addresses.contains(&address) {
+                    result.addresses.push(address);
+                }
+            }
+        }
+        Err(e) => result.error = Some(e.to_string()),
+    }
+    result
+}
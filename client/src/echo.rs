@@ -0,0 +1,241 @@
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use common::echo::Probe;
+use crate::results::EchoSummary;
+use crate::stats::Summary;
+
+/// How long to wait for the echo of a single probe before counting it as lost.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends UDP probes to the server's echo port and times their return.
+///
+/// Unlike ICMP, UDP needs no privileges and is queued like the test traffic by middleboxes that
+/// treat ICMP separately.
+pub struct Prober {
+    socket: UdpSocket,
+    created: Instant,
+    sequence: u64,
+}
+
+impl Prober {
+    /// Opens a UDP socket towards the echo service at `target`, sending from `bind` if given.
+    pub fn new(target: SocketAddr, bind: Option<IpAddr>) -> io::Result<Self> {
+        Ok(Prober { socket: connect(target, bind)?, created: Instant::now(), sequence: 0 })
+    }
+
+    /// Sends one probe and waits for its echo.
+    ///
+    /// # Returns
+    /// - The round-trip time, or `None` if no echo arrived within `timeout`.
+    pub fn probe(&mut self, timeout: Duration) -> io::Result<Option<Duration>> {
+        self.sequence += 1;
+        let sent = Instant::now();
+        let probe = Probe { sequence: self.sequence, sent_ns: (sent - self.created).as_nanos() as u64 };
+        self.socket.send(&probe.encode())?;
+
+        let mut packet = [0u8; 1500];
+        loop {
+            let remaining = match timeout.checked_sub(sent.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ => return Ok(None),
+            };
+            self.socket.set_read_timeout(Some(remaining))?;
+            let len = match self.socket.recv(&mut packet) {
+                Ok(len) => len,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            // Late echoes of earlier probes are skipped
+            if Probe::decode(&packet[..len]).map(|echo| echo.sequence) == Some(self.sequence) {
+                return Ok(Some(sent.elapsed()));
+            }
+        }
+    }
+}
+
+/// Opens a UDP socket connected to `target`, sending from `bind` if given.
+pub fn connect(target: SocketAddr, bind: Option<IpAddr>) -> io::Result<UdpSocket> {
+    let local = match (bind, target) {
+        (Some(address), _) => address,
+        (None, SocketAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        (None, SocketAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0))?;
+    socket.connect(target)?;
+    Ok(socket)
+}
+
+/// Round-trip times of a series of probes.
+#[derive(Default)]
+pub struct EchoSeries {
+    pub sent: usize,
+    /// Round-trip time of every returned probe, in seconds, in the order they were sent.
+    pub rtts: Vec<f64>,
+}
+
+impl EchoSeries {
+    /// Sends one probe and records its outcome; a socket error counts as a lost probe.
+    fn record(&mut self, prober: &mut Prober) {
+        self.sent += 1;
+        if let Ok(Some(rtt)) = prober.probe(REPLY_TIMEOUT) {
+            self.rtts.push(rtt.as_secs_f64());
+        }
+    }
+
+    pub fn summary(&self) -> EchoSummary {
+        let lost = self.sent - self.rtts.len();
+        // Jitter as the mean difference between consecutive round trips, as most tools report it
+        let jitter_seconds = if self.rtts.len() > 1 {
+            Some(self.rtts.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum::<f64>() / (self.rtts.len() - 1) as f64)
+        } else {
+            None
+        };
+        EchoSummary {
+            sent: self.sent,
+            received: self.rtts.len(),
+            loss_percent: if self.sent > 0 { lost as f64 / self.sent as f64 * 100.0 } else { 0.0 },
+            rtt: Summary::of(&self.rtts),
+            jitter_seconds,
+        }
+    }
+}
+
+/// Sends `count` probes, `interval` apart.
+pub fn series(prober: &mut Prober, count: usize, interval: Duration) -> EchoSeries {
+    let mut series = EchoSeries::default();
+    for i in 0..count {
+        let started = Instant::now();
+        series.record(prober);
+        if i + 1 < count {
+            thread::sleep(interval.saturating_sub(started.elapsed()));
+        }
+    }
+    series
+}
+
+/// Sends `count` probes padded to `len` bytes, one every `interval` on a fixed schedule whether or
+/// not earlier ones returned, as real-time traffic such as voice is sent.
+///
+/// # Returns
+/// - The round-trip time of every probe in the order they were sent, in seconds, or `None` for a
+///   probe whose echo did not return within `REPLY_TIMEOUT` of the last probe.
+pub fn paced(target: SocketAddr, bind: Option<IpAddr>, count: usize, interval: Duration, len: usize) -> io::Result<Vec<Option<f64>>> {
+    send_paced(&connect(target, bind)?, Some(count), interval, len, &AtomicBool::new(false))
+}
+
+/// Sends probes like [`paced`] until `count` are sent, if given, or `stop` is set, and waits for
+/// the echoes still out.
+fn send_paced(socket: &UdpSocket, count: Option<usize>, interval: Duration, len: usize, stop: &AtomicBool) -> io::Result<Vec<Option<f64>>> {
+    let mut rtts = Vec::new();
+    let mut packet = [0u8; 1500];
+    let started = Instant::now();
+    let mut received = 0;
+    loop {
+        let now = Instant::now();
+        let sent = rtts.len();
+        let sending = count.is_none_or(|count| sent < count) && !stop.load(Ordering::Relaxed);
+        let due = started + interval * sent as u32;
+        if sending && now >= due {
+            let probe = Probe { sequence: sent as u64, sent_ns: (now - started).as_nanos() as u64 };
+            // A send error is a lost probe, as it would be a lost packet of the real traffic
+            let _ = socket.send(&probe.encode_padded(len));
+            rtts.push(None);
+            continue;
+        }
+        let deadline = if sending { due } else { started + interval * sent.saturating_sub(1) as u32 + REPLY_TIMEOUT };
+        if !sending && (received == sent || now >= deadline) {
+            return Ok(rtts);
+        }
+        socket.set_read_timeout(Some((deadline - now).max(Duration::from_micros(1))))?;
+        let len = match socket.recv(&mut packet) {
+            Ok(len) => len,
+            // Refused means an earlier probe found no echo service, which the losses show
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::ConnectionRefused) => continue,
+            Err(e) => return Err(e),
+        };
+        let returned = started.elapsed().as_nanos() as u64;
+        if let Some(echo) = Probe::decode(&packet[..len]) {
+            // Duplicates keep the first echo
+            if let Some(rtt @ None) = rtts.get_mut(echo.sequence as usize) {
+                *rtt = Some(returned.saturating_sub(echo.sent_ns) as f64 / 1e9);
+                received += 1;
+            }
+        }
+    }
+}
+
+/// Paced probes sent on a background thread until stopped, e.g. for as long as a transfer runs.
+pub struct PacedBackground {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<io::Result<Vec<Option<f64>>>>>,
+}
+
+impl PacedBackground {
+    /// Starts sending probes padded to `len` bytes every `interval` to the echo service at `target`.
+    pub fn start(target: SocketAddr, bind: Option<IpAddr>, interval: Duration, len: usize) -> io::Result<Self> {
+        let socket = connect(target, bind)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let handle = thread::spawn(move || send_paced(&socket, None, interval, len, &stopped));
+        Ok(PacedBackground { stop, handle: Some(handle) })
+    }
+
+    /// Stops sending and waits for the echoes still out.
+    ///
+    /// # Returns
+    /// - The round-trip times as [`paced`] returns them.
+    pub fn finish(mut self) -> io::Result<Vec<Option<f64>>> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(rtts)) => rtts,
+            _ => Err(io::Error::other("the paced probe thread panicked")),
+        }
+    }
+}
+
+impl Drop for PacedBackground {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Probes sent on a background thread while the test runs; stopped when dropped, like
+/// [`crate::ping::Background`].
+pub struct Background {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<EchoSeries>>,
+}
+
+impl Background {
+    /// Starts probing every `interval` until `finish` is called.
+    pub fn start(mut prober: Prober, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut series = EchoSeries::default();
+            while !stopped.load(Ordering::Relaxed) {
+                let started = Instant::now();
+                series.record(&mut prober);
+                thread::sleep(interval.saturating_sub(started.elapsed()));
+            }
+            series
+        });
+        Background { stop, handle: Some(handle) }
+    }
+
+    /// Stops probing and waits for the thread, which finishes the current probe and interval first.
+    pub fn finish(mut self) -> EchoSeries {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.take().and_then(|handle| handle.join().ok()).unwrap_or_default()
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
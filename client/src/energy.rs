@@ -0,0 +1,129 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the kernel exposes RAPL energy counters.
+const POWERCAP: &str = "/sys/class/powercap";
+/// Where the kernel exposes batteries.
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// A cumulative energy reading the meter takes at the start and end of a run.
+enum Counter {
+    /// A RAPL package zone: `energy_uj` counts up in microjoules and wraps at `max_energy_range_uj`.
+    Rapl { energy: PathBuf, max_range: u64 },
+    /// A discharging battery: its remaining energy counts down in microwatt-hours.
+    Battery { directory: PathBuf },
+}
+
+impl Counter {
+    fn read(&self) -> io::Result<u64> {
+        match self {
+            Counter::Rapl { energy, .. } => read_number(energy),
+            Counter::Battery { directory } => match read_number(&directory.join("energy_now")) {
+                Ok(energy) => Ok(energy),
+                // Some batteries report charge (µAh) and voltage (µV) instead of energy
+                Err(_) => {
+                    let charge = read_number(&directory.join("charge_now"))?;
+                    let voltage = read_number(&directory.join("voltage_now"))?;
+                    Ok((charge as u128 * voltage as u128 / 1_000_000) as u64)
+                }
+            },
+        }
+    }
+
+    /// Joules used between two readings.
+    fn joules(&self, start: u64, end: u64) -> f64 {
+        match self {
+            Counter::Rapl { max_range, .. } => {
+                let used = if end >= start { end - start } else { max_range - start + end };
+                used as f64 / 1_000_000.0
+            }
+            // 1 µWh is 3.6 mJ
+            Counter::Battery { .. } => start.saturating_sub(end) as f64 * 3.6e-3,
+        }
+    }
+}
+
+/// Measures the energy the host consumes during a run.
+///
+/// RAPL counters cover every CPU package (including the DRAM the package controls on most
+/// systems), so they measure the whole host's processor energy, not just this process. A battery
+/// covers the whole device, but only while it discharges, and many batteries update their reading
+/// only every few seconds, so short runs read as zero.
+pub struct Meter {
+    source: String,
+    readings: Vec<(Counter, u64)>,
+}
+
+impl Meter {
+    /// Takes the starting readings from RAPL if the host exposes it, and a discharging battery otherwise.
+    pub fn start() -> io::Result<Self> {
+        let rapl = rapl_packages().and_then(take_readings);
+        match rapl {
+            Ok(readings) if !readings.is_empty() => Ok(Meter { source: "RAPL".to_string(), readings }),
+            _ => match discharging_battery() {
+                Some((name, counter)) => Ok(Meter { source: format!("battery {}", name), readings: take_readings(vec![counter])? }),
+                // Recent kernels restrict the RAPL counters to root, which is worth pointing out
+                None => match rapl {
+                    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                        Err(io::Error::new(e.kind(), format!("RAPL counters are not readable ({}); run as root", e)))
+                    }
+                    _ => Err(io::Error::new(io::ErrorKind::NotFound, "no RAPL counters and no discharging battery found")),
+                },
+            },
+        }
+    }
+
+    /// Describes what the meter reads, e.g. "RAPL" or "battery BAT0".
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the joules used since `start`.
+    pub fn finish(self) -> io::Result<f64> {
+        let mut joules = 0.0;
+        for (counter, start) in &self.readings {
+            joules += counter.joules(*start, counter.read()?);
+        }
+        Ok(joules)
+    }
+}
+
+/// Finds the top-level RAPL zones, one per CPU package; their subzones are already included in them.
+fn rapl_packages() -> io::Result<Vec<Counter>> {
+    let mut counters = Vec::new();
+    for entry in fs::read_dir(POWERCAP)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        // "intel-rapl:0" is a package, "intel-rapl:0:1" one of its subzones
+        if !name.starts_with("intel-rapl:") || name.matches(':').count() != 1 {
+            continue;
+        }
+        counters.push(Counter::Rapl { energy: path.join("energy_uj"), max_range: read_number(&path.join("max_energy_range_uj"))? });
+    }
+    Ok(counters)
+}
+
+fn take_readings(counters: Vec<Counter>) -> io::Result<Vec<(Counter, u64)>> {
+    counters.into_iter().map(|counter| counter.read().map(|reading| (counter, reading))).collect()
+}
+
+fn discharging_battery() -> Option<(String, Counter)> {
+    for entry in fs::read_dir(POWER_SUPPLY).ok()?.flatten() {
+        let path = entry.path();
+        let kind = fs::read_to_string(path.join("type")).unwrap_or_default();
+        let status = fs::read_to_string(path.join("status")).unwrap_or_default();
+        if kind.trim() == "Battery" && status.trim() == "Discharging" {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            return Some((name, Counter::Battery { directory: path }));
+        }
+    }
+    None
+}
+
+fn read_number(path: &Path) -> io::Result<u64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a number", path.display())))
+}
@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+use csv::Writer;
+use crate::report::{self, Report};
+use crate::results::RunResult;
+use crate::units::Units;
+
+/// Options of the `export` subcommand.
+#[derive(Clone, clap::Args)]
+#[command(group(clap::ArgGroup::new("to").required(true).multiple(true).args(["report", "csv"])))]
+pub struct ExportArgs {
+    /// Run summaries written with `--json`.
+    #[arg(value_name = "SUMMARY", required = true)]
+    summaries: Vec<PathBuf>,
+
+    /// Write every summary as a report in this format, to `<summary>_report.<format>`; may be given
+    /// more than once.
+    #[arg(long, value_name = "FORMAT", value_enum)]
+    report: Vec<report::Format>,
+
+    /// Write the headline numbers of all summaries to this CSV file, one row per run, e.g. for a
+    /// spreadsheet.
+    #[arg(long, value_name = "FILE")]
+    csv: Option<PathBuf>,
+
+    /// Show data rates and sizes in the reports in decimal (Mbit/s, MB) or binary (Mibit/s, MiB)
+    /// units.
+    #[arg(long, value_name = "UNITS", value_enum, default_value_t = Units::Si)]
+    units: Units,
+
+    /// Write the reports and the CSV file into this directory, creating it if needed.
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+}
+
+/// Writes saved run summaries as reports or CSV rows, without running a test.
+///
+/// The reports do not show the charts, since a summary does not record where its run wrote them.
+///
+/// # Returns
+/// - The process exit code.
+pub fn run(args: &ExportArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    crate::output::create_dir(args.output_dir.as_deref())?;
+    let output = |name: &Path| args.output_dir.as_ref().map_or_else(|| name.to_path_buf(), |dir| dir.join(name));
+
+    let mut results = Vec::new();
+    for path in &args.summaries {
+        let result = RunResult::read_json(path).map_err(|e| format!("{} is not a run summary: {}", path.display(), e))?;
+        results.push((path, result));
+    }
+
+    for (path, result) in &results {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        for format in &args.report {
+            let reporter = format.reporter();
+            let report_path = output(Path::new(&format!("{}_report.{}", stem, reporter.extension())));
+            reporter.write(&Report { result, charts: &[], units: args.units }, &report_path)?;
+            println!("Report of {} saved to {}", path.display(), report_path.display());
+        }
+    }
+
+    if let Some(csv) = &args.csv {
+        let csv = output(csv);
+        let mut wtr = Writer::from_path(&csv)?;
+        wtr.write_record([
+            "Summary",
+            "Run ID",
+            "Started (UTC)",
+            "Server",
+            "Direction",
+            "Tags",
+            "Bytes",
+            "Effective Data Rate (bps)",
+            "Avg Transfer Time (s)",
+            "Loss (%)",
+            "RTT (s)",
+        ])?;
+        for (path, result) in &results {
+            let tags: Vec<String> = result.tags.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+            wtr.write_record([
+                path.display().to_string(),
+                result.run_id.clone(),
+                result.started_at.clone(),
+                result.server.clone(),
+                result.direction.to_string(),
+                tags.join(";"),
+                result.total_bytes.to_string(),
+                result.throughput_bps.map(|throughput| throughput.to_string()).unwrap_or_default(),
+                result.latency_seconds.map(|latency| latency.to_string()).unwrap_or_default(),
+                result.loss_percent.to_string(),
+                result.rtt_seconds.map(|rtt| rtt.to_string()).unwrap_or_default(),
+            ])?;
+        }
+        wtr.flush()?;
+        println!("{} runs exported to {}", results.len(), csv.display());
+    }
+    Ok(0)
+}
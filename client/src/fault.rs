@@ -0,0 +1,120 @@
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+use clap::Args;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Command line options of the fault-injection layer.
+#[derive(Args, Clone)]
+pub struct FaultArgs {
+    /// Add this many milliseconds of delay before every chunk.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    fault_delay_ms: u64,
+
+    /// Cap the receive rate at this many bits per second.
+    #[arg(long, value_name = "BPS")]
+    fault_rate_bps: Option<f64>,
+
+    /// Probability (0-1) that a chunk is preceded by a stall.
+    #[arg(long, value_name = "P", default_value_t = 0.0)]
+    fault_stall_probability: f64,
+
+    /// Length of an injected stall in milliseconds.
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    fault_stall_ms: u64,
+
+    /// Probability (0-1) that a bit of a chunk is flipped, to exercise --verify.
+    #[arg(long, value_name = "P", default_value_t = 0.0)]
+    fault_corrupt_probability: f64,
+
+    /// Seed for the stall and corruption decisions, so runs are reproducible.
+    #[arg(long, value_name = "SEED", default_value_t = 0)]
+    fault_seed: u64,
+}
+
+impl FaultArgs {
+    /// Returns true if any fault was configured.
+    pub fn is_active(&self) -> bool {
+        self.fault_delay_ms > 0 || self.fault_rate_bps.is_some() || self.fault_stall_probability > 0.0
+            || self.fault_corrupt_probability > 0.0
+    }
+}
+
+/// Read adapter that impairs a transport deterministically, so the metrics pipeline, plots, and
+/// regression checks can be exercised without real network impairment.
+///
+/// Faults are applied per chunk: every read that starts a new chunk first waits for the fixed delay
+/// and, with the configured probabilities, an additional stall or a flipped bit in its last byte. Reads never cross a chunk boundary,
+/// so the impairment of each chunk is exact. The rate cap is enforced continuously over the run.
+pub struct FaultInjector<R> {
+    inner: R,
+    chunk_size: usize,
+    delay: Duration,
+    rate_bps: Option<f64>,
+    stall_probability: f64,
+    stall: Duration,
+    corrupt_probability: f64,
+    corrupt_chunk: bool,
+    rng: StdRng,
+    start: Option<Instant>,
+    total_bytes: u64,
+}
+
+impl<R: Read> FaultInjector<R> {
+    /// Wraps `inner`, impairing it as configured by `args`.
+    ///
+    /// # Arguments
+    /// - `inner`: The transport to impair.
+    /// - `args`: Fault configuration.
+    /// - `chunk_size`: Size of the chunks the test reads, in bytes.
+    pub fn new(inner: R, args: &FaultArgs, chunk_size: usize) -> Self {
+        FaultInjector {
+            inner,
+            chunk_size,
+            delay: Duration::from_millis(args.fault_delay_ms),
+            rate_bps: args.fault_rate_bps,
+            stall_probability: args.fault_stall_probability,
+            stall: Duration::from_millis(args.fault_stall_ms),
+            corrupt_probability: args.fault_corrupt_probability,
+            corrupt_chunk: false,
+            rng: StdRng::seed_from_u64(args.fault_seed),
+            start: None,
+            total_bytes: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for FaultInjector<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let offset_in_chunk = (self.total_bytes % self.chunk_size as u64) as usize;
+        if offset_in_chunk == 0 {
+            let mut pause = self.delay;
+            if self.stall_probability > 0.0 && self.rng.gen_bool(self.stall_probability.min(1.0)) {
+                pause += self.stall;
+            }
+            thread::sleep(pause);
+            self.corrupt_chunk = self.corrupt_probability > 0.0 && self.rng.gen_bool(self.corrupt_probability.min(1.0));
+        }
+
+        let limit = buf.len().min(self.chunk_size - offset_in_chunk);
+        let n = self.inner.read(&mut buf[..limit])?;
+        // The last byte is always payload, never part of a header
+        if self.corrupt_chunk && n > 0 && offset_in_chunk + n == self.chunk_size {
+            buf[n - 1] ^= 1;
+        }
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.total_bytes += n as u64;
+
+        // Hold the data back until the capped rate would have delivered it
+        if let Some(rate_bps) = self.rate_bps {
+            let due = start + Duration::from_secs_f64(self.total_bytes as f64 * 8.0 / rate_bps);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        Ok(n)
+    }
+}
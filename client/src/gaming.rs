@@ -0,0 +1,61 @@
+use crate::echo::EchoSeries;
+use crate::results::{EchoSummary, Gaming};
+use crate::stats::Summary;
+
+/// UDP payload of a game update: a position, a few inputs, and a sequence number.
+pub const PACKET_BYTES: usize = 64;
+
+/// Summarizes the round trips of one phase of game traffic.
+fn phase(rtts: &[Option<f64>]) -> EchoSummary {
+    EchoSeries { sent: rtts.len(), rtts: rtts.iter().flatten().cloned().collect() }.summary()
+}
+
+/// Compares the game traffic sent while the link was idle with the traffic sent during the bulk
+/// transfer.
+///
+/// # Arguments
+/// - `rate_hz`: Packets sent per second.
+/// - `idle`, `loaded`: Round-trip times of the packets of each phase, `None` for lost ones.
+pub fn compare(rate_hz: u32, idle: &[Option<f64>], loaded: &[Option<f64>]) -> Gaming {
+    let (idle, loaded) = (phase(idle), phase(loaded));
+    let added = |statistic: fn(&Summary) -> f64| match (&idle.rtt, &loaded.rtt) {
+        (Some(idle), Some(loaded)) => Some(statistic(loaded) - statistic(idle)),
+        _ => None,
+    };
+    Gaming {
+        rate_hz,
+        packet_bytes: PACKET_BYTES,
+        added_median_seconds: added(|rtt| rtt.median),
+        added_p95_seconds: added(|rtt| rtt.p95),
+        added_loss_percent: loaded.loss_percent - idle.loss_percent,
+        idle,
+        loaded,
+    }
+}
+
+pub fn print(gaming: &Gaming) {
+    let line = |phase: &str, summary: &EchoSummary| match &summary.rtt {
+        Some(rtt) => println!(
+            "Game traffic {}: {} of {} packets returned ({:.2}% loss), RTT median {:.3} ms, p95 {:.3} ms, max {:.3} ms, jitter {:.3} ms",
+            phase,
+            summary.received,
+            summary.sent,
+            summary.loss_percent,
+            rtt.median * 1000.0,
+            rtt.p95 * 1000.0,
+            rtt.max * 1000.0,
+            summary.jitter_seconds.unwrap_or_default() * 1000.0
+        ),
+        None => println!("Game traffic {}: none of {} packets returned", phase, summary.sent),
+    };
+    line("idle", &gaming.idle);
+    line("under load", &gaming.loaded);
+    if let (Some(median), Some(p95)) = (gaming.added_median_seconds, gaming.added_p95_seconds) {
+        println!(
+            "Game traffic: load adds {:.3} ms to the median RTT, {:.3} ms to the p95, and {:+.2} points of loss",
+            median * 1000.0,
+            p95 * 1000.0,
+            gaming.added_loss_percent
+        );
+    }
+}
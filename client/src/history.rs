@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+use crate::scheduler;
+use crate::units::Units;
+
+/// Options of the `history` subcommand.
+#[derive(Clone, clap::Args)]
+pub struct HistoryArgs {
+    /// History file the scheduler appended its runs to.
+    #[arg(value_name = "FILE")]
+    history: PathBuf,
+
+    /// Only show the runs of this job.
+    #[arg(long, value_name = "NAME")]
+    job: Option<String>,
+
+    /// List at most this many runs, the newest first.
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    limit: usize,
+
+    /// Show data rates in decimal (Mbit/s) or binary (Mibit/s) units.
+    #[arg(long, value_name = "UNITS", value_enum, default_value_t = Units::Si)]
+    units: Units,
+}
+
+/// Lists the newest runs of the scheduler's history file; `trend` charts them instead.
+///
+/// # Returns
+/// - The process exit code: 0, or `EXIT_TEST_ERROR` if the history holds no runs.
+pub fn run(args: &HistoryArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let records = scheduler::read_history(&args.history)?;
+    let records: Vec<_> = records.iter().filter(|record| args.job.as_ref().is_none_or(|job| &record.job == job)).take(args.limit).collect();
+    if records.is_empty() {
+        let job = args.job.as_ref().map(|job| format!(" of job {}", job)).unwrap_or_default();
+        eprintln!("Test error: {} holds no runs{}", args.history.display(), job);
+        return Ok(crate::EXIT_TEST_ERROR);
+    }
+
+    println!("{:<28} {:<20} {:>4} {:>20} {:>15} {:>8}", "Started (UTC)", "Job", "Exit", "Data Rate", "Transfer Time", "Loss");
+    for record in records {
+        match (&record.result, &record.error) {
+            (Some(result), _) => println!(
+                "{:<28} {:<20} {:>4} {:>20} {:>15} {:>6.2} %",
+                record.started_at,
+                record.job,
+                record.exit_code,
+                args.units.rate_or_na(result.throughput_bps),
+                result.latency_seconds.map_or("n/a".to_string(), |latency| format!("{:.3} ms", latency * 1000.0)),
+                result.loss_percent
+            ),
+            (None, error) => println!(
+                "{:<28} {:<20} {:>4} failed: {}",
+                record.started_at,
+                record.job,
+                record.exit_code,
+                error.as_deref().unwrap_or("no result")
+            ),
+        }
+    }
+    Ok(0)
+}
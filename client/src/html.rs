@@ -0,0 +1,265 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use plotters::style::RGBColor;
+use serde_json::json;
+use crate::plot::{Marks, Mode, OverlayRun, SampleAxis, Series, Theme, XAxis};
+
+/// plotly.js loaded by charts that do not inline a local copy.
+const PLOTLY_CDN: &str = "https://cdn.plot.ly/plotly-2.35.2.min.js";
+
+/// Writes the latency and effective data rate series as an interactive HTML chart, with a tooltip
+/// for every sample and linked zoom on both panels.
+///
+/// Unlike the PNG chart, the series are not smoothed, so individual samples can be inspected.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `latencies`: Download time of each sample, in seconds.
+/// - `data_rates`: Effective data rate of each sample, in bits per second.
+/// - `marks`: Outliers and samples with other traffic on the interface.
+/// - `x`: Where each sample goes on the x axis.
+/// - `theme`: Colors and font sizes.
+/// - `plotly_js`: Local copy of plotly.js to inline, for viewing offline; without one the chart
+///   loads it from the CDN.
+pub fn write_latency_and_data_rate(
+    path: &Path,
+    latencies: &[f64],
+    data_rates: &[f64],
+    marks: &Marks,
+    x: &SampleAxis,
+    theme: &Theme,
+    plotly_js: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Marks { outliers, cross_traffic } = *marks;
+    // Wall-clock positions become timestamps, which plotly shows as dates that zoom to milliseconds
+    let positions: Vec<serde_json::Value> = match x.kind {
+        XAxis::WallClock => x
+            .positions
+            .iter()
+            .map(|&seconds| json!(humantime::format_rfc3339_millis(UNIX_EPOCH + Duration::from_secs_f64(seconds)).to_string()))
+            .collect(),
+        _ => x.positions.iter().map(|&position| json!(position)).collect(),
+    };
+    let x_title = match x.kind {
+        XAxis::Sample => x.sample_desc,
+        XAxis::Elapsed => "Elapsed Time (s)",
+        XAxis::WallClock => "Time (UTC)",
+    };
+    let numbers: Vec<usize> = (1..=latencies.len()).collect();
+    let pick = |values: &[f64]| -> (Vec<serde_json::Value>, Vec<f64>, Vec<usize>) {
+        (outliers.iter().map(|&i| positions[i].clone()).collect(), outliers.iter().map(|&i| values[i]).collect(), outliers.iter().map(|&i| i + 1).collect())
+    };
+    let (outlier_x, outlier_latencies, outlier_numbers) = pick(latencies);
+    // Data rates are plotted in the chosen units rather than scaled by plotly, which only knows SI prefixes
+    let units = theme.units;
+    let data_rates: Vec<f64> = data_rates.iter().map(|&rate| units.rate_value(rate)).collect();
+    let (_, outlier_rates, _) = pick(&data_rates);
+    let rate_name = format!("Effective Data Rate ({})", units.rate_unit());
+    let rate_hover = |sample: &str| format!("{} %{{customdata}}<br>%{{x}}<br>Data rate %{{y:.2f}} {}<extra></extra>", sample, units.rate_unit());
+
+    let traces = json!([
+        {
+            "name": "Latency (s)",
+            "x": positions, "y": latencies, "customdata": numbers,
+            "xaxis": "x", "yaxis": "y",
+            "mode": "lines", "line": { "color": hex(theme.color(Series::Latency)) },
+            "hovertemplate": "Sample %{customdata}<br>%{x}<br>Latency %{y:.6f} s<extra></extra>",
+        },
+        {
+            "name": "Outliers",
+            "x": outlier_x, "y": outlier_latencies, "customdata": outlier_numbers,
+            "xaxis": "x", "yaxis": "y", "legendgroup": "outliers",
+            "mode": "markers", "marker": { "color": hex(theme.color(Series::Outliers)), "symbol": "circle-open", "size": 10 },
+            "hovertemplate": "Outlier: sample %{customdata}<br>%{x}<br>Latency %{y:.6f} s<extra></extra>",
+        },
+        {
+            "name": rate_name,
+            "x": positions, "y": data_rates, "customdata": numbers,
+            "xaxis": "x2", "yaxis": "y2",
+            "mode": "lines", "line": { "color": hex(theme.color(Series::DataRate)) },
+            "hovertemplate": rate_hover("Sample"),
+        },
+        {
+            "name": "Outliers",
+            "x": outlier_x, "y": outlier_rates, "customdata": outlier_numbers,
+            "xaxis": "x2", "yaxis": "y2", "legendgroup": "outliers", "showlegend": false,
+            "mode": "markers", "marker": { "color": hex(theme.color(Series::Outliers)), "symbol": "circle-open", "size": 10 },
+            "hovertemplate": rate_hover("Outlier: sample"),
+        },
+    ]);
+    let x_type = if x.kind == XAxis::WallClock { "date" } else { "linear" };
+    // Shaded across the full height of both panels; a sample spans from the end of the one before it
+    let shapes: Vec<serde_json::Value> = cross_traffic
+        .iter()
+        .flat_map(|&(first, last)| {
+            let left = if first == 0 { positions[0].clone() } else { positions[first - 1].clone() };
+            let right = positions[last].clone();
+            vec![("x", "y domain"), ("x2", "y2 domain")].into_iter().map(move |(xref, yref)| {
+                json!({
+                    "type": "rect", "xref": xref, "yref": yref, "x0": left, "x1": right, "y0": 0, "y1": 1,
+                    "fillcolor": hex(theme.color(Series::CrossTraffic)), "opacity": 0.25, "line": { "width": 0 }, "layer": "below",
+                })
+            })
+        })
+        .collect();
+    let layout = json!({
+        "template": if theme.mode == Mode::Dark { "plotly_dark" } else { "plotly_white" },
+        "paper_bgcolor": hex(theme.background),
+        "plot_bgcolor": hex(theme.background),
+        "font": { "color": hex(theme.foreground), "size": theme.font_size },
+        "grid": { "rows": 2, "columns": 1, "pattern": "independent" },
+        "hovermode": "closest",
+        "xaxis": { "title": { "text": x_title }, "type": x_type },
+        // Zooming either panel zooms both
+        "xaxis2": { "title": { "text": x_title }, "type": x_type, "matches": "x" },
+        "yaxis": { "title": { "text": "Latency (s)" }, "rangemode": "tozero" },
+        "yaxis2": { "title": { "text": format!("Data Rate ({})", units.rate_unit()) }, "rangemode": "tozero" },
+        "shapes": shapes,
+        "height": 900,
+    });
+
+    let script = match plotly_js {
+        Some(local) => format!("<script>{}</script>", fs::read_to_string(local)?),
+        None => format!("<script src=\"{}\"></script>", PLOTLY_CDN),
+    };
+    let page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Latency and Effective Data Rate</title>\n{}\n</head>\n\
+         <body style=\"margin: 0; background: {}\">\n<div id=\"chart\"></div>\n<script>\n\
+         Plotly.newPlot(\"chart\", {}, {}, {{ responsive: true }});\n</script>\n</body>\n</html>\n",
+        script,
+        hex(theme.background),
+        traces,
+        layout
+    );
+    fs::write(path, page)?;
+    println!("Interactive Latency and Effective Data Rate chart saved as {}", path.display());
+
+    Ok(())
+}
+
+/// A table of an HTML report.
+pub struct Table {
+    pub heading: String,
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Writes a report of several runs as one HTML page: its tables, then the latency and effective
+/// data rate of every run overlaid in an interactive chart over their elapsed time.
+///
+/// # Arguments
+/// - `path`: File the report is written to.
+/// - `title`: Heading of the page.
+/// - `intro`: Paragraph below the heading.
+/// - `tables`: Tables, in the order shown.
+/// - `runs`: Runs to overlay; without any the chart is left out.
+/// - `theme`: Colors and font sizes.
+/// - `plotly_js`: Local copy of plotly.js to inline, for viewing offline; without one the page
+///   loads it from the CDN.
+pub fn write_aggregate(
+    path: &Path,
+    title: &str,
+    intro: &str,
+    tables: &[Table],
+    runs: &[OverlayRun],
+    theme: &Theme,
+    plotly_js: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let units = theme.units;
+    let mut body = format!("<h1>{}</h1>\n<p>{}</p>\n", escape(title), escape(intro));
+    for table in tables {
+        body.push_str(&format!("<h2>{}</h2>\n<table>\n<tr>", escape(&table.heading)));
+        for cell in &table.header {
+            body.push_str(&format!("<th>{}</th>", escape(cell)));
+        }
+        body.push_str("</tr>\n");
+        for row in &table.rows {
+            body.push_str("<tr>");
+            for cell in row {
+                body.push_str(&format!("<td>{}</td>", escape(cell)));
+            }
+            body.push_str("</tr>\n");
+        }
+        body.push_str("</table>\n");
+    }
+
+    let mut script = String::new();
+    if !runs.is_empty() {
+        // Each run's two traces share a legend entry, so clicking it hides the run in both panels
+        let traces: Vec<serde_json::Value> = runs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, run)| {
+                let color = hex(theme.run_color(i));
+                let data_rates: Vec<f64> = run.data_rates.iter().map(|&rate| units.rate_value(rate)).collect();
+                vec![
+                    json!({
+                        "name": run.name, "legendgroup": i,
+                        "x": run.offsets, "y": run.latencies, "xaxis": "x", "yaxis": "y",
+                        "mode": "lines", "line": { "color": color },
+                        "hovertemplate": format!("{}<br>%{{x:.3f}} s<br>Latency %{{y:.6f}} s<extra></extra>", run.name),
+                    }),
+                    json!({
+                        "name": run.name, "legendgroup": i, "showlegend": false,
+                        "x": run.offsets, "y": data_rates, "xaxis": "x2", "yaxis": "y2",
+                        "mode": "lines", "line": { "color": color },
+                        "hovertemplate": format!("{}<br>%{{x:.3f}} s<br>Data rate %{{y:.2f}} {}<extra></extra>", run.name, units.rate_unit()),
+                    }),
+                ]
+            })
+            .collect();
+        let layout = json!({
+            "template": if theme.mode == Mode::Dark { "plotly_dark" } else { "plotly_white" },
+            "paper_bgcolor": hex(theme.background),
+            "plot_bgcolor": hex(theme.background),
+            "font": { "color": hex(theme.foreground), "size": theme.font_size },
+            "grid": { "rows": 2, "columns": 1, "pattern": "independent" },
+            "hovermode": "closest",
+            "xaxis": { "title": { "text": "Elapsed Time (s)" } },
+            "xaxis2": { "title": { "text": "Elapsed Time (s)" }, "matches": "x" },
+            "yaxis": { "title": { "text": "Latency (s)" }, "rangemode": "tozero" },
+            "yaxis2": { "title": { "text": format!("Data Rate ({})", units.rate_unit()) }, "rangemode": "tozero" },
+            "height": 900,
+        });
+        body.push_str("<h2>Latency and Effective Data Rate per Run</h2>\n<div id=\"chart\"></div>\n");
+        script = match plotly_js {
+            Some(local) => format!("<script>{}</script>\n", fs::read_to_string(local)?),
+            None => format!("<script src=\"{}\"></script>\n", PLOTLY_CDN),
+        };
+        script.push_str(&format!(
+            "<script>\nPlotly.newPlot(\"chart\", {}, {}, {{ responsive: true }});\n</script>\n",
+            serde_json::Value::Array(traces),
+            layout
+        ));
+    }
+
+    let (background, foreground) = (hex(theme.background), hex(theme.foreground));
+    let page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+         body {{ font-family: sans-serif; font-size: {}pt; margin: 2em; background: {}; color: {}; }}\n\
+         table {{ border-collapse: collapse; margin-bottom: 1em; }}\n\
+         th, td {{ border: 1px solid #80808080; padding: 0.3em 0.6em; text-align: right; }}\n\
+         th:first-child, td:first-child {{ text-align: left; }}\n\
+         </style>\n</head>\n<body>\n{}{}</body>\n</html>\n",
+        escape(title),
+        theme.font_size,
+        background,
+        foreground,
+        body,
+        script
+    );
+    fs::write(path, page)?;
+    println!("Aggregate report saved as {}", path.display());
+
+    Ok(())
+}
+
+/// Escapes text for HTML element content.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn hex(color: RGBColor) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0, color.1, color.2)
+}
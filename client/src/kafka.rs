@@ -0,0 +1,321 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a broker gets to connect and answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the leader may wait for the replicas to acknowledge a record, in milliseconds.
+const PRODUCE_TIMEOUT_MS: i32 = 10_000;
+const CLIENT_ID: &str = "bwtest";
+const API_PRODUCE: i16 = 0;
+const API_METADATA: i16 = 3;
+/// The oldest versions current brokers still accept: Produce v3 is the first to take v2 record
+/// batches, and Kafka 4 no longer answers Metadata below v4.
+const PRODUCE_VERSION: i16 = 3;
+const METADATA_VERSION: i16 = 4;
+/// Acknowledgement by every in-sync replica, so a record is not lost with the leader.
+const ACKS_ALL: i16 = -1;
+
+/// Produces a record to `topic` through the first of `brokers` that answers, on the partition
+/// Kafka's default partitioner picks for `key`, so all records of a probe stay in order.
+///
+/// # Returns
+/// - The partition and offset the record was written at.
+pub fn produce(brokers: &[String], topic: &str, key: &str, value: &[u8]) -> io::Result<(i32, i64)> {
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no Kafka brokers given");
+    for broker in brokers {
+        match produce_through(broker, topic, key, value) {
+            Ok(written) => return Ok(written),
+            Err(e) => last_error = io::Error::new(e.kind(), format!("{}: {}", broker, e)),
+        }
+    }
+    Err(last_error)
+}
+
+fn produce_through(bootstrap: &str, topic: &str, key: &str, value: &[u8]) -> io::Result<(i32, i64)> {
+    let mut connection = Connection::open(bootstrap)?;
+    let metadata = connection.metadata(topic)?;
+    if metadata.partitions.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("topic {} has no partitions", topic)));
+    }
+    let partition = &metadata.partitions[(murmur2(key.as_bytes()) & 0x7fff_ffff) as usize % metadata.partitions.len()];
+    let leader = metadata
+        .brokers
+        .iter()
+        .find(|(id, _)| *id == partition.leader)
+        .map(|(_, address)| address)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("partition {} of {} has no leader", partition.index, topic)))?;
+    // The bootstrap broker is often the leader already
+    let mut leader_connection = if *leader == connection.address { connection } else { Connection::open(leader)? };
+    let offset = leader_connection.produce(topic, partition.index, key.as_bytes(), value)?;
+    Ok((partition.index, offset))
+}
+
+/// The brokers of a cluster and the partitions of one topic.
+struct Metadata {
+    /// Node ID and `HOST:PORT` of every broker.
+    brokers: Vec<(i32, String)>,
+    /// Ordered by index, so a key maps to the same partition as in other clients.
+    partitions: Vec<Partition>,
+}
+
+struct Partition {
+    index: i32,
+    /// Node ID of the broker that takes writes to the partition.
+    leader: i32,
+}
+
+struct Connection {
+    stream: TcpStream,
+    address: String,
+    correlation_id: i32,
+}
+
+impl Connection {
+    fn open(address: &str) -> io::Result<Self> {
+        let resolved = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to an address", address)))?;
+        let stream = TcpStream::connect_timeout(&resolved, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        Ok(Connection { stream, address: address.to_string(), correlation_id: 0 })
+    }
+
+    /// Sends a request with a v1 header and returns the body of its response.
+    fn request(&mut self, api_key: i16, api_version: i16, body: &[u8]) -> io::Result<Vec<u8>> {
+        self.correlation_id += 1;
+        let mut request = Vec::with_capacity(body.len() + 20);
+        request.extend_from_slice(&api_key.to_be_bytes());
+        request.extend_from_slice(&api_version.to_be_bytes());
+        request.extend_from_slice(&self.correlation_id.to_be_bytes());
+        string(&mut request, CLIENT_ID);
+        request.extend_from_slice(body);
+        self.stream.write_all(&(request.len() as i32).to_be_bytes())?;
+        self.stream.write_all(&request)?;
+
+        let mut size = [0u8; 4];
+        self.stream.read_exact(&mut size)?;
+        let mut response = vec![0u8; i32::from_be_bytes(size).max(0) as usize];
+        self.stream.read_exact(&mut response)?;
+        let mut reader = Reader(&response);
+        if reader.i32()? != self.correlation_id {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Kafka response does not match the request"));
+        }
+        Ok(reader.0.to_vec())
+    }
+
+    /// Looks up the brokers and the partitions of `topic`.
+    fn metadata(&mut self, topic: &str) -> io::Result<Metadata> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&1i32.to_be_bytes());
+        string(&mut body, topic);
+        // Creating the topic is left to whoever runs the cluster
+        body.push(0);
+        let response = self.request(API_METADATA, METADATA_VERSION, &body)?;
+
+        let mut reader = Reader(&response);
+        reader.i32()?;
+        let mut brokers = Vec::new();
+        for _ in 0..reader.i32()? {
+            let node = reader.i32()?;
+            let host = reader.string()?;
+            let port = reader.i32()?;
+            reader.string()?;
+            brokers.push((node, format!("{}:{}", host, port)));
+        }
+        reader.string()?;
+        reader.i32()?;
+        let mut partitions = Vec::new();
+        for _ in 0..reader.i32()? {
+            let error = reader.i16()?;
+            let name = reader.string()?;
+            reader.skip(1)?;
+            if error != 0 {
+                return Err(io::Error::other(format!("topic {}: {}", name, error_name(error))));
+            }
+            for _ in 0..reader.i32()? {
+                reader.i16()?;
+                let index = reader.i32()?;
+                let leader = reader.i32()?;
+                // Replicas and in-sync replicas
+                for _ in 0..2 {
+                    let count = reader.i32()?.max(0) as usize;
+                    reader.skip(count * 4)?;
+                }
+                partitions.push(Partition { index, leader });
+            }
+        }
+        partitions.sort_unstable_by_key(|partition| partition.index);
+        Ok(Metadata { brokers, partitions })
+    }
+
+    /// Writes one record to `partition`, returning its offset.
+    fn produce(&mut self, topic: &str, partition: i32, key: &[u8], value: &[u8]) -> io::Result<i64> {
+        let batch = record_batch(key, value);
+        let mut body = Vec::with_capacity(batch.len() + topic.len() + 32);
+        // No transactional ID
+        body.extend_from_slice(&(-1i16).to_be_bytes());
+        body.extend_from_slice(&ACKS_ALL.to_be_bytes());
+        body.extend_from_slice(&PRODUCE_TIMEOUT_MS.to_be_bytes());
+        body.extend_from_slice(&1i32.to_be_bytes());
+        string(&mut body, topic);
+        body.extend_from_slice(&1i32.to_be_bytes());
+        body.extend_from_slice(&partition.to_be_bytes());
+        body.extend_from_slice(&(batch.len() as i32).to_be_bytes());
+        body.extend_from_slice(&batch);
+        let response = self.request(API_PRODUCE, PRODUCE_VERSION, &body)?;
+
+        let mut reader = Reader(&response);
+        reader.i32()?;
+        reader.string()?;
+        reader.i32()?;
+        reader.i32()?;
+        let error = reader.i16()?;
+        let offset = reader.i64()?;
+        if error != 0 {
+            return Err(io::Error::other(format!("partition {} of {}: {}", partition, topic, error_name(error))));
+        }
+        Ok(offset)
+    }
+}
+
+/// Encodes a v2 record batch holding one record, uncompressed and without a producer ID.
+fn record_batch(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut record = vec![0];
+    varint(&mut record, 0);
+    varint(&mut record, 0);
+    varint(&mut record, key.len() as i64);
+    record.extend_from_slice(key);
+    varint(&mut record, value.len() as i64);
+    record.extend_from_slice(value);
+    varint(&mut record, 0);
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    // Everything the CRC covers: from the attributes to the end of the batch
+    let mut checked = Vec::with_capacity(record.len() + 48);
+    checked.extend_from_slice(&0i16.to_be_bytes());
+    checked.extend_from_slice(&0i32.to_be_bytes());
+    checked.extend_from_slice(&timestamp.to_be_bytes());
+    checked.extend_from_slice(&timestamp.to_be_bytes());
+    checked.extend_from_slice(&(-1i64).to_be_bytes());
+    checked.extend_from_slice(&(-1i16).to_be_bytes());
+    checked.extend_from_slice(&(-1i32).to_be_bytes());
+    checked.extend_from_slice(&1i32.to_be_bytes());
+    varint(&mut checked, record.len() as i64);
+    checked.extend_from_slice(&record);
+
+    let mut batch = Vec::with_capacity(checked.len() + 21);
+    batch.extend_from_slice(&0i64.to_be_bytes());
+    // The length counts from the partition leader epoch on
+    batch.extend_from_slice(&((checked.len() + 9) as i32).to_be_bytes());
+    batch.extend_from_slice(&(-1i32).to_be_bytes());
+    batch.push(2);
+    batch.extend_from_slice(&crc32c(&checked).to_be_bytes());
+    batch.extend_from_slice(&checked);
+    batch
+}
+
+/// Reads the big-endian fields of a response in order.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> io::Result<&[u8]> {
+        if self.0.len() < count {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated Kafka response"));
+        }
+        let (taken, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, count: usize) -> io::Result<()> {
+        self.take(count).map(|_| ())
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        self.take(2).map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        self.take(4).map(|bytes| i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        let high = self.i32()? as i64;
+        let low = self.i32()? as u32 as i64;
+        Ok(high << 32 | low)
+    }
+
+    /// A string with an `i16` length, which is -1 for null.
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.i16()?;
+        Ok(String::from_utf8_lossy(self.take(length.max(0) as usize)?).into_owned())
+    }
+}
+
+fn string(buffer: &mut Vec<u8>, text: &str) {
+    buffer.extend_from_slice(&(text.len() as i16).to_be_bytes());
+    buffer.extend_from_slice(text.as_bytes());
+}
+
+/// Appends a zigzag-encoded variable-length integer, as record fields are.
+fn varint(buffer: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// CRC-32C (Castagnoli), which record batches are checked with.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0x82f6_3b78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// MurmurHash2 as Kafka's default partitioner computes it, so records keyed by the same probe land
+/// on the partition a Java producer would pick.
+fn murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747_b28c;
+    const M: u32 = 0x5bd1_e995;
+    let mut hash = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]).wrapping_mul(M);
+        k ^= k >> 24;
+        hash = hash.wrapping_mul(M) ^ k.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate().rev() {
+            hash ^= u32::from(byte) << (8 * i);
+        }
+        hash = hash.wrapping_mul(M);
+    }
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(M);
+    hash ^ hash >> 15
+}
+
+/// Names the Kafka error codes a producer is likely to meet.
+fn error_name(code: i16) -> String {
+    match code {
+        3 => "unknown topic or partition".to_string(),
+        5 => "leader not available".to_string(),
+        6 => "not the leader for the partition".to_string(),
+        7 => "request timed out".to_string(),
+        10 => "record too large".to_string(),
+        19 => "not enough replicas".to_string(),
+        29 => "topic authorization failed".to_string(),
+        _ => format!("error code {}", code),
+    }
+}
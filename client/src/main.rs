@@ -1,188 +1,1948 @@
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::time::{Instant, Duration};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant, SystemTime};
+use clap::{Parser, Subcommand, ValueEnum};
+use connection::ConnectOptions;
+use notify::{Email, Notification, Status};
+use proxy::Proxy;
+use report::{Chart, Report, Reporter};
+use common::protocol::Direction;
+use common::sockopt::Keepalive;
 use csv::Writer;
-use plotters::prelude::*;
-
-/// Calculates the Bandwidth-Delay Product (BDP)
-/// 
-/// BDP represents the maximum amount of data (in bits) that can be in transit in the network.
-/// 
-/// # Arguments
-/// - `bandwidth_bps`: The network bandwidth in bits per second (bps).
-/// - `rtt_seconds`: The round-trip time (RTT) in seconds.
+use results::{Asymmetry, RelayComparison, RepeatSummary, RunResult, ServerSelection, SshTunnel, TunnelComparison};
+use stats::Summary;
+use thresholds::Thresholds;
+use transfer::{Outcome, Outputs};
+use units::Units;
+
+mod aggregate;
+mod anomaly;
+mod baseline;
+mod bottleneck;
+mod burst;
+mod capture;
+mod compare;
+mod connection;
+mod coordinator;
+mod cross_traffic;
+mod dispersion;
+mod dns;
+mod echo;
+mod energy;
+mod export;
+mod gaming;
+mod history;
+mod html;
+mod kafka;
+#[cfg(feature = "fault-injection")]
+mod fault;
+mod metrics;
+mod mqtt;
+mod netdev;
+mod netem;
+mod notify;
+mod output;
+#[cfg(feature = "pdf-report")]
+mod pdf;
+mod peer;
+mod ping;
+mod plot;
+mod proxy;
+mod replot;
+mod report;
+mod results;
+mod s3;
+mod sampling;
+mod scheduler;
+mod sequence;
+mod servers;
+mod sigv4;
+mod sock_diag;
+mod soak;
+mod ssh;
+mod stats;
+mod streaming;
+mod stun;
+mod summary;
+mod tcp_options;
+mod tcp_trace;
+mod thresholds;
+mod traceroute;
+mod transfer;
+mod trend;
+mod tuning;
+mod units;
+mod voip;
+mod wifi;
+mod wire;
+
+/// Directions to test, as chosen on the command line.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Directions {
+    Download,
+    Upload,
+    /// Download, then upload, and compare the two.
+    Both,
+}
+
+/// Bandwidth test client: downloads chunks from the server and reports latency and data rate.
+///
+/// Without a subcommand it runs a test, as `run` does; the other subcommands work on saved results
+/// without touching the network.
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: Args,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a bandwidth test (the default).
+    Run(Box<Args>),
+    /// Combine saved runs into overall statistics, an overlay chart, and one HTML report.
+    Aggregate(aggregate::AggregateArgs),
+    /// Compare saved run summaries side by side, each against the first.
+    Compare(compare::CompareArgs),
+    /// Write saved run summaries as reports or as CSV rows.
+    Export(export::ExportArgs),
+    /// List the runs of a scheduler history file.
+    History(history::HistoryArgs),
+    /// Draw the chart of a run again from its saved metrics CSV, e.g. with other smoothing, size, or
+    /// theme.
+    Replot(replot::ReplotArgs),
+    /// Chart how the runs of a scheduler history file developed over days or weeks.
+    Trend(trend::TrendArgs),
+}
+
+/// Options of a test run.
+#[derive(Clone, Parser)]
+struct Args {
+    /// Address of the test server; repeat to test several, one after the other, and compare them.
+    #[arg(long = "server", value_name = "HOST:PORT", default_value = "127.0.0.1:7878")]
+    targets: Vec<String>,
+
+    /// The server under test: the first --server, or each in turn when several were given.
+    #[arg(skip)]
+    server: String,
+
+    /// File or http:// URL listing candidate servers, one HOST:PORT per line; the one with the
+    /// fastest TCP handshake is tested instead of --server.
+    #[arg(long, value_name = "FILE|URL")]
+    servers: Option<String>,
+
+    /// List the test servers advertised on the local network via mDNS and exit.
+    #[arg(long)]
+    discover: bool,
+
+    /// Outcome of the --servers selection, recorded in the run summary.
+    #[arg(skip)]
+    server_selection: Option<ServerSelection>,
+
+    /// Which way to send the test data.
+    #[arg(long, value_enum, default_value_t = Directions::Download)]
+    direction: Directions,
+
+    /// Local IP address to connect from, to test a specific uplink on multi-homed hosts.
+    #[arg(long, value_name = "IP")]
+    bind: Option<IpAddr>,
+
+    /// Only connect over this IP version. By default, when the server name has both IPv6 and IPv4
+    /// addresses, both are raced (Happy Eyeballs) and the winner is tested; run once with each
+    /// family to compare them.
+    #[arg(long, value_enum, value_name = "FAMILY")]
+    family: Option<connection::Family>,
+
+    /// Network interface to send through (Linux only, uses SO_BINDTODEVICE).
+    #[arg(long, value_name = "NAME")]
+    interface: Option<String>,
+
+    /// Connect to the server through a SOCKS5 (socks5://HOST:PORT) or HTTP CONNECT (http://HOST:PORT)
+    /// proxy.
+    #[arg(long, value_name = "URL")]
+    proxy: Option<Proxy>,
+
+    /// Forward the test connection through SSH to this host with the system's ssh client, for
+    /// networks where only SSH is reachable; --server is then the server's address as seen from
+    /// that host, e.g. 127.0.0.1:7878 for a server running on it.
+    #[arg(long, value_name = "[USER@]HOST[:PORT]", conflicts_with_all = ["servers", "proxy", "relay", "s3_endpoint"])]
+    ssh: Option<String>,
+
+    /// The SSH tunnel of --ssh, recorded in the run summary.
+    #[arg(skip)]
+    ssh_tunnel: Option<SshTunnel>,
+
+    /// DSCP value (0-63) to mark the test traffic with in both directions, e.g. 46 for Expedited Forwarding.
+    #[arg(long, value_name = "VALUE", value_parser = clap::value_parser!(u8).range(0..=63))]
+    dscp: Option<u8>,
+
+    /// TTL / hop limit (1-255) for the test traffic in both directions, constraining the test to a hop radius.
+    #[arg(long, value_name = "HOPS", value_parser = clap::value_parser!(u8).range(1..))]
+    ttl: Option<u8>,
+
+    /// Have the server checksum every chunk and verify it on receipt, reporting corrupted chunks.
+    #[arg(long)]
+    verify: bool,
+
+    /// Have the server send pseudo-random data instead of zeros, from a seed picked and printed for
+    /// this run, and verify every chunk's content against it.
+    #[arg(long)]
+    random_payload: bool,
+
+    /// Like --random-payload, but with a fixed seed so runs are reproducible bit for bit.
+    #[arg(long, value_name = "SEED", conflicts_with = "random_payload")]
+    payload_seed: Option<u64>,
+
+    /// Download this file from under the server's --file-root instead of synthetic chunks, timing
+    /// the network and disk phases separately.
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["random_payload", "payload_seed", "duration", "burst_on_ms"])]
+    file: Option<String>,
+
+    /// Write the file downloaded with --file to this path, timing the disk writes.
+    #[arg(long, value_name = "PATH", requires = "file")]
+    output_file: Option<PathBuf>,
+
+    /// Measure multi-part GET or PUT throughput against this S3-compatible endpoint instead of a
+    /// test server; credentials are read from AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, and
+    /// optionally AWS_SESSION_TOKEN.
+    #[arg(
+        long,
+        value_name = "http://HOST:PORT",
+        requires = "s3_bucket",
+        conflicts_with_all = ["relay", "file", "duration", "netem"]
+    )]
+    s3_endpoint: Option<String>,
+
+    /// Bucket holding the object tested with --s3-endpoint.
+    #[arg(long, value_name = "BUCKET", requires = "s3_endpoint")]
+    s3_bucket: Option<String>,
+
+    /// Key of the object to download, or to upload as a multipart upload.
+    #[arg(long, value_name = "KEY", default_value = "bwtest-object", requires = "s3_endpoint")]
+    s3_key: String,
+
+    /// Region the requests are signed for.
+    #[arg(long, value_name = "REGION", default_value = "us-east-1", requires = "s3_endpoint")]
+    s3_region: String,
+
+    /// Size of each ranged GET or uploaded part.
+    #[arg(long, value_name = "BYTES", default_value_t = 8 * 1024 * 1024, value_parser = clap::value_parser!(u64).range(1..), requires = "s3_endpoint")]
+    s3_part_size: u64,
+
+    /// Parts transferred at once, each over its own connection.
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..), requires = "s3_endpoint")]
+    s3_concurrency: u64,
+
+    /// Number of parts to upload.
+    #[arg(long, value_name = "N", default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..10001), requires = "s3_endpoint")]
+    s3_parts: u32,
+
+    /// Ping the server over ICMP before and during the test, as a round-trip time and loss reference
+    /// independent of the test connection (uses unprivileged ICMP sockets where allowed, raw sockets otherwise).
+    #[arg(long)]
+    ping: bool,
+
+    /// Interval between ICMP echo requests, at least 1 ms.
+    #[arg(long, value_name = "MS", default_value_t = 200, value_parser = clap::value_parser!(u64).range(1..), requires = "ping")]
+    ping_interval_ms: u64,
+
+    /// Probe round-trip time and jitter over UDP, before and during the test, against the echo
+    /// service the server runs on this port (server --udp-echo).
+    #[arg(long, value_name = "PORT")]
+    udp_echo: Option<u16>,
+
+    /// Interval between UDP echo probes, at least 1 ms.
+    #[arg(long, value_name = "MS", default_value_t = 20, value_parser = clap::value_parser!(u64).range(1..), requires = "udp_echo")]
+    udp_echo_interval_ms: u64,
+
+    /// Only measure latency: send --udp-echo-count probes to the echo service and skip the transfer.
+    #[arg(
+        long,
+        requires = "udp_echo",
+        conflicts_with_all = ["relay", "file", "duration", "netem", "s3_endpoint", "repeat", "min_throughput"]
+    )]
+    latency_only: bool,
+
+    /// Number of probes of a latency-only run.
+    #[arg(long, value_name = "N", default_value_t = 100, requires = "latency_only")]
+    udp_echo_count: usize,
+
+    /// Estimate the bandwidth from how far the path spreads out trains of UDP packets sent
+    /// back-to-back through the echo service, instead of saturating it with a transfer.
+    #[arg(
+        long,
+        requires = "udp_echo",
+        conflicts_with_all = ["relay", "file", "duration", "netem", "s3_endpoint", "repeat", "latency_only"]
+    )]
+    dispersion: bool,
+
+    /// Number of packet trains sent per direction.
+    #[arg(long, value_name = "N", default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..), requires = "dispersion")]
+    trains: u32,
+
+    /// Packets per train; 2 sends packet pairs.
+    #[arg(long, value_name = "N", default_value_t = 16, value_parser = clap::value_parser!(u16).range(2..=64), requires = "dispersion")]
+    train_length: u16,
+
+    /// UDP payload of each train packet.
+    #[arg(long, value_name = "BYTES", default_value_t = 1400, value_parser = clap::value_parser!(u16).range(64..=1472), requires = "dispersion")]
+    train_packet_size: u16,
+
+    /// Pause between packet trains.
+    #[arg(long, value_name = "MS", default_value_t = 50, requires = "dispersion")]
+    train_interval_ms: u64,
+
+    /// Send upload trains with UDP segmentation offload (GSO) and receive download trains with
+    /// receive coalescing (GRO), on Linux. Whether offload was active is reported with the estimate.
+    #[arg(long, requires = "dispersion")]
+    udp_offload: bool,
+
+    /// Place a simulated call through the echo service instead of a transfer: a packet the size of
+    /// one voice frame every 20 ms, rated with the E-model as a mean opinion score.
+    #[arg(
+        long,
+        requires = "udp_echo",
+        conflicts_with_all = ["relay", "file", "duration", "netem", "s3_endpoint", "repeat", "latency_only", "dispersion", "min_throughput"]
+    )]
+    voip: bool,
+
+    /// Length of the simulated call.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10.0, value_parser = positive_seconds, requires = "voip")]
+    voip_duration: f64,
+
+    /// Codec the simulated call is encoded with.
+    #[arg(long, value_name = "CODEC", value_enum, default_value_t = voip::Codec::G711, requires = "voip")]
+    voip_codec: voip::Codec,
+
+    /// Send game traffic through the echo service, 64-byte packets at --gaming-rate, first on the
+    /// idle link and then during the transfer, and report the latency and loss the transfer adds.
+    #[arg(
+        long,
+        requires = "udp_echo",
+        conflicts_with_all = ["relay", "tunnel_interface", "s3_endpoint", "latency_only", "dispersion", "voip"]
+    )]
+    gaming: bool,
+
+    /// Game packets sent per second, as a game's tick rate.
+    #[arg(long, value_name = "HZ", default_value_t = 60, value_parser = clap::value_parser!(u32).range(1..=1000), requires = "gaming")]
+    gaming_rate: u32,
+
+    /// Time game traffic is sent on the idle link before the transfer starts.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10.0, value_parser = positive_seconds, requires = "gaming")]
+    gaming_duration: f64,
+
+    /// Test directly against another client that runs with the same session name, instead of
+    /// against the server, e.g. between two branch offices behind NATs. The client testing the
+    /// upload sends UDP data to the one testing the download, through the holes both punch in
+    /// their NATs.
+    #[arg(
+        long,
+        value_name = "SESSION",
+        conflicts_with_all = ["relay", "tunnel_interface", "ssh", "file", "duration", "netem", "s3_endpoint", "repeat", "latency_only", "dispersion", "voip", "gaming"]
+    )]
+    peer: Option<String>,
+
+    /// UDP port of the rendezvous the server runs (server --rendezvous), which introduces the two
+    /// clients of the session to each other.
+    #[arg(long, value_name = "PORT", requires = "peer", conflicts_with = "peer_address")]
+    rendezvous: Option<u16>,
+
+    /// Public address of the other client, as its --stun reported it, instead of a rendezvous.
+    #[arg(long, value_name = "HOST:PORT", requires = "peer")]
+    peer_address: Option<String>,
+
+    /// Local UDP port of the peer-to-peer test. Fix it on a client whose address the other one is
+    /// given with --peer-address, so its NAT keeps the mapping its --stun reported.
+    #[arg(long, value_name = "PORT", default_value_t = 0, requires = "peer")]
+    peer_port: u16,
+
+    /// Rate the sending client paces its data at.
+    #[arg(long, value_name = "BPS", default_value_t = 10_000_000, value_parser = clap::value_parser!(u64).range(1..), requires = "peer")]
+    peer_rate: u64,
+
+    /// How long the sending client sends for.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10.0, value_parser = positive_seconds, requires = "peer")]
+    peer_duration: f64,
+
+    /// Sample the Wi-Fi link of the test interface during the run (signal strength, PHY rate,
+    /// channel, retries) with `iw`, and chart it against the measured data rate (Linux only).
+    #[arg(long)]
+    wifi: bool,
+
+    /// Interval between Wi-Fi link samples, at least 100 ms since each sample runs `iw`.
+    #[arg(long, value_name = "MS", default_value_t = 500, value_parser = clap::value_parser!(u64).range(100..), requires = "wifi")]
+    wifi_interval_ms: u64,
+
+    /// Sample the counters of the test interface during the run and flag intervals in which other
+    /// traffic on the host was significant, shading their samples in the charts (Linux only).
+    #[arg(long)]
+    cross_traffic: bool,
+
+    /// Interval between interface counter samples, at least 10 ms.
+    #[arg(long, value_name = "MS", default_value_t = 500, value_parser = clap::value_parser!(u64).range(10..), requires = "cross_traffic")]
+    cross_traffic_interval_ms: u64,
+
+    /// Flag an interval when other traffic reached this share of the test's own bytes in it.
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0, value_parser = non_negative, requires = "cross_traffic")]
+    cross_traffic_threshold: f64,
+
+    /// Leave the samples that overlap flagged intervals out of the latency and data rate
+    /// distributions, outliers, and stability.
+    #[arg(long, requires = "cross_traffic")]
+    exclude_cross_traffic: bool,
+
+    /// Measure the energy the host consumes during the run, from RAPL counters or a discharging
+    /// battery (Linux only), and report it per gigabyte transferred.
+    #[arg(long)]
+    energy: bool,
+
+    /// Capture the test connection's packets to a pcap file alongside the results, for analysis in
+    /// Wireshark (Linux, requires root or CAP_NET_RAW).
+    #[arg(long)]
+    capture: bool,
+
+    /// Bytes kept of every captured packet; the default covers the IP and TCP headers with options.
+    #[arg(long, value_name = "BYTES", default_value_t = 128, value_parser = clap::value_parser!(u32).range(40..=262_144), requires = "capture")]
+    capture_snaplen: u32,
+
+    /// Stop writing captured packets once the pcap file reaches this size.
+    #[arg(long, value_name = "MB", default_value_t = 100, requires = "capture")]
+    capture_max_mb: u64,
+
+    /// Record the kernel's retransmissions and congestion window changes of the test connection
+    /// from the tcp tracepoints, on the run's timeline (Linux, requires root and tracefs).
+    #[arg(long)]
+    tcp_events: bool,
+
+    /// Record the kernel's state of the test socket (queues, timers, memory, TCP_INFO, as `ss -tmio`
+    /// shows it) before, during, and after the run in the JSON summary (Linux).
+    #[arg(long)]
+    socket_snapshots: bool,
+
+    /// Seconds between the socket snapshots taken during the run.
+    #[arg(long, value_name = "SECONDS", default_value_t = 1.0, value_parser = positive_seconds, requires = "socket_snapshots")]
+    socket_snapshot_interval: f64,
+
+    /// Ask this STUN server for the public address and port our UDP traffic is mapped to, showing
+    /// whether the host is behind a NAT.
+    #[arg(long, value_name = "HOST:PORT")]
+    stun: Option<String>,
+
+    /// Trace the path to the server with UDP, ICMP, or TCP probes before the test and record it in the
+    /// summary (IPv4, requires root or CAP_NET_RAW).
+    #[arg(long, value_name = "MODE", value_enum)]
+    traceroute: Option<traceroute::TraceMode>,
+
+    /// Largest TTL the traceroute tries before giving up.
+    #[arg(long, value_name = "HOPS", default_value_t = 30, value_parser = clap::value_parser!(u8).range(1..), requires = "traceroute")]
+    traceroute_max_hops: u8,
+
+    /// Also run the test through this server (started with --allow-relay for --server) and compare
+    /// the relayed path with the direct one.
+    #[arg(long, value_name = "HOST:PORT")]
+    relay: Option<String>,
+
+    /// Also run the test bound to this tunnel or VPN interface, e.g. wg0 or tun0, and report what
+    /// the tunnel costs against the direct run side by side (Linux only, uses SO_BINDTODEVICE).
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["relay", "ssh", "s3_endpoint", "latency_only", "dispersion", "voip"])]
+    tunnel_interface: Option<String>,
+
+    /// Server to test through the tunnel, when it is reached at another address there than
+    /// --server, e.g. its address inside the VPN.
+    #[arg(long, value_name = "HOST:PORT", requires = "tunnel_interface")]
+    tunnel_server: Option<String>,
+
+    /// Back the receive buffer with hugepages where the OS supports it.
+    #[arg(long)]
+    hugepages: bool,
+
+    /// Fail the run if the average effective data rate is below this many bits per second.
+    #[arg(long, value_name = "BPS", value_parser = non_negative)]
+    min_throughput: Option<f64>,
+
+    /// Fail the run if the average chunk download time exceeds this many seconds.
+    #[arg(long, value_name = "SECONDS", value_parser = seconds)]
+    max_latency: Option<f64>,
+
+    /// Fail the run if more than this percentage of the expected data was not received.
+    #[arg(long, value_name = "PERCENT", value_parser = percent)]
+    max_loss: Option<f64>,
+
+    /// Write the run summary as JSON to this file, e.g. to use later as a baseline.
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+
+    /// Write the CSV, JSON, chart, and report files into this directory, creating it if needed.
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Name output files by this template, e.g. `{date}_{server}_{direction}`, so runs do not
+    /// overwrite each other. `{name}` stands for the usual file name and is appended if missing.
+    #[arg(long, value_name = "TEMPLATE", value_parser = output::parse_template)]
+    name_template: Option<String>,
+
+    /// Wall-clock start of the invocation, which `{date}` in the name template refers to.
+    #[arg(skip)]
+    started_at: Option<SystemTime>,
+
+    /// Also write the run summary as a report in this format, to `report.<format>`; may be given
+    /// more than once. `md` is Markdown with tables and the charts, for GitHub issues and wikis.
+    #[arg(long, value_name = "FORMAT", value_enum)]
+    report: Vec<report::Format>,
+
+    /// POST the outcome and the run summary as JSON to this http:// URL when the run finishes; may
+    /// be given more than once.
+    #[arg(long, value_name = "URL")]
+    webhook: Vec<String>,
+
+    /// Call the webhooks after every run, or only after one that failed a threshold, regressed, or
+    /// was cut short.
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = notify::Trigger::Finish, requires = "webhook")]
+    webhook_on: notify::Trigger,
+
+    /// Email the outcome and the run summary to this address when the run finishes; may be given
+    /// more than once.
+    #[arg(long, value_name = "ADDRESS")]
+    email_to: Vec<String>,
+
+    /// Sender of the emails (default bwtest@ this host's name).
+    #[arg(long, value_name = "ADDRESS", requires = "email_to")]
+    email_from: Option<String>,
+
+    /// SMTP relay the emails are handed to, which must accept them without authentication, e.g.
+    /// the probe's local mail server.
+    #[arg(long, value_name = "HOST:PORT", default_value = "localhost:25", requires = "email_to")]
+    smtp_server: String,
+
+    /// Send the emails after every run, or only after one that failed a threshold, regressed, or
+    /// was cut short.
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = notify::Trigger::Finish, requires = "email_to")]
+    email_on: notify::Trigger,
+
+    /// Attach the interactive HTML chart to the emails.
+    #[arg(long, requires_all = ["email_to", "html"])]
+    email_html: bool,
+
+    /// Publish the run summary and per-interval samples to this MQTT broker, e.g. for Home
+    /// Assistant. The password, if the broker needs one, is read from BWTEST_MQTT_PASSWORD.
+    #[arg(long, value_name = "HOST:PORT")]
+    mqtt_broker: Option<String>,
+
+    /// Topic prefix: the summary is published, retained, to `<PREFIX>/summary` and the samples to
+    /// `<PREFIX>/samples` (default bwtest/ this host's name).
+    #[arg(long, value_name = "PREFIX", requires = "mqtt_broker")]
+    mqtt_topic: Option<String>,
+
+    /// Length of the intervals the published samples summarize.
+    #[arg(long, value_name = "SECONDS", default_value_t = 1.0, value_parser = positive_seconds, requires = "mqtt_broker")]
+    mqtt_interval: f64,
+
+    /// User name to log in to the MQTT broker with.
+    #[arg(long, value_name = "NAME", requires = "mqtt_broker")]
+    mqtt_username: Option<String>,
+
+    /// Produce the outcome and the run summary as a record to Kafka through these bootstrap
+    /// brokers (HOST:PORT, comma-separated), keyed by --probe-id.
+    #[arg(long, value_name = "HOST:PORT", value_delimiter = ',')]
+    kafka_brokers: Vec<String>,
+
+    /// Kafka topic the records are produced to.
+    #[arg(long, value_name = "TOPIC", default_value = "bwtest-results", requires = "kafka_brokers")]
+    kafka_topic: String,
+
+    /// Identifies this probe in the results it publishes (default this host's name).
+    #[arg(long, value_name = "ID")]
+    probe_id: Option<String>,
+
+    /// Tag the run, e.g. `--tag isp=acme --tag site=lab`, to slice results by later; may be given
+    /// more than once. Tags are columns of every CSV row and fields of the JSON summary and of
+    /// everything published.
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = output::parse_tag)]
+    tags: Vec<(String, String)>,
+
+    /// Compare this run against a JSON summary from an earlier run and flag regressions.
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
+
+    /// Percentage a metric may worsen relative to the baseline before it counts as a regression.
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0, value_parser = non_negative)]
+    tolerance: f64,
+
+    /// Ask the server to send in bursts at line rate lasting this many milliseconds.
+    #[arg(long, value_name = "MS", requires = "burst_off_ms")]
+    burst_on_ms: Option<u64>,
+
+    /// Idle time between bursts in milliseconds.
+    #[arg(long, value_name = "MS", requires = "burst_on_ms")]
+    burst_off_ms: Option<u64>,
+
+    /// Ask the server to shape its download with a token bucket filling at this many bits per
+    /// second, to emulate a shaped link such as a cable modem's.
+    #[arg(long, value_name = "BPS", value_parser = clap::value_parser!(u64).range(1..))]
+    shape_rate_bps: Option<u64>,
+
+    /// Size of the server's token bucket: after an idle period, up to this many bytes leave at line
+    /// rate before the shaping rate takes over. Defaults to 10 ms at the shaping rate.
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..), requires = "shape_rate_bps")]
+    shape_burst_bytes: Option<u64>,
+
+    /// Request small chunks and merge them into samples sized from the data rate measured so far,
+    /// so every sample takes about --adaptive-chunk-ms whether the link runs at 1 Mbit/s or
+    /// 10 Gbit/s.
+    #[arg(long, conflicts_with_all = ["s3_endpoint", "latency_only", "dispersion", "voip"])]
+    adaptive_chunks: bool,
+
+    /// Time each adaptive sample is sized to take.
+    #[arg(long, value_name = "MS", default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..), requires = "adaptive_chunks")]
+    adaptive_chunk_ms: u64,
+
+    /// Play the download out as a stream at this many bits per second, e.g. 25000000 for 4K
+    /// video, through a player's buffer, and report when playback stalls to rebuffer.
+    #[arg(
+        long,
+        value_name = "BPS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["s3_endpoint", "latency_only", "dispersion", "voip", "burst_on_ms"]
+    )]
+    stream_bitrate: Option<u64>,
+
+    /// Media time the player buffers before playback starts, and again after a stall.
+    #[arg(long, value_name = "SECONDS", default_value_t = 2.0, value_parser = seconds, requires = "stream_bitrate")]
+    stream_startup: f64,
+
+    /// Media time the player's buffer holds; once it is full, the player reads only as fast as it
+    /// plays.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30.0, value_parser = positive_seconds, requires = "stream_bitrate")]
+    stream_buffer: f64,
+
+    /// Flag chunks whose download time exceeds the median by more than this many scaled median
+    /// absolute deviations.
+    #[arg(long, value_name = "K", default_value_t = 3.5, value_parser = positive)]
+    outlier_k: f64,
+
+    /// What the x axis of the latency and data rate chart shows: sample numbers, seconds since the
+    /// start of the run, or UTC time of day.
+    #[arg(long, value_name = "AXIS", value_enum, default_value_t = plot::XAxis::Sample)]
+    x_axis: plot::XAxis,
+
+    /// Show data rates and sizes in decimal (Mbit/s, MB) or binary (Mibit/s, MiB) units in the
+    /// console, reports, and charts.
+    #[arg(long, value_name = "UNITS", value_enum, default_value_t = Units::Si)]
+    units: Units,
+
+    /// Color the end-of-run summary: failed thresholds and regressions in red, anomalies in yellow,
+    /// a pass in green. `auto` colors it on a terminal unless NO_COLOR is set.
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = summary::ColorChoice::Auto)]
+    summary_color: summary::ColorChoice,
+
+    #[command(flatten)]
+    chart: plot::ChartOptions,
+
+    /// Also write the latency and data rate chart as interactive HTML, with a tooltip for every
+    /// sample and zoom.
+    #[arg(long)]
+    html: bool,
+
+    /// Inline this local copy of plotly.js into the HTML chart, so it can be viewed offline,
+    /// instead of loading it from the CDN.
+    #[arg(long, value_name = "FILE", requires = "html")]
+    plotly_js: Option<PathBuf>,
+
+    /// Run a soak test for this many seconds instead of a fixed number of chunks.
+    #[arg(long, value_name = "SECONDS", value_parser = positive_seconds)]
+    duration: Option<f64>,
+
+    /// Length of the short rolling summary window in a soak test; the long window is five times as long.
+    #[arg(long, value_name = "SECONDS", default_value_t = 60.0, value_parser = positive_seconds, requires = "duration")]
+    summary_interval: f64,
+
+    /// Start a new per-chunk CSV file after this many seconds in a soak test.
+    #[arg(long, value_name = "SECONDS", default_value_t = 3600.0, value_parser = positive_seconds, requires = "duration")]
+    rotate_after: f64,
+
+    /// Overwrite the per-chunk CSV file with each run instead of appending to it under the run's ID.
+    #[arg(long)]
+    per_run_files: bool,
+
+    /// Give up on connecting to the server (or proxy) after this many seconds (0 waits for the OS).
+    #[arg(long, value_name = "SECONDS", default_value_t = 10.0, value_parser = seconds)]
+    connect_timeout: f64,
+
+    /// Abort the run if a read or write on the connection makes no progress for this many seconds,
+    /// including the proxy handshake (0 waits forever).
+    #[arg(long, visible_alias = "io-timeout", value_name = "SECONDS", default_value_t = 30.0, value_parser = seconds)]
+    stall_timeout: f64,
+
+    /// Enable TCP keepalive on the connection, so paused tests survive NAT and firewall idle timeouts
+    /// and a vanished server is detected.
+    #[arg(long)]
+    keepalive: bool,
+
+    /// Idle time before the first keepalive probe (OS default if not given).
+    #[arg(long, value_name = "SECONDS", requires = "keepalive")]
+    keepalive_idle: Option<u64>,
+
+    /// Time between unanswered keepalive probes (OS default if not given).
+    #[arg(long, value_name = "SECONDS", requires = "keepalive")]
+    keepalive_interval: Option<u64>,
+
+    /// Unanswered keepalive probes before the connection is dropped (OS default if not given).
+    #[arg(long, value_name = "N", requires = "keepalive")]
+    keepalive_count: Option<u32>,
+
+    /// Receive and send buffer size of the test connection (SO_RCVBUF and SO_SNDBUF), set before
+    /// connecting so the window scale is chosen to match. Fixing it disables buffer auto-tuning on Linux.
+    #[arg(long, value_name = "BYTES")]
+    socket_buffer: Option<usize>,
+
+    /// Disable Nagle's algorithm (TCP_NODELAY) on the test connection.
+    #[arg(long)]
+    nodelay: bool,
+
+    /// Send the test request in the SYN with TCP Fast Open (Linux; the server needs --fast-open).
+    /// The first connection to a server only fetches its cookie, so use --repeat to see the saving.
+    #[arg(long)]
+    fast_open: bool,
+
+    /// Have both sides send a heartbeat this often while their direction of the connection is
+    /// idle, and end the session once the peer has been silent for three intervals.
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    heartbeat_ms: Option<u64>,
+
+    /// Reconnect up to this many times in total if the connection drops or stalls, and resume the
+    /// remaining chunks instead of aborting.
+    #[arg(long, value_name = "ATTEMPTS", default_value_t = 0)]
+    reconnect: u32,
+
+    /// Wait before the first reconnect attempt after a drop; doubled after every failed attempt.
+    #[arg(long, value_name = "MS", default_value_t = 500, requires = "reconnect")]
+    reconnect_backoff_ms: u64,
+
+    /// Run the whole test this many times and evaluate the run with the median data rate, reporting a
+    /// confidence interval over all runs.
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "netem")]
+    repeat: u32,
+
+    /// Run the test once per netem impairment (tc netem parameters such as "delay 50ms loss 1%",
+    /// or "none"), configuring it on --netem-interface before the run and clearing it after.
+    #[arg(long, value_name = "PARAMS", requires = "netem_interface")]
+    netem: Vec<String>,
+
+    /// Interface the netem impairments are applied to (Linux, requires CAP_NET_ADMIN).
+    #[arg(long, value_name = "NAME")]
+    netem_interface: Option<String>,
+
+    /// Run as an agent: accept test jobs from a coordinator on this address and run them one at a
+    /// time, with the test options the coordinator sends.
+    #[arg(long, value_name = "HOST:PORT", conflicts_with = "coordinate")]
+    agent: Option<SocketAddr>,
+
+    /// Start the test given after `--` on every agent listed in FILE at the same moment and combine
+    /// their results; one `AGENT:PORT [SERVER:PORT]` per line, agents without a server test --server.
+    #[arg(long, value_name = "FILE|URL")]
+    coordinate: Option<String>,
+
+    /// Measure every pair of nodes listed in FILE, one `AGENT:PORT SERVER:PORT` per line: each
+    /// node's agent runs the test given after `--` against every other node's server in turn.
+    #[arg(long, value_name = "FILE|URL", conflicts_with_all = ["agent", "coordinate"])]
+    mesh: Option<String>,
+
+    /// How far ahead the coordinator schedules the synchronized start, to leave time to deliver the
+    /// job to every agent.
+    #[arg(long, value_name = "SECONDS", default_value_t = 2.0, value_parser = seconds, requires = "coordinate")]
+    start_delay: f64,
+
+    /// Run the named test jobs of a JSON schedule file forever, each on its cron schedule (in UTC),
+    /// and keep a history of their runs.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["agent", "coordinate", "mesh"])]
+    schedule: Option<String>,
+
+    /// File the scheduler appends every run to, one JSON record per line.
+    #[arg(long, value_name = "FILE", default_value = "bwtest_history.jsonl", requires = "schedule")]
+    history: PathBuf,
+
+    /// Serve the scheduled jobs and their history as JSON over HTTP on this address.
+    #[arg(long, value_name = "HOST:PORT", requires = "schedule")]
+    api: Option<SocketAddr>,
+
+    /// Flag a scheduled run whose data rate, latency, or loss deviates from the job's moving
+    /// average by more than this many standard deviations. Anomalous runs fail like a missed
+    /// threshold, so they trigger the job's notifications.
+    #[arg(long, value_name = "SIGMAS", value_parser = positive, requires = "schedule")]
+    anomaly_sigmas: Option<f64>,
+
+    /// Weight of the newest run in the moving average and variance (EWMA), above 0 and at most 1;
+    /// higher values follow changes faster.
+    #[arg(long, value_name = "ALPHA", default_value_t = 0.2, value_parser = weight, requires = "anomaly_sigmas")]
+    anomaly_alpha: f64,
+
+    /// Runs of a job to learn from before any is judged.
+    #[arg(long, value_name = "N", default_value_t = 5, requires = "anomaly_sigmas")]
+    anomaly_warmup: usize,
+
+    /// Moving averages of the scheduled job this run belongs to.
+    #[arg(skip)]
+    monitor: Option<anomaly::Monitor>,
+
+    /// Test options the coordinator or mesh passes to its agents.
+    #[arg(last = true, value_name = "TEST OPTIONS")]
+    agent_args: Vec<String>,
+
+    #[cfg(feature = "fault-injection")]
+    #[command(flatten)]
+    fault: fault::FaultArgs,
+}
+
+impl Args {
+    /// Parses the options of a test run, with the first `--server` as the server under test.
+    fn parse_arguments<I, T>(arguments: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        Ok(Args::try_parse_from(arguments)?.with_first_server())
+    }
+
+    /// Makes the first `--server` the server under test.
+    fn with_first_server(mut self) -> Self {
+        self.server = self.targets[0].clone();
+        self
+    }
+
+    /// Returns the direction of a single run; `both` is handled by `run_both_directions`.
+    fn single_direction(&self) -> Direction {
+        match self.direction {
+            Directions::Upload => Direction::Upload,
+            Directions::Download | Directions::Both => Direction::Download,
+        }
+    }
+}
+
+/// Exit code when the test completed but a threshold assertion failed.
+const EXIT_THRESHOLD_FAILURE: i32 = 1;
+/// Exit code when the test itself could not be carried out.
+const EXIT_TEST_ERROR: i32 = 2;
+
+/// How long --ssh waits for ssh to log in, which may include typing a password.
+const SSH_LOGIN_WAIT: Duration = Duration::from_secs(120);
+
+/// How long --discover waits for servers to answer.
+const DISCOVERY_WAIT: Duration = Duration::from_secs(2);
+
+fn main() {
+    let cli = Cli::parse();
+    let mut args = match cli.command {
+        None => cli.run,
+        Some(Command::Run(args)) => *args,
+        Some(Command::Aggregate(aggregate)) => exit_with(aggregate::run(&aggregate)),
+        Some(Command::Compare(compare)) => exit_with(compare::run(&compare)),
+        Some(Command::Export(export)) => exit_with(export::run(&export)),
+        Some(Command::History(history)) => exit_with(history::run(&history)),
+        Some(Command::Replot(replot)) => exit_with(replot::run(&replot)),
+        Some(Command::Trend(trend)) => exit_with(trend::run(&trend)),
+    }
+    .with_first_server();
+    if args.discover {
+        process::exit(discover());
+    }
+    if let Some(address) = args.agent {
+        process::exit(coordinator::serve(address));
+    }
+    if let Err(e) = output::prepare(&mut args) {
+        eprintln!("Test error: {}", e);
+        process::exit(EXIT_TEST_ERROR);
+    }
+    if let Some(agents) = &args.coordinate {
+        process::exit(coordinator::coordinate(&args, agents).unwrap_or_else(|e| {
+            eprintln!("Test error: {}", e);
+            EXIT_TEST_ERROR
+        }));
+    }
+    if let Some(config) = &args.schedule {
+        process::exit(scheduler::run(&args, config).unwrap_or_else(|e| {
+            eprintln!("Test error: {}", e);
+            EXIT_TEST_ERROR
+        }));
+    }
+    if let Some(nodes) = &args.mesh {
+        process::exit(coordinator::mesh(&args, nodes).unwrap_or_else(|e| {
+            eprintln!("Test error: {}", e);
+            EXIT_TEST_ERROR
+        }));
+    }
+
+    // The tunnel stays open until the run is over
+    let code = match tunnel_server(&mut args).and_then(|_tunnel| select_server(&mut args).and_then(|_| run(&args))) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Test error: {}", e);
+            EXIT_TEST_ERROR
+        }
+    };
+
+    process::exit(code);
+}
+
+/// Exits with the exit code of a subcommand, or `EXIT_TEST_ERROR` if it failed.
+fn exit_with(code: Result<i32, Box<dyn std::error::Error>>) -> ! {
+    process::exit(code.unwrap_or_else(|e| {
+        eprintln!("Test error: {}", e);
+        EXIT_TEST_ERROR
+    }))
+}
+
+/// Lists the test servers that answer an mDNS query.
 ///
 /// # Returns
-/// - The Bandwidth-Delay Product in bits.
-fn calculate_bdp(bandwidth_bps: f64, rtt_seconds: f64) -> f64 {
-    bandwidth_bps * rtt_seconds
+/// - The process exit code.
+fn discover() -> i32 {
+    let servers = match servers::discover(DISCOVERY_WAIT) {
+        Ok(servers) => servers,
+        Err(e) => {
+            eprintln!("Discovery failed: {}", e);
+            return EXIT_TEST_ERROR;
+        }
+    };
+    if servers.is_empty() {
+        println!("No test servers found on the local network");
+    }
+    for server in &servers {
+        let addresses: Vec<String> = server.addresses.iter().map(|address| SocketAddr::new(*address, server.port).to_string()).collect();
+        println!("{}  {}  {}", server.instance, server.host, addresses.join(" "));
+    }
+    0
+}
+
+/// Picks the nearest server from the `--servers` list, if one was given, and tests against it.
+fn select_server(args: &mut Args) -> Result<(), Box<dyn std::error::Error>> {
+    let source = match &args.servers {
+        Some(source) => source,
+        None => return Ok(()),
+    };
+    let servers = servers::load(source)?;
+    println!("Probing {} servers...", servers.len());
+    let selection = servers::select_nearest(&servers, &connect_options(args))?;
+    for candidate in &selection.candidates {
+        match (candidate.rtt_seconds, &candidate.error) {
+            (Some(rtt), _) => println!("  {:<40} {:>10.3} ms", candidate.server, rtt * 1000.0),
+            (None, error) => println!("  {:<40} unreachable: {}", candidate.server, error.as_deref().unwrap_or_default()),
+        }
+    }
+    println!("Selected {}", selection.chosen);
+
+    args.server = selection.chosen.clone();
+    args.server_selection = Some(selection);
+    Ok(())
 }
 
-/// Calculates the Effective Data Rate, which represents the average data rate achieved over the entire transfer.
-/// This takes into account the total data transferred and the total time taken.
+/// Forwards a local port to the server through SSH, if --ssh was given, and tests against it.
 ///
-/// # Arguments
-/// - `total_data_bits`: Total amount of data transferred, in bits.
-/// - `total_time_seconds`: Total time taken for the transfer, in seconds.
+/// # Returns
+/// - The tunnel, which closes when dropped.
+fn tunnel_server(args: &mut Args) -> Result<Option<ssh::Tunnel>, Box<dyn std::error::Error>> {
+    let destination = match &args.ssh {
+        Some(destination) => destination.clone(),
+        None => return Ok(None),
+    };
+    if args.targets.len() > 1 {
+        return Err("--ssh forwards to a single --server".into());
+    }
+    println!("Forwarding to {} through SSH to {}...", args.server, destination);
+    let started = Instant::now();
+    let tunnel = ssh::Tunnel::open(&destination, &args.server, args.bind, timeout(args.connect_timeout), SSH_LOGIN_WAIT)?;
+    let setup = started.elapsed();
+    println!("SSH tunnel up after {:.3}s, testing through {}", setup.as_secs_f64(), tunnel.local);
+
+    args.ssh_tunnel = Some(SshTunnel { destination, target: args.server.clone(), setup_seconds: setup.as_secs_f64() });
+    args.server = tunnel.local.to_string();
+    args.targets = vec![args.server.clone()];
+    Ok(Some(tunnel))
+}
+
+/// Socket options for connections to the server, as chosen on the command line.
+fn connect_options(args: &Args) -> ConnectOptions {
+    ConnectOptions {
+        bind: args.bind,
+        interface: args.interface.clone(),
+        dscp: args.dscp,
+        ttl: args.ttl,
+        proxy: args.proxy.clone(),
+        connect_timeout: timeout(args.connect_timeout),
+        io_timeout: timeout(args.stall_timeout),
+        keepalive: if args.keepalive {
+            Some(Keepalive {
+                idle: args.keepalive_idle.map(Duration::from_secs),
+                interval: args.keepalive_interval.map(Duration::from_secs),
+                count: args.keepalive_count,
+            })
+        } else {
+            None
+        },
+        socket_buffer: args.socket_buffer,
+        nodelay: args.nodelay,
+        fast_open: args.fast_open,
+        family: args.family,
+    }
+}
+
+/// An average transfer time in seconds, or "n/a" if no chunk arrived to average.
+fn seconds_or_na(seconds: Option<f64>) -> String {
+    seconds.map_or("n/a".to_string(), |seconds| format!("{:.5}s", seconds))
+}
+
+/// An average data rate in bits per second, or "n/a" if no data arrived to time.
+fn bps_or_na(bps: Option<f64>) -> String {
+    bps.map_or("n/a".to_string(), |bps| format!("{:.2} bps", bps))
+}
+
+/// Chart colors and font sizes, as chosen on the command line.
+fn chart_theme(args: &Args) -> plot::Theme {
+    args.chart.theme(args.units)
+}
+
+/// The MQTT broker to publish to, if one was given.
+fn mqtt_broker(args: &Args) -> Option<mqtt::Broker> {
+    args.mqtt_broker.as_ref().map(|address| mqtt::Broker {
+        address: address.clone(),
+        username: args.mqtt_username.clone(),
+        password: std::env::var(mqtt::PASSWORD_VARIABLE).ok(),
+    })
+}
+
+/// Prefix of the MQTT topics, which defaults to one per probe.
+fn mqtt_topic(args: &Args) -> String {
+    args.mqtt_topic.clone().unwrap_or_else(|| format!("bwtest/{}", notify::hostname()))
+}
+
+/// Parses a time in seconds from the command line, which must be one a `Duration` can hold: finite
+/// and not negative.
+fn seconds(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value.parse().map_err(|_| format!("'{}' is not a number of seconds", value))?;
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(_) => Ok(seconds),
+        Err(_) => Err(format!("'{}' is out of range; seconds must be finite and not negative", value)),
+    }
+}
+
+/// Parses a time in seconds that must be longer than zero.
+fn positive_seconds(value: &str) -> Result<f64, String> {
+    match seconds(value)? {
+        seconds if seconds > 0.0 => Ok(seconds),
+        _ => Err(format!("'{}' must be longer than zero seconds", value)),
+    }
+}
+
+/// Parses a number from the command line that must be finite and not negative, such as a rate or
+/// a percentage.
+fn non_negative(value: &str) -> Result<f64, String> {
+    let number: f64 = value.parse().map_err(|_| format!("'{}' is not a number", value))?;
+    if number.is_finite() && number >= 0.0 {
+        Ok(number)
+    } else {
+        Err(format!("'{}' is out of range; it must be finite and not negative", value))
+    }
+}
+
+/// Parses a number from the command line that must be finite and greater than zero.
+fn positive(value: &str) -> Result<f64, String> {
+    match non_negative(value)? {
+        number if number > 0.0 => Ok(number),
+        _ => Err(format!("'{}' must be greater than zero", value)),
+    }
+}
+
+/// Parses a weight from the command line, above 0 and at most 1.
+fn weight(value: &str) -> Result<f64, String> {
+    match positive(value)? {
+        weight if weight <= 1.0 => Ok(weight),
+        _ => Err(format!("'{}' is out of range; a weight must be above 0 and at most 1", value)),
+    }
+}
+
+/// Parses a percentage from the command line, from 0 to 100.
+fn percent(value: &str) -> Result<f64, String> {
+    match non_negative(value)? {
+        percent if percent <= 100.0 => Ok(percent),
+        _ => Err(format!("'{}' is out of range; a percentage must be from 0 to 100", value)),
+    }
+}
+
+/// Converts a timeout in seconds from the command line, where zero means none.
+fn timeout(seconds: f64) -> Option<Duration> {
+    if seconds > 0.0 {
+        Some(Duration::from_secs_f64(seconds))
+    } else {
+        None
+    }
+}
+
+/// Runs the test, repeatedly if requested, in both directions if requested, or the netem matrix if
+/// impairments were given.
 ///
 /// # Returns
-/// - The Effective Data Rate in bits per second.
-fn calculate_effective_data_rate(total_data_bits: f64, total_time_seconds: f64) -> f64 {
-    total_data_bits / total_time_seconds
+/// - The process exit code.
+fn run(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    if let Some(file) = &args.file {
+        if args.direction != Directions::Download {
+            return Err("--file only supports --direction download".into());
+        }
+        // The request line separates its fields with whitespace
+        if file.contains(char::is_whitespace) {
+            return Err(format!("file name '{}' must not contain whitespace", file).into());
+        }
+    }
+    if args.shape_rate_bps.is_some() && args.direction != Directions::Download {
+        return Err("--shape-rate-bps only supports --direction download, as it shapes what the server sends".into());
+    }
+    if args.stream_bitrate.is_some() {
+        if args.direction != Directions::Download {
+            return Err("--stream-bitrate only supports --direction download, as the stream plays what is received".into());
+        }
+        if args.stream_startup > args.stream_buffer {
+            return Err(format!("--stream-startup of {} s does not fit in the --stream-buffer of {} s", args.stream_startup, args.stream_buffer).into());
+        }
+    }
+    if args.targets.len() > 1 {
+        let other_mode = args.direction == Directions::Both || args.relay.is_some() || args.tunnel_interface.is_some() || args.repeat > 1 || !args.netem.is_empty();
+        if other_mode || args.servers.is_some() || args.latency_only || args.dispersion || args.voip || args.gaming || args.peer.is_some() {
+            return Err("several --server cannot be combined with --servers, --direction both, --relay, --tunnel-interface, --repeat, --netem, --latency-only, --dispersion, --voip, --gaming, or --peer".into());
+        }
+        return run_servers(args);
+    }
+    if args.latency_only {
+        return run_latency_only(args);
+    }
+    if args.dispersion {
+        return run_dispersion(args);
+    }
+    if args.voip {
+        return run_voip(args);
+    }
+    if args.gaming {
+        if args.direction == Directions::Both || args.repeat > 1 || !args.netem.is_empty() {
+            return Err("--gaming cannot be combined with --direction both, --repeat, or --netem".into());
+        }
+        return run_gaming(args);
+    }
+    if let Some(session) = &args.peer {
+        if args.direction == Directions::Both {
+            return Err("--peer tests one direction; run one client with --direction upload and the other with --direction download".into());
+        }
+        return run_peer(args, session);
+    }
+    if args.relay.is_some() && (args.direction == Directions::Both || args.repeat > 1 || !args.netem.is_empty()) {
+        return Err("--relay cannot be combined with --direction both, --repeat, or --netem".into());
+    }
+    if args.tunnel_interface.is_some() && (args.direction == Directions::Both || args.repeat > 1 || !args.netem.is_empty()) {
+        return Err("--tunnel-interface cannot be combined with --direction both, --repeat, or --netem".into());
+    }
+    if args.direction == Directions::Both {
+        if args.repeat > 1 || !args.netem.is_empty() {
+            return Err("--direction both cannot be combined with --repeat or --netem".into());
+        }
+        return run_both_directions(args);
+    }
+    if let Some(relay) = &args.relay {
+        return run_relay_comparison(args, relay);
+    }
+    if let Some(interface) = &args.tunnel_interface {
+        return run_tunnel_comparison(args, interface);
+    }
+    if !args.netem.is_empty() {
+        return run_netem_matrix(args);
+    }
+    if args.repeat > 1 {
+        return run_repeated(args);
+    }
+
+    let direction = args.single_direction();
+    let outcome = transfer::run_test(args, &Outputs::new(args, direction, None), direction)?;
+    evaluate(args, outcome)
+}
+
+/// Measures the download and then the upload, and reports how the two directions compare.
+///
+/// The download run is evaluated against the thresholds and the baseline and carries the comparison
+/// in its summary; the upload run writes its own `upload_*` files.
+fn run_both_directions(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    println!("=== Download ===");
+    let mut download = transfer::run_test(args, &Outputs::new(args, Direction::Download, None), Direction::Download)?;
+    println!("=== Upload ===");
+    let upload = transfer::run_test(args, &Outputs::new(args, Direction::Upload, None), Direction::Upload)?;
+
+    let asymmetry = Asymmetry::new(&download.result, &upload.result);
+    println!("=== Asymmetry ===");
+    println!(
+        "Effective Data Rate: download {}, upload {}, ratio {}",
+        bps_or_na(asymmetry.download_throughput_bps),
+        bps_or_na(asymmetry.upload_throughput_bps),
+        asymmetry.ratio.map_or("n/a".to_string(), |ratio| format!("{:.2}:1", ratio))
+    );
+    println!(
+        "Avg Transfer Time under load: download {}, upload {}",
+        seconds_or_na(asymmetry.download_latency_seconds),
+        seconds_or_na(asymmetry.upload_latency_seconds)
+    );
+    if let (Some(down), Some(up)) = (asymmetry.download_one_way_delay_seconds, asymmetry.upload_one_way_delay_seconds) {
+        println!("Avg One-Way Delay under load: download {:.3} ms, upload {:.3} ms (assumes synchronized clocks)", down * 1000.0, up * 1000.0);
+    }
+    if !download.data_rates.is_empty() || !upload.data_rates.is_empty() {
+        let path = output::path(args, None, "asymmetry.png");
+        plot::plot_asymmetry(
+            &path,
+            (&download.latencies, &download.data_rates),
+            (&upload.latencies, &upload.data_rates),
+            &chart_theme(args),
+        )?;
+        download.charts.push(Chart { title: "Download and upload", path });
+    }
+    // The report covers both directions, so it links the upload charts too
+    download.charts.extend(upload.charts);
+
+    download.result.asymmetry = Some(asymmetry);
+    if download.transfer_error.is_none() {
+        download.transfer_error = upload.transfer_error;
+    }
+    evaluate(args, download)
 }
 
-/// Calculates the TCP Throughput, which is typically limited by the BDP in networks with high latency.
-/// This considers the size of the congestion window and RTT.
+/// Measures the direct path to the server and then the path through the tunnel or VPN on
+/// `interface`, and shows what the tunnel costs side by side.
 ///
-/// # Arguments
-/// - `window_size_bits`: Size of the TCP congestion window in bits.
-/// - `rtt_seconds`: The round-trip time (RTT) in seconds.
+/// The direct run is evaluated against the thresholds and the baseline and carries the comparison in
+/// its summary and report; the tunneled run writes its own `*_tunnel` files.
+fn run_tunnel_comparison(args: &Args, interface: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    let direction = args.single_direction();
+    println!("=== Direct ===");
+    let mut direct = transfer::run_test(args, &Outputs::new(args, direction, None), direction)?;
+    println!("=== Through {} ===", interface);
+    let server = args.tunnel_server.clone().unwrap_or_else(|| args.server.clone());
+    let tunneled_args = Args { interface: Some(interface.to_string()), server: server.clone(), targets: vec![server], ..args.clone() };
+    let tunneled = transfer::run_test(&tunneled_args, &Outputs::new(args, direction, Some("tunnel")), direction)?;
+
+    let comparison = TunnelComparison::new(interface, args.tunnel_server.as_deref(), &direct.result, &tunneled.result);
+    println!("=== Tunnel overhead ===");
+    let through = format!("Through {}", interface);
+    println!("{:<20} {:>22} {:>22} {:>14}", "", "Direct", through, "Tunnel adds");
+    for [metric, direct, tunneled, added] in report::tunnel_rows(&comparison, args.units) {
+        println!("{:<20} {:>22} {:>22} {:>14}", metric, direct, tunneled, added);
+    }
+    if !direct.latencies.is_empty() && !tunneled.latencies.is_empty() {
+        let path = output::path(args, None, "tunnel_comparison.png");
+        let runs = [
+            plot::OverlayRun { name: "Direct", offsets: &direct.offsets, latencies: &direct.latencies, data_rates: &direct.data_rates },
+            plot::OverlayRun { name: &through, offsets: &tunneled.offsets, latencies: &tunneled.latencies, data_rates: &tunneled.data_rates },
+        ];
+        plot::plot_overlay(&path, &runs, &chart_theme(args))?;
+        direct.charts.push(Chart { title: "Direct and through the tunnel", path });
+    }
+    // The report covers both paths, so it links the tunneled run's charts too
+    direct.charts.extend(tunneled.charts);
+
+    direct.result.tunnel_comparison = Some(comparison);
+    if direct.transfer_error.is_none() {
+        direct.transfer_error = tunneled.transfer_error;
+    }
+    evaluate(args, direct)
+}
+
+/// Measures the direct path to the server and then the path through `relay`, and reports what the
+/// detour costs.
 ///
-/// # Returns
-/// - The TCP Throughput in bits per second.
-fn calculate_tcp_throughput(window_size_bits: f64, rtt_seconds: f64) -> f64 {
-    window_size_bits / rtt_seconds
+/// The direct run is evaluated against the thresholds and the baseline and carries the comparison in
+/// its summary; the relayed run writes its own `*_relay` files.
+fn run_relay_comparison(args: &Args, relay: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    let direction = args.single_direction();
+    println!("=== Direct ===");
+    let direct_args = Args { relay: None, ..args.clone() };
+    let mut direct = transfer::run_test(&direct_args, &Outputs::new(args, direction, None), direction)?;
+    println!("=== Via relay {} ===", relay);
+    let relayed = transfer::run_test(args, &Outputs::new(args, direction, Some("relay")), direction)?;
+
+    let comparison = RelayComparison::new(relay, &direct.result, &relayed.result);
+    println!("=== Relay overhead ===");
+    println!(
+        "Effective Data Rate: direct {}, via relay {}{}",
+        bps_or_na(comparison.direct_throughput_bps),
+        bps_or_na(comparison.relayed_throughput_bps),
+        comparison.ratio.map(|ratio| format!(" ({:.1}% of direct)", ratio * 100.0)).unwrap_or_default()
+    );
+    println!(
+        "Avg Transfer Time: direct {}, via relay {}",
+        seconds_or_na(comparison.direct_latency_seconds),
+        seconds_or_na(comparison.relayed_latency_seconds)
+    );
+    let first_byte = |result: &RunResult| result.setup.as_ref().and_then(|setup| setup.first_byte_seconds);
+    if let (Some(direct), Some(relayed)) = (first_byte(&direct.result), first_byte(&relayed.result)) {
+        println!(
+            "Time to First Byte: direct {:.3} ms, via relay {:.3} ms",
+            direct * 1000.0,
+            relayed * 1000.0
+        );
+    }
+
+    direct.result.relay_comparison = Some(comparison);
+    if direct.transfer_error.is_none() {
+        direct.transfer_error = relayed.transfer_error;
+    }
+    evaluate(args, direct)
+}
+
+/// Probes the server's UDP echo service without a transfer, evaluating the round-trip time against
+/// `--max-latency` and the probe loss against `--max-loss`.
+fn run_latency_only(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    let server = args.server.to_socket_addrs()?.next().ok_or_else(|| format!("{} did not resolve to an address", args.server))?;
+    let target = SocketAddr::new(server.ip(), args.udp_echo.unwrap_or_default());
+    let mut prober = echo::Prober::new(target, args.bind)?;
+    println!("Probing UDP echo at {} ({} probes)...", target, args.udp_echo_count);
+    let started_at = SystemTime::now();
+    let summary = echo::series(&mut prober, args.udp_echo_count, Duration::from_millis(args.udp_echo_interval_ms)).summary();
+    transfer::print_echo("idle", &summary);
+    // Like an incomplete transfer, a silent echo service is only acceptable under a loss threshold
+    let transfer_error = if summary.received == 0 {
+        Some(io::Error::new(io::ErrorKind::TimedOut, format!("no UDP echo replies from {}; is the server running with --udp-echo?", target)))
+    } else {
+        None
+    };
+
+    let result = RunResult {
+        direction: args.single_direction(),
+        started_at: humantime::format_rfc3339_micros(started_at).to_string(),
+        run_id: results::new_run_id(started_at),
+        tags: output::tags(args),
+        server: args.server.clone(),
+        latency_seconds: summary.rtt.as_ref().map(|rtt| rtt.mean),
+        loss_percent: summary.loss_percent,
+        udp_echo_idle: Some(summary),
+        ..RunResult::default()
+    };
+    evaluate(args, Outcome { result, transfer_error, latencies: Vec::new(), data_rates: Vec::new(), offsets: Vec::new(), charts: Vec::new() })
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut stream = TcpStream::connect("127.0.0.1:7878")?;
-    println!("Connected to the server...");
+/// Places a simulated call through the server's UDP echo service instead of a transfer, and rates
+/// its quality. The round-trip time and loss of its packets are evaluated as the run's latency and
+/// loss, so `--max-latency` and `--max-loss` apply to them.
+fn run_voip(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    let server = args.server.to_socket_addrs()?.next().ok_or_else(|| format!("{} did not resolve to an address", args.server))?;
+    let target = SocketAddr::new(server.ip(), args.udp_echo.unwrap_or_default());
+    println!("Calling through the UDP echo at {} for {} s ({})...", target, args.voip_duration, args.voip_codec);
+    let started_at = SystemTime::now();
+    let call = voip::call(target, args.bind, args.voip_codec, Duration::from_secs_f64(args.voip_duration))?;
+    voip::print(&call);
+    let transfer_error = if call.packets_received == 0 {
+        Some(io::Error::new(io::ErrorKind::TimedOut, format!("no packets of the call returned from {}; is the server running with --udp-echo?", target)))
+    } else {
+        None
+    };
 
-    let mut buffer = vec![0u8; 1_000_000];
-    let mut total_data_transferred = 0;
-    let mut total_time = Duration::new(0, 0);
-    let chunk_size = buffer.len() as f64 * 8.0;
+    let result = RunResult {
+        direction: args.single_direction(),
+        started_at: humantime::format_rfc3339_micros(started_at).to_string(),
+        run_id: results::new_run_id(started_at),
+        tags: output::tags(args),
+        server: args.server.clone(),
+        latency_seconds: call.rtt.as_ref().map(|rtt| rtt.mean),
+        loss_percent: call.loss_percent,
+        voip: Some(call),
+        ..RunResult::default()
+    };
+    evaluate(args, Outcome { result, transfer_error, latencies: Vec::new(), data_rates: Vec::new(), offsets: Vec::new(), charts: Vec::new() })
+}
+
+/// Sends game traffic through the server's UDP echo service on the idle link, then again while a
+/// transfer loads it, and reports what the load adds to its latency and loss. The transfer is
+/// evaluated as usual.
+fn run_gaming(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    let server = args.server.to_socket_addrs()?.next().ok_or_else(|| format!("{} did not resolve to an address", args.server))?;
+    let target = SocketAddr::new(server.ip(), args.udp_echo.unwrap_or_default());
+    let interval = Duration::from_secs_f64(1.0 / args.gaming_rate as f64);
+    let packets = ((args.gaming_duration * args.gaming_rate as f64).round() as usize).max(1);
+    println!(
+        "Sending game traffic through the UDP echo at {} ({} Hz, {} bytes) on the idle link for {} s...",
+        target,
+        args.gaming_rate,
+        gaming::PACKET_BYTES,
+        args.gaming_duration
+    );
+    let idle = echo::paced(target, args.bind, packets, interval, gaming::PACKET_BYTES)?;
 
-    let mut wtr = Writer::from_path("download_metrics.csv")?;
-    wtr.write_record(&["Chunk", "Download Time (s)", "Effective Data Rate (bps)"])?;
+    println!("=== Game traffic under load ===");
+    let direction = args.single_direction();
+    let background = echo::PacedBackground::start(target, args.bind, interval, gaming::PACKET_BYTES)?;
+    let mut outcome = transfer::run_test(args, &Outputs::new(args, direction, None), direction)?;
+    let loaded = background.finish()?;
 
-    let mut latencies = Vec::new();
-    let mut data_rates = Vec::new();
+    let gaming = gaming::compare(args.gaming_rate, &idle, &loaded);
+    gaming::print(&gaming);
+    outcome.result.gaming = Some(gaming);
+    evaluate(args, outcome)
+}
 
-    for i in 1..=100 {
-        let start = Instant::now();
-        stream.read_exact(&mut buffer)?;
+/// Tests directly against another client of the same session instead of the server. The two find
+/// each other through the server's rendezvous or a given address, punch holes through their NATs,
+/// and the client testing the upload sends to the one testing the download. Both evaluate the data
+/// rate and loss the receiver saw.
+fn run_peer(args: &Args, session: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(SocketAddr::new(args.bind.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)), args.peer_port))?;
+    let local = socket.local_addr()?;
+    let stun = match &args.stun {
+        Some(server) => {
+            let mapping = stun::query_from(&socket, server, connection::resolve(server, Some(local.ip()), None)?)?;
+            println!("Public address of this client: {} (local {})", mapping.mapped_address, mapping.local_address);
+            Some(mapping)
+        }
+        None => None,
+    };
+    let sender = args.single_direction() == Direction::Upload;
+    let started_at = SystemTime::now();
+    let (introduced, rendezvous) = match (&args.peer_address, args.rendezvous) {
+        (Some(address), _) => (connection::resolve(address, Some(local.ip()), None)?, false),
+        (None, Some(port)) => {
+            let server = SocketAddr::new(connection::resolve(&args.server, Some(local.ip()), None)?.ip(), port);
+            println!("Waiting at the rendezvous {} for the other client of session '{}'...", server, session);
+            (peer::rendezvous(&socket, session, server, sender, peer::RENDEZVOUS_WAIT)?, true)
+        }
+        (None, None) => return Err("--peer needs --rendezvous or --peer-address to find the other client".into()),
+    };
 
-        let duration = start.elapsed();
-        total_time += duration;
-        total_data_transferred += buffer.len();
+    println!("Punching through to {}...", introduced);
+    let punch_started = Instant::now();
+    let peer_address = peer::punch(&socket, session, introduced, sender)?;
+    let punch_seconds = punch_started.elapsed().as_secs_f64();
+    println!("Reached the other client at {} after {:.3} s", peer_address, punch_seconds);
+    let receipt = if sender {
+        println!("Sending at {} for {} s...", args.units.rate(args.peer_rate as f64), args.peer_duration);
+        peer::send(&socket, peer_address, args.peer_rate, Duration::from_secs_f64(args.peer_duration))?
+    } else {
+        println!("Receiving...");
+        peer::receive(&socket, session, peer_address)?
+    };
+
+    let test = results::PeerTest {
+        session: session.to_string(),
+        local_address: local.to_string(),
+        peer_address: peer_address.to_string(),
+        rendezvous,
+        punch_seconds,
+        sender,
+        packet_bytes: peer::PACKET_LEN,
+        target_rate_bps: receipt.rate_bps.map(|rate| rate as f64),
+        packets_sent: receipt.packets_sent,
+        packets_received: receipt.packets_received,
+        bytes_received: receipt.bytes_received,
+        data_rate_bps: receipt.data_rate_bps(),
+        loss_percent: receipt.loss_percent(),
+    };
+    peer::print(&test, args.units);
+    let result = RunResult {
+        direction: args.single_direction(),
+        started_at: humantime::format_rfc3339_micros(started_at).to_string(),
+        run_id: results::new_run_id(started_at),
+        tags: output::tags(args),
+        server: test.peer_address.clone(),
+        throughput_bps: Some(test.data_rate_bps),
+        loss_percent: test.loss_percent,
+        total_bytes: test.bytes_received,
+        stun,
+        peer: Some(test),
+        ..RunResult::default()
+    };
+    evaluate(args, Outcome { result, transfer_error: None, latencies: Vec::new(), data_rates: Vec::new(), offsets: Vec::new(), charts: Vec::new() })
+}
+
+/// Estimates the bandwidth of each direction from packet trains sent through the server's UDP echo
+/// service, without a transfer. The estimate of the download (the upload if only it was tested)
+/// is evaluated as the run's data rate, so `--min-throughput` applies to it.
+fn run_dispersion(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    let server = args.server.to_socket_addrs()?.next().ok_or_else(|| format!("{} did not resolve to an address", args.server))?;
+    let target = SocketAddr::new(server.ip(), args.udp_echo.unwrap_or_default());
+    let settings = dispersion::Settings {
+        trains: args.trains as usize,
+        packets: args.train_length,
+        packet_len: usize::from(args.train_packet_size),
+        interval: Duration::from_millis(args.train_interval_ms),
+        offload: args.udp_offload,
+    };
+    let directions = match args.direction {
+        Directions::Download => vec![Direction::Download],
+        Directions::Upload => vec![Direction::Upload],
+        Directions::Both => vec![Direction::Download, Direction::Upload],
+    };
+    let started_at = SystemTime::now();
+    let mut estimates = Vec::new();
+    for direction in directions {
+        println!("Sending {} {}-packet trains ({}) through the UDP echo at {}...", settings.trains, settings.packets, direction, target);
+        let estimate = dispersion::estimate(target, args.bind, direction, &settings)?;
+        dispersion::print(&estimate, args.units);
+        estimates.push(estimate);
+    }
+    // As with the latency-only run, no estimate at all is only acceptable under a loss threshold
+    let transfer_error = if estimates.iter().all(|estimate| estimate.rate.is_none()) {
+        Some(io::Error::new(io::ErrorKind::TimedOut, format!("no complete packet trains through {}; is the server running with --udp-echo?", target)))
+    } else {
+        None
+    };
+
+    let sent: usize = estimates.iter().map(|estimate| estimate.trains_sent).sum();
+    let used: usize = estimates.iter().map(|estimate| estimate.trains_used).sum();
+    let result = RunResult {
+        direction: args.single_direction(),
+        started_at: humantime::format_rfc3339_micros(started_at).to_string(),
+        run_id: results::new_run_id(started_at),
+        tags: output::tags(args),
+        server: args.server.clone(),
+        throughput_bps: estimates[0].rate.as_ref().map(|rate| rate.available_bps),
+        loss_percent: (sent - used) as f64 / sent as f64 * 100.0,
+        dispersion: estimates,
+        ..RunResult::default()
+    };
+    evaluate(args, Outcome { result, transfer_error, latencies: Vec::new(), data_rates: Vec::new(), offsets: Vec::new(), charts: Vec::new() })
+}
+
+/// Saves the run summary, compares it with the baseline, and checks the configured thresholds.
+///
+/// # Returns
+/// - The process exit code: 0 on success, `EXIT_THRESHOLD_FAILURE` if a threshold was violated or
+///   the run regressed against the baseline, or `EXIT_TEST_ERROR` if the transfer was cut short and
+///   no loss threshold allows for it.
+fn evaluate(args: &Args, outcome: Outcome) -> Result<i32, Box<dyn std::error::Error>> {
+    let result = &outcome.result;
+    let transfer_error = outcome.transfer_error;
+
+    let report = Report { result, charts: &outcome.charts, units: args.units };
+    if let Some(path) = &args.json {
+        let path = output::path(args, None, path);
+        report::Json.write(&report, &path)?;
+        println!("Run summary saved to {}", path.display());
+    }
+    for format in &args.report {
+        let reporter = format.reporter();
+        let path = output::path(args, None, format!("report.{}", reporter.extension()));
+        reporter.write(&report, &path)?;
+        println!("Report saved to {}", path.display());
+    }
+
+    let mut regressions = Vec::new();
+    if let Some(path) = &args.baseline {
+        let baseline = RunResult::read_json(path)?;
+        println!("Comparison with baseline {} (tolerance {:.1}%):", path.display(), args.tolerance);
+        for delta in baseline::compare(&baseline, result, args.tolerance) {
+            println!(
+                "  {}: {:.5} -> {:.5} ({:+.2}%){}",
+                delta.metric,
+                delta.baseline,
+                delta.current,
+                delta.change_percent,
+                if delta.regression { "  REGRESSION" } else { "" }
+            );
+            if delta.regression {
+                regressions.push(delta.metric);
+            }
+        }
+    }
+
+    let thresholds = thresholds(args);
+    let violations = thresholds.evaluate(result.throughput_bps, result.latency_seconds, result.loss_percent);
+
+    // A run cut short says nothing about how the job usually performs
+    let anomalies = match &args.monitor {
+        Some(monitor) if transfer_error.is_none() => monitor.check(result),
+        _ => Vec::new(),
+    };
+
+    let (status, exit) = if !violations.is_empty() || !regressions.is_empty() {
+        (Status::ThresholdFailed, Ok(EXIT_THRESHOLD_FAILURE))
+    } else {
+        match transfer_error {
+            // An incomplete transfer is only acceptable when the user explicitly allowed for loss
+            Some(e) if thresholds.max_loss_percent.is_none() => (Status::Incomplete, Err(e)),
+            _ if !anomalies.is_empty() => (Status::Anomalous, Ok(EXIT_THRESHOLD_FAILURE)),
+            _ => (Status::Passed, Ok(0)),
+        }
+    };
+    let verdict = summary::Verdict {
+        status,
+        violations: &violations,
+        regressions: &regressions,
+        anomalies: &anomalies,
+        thresholds_set: thresholds.is_set(),
+    };
+    summary::print(result, args.units, &verdict, args.summary_color.enabled());
+
+    let notification = Notification {
+        status,
+        exit_code: *exit.as_ref().unwrap_or(&EXIT_TEST_ERROR),
+        violations: &violations,
+        regressions,
+        anomalies: &anomalies,
+        error: exit.as_ref().err().map(|e| e.to_string()),
+        result,
+    };
+    publish(args, &notification, &outcome.charts);
+    Ok(exit?)
+}
+
+/// The limits given with `--min-throughput`, `--max-latency`, and `--max-loss`.
+fn thresholds(args: &Args) -> Thresholds {
+    Thresholds {
+        min_throughput_bps: args.min_throughput,
+        max_latency_seconds: args.max_latency,
+        max_loss_percent: args.max_loss,
+    }
+}
 
-        let download_time = duration.as_secs_f64();
-        let effective_data_rate = chunk_size / download_time;
+/// Checks every run of a series against the thresholds and the baseline, as `evaluate` checks a
+/// single run, and prints what each one missed.
+///
+/// # Returns
+/// - Whether any run missed a threshold or regressed against the baseline.
+fn check_runs<'a>(args: &Args, runs: impl Iterator<Item = (&'a str, &'a RunResult)>) -> Result<bool, Box<dyn std::error::Error>> {
+    let thresholds = thresholds(args);
+    let baseline = args.baseline.as_deref().map(RunResult::read_json).transpose()?;
+    let mut missed = false;
+    for (name, result) in runs {
+        for violation in thresholds.evaluate(result.throughput_bps, result.latency_seconds, result.loss_percent) {
+            println!("FAIL: {}: {}", name, violation);
+            missed = true;
+        }
+        let deltas = baseline.as_ref().map(|baseline| baseline::compare(baseline, result, args.tolerance)).unwrap_or_default();
+        for delta in deltas.iter().filter(|delta| delta.regression) {
+            println!(
+                "REGRESSION: {}: {} {:.5} -> {:.5} ({:+.2}%)",
+                name, delta.metric, delta.baseline, delta.current, delta.change_percent
+            );
+            missed = true;
+        }
+    }
+    Ok(missed)
+}
 
-        latencies.push(download_time);
-        data_rates.push(effective_data_rate);
+/// Sends the outcome of the run to the webhooks, email recipients, MQTT broker, and Kafka topic
+/// given on the command line. Failures are reported and do not change the exit code.
+fn publish(args: &Args, notification: &Notification, charts: &[Chart]) {
+    if !args.webhook.is_empty() && notification.is_due(args.webhook_on) {
+        notify::send_webhooks(&args.webhook, notification);
+    }
+    if !args.email_to.is_empty() && notification.is_due(args.email_on) {
+        let attachments = if args.email_html {
+            charts.iter().filter(|chart| chart.path.extension().is_some_and(|extension| extension == "html")).map(|chart| chart.path.clone()).collect()
+        } else {
+            Vec::new()
+        };
+        let email = Email {
+            server: &args.smtp_server,
+            from: args.email_from.clone().unwrap_or_else(notify::default_sender),
+            to: &args.email_to,
+            attachments,
+            units: args.units,
+        };
+        notify::send_email(&email, notification);
+    }
+    if let Some(broker) = mqtt_broker(args) {
+        let topic = format!("{}/summary", mqtt_topic(args));
+        let published = serde_json::to_vec(notification).map_err(io::Error::other).and_then(|payload| mqtt::publish_once(&broker, &topic, &payload, true));
+        match published {
+            Ok(()) => println!("Run summary published to MQTT topic {}", topic),
+            Err(e) => eprintln!("MQTT broker {} failed: {}", broker.address, e),
+        }
+    }
+    if !args.kafka_brokers.is_empty() {
+        let key = args.probe_id.clone().unwrap_or_else(notify::hostname);
+        let produced = serde_json::to_vec(notification).map_err(io::Error::other).and_then(|value| kafka::produce(&args.kafka_brokers, &args.kafka_topic, &key, &value));
+        match produced {
+            Ok((partition, offset)) => println!("Run summary produced to Kafka topic {} (partition {}, offset {})", args.kafka_topic, partition, offset),
+            Err(e) => eprintln!("Kafka failed: {}", e),
+        }
+    }
+}
 
-        wtr.write_record(&[i.to_string(), download_time.to_string(), effective_data_rate.to_string()])?;
-        println!("Chunk {}: Download Time: {:.2}s, Effective Data Rate: {:.2} bps", i, download_time, effective_data_rate);
+/// Runs the test `--repeat` times and evaluates the run with the median data rate.
+///
+/// Each run writes its own per-run files with a `_run<N>` suffix. The spread of the data rate over
+/// all runs is reported as a bootstrap confidence interval of the median and saved in the summary
+/// of the evaluated run, so one noisy run does not decide the outcome. A run that fails is reported
+/// and the series continues with the next one.
+///
+/// # Returns
+/// - The process exit code, as for a single run; a failed or cut-short run anywhere in the series
+///   makes the test incomplete.
+fn run_repeated(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut runs = Vec::new();
+    for i in 1..=args.repeat {
+        println!("=== Run {} of {} ===", i, args.repeat);
+        let direction = args.single_direction();
+        let run = transfer::run_test(args, &Outputs::new(args, direction, Some(&format!("run{}", i))), direction);
+        if let Err(e) = &run {
+            eprintln!("Run {} failed: {}", i, e);
+        }
+        runs.push(run.map_err(|e| e.to_string()));
     }
 
+    let report_path = output::path(args, None, "repeat_report.csv");
+    let tags = output::tags(args);
+    let mut wtr = Writer::from_path(&report_path)?;
+    wtr.write_record(tags.keys().map(String::as_str).chain(["Run", "Effective Data Rate (bps)", "Avg Download Time (s)", "Loss (%)", "Error"]))?;
+    println!("=== Repeat report ===");
+    for (i, run) in runs.iter().enumerate() {
+        match run {
+            Ok(outcome) => {
+                let result = &outcome.result;
+                println!(
+                    "Run {:<4} {:>24} {:>14} {:>7.2} %",
+                    i + 1,
+                    bps_or_na(result.throughput_bps),
+                    seconds_or_na(result.latency_seconds),
+                    result.loss_percent
+                );
+                wtr.write_record(tags.values().cloned().chain([
+                    (i + 1).to_string(),
+                    result.throughput_bps.map(|throughput| throughput.to_string()).unwrap_or_default(),
+                    result.latency_seconds.map(|latency| latency.to_string()).unwrap_or_default(),
+                    result.loss_percent.to_string(),
+                    outcome.transfer_error.as_ref().map(|e| e.to_string()).unwrap_or_default(),
+                ]))?;
+            }
+            Err(e) => {
+                println!("Run {:<4} failed: {}", i + 1, e);
+                wtr.write_record(tags.values().cloned().chain([(i + 1).to_string(), String::new(), String::new(), String::new(), e.clone()]))?;
+            }
+        }
+    }
     wtr.flush()?;
-    println!("Download metrics saved to download_metrics.csv");
+    println!("Repeat report saved to {}", report_path.display());
 
-    let total_data_bits = total_data_transferred as f64 * 8.0;
-    let total_time_seconds = total_time.as_secs_f64();
-    let avg_effective_data_rate = calculate_effective_data_rate(total_data_bits, total_time_seconds);
-    let rtt_seconds = 0.2;
-    let bdp = calculate_bdp(avg_effective_data_rate, rtt_seconds);
-    let tcp_window_size_bits = 64_000.0 * 8.0;
-    let tcp_throughput = calculate_tcp_throughput(tcp_window_size_bits, rtt_seconds);
+    // Runs that failed or received no data have no rate to rank
+    let mut measured: Vec<(usize, f64)> = runs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, run)| run.as_ref().ok()?.result.throughput_bps.map(|throughput| (i, throughput)))
+        .collect();
+    let throughputs: Vec<f64> = measured.iter().map(|(_, throughput)| *throughput).collect();
+    let summary = Summary::of(&throughputs).ok_or("no run received any data")?;
+    let (ci_low, ci_high) = stats::bootstrap_median_ci(&throughputs, 0.95, 10_000).ok_or("no run received any data")?;
+    println!(
+        "Median Effective Data Rate over {} runs: {:.2} bps, 95% CI [{:.2}, {:.2}] bps",
+        throughputs.len(),
+        summary.median,
+        ci_low,
+        ci_high
+    );
 
-    println!("Total Data Transferred: {:.2} MB", total_data_transferred as f64 / 1_000_000.0);
-    println!("Average Effective Data Rate: {:.2} bps", avg_effective_data_rate);
-    println!("Calculated BDP: {:.2} bits", bdp);
-    println!("TCP Throughput: {:.2} bps", tcp_throughput);
+    // The lower of the two middle runs for an even count, so the evaluated run is a real one
+    measured.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let median_run = measured[(measured.len() - 1) / 2].0;
+    println!("Evaluating run {}, which has the median data rate", median_run + 1);
 
-    plot_latency_and_data_rate(&latencies, &data_rates)?;
+    let mut outcome = runs.remove(median_run)?;
+    outcome.result.repeat = Some(RepeatSummary {
+        throughputs_bps: throughputs,
+        median_throughput_bps: summary.median,
+        median_ci_low_bps: ci_low,
+        median_ci_high_bps: ci_high,
+    });
+    // A failed or cut-short run anywhere in the series makes the whole test incomplete
+    if outcome.transfer_error.is_none() {
+        outcome.transfer_error = runs.into_iter().enumerate().find_map(|(i, run)| match run {
+            Ok(outcome) => outcome.transfer_error,
+            Err(e) => Some(io::Error::other(format!("run {} failed: {}", i + 1 + usize::from(i >= median_run), e))),
+        });
+    }
+    evaluate(args, outcome)
+}
 
-    Ok(())
+/// Runs the test once under each netem impairment and writes a consolidated report.
+///
+/// Each scenario writes its own per-run files with a `_netem<N>` suffix. A scenario that fails is
+/// reported and the matrix continues with the next one. Every scenario is checked against the
+/// thresholds and the baseline.
+///
+/// # Returns
+/// - The process exit code: `EXIT_THRESHOLD_FAILURE` if a scenario missed a threshold or
+///   regressed, `EXIT_TEST_ERROR` if one did not complete, 0 otherwise.
+fn run_netem_matrix(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    let interface = args.netem_interface.as_deref().ok_or("--netem requires --netem-interface")?;
+    let mut rows = Vec::new();
+
+    for (i, params) in args.netem.iter().enumerate() {
+        println!("=== Scenario {}: netem {} on {} ===", i + 1, params, interface);
+        let outcome = netem::apply(interface, params)
+            .map_err(|e| e.into())
+            .and_then(|_guard| {
+                let direction = args.single_direction();
+                transfer::run_test(args, &Outputs::new(args, direction, Some(&format!("netem{}", i + 1))), direction)
+            });
+
+        match outcome {
+            Ok(outcome) => {
+                let error = outcome.transfer_error.map(|e| e.to_string());
+                rows.push((params.as_str(), Some(outcome.result), error));
+            }
+            Err(e) => {
+                eprintln!("Scenario {} failed: {}", i + 1, e);
+                rows.push((params.as_str(), None, Some(e.to_string())));
+            }
+        }
+    }
+
+    let report_path = output::path(args, None, "netem_report.csv");
+    let tags = output::tags(args);
+    let mut wtr = Writer::from_path(&report_path)?;
+    wtr.write_record(tags.keys().map(String::as_str).chain(["Scenario", "Effective Data Rate (bps)", "Avg Download Time (s)", "Loss (%)", "Error"]))?;
+    println!("=== Netem matrix report ===");
+    for (params, result, error) in &rows {
+        match result {
+            Some(result) => {
+                println!(
+                    "{:<30} {:>24} {:>14} {:>7.2} %",
+                    params,
+                    bps_or_na(result.throughput_bps),
+                    seconds_or_na(result.latency_seconds),
+                    result.loss_percent
+                );
+                wtr.write_record(tags.values().cloned().chain([
+                    params.to_string(),
+                    result.throughput_bps.map(|throughput| throughput.to_string()).unwrap_or_default(),
+                    result.latency_seconds.map(|latency| latency.to_string()).unwrap_or_default(),
+                    result.loss_percent.to_string(),
+                    error.clone().unwrap_or_default(),
+                ]))?;
+            }
+            None => {
+                println!("{:<30} failed: {}", params, error.as_deref().unwrap_or_default());
+                wtr.write_record(tags.values().map(String::as_str).chain([params, "", "", "", error.as_deref().unwrap_or_default()]))?;
+            }
+        }
+    }
+    wtr.flush()?;
+    println!("Netem matrix report saved to {}", report_path.display());
+
+    if let Some(path) = &args.json {
+        let path = output::path(args, None, path);
+        let scenarios: Vec<serde_json::Value> = rows
+            .iter()
+            .map(|(params, result, error)| serde_json::json!({ "netem": params, "result": result, "error": error }))
+            .collect();
+        serde_json::to_writer_pretty(std::fs::File::create(&path)?, &scenarios)?;
+        println!("Scenario summaries saved to {}", path.display());
+    }
+
+    let missed = check_runs(args, rows.iter().filter_map(|(params, result, _)| Some((*params, result.as_ref()?))))?;
+    let failed = rows.iter().any(|(_, _, error)| error.is_some());
+    Ok(if missed {
+        EXIT_THRESHOLD_FAILURE
+    } else if failed {
+        EXIT_TEST_ERROR
+    } else {
+        0
+    })
 }
 
-fn plot_latency_and_data_rate(latencies: &[f64], data_rates: &[f64]) -> Result<(), Box<dyn std::error::Error>> {
-    let root = BitMapBackend::new("latency_data_rate.png", (1280, 960)).into_drawing_area();
-    root.fill(&WHITE)?;
-
-    let areas = root.split_evenly((2, 1));
-
-    let avg_latency = latencies.iter().sum::<f64>() / latencies.len() as f64;
-    let avg_data_rate = data_rates.iter().sum::<f64>() / data_rates.len() as f64;
-
-    let smoothed_latencies: Vec<f64> = latencies.windows(5).map(|w| w.iter().sum::<f64>() / w.len() as f64).collect();
-    let smoothed_data_rates: Vec<f64> = data_rates.windows(5).map(|w| w.iter().sum::<f64>() / w.len() as f64).collect();
-
-    let mut latency_chart = ChartBuilder::on(&areas[0])
-        .caption("Latency per Download (Smoothed)", ("sans-serif", 24).into_font())
-        .margin(10)
-        .x_label_area_size(40)
-        .y_label_area_size(60)
-        .build_cartesian_2d(1..smoothed_latencies.len() as i32, 0.0..smoothed_latencies.iter().cloned().fold(0./0., f64::max))?;
-    
-    latency_chart.configure_mesh()
-        .x_desc("Download Number")
-        .y_desc("Latency (s)")
-        .y_label_formatter(&|y| format!("{:.5}", y))
-        .axis_desc_style(("sans-serif", 14))
-        .label_style(("sans-serif", 12))
-        .light_line_style(&WHITE.mix(0.7))
-        .draw()?;
-    
-    latency_chart.draw_series(LineSeries::new(
-        (1..).zip(smoothed_latencies.iter().cloned()),
-        &RED,
-    ))?
-    .label("Latency (s) (Smoothed)")
-    .legend(|(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], &RED));
-
-    latency_chart.draw_series(std::iter::once(PathElement::new(
-        [(1, avg_latency), (smoothed_latencies.len() as i32, avg_latency)], 
-        RED.mix(0.5).stroke_width(2)
-    )))?
-    .label(format!("Avg Latency: {:.5} s", avg_latency))
-    .legend(|(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], RED.mix(0.5)));
-
-    latency_chart.configure_series_labels()
-        .border_style(&BLACK)
-        .background_style(&WHITE.mix(0.8))
-        .label_font(("sans-serif", 12))
-        .draw()?;
-
-    let mut data_rate_chart = ChartBuilder::on(&areas[1])
-        .caption("Effective Data Rate per Download (Smoothed)", ("sans-serif", 24).into_font())
-        .margin(10)
-        .x_label_area_size(40)
-        .y_label_area_size(60)
-        .build_cartesian_2d(1..smoothed_data_rates.len() as i32, 0.0..(avg_data_rate * 2.0))?;
-    
-    data_rate_chart.configure_mesh()
-        .x_desc("Download Number")
-        .y_desc("Data Rate (bps)")
-        .y_label_formatter(&|y| format!("{:.2e}", y))
-        .axis_desc_style(("sans-serif", 14))
-        .label_style(("sans-serif", 12))
-        .light_line_style(&WHITE.mix(0.7))
-        .draw()?;
-    
-    data_rate_chart.draw_series(LineSeries::new(
-        (1..).zip(smoothed_data_rates.iter().cloned()),
-        &BLUE,
-    ))?
-    .label("Effective Data Rate (bps) (Smoothed)")
-    .legend(|(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], &BLUE));
-    
-    data_rate_chart.draw_series(std::iter::once(PathElement::new(
-        [(1, avg_data_rate), (smoothed_data_rates.len() as i32, avg_data_rate)], 
-        BLUE.mix(0.5).stroke_width(2)
-    )))?
-    .label(format!("Avg Data Rate: {:.2e} bps", avg_data_rate))
-    .legend(|(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], BLUE.mix(0.5)));
-
-    data_rate_chart.configure_series_labels()
-        .border_style(&BLACK)
-        .background_style(&WHITE.mix(0.8))
-        .label_font(("sans-serif", 12))
-        .draw()?;
-
-    println!("Refined Latency and Effective Data Rate chart saved as latency_data_rate.png");
+/// Tests every `--server` in turn and compares them in `servers_report.csv`, `servers.png`, and, with
+/// `--json`, an array of their run summaries.
+///
+/// The servers are tested one after the other rather than at once, since concurrent tests would
+/// share the local link and each measure only part of it. Every server is checked against the
+/// thresholds and the baseline.
+///
+/// # Returns
+/// - The process exit code: `EXIT_THRESHOLD_FAILURE` if a server missed a threshold or regressed,
+///   `EXIT_TEST_ERROR` if one could not be tested, 0 otherwise.
+fn run_servers(args: &Args) -> Result<i32, Box<dyn std::error::Error>> {
+    let direction = args.single_direction();
+    let mut rows = Vec::new();
+    for (i, target) in args.targets.iter().enumerate() {
+        println!("=== Server {} of {}: {} ===", i + 1, args.targets.len(), target);
+        let server_args = Args { server: target.clone(), ..args.clone() };
+        match transfer::run_test(&server_args, &Outputs::new(&server_args, direction, Some(&format!("server{}", i + 1))), direction) {
+            Ok(outcome) => {
+                let error = outcome.transfer_error.map(|e| e.to_string());
+                rows.push((target.as_str(), Some(outcome.result), error));
+            }
+            Err(e) => {
+                eprintln!("Testing {} failed: {}", target, e);
+                rows.push((target.as_str(), None, Some(e.to_string())));
+            }
+        }
+    }
+
+    let report_path = output::path(args, None, "servers_report.csv");
+    let tags = output::tags(args);
+    let mut wtr = Writer::from_path(&report_path)?;
+    wtr.write_record(tags.keys().map(String::as_str).chain([
+        "Server",
+        "Effective Data Rate (bps)",
+        "Avg Transfer Time (s)",
+        "Loss (%)",
+        "TCP Connect (s)",
+        "Error",
+    ]))?;
+    println!("=== Server comparison ===");
+    println!("{:<30} {:>20} {:>15} {:>8} {:>13}", "Server", "Data Rate", "Transfer Time", "Loss", "TCP Connect");
+    let connect_seconds = |result: &RunResult| result.setup.as_ref().map(|setup| setup.connect_seconds);
+    for (server, result, error) in &rows {
+        match result {
+            Some(result) => {
+                println!(
+                    "{:<30} {:>20} {:>15} {:>6.2} % {:>13}{}",
+                    server,
+                    args.units.rate_or_na(result.throughput_bps),
+                    result.latency_seconds.map_or("n/a".to_string(), |latency| format!("{:.3} ms", latency * 1000.0)),
+                    result.loss_percent,
+                    connect_seconds(result).map(|seconds| format!("{:.3} ms", seconds * 1000.0)).unwrap_or_default(),
+                    error.as_ref().map(|error| format!("  (incomplete: {})", error)).unwrap_or_default()
+                );
+                wtr.write_record(tags.values().cloned().chain([
+                    server.to_string(),
+                    result.throughput_bps.map(|throughput| throughput.to_string()).unwrap_or_default(),
+                    result.latency_seconds.map(|latency| latency.to_string()).unwrap_or_default(),
+                    result.loss_percent.to_string(),
+                    connect_seconds(result).map(|seconds| seconds.to_string()).unwrap_or_default(),
+                    error.clone().unwrap_or_default(),
+                ]))?;
+            }
+            None => {
+                println!("{:<30} failed: {}", server, error.as_deref().unwrap_or_default());
+                wtr.write_record(tags.values().map(String::as_str).chain([server, "", "", "", "", error.as_deref().unwrap_or_default()]))?;
+            }
+        }
+    }
+    wtr.flush()?;
+    println!("Server comparison saved to {}", report_path.display());
 
-    Ok(())
+    let measured: Vec<(&str, &RunResult)> = rows.iter().filter_map(|(server, result, _)| result.as_ref().map(|result| (*server, result))).collect();
+    let rates = measured.iter().filter_map(|(server, result)| result.throughput_bps.map(|throughput| (server, throughput)));
+    if let Some((fastest, throughput)) = rates.max_by(|a, b| a.1.total_cmp(&b.1)) {
+        println!("Fastest: {} at {}", fastest, args.units.rate(throughput));
+    }
+    if !measured.is_empty() {
+        // A server that sent no data gets no bars
+        let bars: Vec<plot::ServerBar> = measured
+            .iter()
+            .map(|(server, result)| (*server, result.throughput_bps.unwrap_or_default(), result.latency_seconds.unwrap_or_default()))
+            .collect();
+        plot::plot_servers(&output::path(args, None, "servers.png"), &bars, &chart_theme(args))?;
+    }
+
+    if let Some(path) = &args.json {
+        let path = output::path(args, None, path);
+        let servers: Vec<serde_json::Value> =
+            rows.iter().map(|(server, result, error)| serde_json::json!({ "server": server, "result": result, "error": error })).collect();
+        serde_json::to_writer_pretty(std::fs::File::create(&path)?, &servers)?;
+        println!("Server summaries saved to {}", path.display());
+    }
+
+    let missed = check_runs(args, measured.into_iter())?;
+    let failed = rows.iter().any(|(_, _, error)| error.is_some());
+    Ok(if missed {
+        EXIT_THRESHOLD_FAILURE
+    } else if failed {
+        EXIT_TEST_ERROR
+    } else {
+        0
+    })
 }
@@ -0,0 +1,65 @@
+/// Calculates the Bandwidth-Delay Product (BDP)
+/// 
+/// BDP represents the maximum amount of data (in bits) that can be in transit in the network.
+/// 
+/// # Arguments
+/// - `bandwidth_bps`: The network bandwidth in bits per second (bps).
+/// - `rtt_seconds`: The round-trip time (RTT) in seconds.
+///
+/// # Returns
+/// - The Bandwidth-Delay Product in bits.
+pub fn calculate_bdp(bandwidth_bps: f64, rtt_seconds: f64) -> f64 {
+    bandwidth_bps * rtt_seconds
+}
+
+/// Calculates the Effective Data Rate, which represents the average data rate achieved over the entire transfer.
+/// This takes into account the total data transferred and the total time taken.
+///
+/// # Arguments
+/// - `total_data_bits`: Total amount of data transferred, in bits.
+/// - `total_time_seconds`: Total time taken for the transfer, in seconds.
+///
+/// # Returns
+/// - The Effective Data Rate in bits per second.
+pub fn calculate_effective_data_rate(total_data_bits: f64, total_time_seconds: f64) -> f64 {
+    total_data_bits / total_time_seconds
+}
+
+/// Calculates the TCP Throughput, which is typically limited by the BDP in networks with high latency.
+/// This considers the size of the congestion window and RTT.
+///
+/// # Arguments
+/// - `window_size_bits`: Size of the TCP congestion window in bits.
+/// - `rtt_seconds`: The round-trip time (RTT) in seconds.
+///
+/// # Returns
+/// - The TCP Throughput in bits per second.
+pub fn calculate_tcp_throughput(window_size_bits: f64, rtt_seconds: f64) -> f64 {
+    window_size_bits / rtt_seconds
+}
+
+/// Largest window TCP can advertise without window scaling, in bytes.
+const UNSCALED_WINDOW_LIMIT: u64 = 65_535;
+
+/// Largest window scale shift allowed by RFC 7323.
+pub const MAX_WINDOW_SCALE: u8 = 14;
+
+/// Calculates the largest window the client's socket buffer allows on a connection: the receive
+/// window it can advertise when downloading, or the data it can keep in flight when uploading.
+///
+/// # Arguments
+/// - `buffer_bytes`: The socket's receive (`SO_RCVBUF`) or send (`SO_SNDBUF`) buffer size as reported by the OS.
+/// - `window_scale`: The window scale shift that applies to the window, or `None` if window scaling
+///   was not negotiated.
+///
+/// # Returns
+/// - The maximum window in bytes.
+pub fn max_window(buffer_bytes: u64, window_scale: Option<u8>) -> u64 {
+    // Linux reports twice the usable size and keeps the other half for bookkeeping overhead
+    let usable = if cfg!(target_os = "linux") { buffer_bytes / 2 } else { buffer_bytes };
+    let limit = match window_scale {
+        Some(shift) => UNSCALED_WINDOW_LIMIT << shift.min(MAX_WINDOW_SCALE),
+        None => UNSCALED_WINDOW_LIMIT,
+    };
+    usable.min(limit)
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use common::protocol::Direction;
+use serde_json::json;
+
+/// How long the broker gets to connect and answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Environment variable holding the broker password, kept off the command line.
+pub const PASSWORD_VARIABLE: &str = "BWTEST_MQTT_PASSWORD";
+
+/// An MQTT broker and how to log in to it.
+#[derive(Clone)]
+pub struct Broker {
+    /// `HOST:PORT` of a plain TCP listener.
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// An MQTT 3.1.1 session that publishes with QoS 0.
+pub struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    /// Connects and logs in with a clean session and no keepalive, so an idle session is not
+    /// dropped between publications.
+    pub fn open(broker: &Broker) -> io::Result<Self> {
+        let address = broker
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to an address", broker.address)))?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        // Protocol name and level, connect flags, and the keepalive in seconds
+        let mut flags = 0x02;
+        let mut body = Vec::new();
+        string(&mut body, "MQTT");
+        body.push(4);
+        let flags_at = body.len();
+        body.push(0);
+        body.extend_from_slice(&0u16.to_be_bytes());
+        string(&mut body, &format!("bwtest-{}", process::id()));
+        if let Some(username) = &broker.username {
+            flags |= 0x80;
+            string(&mut body, username);
+            if let Some(password) = &broker.password {
+                flags |= 0x40;
+                string(&mut body, password);
+            }
+        }
+        body[flags_at] = flags;
+        stream.write_all(&packet(0x10, &body))?;
+
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the broker did not answer with CONNACK"));
+        }
+        match connack[3] {
+            0 => Ok(Connection { stream }),
+            4 | 5 => Err(io::Error::new(io::ErrorKind::PermissionDenied, "the broker refused the username or password")),
+            code => Err(io::Error::other(format!("the broker refused the connection (return code {})", code))),
+        }
+    }
+
+    /// Publishes `payload` to `topic`; a retained message is also handed to later subscribers.
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        let mut body = Vec::with_capacity(topic.len() + 2 + payload.len());
+        string(&mut body, topic);
+        body.extend_from_slice(payload);
+        self.stream.write_all(&packet(0x30 | u8::from(retain), &body))
+    }
+
+    /// Disconnects cleanly, so the broker does not treat the session as lost.
+    pub fn close(mut self) {
+        let _ = self.stream.write_all(&[0xe0, 0]);
+    }
+}
+
+/// Publishes one message and disconnects.
+pub fn publish_once(broker: &Broker, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+    let mut connection = Connection::open(broker)?;
+    connection.publish(topic, payload, retain)?;
+    connection.close();
+    Ok(())
+}
+
+/// A sample of the transfer, as handed to `Sampler::record`.
+struct Sample {
+    offset: f64,
+    seconds: f64,
+    bytes: usize,
+}
+
+/// Publishes the data rate and transfer time of every interval of a run from a separate thread,
+/// so a slow broker does not hold up the transfer.
+pub struct Sampler {
+    sender: Option<Sender<Sample>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Sampler {
+    /// Connects to the broker and publishes a summary of the samples recorded in every `interval`
+    /// of the run to `topic`. If the broker cannot be reached the samples are dropped.
+    ///
+    /// # Arguments
+    /// - `started_at`: Wall-clock start of the run, which sample offsets are measured from.
+    /// - `tags`: Tags of the run, included in every sample.
+    pub fn start(broker: Broker, topic: String, interval: Duration, direction: Direction, started_at: SystemTime, tags: BTreeMap<String, String>) -> Self {
+        let (sender, receiver) = mpsc::channel::<Sample>();
+        let handle = thread::spawn(move || {
+            let mut connection = match Connection::open(&broker) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    eprintln!("MQTT broker {} unavailable, not publishing samples: {}", broker.address, e);
+                    return;
+                }
+            };
+            let interval = interval.as_secs_f64();
+            let (mut chunks, mut bytes, mut seconds, mut end) = (0usize, 0usize, 0.0, interval);
+            let publish = |connection: &mut Connection, offset: f64, chunks: usize, bytes: usize, seconds: f64| {
+                let payload = json!({
+                    "direction": direction,
+                    "tags": tags,
+                    "offset_seconds": offset,
+                    "timestamp": humantime::format_rfc3339_micros(started_at + Duration::from_secs_f64(offset)).to_string(),
+                    "chunks": chunks,
+                    "bytes": bytes,
+                    "throughput_bps": bytes as f64 * 8.0 / seconds,
+                    "avg_latency_seconds": seconds / chunks as f64,
+                });
+                if let Err(e) = connection.publish(&topic, payload.to_string().as_bytes(), false) {
+                    eprintln!("MQTT sample could not be published: {}", e);
+                }
+            };
+            let mut last_offset = 0.0;
+            for sample in receiver {
+                // Intervals without a chunk are skipped rather than published as zero
+                if sample.offset >= end && chunks > 0 {
+                    publish(&mut connection, last_offset, chunks, bytes, seconds);
+                    (chunks, bytes, seconds) = (0, 0, 0.0);
+                }
+                while sample.offset >= end {
+                    end += interval;
+                }
+                chunks += 1;
+                bytes += sample.bytes;
+                seconds += sample.seconds;
+                last_offset = sample.offset;
+            }
+            if chunks > 0 {
+                publish(&mut connection, last_offset, chunks, bytes, seconds);
+            }
+            connection.close();
+        });
+        Sampler { sender: Some(sender), handle: Some(handle) }
+    }
+
+    /// Records a chunk that ended `offset` seconds into the run after `seconds` of transfer.
+    pub fn record(&self, offset: f64, seconds: f64, bytes: usize) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Sample { offset, seconds, bytes });
+        }
+    }
+
+    /// Publishes the last, partial interval and disconnects.
+    pub fn finish(mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Prefixes an MQTT string with its length.
+fn string(buffer: &mut Vec<u8>, text: &str) {
+    buffer.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(text.as_bytes());
+}
+
+/// Frames a control packet: its type and flags, the remaining length as a variable-length integer
+/// of seven bits per byte, and the body.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        packet.push(if length > 0 { byte | 0x80 } else { byte });
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::net::IpAddr;
+
+/// Bytes and packets an interface has received and sent since it came up.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Counters {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+}
+
+impl Counters {
+    /// Counters accumulated since `earlier`, tolerating a counter that wrapped or was reset.
+    pub fn since(&self, earlier: &Counters) -> Counters {
+        Counters {
+            rx_bytes: self.rx_bytes.saturating_sub(earlier.rx_bytes),
+            rx_packets: self.rx_packets.saturating_sub(earlier.rx_packets),
+            tx_bytes: self.tx_bytes.saturating_sub(earlier.tx_bytes),
+            tx_packets: self.tx_packets.saturating_sub(earlier.tx_packets),
+        }
+    }
+}
+
+/// Reads the counters of `interface` from /proc/net/dev.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn read(interface: &str) -> io::Result<Counters> {
+    let table = std::fs::read_to_string("/proc/net/dev")?;
+    // Two header lines, then "name: rx_bytes rx_packets (6 more) tx_bytes tx_packets ..."
+    for line in table.lines().skip(2) {
+        let (name, fields) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        if name.trim() != interface {
+            continue;
+        }
+        let fields: Vec<u64> = fields.split_whitespace().filter_map(|field| field.parse().ok()).collect();
+        if fields.len() < 10 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("malformed /proc/net/dev line for {}", interface)));
+        }
+        return Ok(Counters { rx_bytes: fields[0], rx_packets: fields[1], tx_bytes: fields[8], tx_packets: fields[9] });
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("interface {} not found in /proc/net/dev", interface)))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub fn read(_interface: &str) -> io::Result<Counters> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Interface counters are only supported on Linux"))
+}
+
+/// Link, IP, and TCP header bytes an interface counts for each packet of a TCP connection: the
+/// Ethernet header, the IP header without options, and a TCP header with the timestamp option.
+pub fn header_bytes_per_packet(address: IpAddr) -> u64 {
+    let ip_header = match address {
+        IpAddr::V4(_) => 20,
+        IpAddr::V6(_) => 40,
+    };
+    14 + ip_header + 32
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::process::Command;
+
+/// Scenario name that runs without any impairment, as a reference point in the matrix.
+pub const NO_IMPAIRMENT: &str = "none";
+
+/// An impairment configured with `tc qdisc ... netem` on an interface.
+///
+/// The qdisc is removed again when the guard is dropped, so an aborted run does not leave the
+/// interface impaired.
+pub struct NetemGuard {
+    interface: String,
+    active: bool,
+}
+
+/// Replaces the root qdisc of `interface` with netem using the given parameters.
+///
+/// Requires Linux and CAP_NET_ADMIN.
+///
+/// # Arguments
+/// - `interface`: Interface to impair, e.g. `eth0` or `lo`.
+/// - `params`: netem parameters as accepted by tc, e.g. `delay 50ms loss 1% rate 100mbit`, or
+///   `none` to leave the interface unimpaired.
+///
+/// # Returns
+/// - A guard that clears the qdisc when dropped.
+pub fn apply(interface: &str, params: &str) -> io::Result<NetemGuard> {
+    if params == NO_IMPAIRMENT {
+        return Ok(NetemGuard { interface: interface.to_string(), active: false });
+    }
+
+    let mut args = vec!["qdisc", "replace", "dev", interface, "root", "netem"];
+    args.extend(params.split_whitespace());
+    tc(&args)?;
+
+    Ok(NetemGuard { interface: interface.to_string(), active: true })
+}
+
+impl Drop for NetemGuard {
+    fn drop(&mut self) {
+        if self.active {
+            if let Err(e) = tc(&["qdisc", "del", "dev", &self.interface, "root"]) {
+                eprintln!("Failed to clear netem on {}: {}", self.interface, e);
+            }
+        }
+    }
+}
+
+fn tc(args: &[&str]) -> io::Result<()> {
+    let output = Command::new("tc").args(args).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "tc {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).lines().next().unwrap_or_default()
+        )))
+    }
+}
@@ -0,0 +1,312 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use clap::ValueEnum;
+use serde::Serialize;
+use crate::anomaly::Anomaly;
+use crate::report;
+use crate::results::RunResult;
+use crate::thresholds::Violation;
+use crate::units::Units;
+
+/// How long a notification endpoint gets to connect and answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// When notifications are sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Trigger {
+    /// After every run, whatever its outcome.
+    Finish,
+    /// Only after a run that missed a threshold, regressed against the baseline, deviated from the
+    /// job's recent runs, or was cut short.
+    Failure,
+}
+
+/// How a run ended, as far as its thresholds are concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Passed,
+    /// A threshold was violated or a metric regressed against the baseline.
+    ThresholdFailed,
+    /// The transfer was cut short and no loss threshold allows for it.
+    Incomplete,
+    /// A scheduled run deviated from the moving average of the job's earlier runs.
+    Anomalous,
+}
+
+/// What is sent when a run finishes: its outcome and the full run summary.
+#[derive(Serialize)]
+pub struct Notification<'a> {
+    pub status: Status,
+    pub exit_code: i32,
+    pub violations: &'a [Violation],
+    /// Metrics that regressed against the baseline.
+    pub regressions: Vec<&'static str>,
+    /// Metrics that deviated from the job's moving average.
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub anomalies: &'a [Anomaly],
+    /// The error that cut the transfer short.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub result: &'a RunResult,
+}
+
+impl Notification<'_> {
+    /// Whether the notification is due under `trigger`.
+    pub fn is_due(&self, trigger: Trigger) -> bool {
+        trigger == Trigger::Finish || self.status != Status::Passed
+    }
+}
+
+/// POSTs the notification as JSON to every webhook URL. A webhook that fails is reported and does
+/// not change the outcome of the run.
+pub fn send_webhooks(urls: &[String], notification: &Notification) {
+    let body = match serde_json::to_vec(notification) {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("Webhook notification could not be encoded: {}", e);
+            return;
+        }
+    };
+    for url in urls {
+        match post(url, "application/json", &body) {
+            Ok(()) => println!("Webhook notified: {}", url),
+            Err(e) => eprintln!("Webhook {} failed: {}", url, e),
+        }
+    }
+}
+
+/// Where and to whom run summaries are emailed.
+pub struct Email<'a> {
+    /// SMTP relay as `HOST:PORT`, which must accept mail from this host without authentication.
+    pub server: &'a str,
+    pub from: String,
+    pub to: &'a [String],
+    /// Files attached besides the JSON summary, e.g. the interactive HTML chart.
+    pub attachments: Vec<PathBuf>,
+    /// Units the data rates and sizes in the text are shown in.
+    pub units: Units,
+}
+
+/// Emails the notification: the outcome and headline numbers as text, with the run summary
+/// attached as JSON. An email that cannot be sent is reported and does not change the outcome of
+/// the run.
+pub fn send_email(email: &Email, notification: &Notification) {
+    match message(email, notification).and_then(|message| submit(email, &message)) {
+        Ok(()) => println!("Run summary emailed to {}", email.to.join(", ")),
+        Err(e) => eprintln!("Email through {} failed: {}", email.server, e),
+    }
+}
+
+/// Sender address used when none is given: the tool at this host.
+pub fn default_sender() -> String {
+    format!("bwtest@{}", hostname())
+}
+
+/// This host's name, for addresses and topics that identify the probe.
+pub fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Builds the MIME message. The relay adds the Date and Message-ID headers.
+fn message(email: &Email, notification: &Notification) -> io::Result<String> {
+    let result = notification.result;
+    let status = match notification.status {
+        Status::Passed => "passed",
+        Status::ThresholdFailed => "failed its thresholds",
+        Status::Incomplete => "incomplete",
+        Status::Anomalous => "deviated from its recent runs",
+    };
+    let mut text = format!("Bandwidth test {} from {}, started {}: {}.\n\n", result.direction, result.server, result.started_at, status);
+    for (metric, value) in report::summary_rows(result, email.units) {
+        let _ = writeln!(text, "{}: {}", metric, value);
+    }
+    if !notification.violations.is_empty() || !notification.regressions.is_empty() || !notification.anomalies.is_empty() || notification.error.is_some() {
+        text.push('\n');
+    }
+    for violation in notification.violations {
+        let _ = writeln!(text, "FAIL: {}", violation);
+    }
+    if !notification.regressions.is_empty() {
+        let _ = writeln!(text, "Regressed against the baseline: {}", notification.regressions.join(", "));
+    }
+    for anomaly in notification.anomalies {
+        let _ = writeln!(text, "ANOMALY: {}", anomaly);
+    }
+    if let Some(error) = &notification.error {
+        let _ = writeln!(text, "Error: {}", error);
+    }
+    if !result.recommendations.is_empty() {
+        text.push_str("\nRecommendations:\n");
+        for recommendation in &result.recommendations {
+            let _ = writeln!(text, "- {}. {}", recommendation.finding, recommendation.action);
+        }
+    }
+    text.push_str("\nThe full run summary is attached as JSON.\n");
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let boundary = format!("bwtest-{}-{}", process::id(), nanos);
+    let mut message = format!(
+        "From: <{}>\r\nTo: {}\r\nSubject: Bandwidth test {}: {} from {}, {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+        email.from,
+        email.to.iter().map(|to| format!("<{}>", to)).collect::<Vec<_>>().join(", "),
+        status,
+        result.direction,
+        result.server,
+        email.units.rate_or_na(result.throughput_bps),
+        boundary
+    );
+    let part = |message: &mut String, content_type: &str, name: Option<&str>, data: &[u8]| {
+        let _ = write!(message, "--{}\r\nContent-Type: {}\r\nContent-Transfer-Encoding: base64\r\n", boundary, content_type);
+        if let Some(name) = name {
+            let _ = write!(message, "Content-Disposition: attachment; filename=\"{}\"\r\n", name);
+        }
+        message.push_str("\r\n");
+        // Lines of 76 characters, as MIME requires
+        let encoded = base64(data);
+        for line in encoded.as_bytes().chunks(76) {
+            message.push_str(std::str::from_utf8(line).unwrap_or_default());
+            message.push_str("\r\n");
+        }
+    };
+    part(&mut message, "text/plain; charset=utf-8", None, text.as_bytes());
+    let json = serde_json::to_vec_pretty(result).map_err(io::Error::other)?;
+    part(&mut message, "application/json", Some("summary.json"), &json);
+    for path in &email.attachments {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        part(&mut message, "text/html; charset=utf-8", Some(&name), &fs::read(path)?);
+    }
+    let _ = write!(message, "--{}--\r\n", boundary);
+    Ok(message)
+}
+
+/// Hands the message to the relay in one SMTP session.
+fn submit(email: &Email, message: &str) -> io::Result<()> {
+    let address = email
+        .server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to an address", email.server)))?;
+    let stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    expect(&mut reader, 220)?;
+    let mut command = |line: String, code: u16| -> io::Result<()> {
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\r\n")?;
+        expect(&mut reader, code)
+    };
+    command(format!("EHLO {}", hostname()), 250)?;
+    command(format!("MAIL FROM:<{}>", email.from), 250)?;
+    for to in email.to {
+        command(format!("RCPT TO:<{}>", to), 250)?;
+    }
+    command("DATA".to_string(), 354)?;
+    // A line starting with a dot gets a second one, so it does not end the message
+    let mut data = String::with_capacity(message.len() + 8);
+    for line in message.split_inclusive("\r\n") {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+    }
+    data.push('.');
+    command(data, 250)?;
+    command("QUIT".to_string(), 221)
+}
+
+/// Reads a reply, which may span several lines, and checks its code.
+fn expect(reader: &mut impl BufRead, code: u16) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the SMTP server closed the connection"));
+        }
+        if !line.starts_with(&code.to_string()) {
+            return Err(io::Error::other(format!("the SMTP server answered {}", line.trim_end())));
+        }
+        // "250-" continues the reply, "250 " ends it
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Sends `body` with a minimal HTTP/1.1 POST and checks that the endpoint accepted it.
+fn post(url: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    let location = match url.strip_prefix("http://") {
+        Some(location) => location,
+        None if url.starts_with("https://") => {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "TLS is not supported; use an http:// URL or a local TLS-terminating proxy"))
+        }
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "webhook URL must start with http://")),
+    };
+    let (authority, path) = match location.find('/') {
+        Some(slash) => location.split_at(slash),
+        None => (location, "/"),
+    };
+    let address = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to an address", authority)))?;
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+
+    // Only the status line matters; the endpoint closes the connection after it
+    let mut head = [0u8; 256];
+    let mut read = 0;
+    while read < head.len() && !head[..read].contains(&b'\n') {
+        match stream.read(&mut head[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    let status = String::from_utf8_lossy(&head[..read]).lines().next().unwrap_or_default().to_string();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some(_) => Err(io::Error::other(format!("endpoint answered {}", status))),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "no HTTP response")),
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use common::protocol::Direction;
+use crate::results;
+use crate::{Args, Directions};
+
+/// Placeholders a file name template may contain.
+const PLACEHOLDERS: [&str; 4] = ["{date}", "{server}", "{direction}", "{name}"];
+
+/// Checks a `--name-template` for unknown placeholders and characters that would leave the output
+/// directory.
+pub fn parse_template(template: &str) -> Result<String, String> {
+    if template.contains(['/', '\\']) {
+        return Err("the template must not contain path separators; use --output-dir for directories".to_string());
+    }
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..].find('}').ok_or("unclosed '{' in the template")? + open;
+        let placeholder = &rest[open..=close];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!("unknown placeholder {}; the template may use {}", placeholder, PLACEHOLDERS.join(", ")));
+        }
+        rest = &rest[close + 1..];
+    }
+    Ok(template.to_string())
+}
+
+/// Parses a `--tag` as its key and value. Keys are limited to letters, digits, `_`, `-`, and `.`, so
+/// they work as CSV column names and exporter labels alike.
+pub fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    let (key, value) = tag.split_once('=').ok_or("expected KEY=VALUE")?;
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return Err(format!("tag key '{}' must be letters, digits, '_', '-', or '.'", key));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// The `--tag`s of the run, sorted by key so CSV columns line up whatever order they were given
+/// in. A key given twice keeps its last value.
+pub fn tags(args: &Args) -> BTreeMap<String, String> {
+    args.tags.iter().cloned().collect()
+}
+
+/// Stamps the invocation's start time, which `{date}` refers to, and creates the output directory.
+pub fn prepare(args: &mut Args) -> Result<(), String> {
+    args.started_at = Some(SystemTime::now());
+    create_dir(args.output_dir.as_deref())
+}
+
+/// Creates the output directory, if one was given.
+pub fn create_dir(dir: Option<&Path>) -> Result<(), String> {
+    match dir {
+        Some(dir) => fs::create_dir_all(dir).map_err(|e| format!("output directory {} could not be created: {}", dir.display(), e)),
+        None => Ok(()),
+    }
+}
+
+/// Returns where an output file is written: `name` expanded by the `--name-template`, if one was
+/// given, under the `--output-dir`. Absolute paths are used as they are.
+///
+/// # Arguments
+/// - `direction`: Direction of the run the file belongs to, or `None` for files covering the
+///   whole invocation, for which `{direction}` is the `--direction` given.
+/// - `name`: File name without a template, e.g. `download_metrics.csv`.
+pub fn path(args: &Args, direction: Option<Direction>, name: impl AsRef<Path>) -> PathBuf {
+    let name = name.as_ref();
+    if name.is_absolute() {
+        return name.to_path_buf();
+    }
+    let name = match (&args.name_template, name.file_name()) {
+        (Some(template), Some(file_name)) => name.with_file_name(expand(args, template, direction, &file_name.to_string_lossy())),
+        _ => name.to_path_buf(),
+    };
+    match &args.output_dir {
+        Some(dir) => dir.join(name),
+        None => name,
+    }
+}
+
+/// Fills in the placeholders of `template`. A template without `{name}` is followed by `_` and the
+/// file's own name, so the files of one run still get distinct names.
+fn expand(args: &Args, template: &str, direction: Option<Direction>, name: &str) -> String {
+    let date = results::compact_timestamp(args.started_at.unwrap_or_else(SystemTime::now));
+    let server = args.s3_endpoint.as_deref().unwrap_or(&args.server);
+    let server: String = server
+        .trim_start_matches("http://")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' })
+        .collect();
+    let direction = match (direction, args.direction) {
+        (Some(direction), _) => direction.to_string(),
+        (None, Directions::Download) => "download".to_string(),
+        (None, Directions::Upload) => "upload".to_string(),
+        (None, Directions::Both) => "both".to_string(),
+    };
+    let expanded = template.replace("{date}", &date).replace("{server}", &server).replace("{direction}", &direction);
+    if expanded.contains("{name}") {
+        expanded.replace("{name}", name)
+    } else {
+        format!("{}_{}", expanded, name)
+    }
+}
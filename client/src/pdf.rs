@@ -0,0 +1,496 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use common::protocol::Direction;
+use crate::report::{self, Report, Reporter};
+use crate::results::RunResult;
+use crate::stats::Summary;
+use crate::tuning::HostSettings;
+use crate::units::Units;
+
+/// A4 in points.
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 56.0;
+const BODY_SIZE: f64 = 10.0;
+/// Width of the label column of a two-column table.
+const LABEL_WIDTH: f64 = 150.0;
+/// Advance widths of Helvetica for the printable ASCII characters, in 1/1000 of the font size,
+/// from the font's AFM metrics. Characters outside the range count as a digit.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278,
+    278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722,
+    667, 944, 667, 667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, 556,
+    556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// A PDF report for handing to customers and ISPs: the headline numbers, how they were measured,
+/// the findings, the charts, and the environment the test ran in.
+///
+/// The document is written directly, with the standard Helvetica fonts, so no font has to be
+/// embedded; PNG charts are embedded as they are, which PDF's Flate filter decodes natively.
+pub struct Pdf;
+
+impl Reporter for Pdf {
+    fn extension(&self) -> &'static str {
+        "pdf"
+    }
+
+    fn write(&self, report: &Report, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let result = report.result;
+        let mut doc = Document::new();
+        doc.text("Bandwidth Test Report", Font::Bold, 20.0);
+        doc.text(&format!("{} from {}, started {}", capitalize(&result.direction.to_string()), result.server, result.started_at), Font::Regular, BODY_SIZE);
+
+        doc.heading("Summary");
+        for (metric, value) in report::summary_rows(result, report.units) {
+            doc.row(&[metric.to_string(), value], &[LABEL_WIDTH, PAGE_WIDTH - 2.0 * MARGIN - LABEL_WIDTH], Font::Regular);
+        }
+
+        let rate_name = format!("Data rate ({})", report.units.rate_unit());
+        let distributions = [
+            ("Transfer time (ms)", result.latency_stats.as_ref(), 1000.0),
+            (rate_name.as_str(), result.throughput_stats.as_ref(), 1.0 / report.units.mega()),
+        ];
+        if distributions.iter().any(|(_, stats, _)| stats.is_some()) {
+            doc.heading("Distribution");
+            let widths = [LABEL_WIDTH - 40.0, 48.0, 48.0, 48.0, 48.0, 48.0, 48.0, 48.0, 49.0];
+            let header = ["", "Samples", "Mean", "Median", "Min", "p5", "p95", "p99", "Max"];
+            doc.row(&header.map(str::to_string), &widths, Font::Bold);
+            for (name, stats, scale) in distributions {
+                if let Some(Summary { samples, mean, median, min, p5, p95, p99, max, .. }) = stats {
+                    let mut cells = vec![name.to_string(), samples.to_string()];
+                    cells.extend([mean, median, min, p5, p95, p99, max].iter().map(|value| format!("{:.3}", *value * scale)));
+                    doc.row(&cells, &widths, Font::Regular);
+                }
+            }
+        }
+
+        let warnings = report::warnings(result);
+        if !result.recommendations.is_empty() || !warnings.is_empty() {
+            doc.heading("Findings");
+            for recommendation in &result.recommendations {
+                doc.bullet(&format!("{}. {}", recommendation.finding, recommendation.action));
+            }
+            for warning in warnings {
+                doc.bullet(warning);
+            }
+        }
+
+        doc.heading("Methodology");
+        for paragraph in methodology(result, report.units) {
+            doc.paragraph(&paragraph);
+        }
+
+        doc.heading("Environment");
+        for (name, value) in environment(result) {
+            doc.row(&[name.to_string(), value], &[LABEL_WIDTH, PAGE_WIDTH - 2.0 * MARGIN - LABEL_WIDTH], Font::Regular);
+        }
+
+        for chart in report.charts {
+            let is_png = chart.path.extension().is_some_and(|extension| extension == "png");
+            let embedded = if is_png { fs::read(&chart.path).ok().and_then(|png| doc.image(chart.title, &png)) } else { None };
+            if embedded.is_none() {
+                doc.heading(chart.title);
+                doc.paragraph(&format!("See {}.", chart.path.display()));
+            }
+        }
+
+        fs::write(path, doc.finish())?;
+        Ok(())
+    }
+}
+
+/// How the run measured what the summary shows, in plain words.
+fn methodology(result: &RunResult, units: Units) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    match &result.object_storage {
+        Some(storage) => paragraphs.push(format!(
+            "The client transferred the object {}/{} at {} in {} parts of {} bytes, {} at a time ({} in total). Each part was timed from its request to its last byte.",
+            storage.bucket,
+            storage.key,
+            result.server,
+            storage.parts,
+            storage.part_size,
+            storage.concurrency,
+            units.size(result.total_bytes as f64)
+        )),
+        None => paragraphs.push(format!(
+            "The {} sent {} chunks of test data over a single TCP connection ({} in total), of which {} arrived. Each chunk was timed where it arrived: {}.",
+            match result.direction {
+                Direction::Download => format!("server at {}", result.server),
+                Direction::Upload => format!("client, from {} to the server at {},", result.local_address, result.server),
+            },
+            result.chunks_expected,
+            units.size(result.total_bytes as f64),
+            result.chunks_received,
+            match result.direction {
+                Direction::Download => "by the client, from when it started reading the chunk to its last byte",
+                Direction::Upload => "by the server, which acknowledged each chunk with the time it was received",
+            }
+        )),
+    }
+    paragraphs.push(
+        "The effective data rate is the total data transferred, in bits, divided by the total transfer time. Transfer times and per-chunk data rates are summarized by their distribution, and loss is the share of the expected data that did not arrive."
+            .to_string(),
+    );
+    if result.rtt_seconds.is_some() {
+        paragraphs.push(
+            "The RTT is the smoothed round-trip time the operating system measured on the test connection; it and the socket buffer give the bandwidth-delay product and the TCP throughput the window allows."
+                .to_string(),
+        );
+    }
+    paragraphs
+}
+
+/// The host, software, and network settings the run measured with.
+fn environment(result: &RunResult) -> Vec<(&'static str, String)> {
+    let mut rows = vec![("Client", format!("bandwidth-tests client {}", env!("CARGO_PKG_VERSION")))];
+    let (host, system) = uname();
+    if let Some(host) = host {
+        rows.push(("Host", host));
+    }
+    rows.push(("Operating system", system));
+    let cpu = fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|cpuinfo| cpuinfo.lines().find_map(|line| line.strip_prefix("model name").map(|model| model.trim_start_matches([' ', '\t', ':']).to_string())));
+    let cores = std::thread::available_parallelism().map(|cores| cores.get()).unwrap_or(1);
+    rows.push(("CPU", match cpu {
+        Some(model) => format!("{} ({} logical cores)", model, cores),
+        None => format!("{} logical cores", cores),
+    }));
+    if let Some(algorithm) = HostSettings::read().congestion_control {
+        rows.push(("Congestion control", algorithm));
+    }
+    rows.push(("Local address", result.local_address.clone()));
+    if let Some(interface) = &result.interface {
+        rows.push(("Interface", interface.clone()));
+    }
+    if let Some(proxy) = &result.proxy {
+        rows.push(("Proxy", proxy.clone()));
+    }
+    if let Some(tunnel) = &result.ssh_tunnel {
+        rows.push(("SSH tunnel", format!("{} to {}", tunnel.destination, tunnel.target)));
+    }
+    if let Some(relay) = &result.relay {
+        rows.push(("Relay", relay.clone()));
+    }
+    rows
+}
+
+/// The host name, and the operating system with its release and architecture.
+#[cfg(unix)]
+fn uname() -> (Option<String>, String) {
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return (None, format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH));
+    }
+    let field = |chars: &[libc::c_char]| {
+        let bytes: Vec<u8> = chars.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    (Some(field(&name.nodename)), format!("{} {} ({})", field(&name.sysname), field(&name.release), field(&name.machine)))
+}
+
+#[cfg(not(unix))]
+fn uname() -> (Option<String>, String) {
+    (std::env::var("COMPUTERNAME").ok(), format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH))
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+#[derive(Clone, Copy)]
+enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+}
+
+/// A PDF document laid out top to bottom, starting a new page whenever the next item does not fit.
+struct Document {
+    /// Bodies of the objects so far; object `n` is at index `n - 1`. The first four are filled in
+    /// by `finish`: the catalog, the page tree, and the two fonts.
+    objects: Vec<Vec<u8>>,
+    pages: Vec<usize>,
+    /// Content stream of the current page.
+    content: String,
+    /// Image objects drawn on the current page.
+    images: Vec<usize>,
+    /// Baseline of the next line on the current page.
+    y: f64,
+}
+
+impl Document {
+    fn new() -> Self {
+        Document { objects: vec![Vec::new(); 4], pages: Vec::new(), content: String::new(), images: Vec::new(), y: PAGE_HEIGHT - MARGIN }
+    }
+
+    fn add(&mut self, body: Vec<u8>) -> usize {
+        self.objects.push(body);
+        self.objects.len()
+    }
+
+    /// Makes room for `height` points on the current page, or starts a new one.
+    fn reserve(&mut self, height: f64) {
+        if self.y - height < MARGIN && !self.content.is_empty() {
+            self.end_page();
+        }
+    }
+
+    fn end_page(&mut self) {
+        let number = self.pages.len() + 1;
+        self.draw_text(&format!("Page {}", number), Font::Regular, 8.0, PAGE_WIDTH - MARGIN - 30.0, MARGIN / 2.0);
+        let content = std::mem::take(&mut self.content);
+        let stream = self.add(stream(&format!("/Length {}", content.len()), content.as_bytes()));
+        let images: String = self.images.drain(..).map(|image| format!(" /Im{} {} 0 R", image, image)).collect();
+        let page = format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> /XObject <<{} >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH, PAGE_HEIGHT, images, stream
+        );
+        let page = self.add(page.into_bytes());
+        self.pages.push(page);
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    fn draw_text(&mut self, text: &str, font: Font, size: f64, x: f64, y: f64) {
+        let _ = writeln!(self.content, "BT /{} {} Tf {:.2} {:.2} Td ({}) Tj ET", font.resource(), size, x, y, escape(text));
+    }
+
+    /// Writes `text` wrapped to the page width.
+    fn text(&mut self, text: &str, font: Font, size: f64) {
+        for line in wrap(text, size, PAGE_WIDTH - 2.0 * MARGIN) {
+            self.reserve(size * 1.4);
+            self.y -= size * 1.4;
+            self.draw_text(&line, font, size, MARGIN, self.y);
+        }
+    }
+
+    fn heading(&mut self, text: &str) {
+        // Keep a heading with at least a few lines of what follows it
+        self.reserve(14.0 * 1.4 + 12.0 + BODY_SIZE * 1.4 * 3.0);
+        self.y -= 12.0;
+        self.text(text, Font::Bold, 14.0);
+        self.y -= 4.0;
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        self.text(text, Font::Regular, BODY_SIZE);
+        self.y -= BODY_SIZE * 0.6;
+    }
+
+    fn bullet(&mut self, text: &str) {
+        let lines = wrap(text, BODY_SIZE, PAGE_WIDTH - 2.0 * MARGIN - 12.0);
+        for (i, line) in lines.iter().enumerate() {
+            self.reserve(BODY_SIZE * 1.4);
+            self.y -= BODY_SIZE * 1.4;
+            if i == 0 {
+                self.draw_text("-", Font::Regular, BODY_SIZE, MARGIN + 2.0, self.y);
+            }
+            self.draw_text(line, Font::Regular, BODY_SIZE, MARGIN + 12.0, self.y);
+        }
+        self.y -= BODY_SIZE * 0.4;
+    }
+
+    /// Writes a table row, each cell wrapped to its column, with a rule beneath it.
+    fn row(&mut self, cells: &[String], widths: &[f64], font: Font) {
+        let wrapped: Vec<Vec<String>> = cells.iter().zip(widths).map(|(cell, width)| wrap(cell, BODY_SIZE, width - 6.0)).collect();
+        let lines = wrapped.iter().map(Vec::len).max().unwrap_or(1).max(1);
+        let height = lines as f64 * BODY_SIZE * 1.4 + 4.0;
+        self.reserve(height);
+        let top = self.y;
+        let mut x = MARGIN;
+        for (cell, width) in wrapped.iter().zip(widths) {
+            for (i, line) in cell.iter().enumerate() {
+                self.draw_text(line, font, BODY_SIZE, x, top - (i + 1) as f64 * BODY_SIZE * 1.4);
+            }
+            x += width;
+        }
+        self.y = top - height;
+        let _ = writeln!(self.content, "0.8 G 0.5 w {:.2} {:.2} m {:.2} {:.2} l S 0 G", MARGIN, self.y + 1.0, x, self.y + 1.0);
+    }
+
+    /// Draws a PNG chart on a page of its own, under `title`, scaled to the page width.
+    ///
+    /// # Returns
+    /// - `None` if the PNG is not one PDF can take as it is: 8-bit RGB or gray, not interlaced.
+    fn image(&mut self, title: &str, png: &[u8]) -> Option<()> {
+        let image = Png::parse(png)?;
+        let colors = match image.color_type {
+            0 => 1,
+            2 => 3,
+            _ => return None,
+        };
+        if image.bit_depth != 8 || image.interlaced {
+            return None;
+        }
+        let dictionary = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} /BitsPerComponent 8 /Filter /FlateDecode \
+             /DecodeParms << /Predictor 15 /Colors {} /BitsPerComponent 8 /Columns {} >> /Length {}",
+            image.width,
+            image.height,
+            if colors == 1 { "DeviceGray" } else { "DeviceRGB" },
+            colors,
+            image.width,
+            image.data.len()
+        );
+        let object = self.add(stream(&dictionary, &image.data));
+
+        if !self.content.is_empty() {
+            self.end_page();
+        }
+        self.text(title, Font::Bold, 14.0);
+        self.y -= 8.0;
+        let width = PAGE_WIDTH - 2.0 * MARGIN;
+        let height = (width * image.height as f64 / image.width as f64).min(self.y - MARGIN);
+        let width = height * image.width as f64 / image.height as f64;
+        self.y -= height;
+        let _ = writeln!(self.content, "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im{} Do Q", width, height, MARGIN, self.y, object);
+        self.images.push(object);
+        Some(())
+    }
+
+    /// Finishes the last page and returns the file.
+    fn finish(mut self) -> Vec<u8> {
+        if !self.content.is_empty() || self.pages.is_empty() {
+            self.end_page();
+        }
+        self.objects[0] = b"<< /Type /Catalog /Pages 2 0 R >>".to_vec();
+        let kids: String = self.pages.iter().map(|page| format!("{} 0 R ", page)).collect();
+        self.objects[1] = format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.trim_end(), self.pages.len()).into_bytes();
+        self.objects[2] = b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_vec();
+        self.objects[3] = b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_vec();
+
+        // The comment of high bytes marks the file as binary for transfer programs
+        let mut file = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, body) in self.objects.iter().enumerate() {
+            offsets.push(file.len());
+            file.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            file.extend_from_slice(body);
+            file.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = file.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(table, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(table, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", self.objects.len() + 1, xref);
+        file.extend_from_slice(table.as_bytes());
+        file
+    }
+}
+
+/// A stream object with the given dictionary entries.
+fn stream(dictionary: &str, data: &[u8]) -> Vec<u8> {
+    let mut body = format!("<< {} >>\nstream\n", dictionary).into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(b"\nendstream");
+    body
+}
+
+/// Escapes text for a PDF string in WinAnsi encoding; characters it cannot show become `?`.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            // Latin-1 letters and signs have the same codes in WinAnsi
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(escaped, "\\{:03o}", c as u32);
+            }
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+/// Width of `text` in Helvetica at `size` points.
+fn text_width(text: &str, size: f64) -> f64 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c {
+            ' '..='~' => HELVETICA_WIDTHS[c as usize - 32] as u32,
+            _ => 556,
+        })
+        .sum();
+    units as f64 * size / 1000.0
+}
+
+/// Breaks `text` into lines no wider than `width` at spaces, or anywhere in a word that is wider on
+/// its own.
+fn wrap(text: &str, size: f64, width: f64) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+        if text_width(&candidate, size) <= width || line.is_empty() && text_width(word, size) <= width {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        for c in word.chars() {
+            if !line.is_empty() && text_width(&format!("{}{}", line, c), size) > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            line.push(c);
+        }
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// The parts of a PNG file needed to embed it in a PDF.
+struct Png {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+    /// The zlib stream of the IDAT chunks, with the PNG row filters that PDF's predictor undoes.
+    data: Vec<u8>,
+}
+
+impl Png {
+    fn parse(bytes: &[u8]) -> Option<Png> {
+        let mut rest = bytes.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+        let mut png = Png { width: 0, height: 0, bit_depth: 0, color_type: 0, interlaced: false, data: Vec::new() };
+        while rest.len() >= 12 {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let kind = &rest[4..8];
+            let data = rest.get(8..8 + length)?;
+            match kind {
+                b"IHDR" if length >= 13 => {
+                    png.width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                    png.height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+                    png.bit_depth = data[8];
+                    png.color_type = data[9];
+                    png.interlaced = data[12] != 0;
+                }
+                b"IDAT" => png.data.extend_from_slice(data),
+                b"IEND" => break,
+                _ => {}
+            }
+            rest = rest.get(12 + length..)?;
+        }
+        (png.width > 0 && png.height > 0 && !png.data.is_empty()).then_some(png)
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+use common::peer::Message;
+use crate::results::PeerTest;
+use crate::units::Units;
+
+/// UDP payload of a data packet, small enough to pass unfragmented through tunnels and over IPv6.
+pub const PACKET_LEN: usize = 1200;
+/// How long a client waits at the rendezvous for the other client of its session, as long as the
+/// server keeps a registration.
+pub const RENDEZVOUS_WAIT: Duration = Duration::from_secs(120);
+
+const REGISTER_INTERVAL: Duration = Duration::from_millis(500);
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+/// How long hole punching goes on before the other client counts as unreachable.
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the receiver waits for the first data packet, and for each one after it.
+const DATA_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the sender announces that it is done, until the receiver's report arrives.
+const DONE_ATTEMPTS: usize = 10;
+const DONE_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the receiver answers the sender's repeated announcements after its report, in case
+/// the report was lost.
+const LINGER: Duration = Duration::from_secs(1);
+
+/// The receiver's account of a test, which both clients report.
+pub struct Receipt {
+    /// Packets the sender sent, or as many as the highest sequence number shows if its count was
+    /// lost.
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    /// Time from the first packet received to the last, in seconds.
+    pub receive_seconds: f64,
+    /// Rate the sender paced its packets at, in bits per second, unless its announcement was lost.
+    pub rate_bps: Option<u64>,
+}
+
+impl Receipt {
+    /// Data rate the receiver saw, in bits per second. The first packet only marks when the span
+    /// starts, so it is not counted.
+    pub fn data_rate_bps(&self) -> f64 {
+        if self.packets_received > 1 && self.receive_seconds > 0.0 {
+            let bytes = self.bytes_received as f64 * (self.packets_received - 1) as f64 / self.packets_received as f64;
+            bytes * 8.0 / self.receive_seconds
+        } else {
+            0.0
+        }
+    }
+
+    pub fn loss_percent(&self) -> f64 {
+        if self.packets_sent == 0 {
+            return 0.0;
+        }
+        self.packets_sent.saturating_sub(self.packets_received) as f64 / self.packets_sent as f64 * 100.0
+    }
+}
+
+/// Registers `session` at the rendezvous port `server` until it answers with the other client's
+/// public address.
+///
+/// The other client may get its answer first and start punching before ours arrives; its first
+/// punch then tells us where it is just as well.
+///
+/// # Arguments
+/// - `sender`: Whether this client sends the test data, checked against the other's punches.
+pub fn rendezvous(socket: &UdpSocket, session: &str, server: SocketAddr, sender: bool, wait: Duration) -> io::Result<SocketAddr> {
+    let deadline = Instant::now() + wait;
+    let register = Message::Register { session: session.to_string() }.encode();
+    let mut packet = [0u8; 1500];
+    while Instant::now() < deadline {
+        socket.send_to(&register, server)?;
+        let found = receive_until(socket, &mut packet, Instant::now() + REGISTER_INTERVAL, |message, from, _| match message {
+            Message::PeerAddress { address } if from == server => Some((address, !sender)),
+            Message::Punch { session: theirs, sender: their_sender, .. } if theirs == session => Some((from, their_sender)),
+            _ => None,
+        })?;
+        match found {
+            Some((_, their_sender)) if their_sender == sender => return Err(same_role(sender)),
+            Some((address, _)) => return Ok(address),
+            None => {}
+        }
+    }
+    Err(io::Error::new(
+        ErrorKind::TimedOut,
+        format!("no other client registered session '{}' at {} within {} s", session, server, wait.as_secs()),
+    ))
+}
+
+/// Sends punches to the other client until both have heard from each other, which opens the
+/// mappings of the NATs on either side for the test traffic.
+///
+/// # Arguments
+/// - `peer`: Address the other client was introduced with.
+/// - `sender`: Whether this client sends the test data. The sender waits until the receiver
+///   confirms it heard us; the receiver stops once it heard the sender and keeps answering its
+///   punches while it waits for the data.
+///
+/// # Returns
+/// - The address the other client's punches came from, which is where its NAT lets our traffic
+///   in, even if it differs from the introduced one.
+pub fn punch(socket: &UdpSocket, session: &str, peer: SocketAddr, sender: bool) -> io::Result<SocketAddr> {
+    let deadline = Instant::now() + PUNCH_TIMEOUT;
+    let mut peer = peer;
+    let (mut heard, mut confirmed) = (false, false);
+    let mut next_punch = Instant::now();
+    let mut packet = [0u8; 1500];
+    loop {
+        if heard && (confirmed || !sender) {
+            let punch = Message::Punch { session: session.to_string(), sender, heard: true }.encode();
+            // In case the other client still waits to hear that we heard it
+            for _ in 0..3 {
+                socket.send_to(&punch, peer)?;
+            }
+            return Ok(peer);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "no answer from the other client at {} within {} s; hole punching fails through a NAT that gives every destination its own port (a symmetric NAT)",
+                    peer,
+                    PUNCH_TIMEOUT.as_secs()
+                ),
+            ));
+        }
+        if now >= next_punch {
+            socket.send_to(&Message::Punch { session: session.to_string(), sender, heard }.encode(), peer)?;
+            next_punch += PUNCH_INTERVAL;
+        }
+        let answer = receive_until(socket, &mut packet, next_punch.min(deadline), |message, from, _| match message {
+            Message::Punch { session: theirs, sender: their_sender, heard: they_heard } if theirs == session => {
+                Some((from, their_sender, they_heard))
+            }
+            _ => None,
+        })?;
+        if let Some((from, their_sender, they_heard)) = answer {
+            if their_sender == sender {
+                // So the other client learns it too, instead of waiting for punches that stopped
+                socket.send_to(&Message::Punch { session: session.to_string(), sender, heard: true }.encode(), from)?;
+                return Err(same_role(sender));
+            }
+            peer = from;
+            heard = true;
+            confirmed |= they_heard;
+        }
+    }
+}
+
+fn same_role(sender: bool) -> io::Error {
+    let role = if sender { "send" } else { "receive" };
+    io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("both clients want to {}; run one with --direction upload and the other with --direction download", role),
+    )
+}
+
+/// Sends data packets to the receiving client, paced at `rate_bps` for `duration`, then asks for
+/// its report.
+pub fn send(socket: &UdpSocket, peer: SocketAddr, rate_bps: u64, duration: Duration) -> io::Result<Receipt> {
+    let interval = PACKET_LEN as f64 * 8.0 / rate_bps as f64;
+    let total = ((duration.as_secs_f64() / interval).round() as u64).max(1);
+    let started = Instant::now();
+    let mut sent = 0;
+    while sent < total {
+        // Every packet that is due goes out at once, so the rate holds when the OS sleeps longer
+        // than the interval between packets
+        let due = ((started.elapsed().as_secs_f64() / interval) as u64 + 1).min(total);
+        while sent < due {
+            socket.send_to(&Message::encode_data(sent, PACKET_LEN), peer)?;
+            sent += 1;
+        }
+        thread::sleep((started + Duration::from_secs_f64(sent as f64 * interval)).saturating_duration_since(Instant::now()));
+    }
+
+    let done = Message::Done { packets: sent, rate_bps }.encode();
+    let mut packet = [0u8; 1500];
+    for _ in 0..DONE_ATTEMPTS {
+        socket.send_to(&done, peer)?;
+        let report = receive_until(socket, &mut packet, Instant::now() + DONE_INTERVAL, |message, from, _| match message {
+            Message::Report { packets, bytes, receive_ns } if from == peer => Some(Receipt {
+                packets_sent: sent,
+                packets_received: packets,
+                bytes_received: bytes,
+                receive_seconds: receive_ns as f64 / 1e9,
+                rate_bps: Some(rate_bps),
+            }),
+            _ => None,
+        })?;
+        if let Some(report) = report {
+            return Ok(report);
+        }
+    }
+    Err(io::Error::new(ErrorKind::TimedOut, format!("sent {} packets, but no report came back from {}", sent, peer)))
+}
+
+/// Counts the data packets the sending client sends until it is done, and sends it the count.
+pub fn receive(socket: &UdpSocket, session: &str, peer: SocketAddr) -> io::Result<Receipt> {
+    let heard = Message::Punch { session: session.to_string(), sender: false, heard: true }.encode();
+    let mut packet = vec![0u8; 65536];
+    let (mut packets, mut bytes, mut highest) = (0u64, 0u64, None);
+    let (mut first, mut last) = (None, Instant::now());
+    let mut waiting_since = Instant::now();
+    let (sent, rate_bps) = loop {
+        let message = receive_until(socket, &mut packet, Instant::now() + DONE_INTERVAL, |message, from, len| {
+            (from == peer).then_some((message, len))
+        })?;
+        match message {
+            Some((Message::Data { sequence }, len)) => {
+                let now = Instant::now();
+                first.get_or_insert(now);
+                last = now;
+                packets += 1;
+                bytes += len as u64;
+                highest = highest.max(Some(sequence));
+                waiting_since = now;
+            }
+            // The sender punches until it hears that we heard it
+            Some((Message::Punch { .. }, _)) => {
+                socket.send_to(&heard, peer)?;
+            }
+            Some((Message::Done { packets: sent, rate_bps }, _)) => break (sent, Some(rate_bps)),
+            _ => {}
+        }
+        if waiting_since.elapsed() >= DATA_TIMEOUT {
+            match highest {
+                // Every announcement that the sender is done was lost
+                Some(highest) => break (highest + 1, None),
+                None => return Err(io::Error::new(ErrorKind::TimedOut, format!("no test data arrived from {}", peer))),
+            }
+        }
+    };
+
+    let receive_seconds = first.map_or(0.0, |first| last.duration_since(first).as_secs_f64());
+    let report = Message::Report { packets, bytes, receive_ns: (receive_seconds * 1e9) as u64 }.encode();
+    socket.send_to(&report, peer)?;
+    let linger = Instant::now() + LINGER;
+    while Instant::now() < linger {
+        let done = receive_until(socket, &mut packet, linger, |message, from, _| (from == peer && matches!(message, Message::Done { .. })).then_some(()))?;
+        if done.is_some() {
+            socket.send_to(&report, peer)?;
+        }
+    }
+    Ok(Receipt { packets_sent: sent, packets_received: packets, bytes_received: bytes, receive_seconds, rate_bps })
+}
+
+/// Receives datagrams until `accept` returns a value for one of them, or until `deadline`.
+///
+/// Datagrams that are not of the protocol, and errors that only report an earlier datagram as
+/// undeliverable, are skipped.
+fn receive_until<T>(
+    socket: &UdpSocket,
+    packet: &mut [u8],
+    deadline: Instant,
+    mut accept: impl FnMut(Message, SocketAddr, usize) -> Option<T>,
+) -> io::Result<Option<T>> {
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(left))?;
+        match socket.recv_from(packet) {
+            Ok((len, sender)) => {
+                if let Some(accepted) = Message::decode(&packet[..len]).and_then(|message| accept(message, sender, len)) {
+                    return Ok(Some(accepted));
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+            Err(e) if matches!(e.kind(), ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn print(test: &PeerTest, units: Units) {
+    println!(
+        "Peer-to-peer ({}): {} of {} packets of {} bytes arrived ({:.2}% loss) at {}{}",
+        if test.sender { "sent" } else { "received" },
+        test.packets_received,
+        test.packets_sent,
+        test.packet_bytes,
+        test.loss_percent,
+        units.rate(test.data_rate_bps),
+        test.target_rate_bps.map(|rate| format!(", paced at {}", units.rate(rate))).unwrap_or_default()
+    );
+}
@@ -0,0 +1,203 @@
+use std::io::{self, ErrorKind, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use socket2::{Domain, Protocol, Socket, Type};
+use crate::results::PingSummary;
+use crate::stats::Summary;
+
+/// How long to wait for the reply to a single echo request before counting it as lost.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+const ECHO_REQUEST_V4: u8 = 8;
+pub const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// Bytes of payload carried by each echo request, after the 8-byte ICMP header.
+const PAYLOAD_LEN: usize = 56;
+
+/// Sends ICMP echo requests to one host and times the replies, independently of the test's TCP
+/// connection.
+///
+/// Unprivileged ICMP sockets (`SOCK_DGRAM`, allowed on Linux by `net.ipv4.ping_group_range` and on
+/// macOS) are preferred; raw sockets, which need root or CAP_NET_RAW, are the fallback.
+pub struct Pinger {
+    socket: Socket,
+    ipv6: bool,
+    /// Whether received IPv4 packets still carry their IP header.
+    raw: bool,
+    identifier: u16,
+    sequence: u16,
+}
+
+impl Pinger {
+    /// Opens an ICMP socket towards `target`.
+    pub fn new(target: IpAddr) -> io::Result<Self> {
+        let ipv6 = target.is_ipv6();
+        let (domain, protocol) = if ipv6 { (Domain::IPV6, Protocol::ICMPV6) } else { (Domain::IPV4, Protocol::ICMPV4) };
+        let (socket, raw) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+            Ok(socket) => (socket, false),
+            Err(_) => (Socket::new(domain, Type::RAW, Some(protocol))?, true),
+        };
+        socket.connect(&SocketAddr::new(target, 0).into())?;
+
+        Ok(Pinger { socket, ipv6, raw, identifier: rand::random(), sequence: 0 })
+    }
+
+    /// Sends one echo request and waits for its reply.
+    ///
+    /// # Returns
+    /// - The round-trip time, or `None` if no reply arrived within `timeout`.
+    pub fn ping(&mut self, timeout: Duration) -> io::Result<Option<Duration>> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let sent = Instant::now();
+        self.socket.send(&echo_request(self.ipv6, self.identifier, self.sequence))?;
+
+        let mut packet = [0u8; 1500];
+        loop {
+            let remaining = match timeout.checked_sub(sent.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ => return Ok(None),
+            };
+            self.socket.set_read_timeout(Some(remaining))?;
+            let len = match (&self.socket).read(&mut packet) {
+                Ok(len) => len,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            // Late replies to earlier requests and other ICMP traffic are skipped
+            if self.is_reply(&packet[..len]) {
+                return Ok(Some(sent.elapsed()));
+            }
+        }
+    }
+
+    fn is_reply(&self, packet: &[u8]) -> bool {
+        let icmp = if self.raw && !self.ipv6 {
+            let header_len = usize::from(packet.first().map_or(0, |b| b & 0x0f)) * 4;
+            packet.get(header_len..).unwrap_or_default()
+        } else {
+            packet
+        };
+        if icmp.len() < 8 {
+            return false;
+        }
+        let reply_type = if self.ipv6 { ECHO_REPLY_V6 } else { ECHO_REPLY_V4 };
+        // Unprivileged sockets replace the identifier with their own and only deliver matching replies
+        let identifier_matches = !self.raw || icmp[4..6] == self.identifier.to_be_bytes();
+        icmp[0] == reply_type && identifier_matches && icmp[6..8] == self.sequence.to_be_bytes()
+    }
+}
+
+/// Builds an ICMP or ICMPv6 echo request.
+pub fn echo_request(ipv6: bool, identifier: u16, sequence: u16) -> Vec<u8> {
+    let mut packet = vec![0u8; 8 + PAYLOAD_LEN];
+    packet[0] = if ipv6 { ECHO_REQUEST_V6 } else { ECHO_REQUEST_V4 };
+    packet[4..6].copy_from_slice(&identifier.to_be_bytes());
+    packet[6..8].copy_from_slice(&sequence.to_be_bytes());
+    // The kernel fills in the ICMPv6 checksum, which covers a pseudo-header it alone knows
+    if !ipv6 {
+        let checksum = internet_checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    packet
+}
+
+/// Internet checksum (RFC 1071): the ones' complement of the ones' complement sum of 16-bit words.
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Round-trip times of a series of echo requests.
+#[derive(Default)]
+pub struct PingSeries {
+    pub sent: usize,
+    /// Round-trip time of every answered request, in seconds.
+    pub rtts: Vec<f64>,
+    /// When each answered request was sent.
+    pub sent_at: Vec<Instant>,
+}
+
+impl PingSeries {
+    /// Sends one request and records its outcome; a socket error counts as a lost request.
+    fn record(&mut self, pinger: &mut Pinger) {
+        self.sent += 1;
+        let sent_at = Instant::now();
+        if let Ok(Some(rtt)) = pinger.ping(REPLY_TIMEOUT) {
+            self.rtts.push(rtt.as_secs_f64());
+            self.sent_at.push(sent_at);
+        }
+    }
+
+    pub fn summary(&self) -> PingSummary {
+        let lost = self.sent - self.rtts.len();
+        PingSummary {
+            sent: self.sent,
+            received: self.rtts.len(),
+            loss_percent: if self.sent > 0 { lost as f64 / self.sent as f64 * 100.0 } else { 0.0 },
+            rtt: Summary::of(&self.rtts),
+        }
+    }
+}
+
+/// Sends `count` echo requests, `interval` apart.
+pub fn series(pinger: &mut Pinger, count: usize, interval: Duration) -> PingSeries {
+    let mut series = PingSeries::default();
+    for i in 0..count {
+        let started = Instant::now();
+        series.record(pinger);
+        if i + 1 < count {
+            thread::sleep(interval.saturating_sub(started.elapsed()));
+        }
+    }
+    series
+}
+
+/// Echo requests sent on a background thread while the test runs.
+///
+/// The thread is also stopped when this is dropped, so a run that ends with an error does not leave
+/// it pinging.
+pub struct Background {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<PingSeries>>,
+}
+
+impl Background {
+    /// Starts pinging every `interval` until `finish` is called.
+    pub fn start(mut pinger: Pinger, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut series = PingSeries::default();
+            while !stopped.load(Ordering::Relaxed) {
+                let started = Instant::now();
+                series.record(&mut pinger);
+                thread::sleep(interval.saturating_sub(started.elapsed()));
+            }
+            series
+        });
+        Background { stop, handle: Some(handle) }
+    }
+
+    /// Stops pinging and waits for the thread, which finishes the current request and interval first.
+    pub fn finish(mut self) -> PingSeries {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.take().and_then(|handle| handle.join().ok()).unwrap_or_default()
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
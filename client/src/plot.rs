@@ -0,0 +1,1221 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use clap::ValueEnum;
+use plotters::prelude::*;
+use plotters::style::Palette as _;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use crate::units::Units;
+
+/// What the x axis of the per-sample charts shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum XAxis {
+    /// Sample number, e.g. the chunk index.
+    #[default]
+    Sample,
+    /// Seconds since the start of the run, comparable across chunk sizes and durations.
+    Elapsed,
+    /// UTC time of day, for lining charts up with external monitoring.
+    WallClock,
+}
+
+impl XAxis {
+    /// Returns the x position of each sample.
+    ///
+    /// # Arguments
+    /// - `offsets`: When each sample completed, in seconds from the start of the run.
+    /// - `started_at`: Wall-clock start of the run.
+    pub fn positions(self, offsets: &[f64], started_at: SystemTime) -> Vec<f64> {
+        match self {
+            XAxis::Sample => (1..=offsets.len()).map(|i| i as f64).collect(),
+            XAxis::Elapsed => offsets.to_vec(),
+            XAxis::WallClock => {
+                let start = started_at.duration_since(UNIX_EPOCH).map(|since| since.as_secs_f64()).unwrap_or_default();
+                offsets.iter().map(|offset| start + offset).collect()
+            }
+        }
+    }
+
+    fn desc(self, sample_desc: &str) -> String {
+        match self {
+            XAxis::Sample => sample_desc.to_string(),
+            XAxis::Elapsed => "Elapsed Time (s)".to_string(),
+            XAxis::WallClock => "Time (UTC)".to_string(),
+        }
+    }
+
+    fn label(self, x: f64) -> String {
+        match self {
+            XAxis::Sample => format!("{:.0}", x),
+            XAxis::Elapsed => format!("{:.3}", x),
+            XAxis::WallClock => {
+                let of_day = x.rem_euclid(86_400.0);
+                let (hours, minutes, seconds) = ((of_day / 3600.0) as u32, (of_day % 3600.0 / 60.0) as u32, of_day % 60.0);
+                format!("{:02}:{:02}:{:06.3}", hours, minutes, seconds)
+            }
+        }
+    }
+}
+
+/// The x axis of a per-sample chart.
+pub struct SampleAxis<'a> {
+    pub kind: XAxis,
+    /// Position of each sample, as returned by [`XAxis::positions`].
+    pub positions: &'a [f64],
+    /// Label of the axis when it shows sample numbers, describing what one sample is.
+    pub sample_desc: &'a str,
+}
+
+/// Samples singled out in a per-sample chart.
+#[derive(Clone, Copy)]
+pub struct Marks<'a> {
+    /// Indices of samples to mark with their unsmoothed values.
+    pub outliers: &'a [usize],
+    /// First and last index of each run of samples that overlap other traffic on the interface,
+    /// which are shaded.
+    pub cross_traffic: &'a [(usize, usize)],
+}
+
+/// Background of the charts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Mode {
+    #[default]
+    Light,
+    /// Dark background with light text, for dark-mode dashboards.
+    Dark,
+}
+
+/// Colors of the data series.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Palette {
+    #[default]
+    Default,
+    /// Okabe-Ito colors, distinguishable with the common forms of color blindness.
+    Colorblind,
+}
+
+/// A data series whose color can be overridden.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Series {
+    Latency,
+    DataRate,
+    Outliers,
+    /// Download series of the download vs upload chart.
+    Download,
+    /// Upload series of the download vs upload chart.
+    Upload,
+    PhyRate,
+    Signal,
+    /// Shading of the samples that overlap other traffic on the interface.
+    CrossTraffic,
+    /// Playout buffer level of a simulated stream.
+    Buffer,
+    /// Shading of the stalls of a simulated stream.
+    Rebuffer,
+}
+
+/// A color given for one series on the command line, as `SERIES=#RRGGBB`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorOverride {
+    pub series: Series,
+    pub color: RGBColor,
+}
+
+impl FromStr for ColorOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (series, color) = s.split_once('=').ok_or_else(|| format!("'{}' is not SERIES=#RRGGBB", s))?;
+        let series = Series::from_str(series, true)?;
+        let hex = color.strip_prefix('#').unwrap_or(color);
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|channel| u8::from_str_radix(channel, 16).ok());
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(ColorOverride { series, color: RGBColor(r, g, b) }),
+            _ => Err(format!("'{}' is not a color in #RRGGBB form", color)),
+        }
+    }
+}
+
+/// Chart options shared by the test run and the subcommands that chart saved results.
+#[derive(Clone, Debug, clap::Args)]
+pub struct ChartOptions {
+    /// Background of the charts.
+    #[arg(long, value_name = "MODE", value_enum, default_value_t = Mode::Light)]
+    pub theme: Mode,
+
+    /// Colors of the chart series; `colorblind` uses a palette distinguishable with color blindness.
+    #[arg(long, value_name = "PALETTE", value_enum, default_value_t = Palette::Default)]
+    pub palette: Palette,
+
+    /// Size of chart labels and legends in points; titles and axis descriptions scale with it.
+    #[arg(long, value_name = "POINTS", default_value_t = 12, value_parser = clap::value_parser!(u32).range(6..=48))]
+    pub font_size: u32,
+
+    /// Override the color of one chart series, e.g. `data-rate=#ff8800`; series are latency,
+    /// data-rate, outliers, download, upload, phy-rate, and signal.
+    #[arg(long, value_name = "SERIES=#RRGGBB")]
+    pub color: Vec<ColorOverride>,
+
+    /// Size of the PNG charts in pixels.
+    #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "1280x960", value_parser = parse_chart_size)]
+    pub chart_size: (u32, u32),
+
+    /// Number of samples each point of the latency and data rate lines averages; 1 draws the
+    /// samples as measured. Defaults to 5, or fewer for short series.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub smoothing: Option<u32>,
+}
+
+impl ChartOptions {
+    /// The theme these options describe, with data rates in `units`.
+    pub fn theme(&self, units: Units) -> Theme {
+        Theme {
+            size: self.chart_size,
+            smoothing: self.smoothing.map(|n| n as usize),
+            ..Theme::new(self.theme, self.palette, self.font_size, &self.color, units)
+        }
+    }
+}
+
+fn parse_chart_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s.split_once('x').ok_or_else(|| format!("'{}' is not WIDTHxHEIGHT", s))?;
+    match (width.parse::<u32>(), height.parse::<u32>()) {
+        (Ok(width), Ok(height)) if (320..=8192).contains(&width) && (240..=8192).contains(&height) => Ok((width, height)),
+        (Ok(_), Ok(_)) => Err(format!("'{}' is outside 320x240 to 8192x8192", s)),
+        _ => Err(format!("'{}' is not WIDTHxHEIGHT in pixels", s)),
+    }
+}
+
+/// Colors and font sizes of the charts.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub mode: Mode,
+    pub background: RGBColor,
+    pub foreground: RGBColor,
+    /// Size of tick labels and legends in points; titles and axis descriptions are scaled from it.
+    pub font_size: u32,
+    /// Units of the data rate axes.
+    pub units: Units,
+    /// Width and height of the PNG charts in pixels.
+    pub size: (u32, u32),
+    /// Samples averaged into each point of the latency and data rate lines, or `None` for the
+    /// default of up to 5.
+    pub smoothing: Option<usize>,
+    overrides: Vec<ColorOverride>,
+    palette: Palette,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::new(Mode::Light, Palette::Default, 12, &[], Units::Si)
+    }
+}
+
+impl Theme {
+    pub fn new(mode: Mode, palette: Palette, font_size: u32, overrides: &[ColorOverride], units: Units) -> Self {
+        let (background, foreground) = match mode {
+            Mode::Light => (WHITE, BLACK),
+            Mode::Dark => (RGBColor(30, 30, 30), RGBColor(220, 220, 220)),
+        };
+        Theme {
+            mode,
+            background,
+            foreground,
+            font_size,
+            units,
+            size: (1280, 960),
+            smoothing: None,
+            overrides: overrides.to_vec(),
+            palette,
+        }
+    }
+
+    /// Returns the color of a series, taking overrides into account.
+    pub fn color(&self, series: Series) -> RGBColor {
+        if let Some(color) = self.overrides.iter().rev().find(|color| color.series == series) {
+            return color.color;
+        }
+        match (self.palette, series) {
+            (_, Series::Outliers) => self.foreground,
+            (Palette::Colorblind, Series::Latency) => RGBColor(213, 94, 0),
+            (Palette::Colorblind, Series::DataRate | Series::Download) => RGBColor(0, 114, 178),
+            (Palette::Colorblind, Series::Upload) => RGBColor(230, 159, 0),
+            (Palette::Colorblind, Series::PhyRate) => RGBColor(0, 158, 115),
+            (Palette::Colorblind, Series::Signal) => RGBColor(204, 121, 167),
+            (Palette::Colorblind, Series::CrossTraffic) => RGBColor(240, 228, 66),
+            (Palette::Colorblind, Series::Buffer) => RGBColor(86, 180, 233),
+            (Palette::Colorblind, Series::Rebuffer) => RGBColor(213, 94, 0),
+            // Pure colors are too dark to read on a dark background, so it gets lighter shades
+            (Palette::Default, Series::Latency | Series::Signal) => self.shade(RED, RGBColor(255, 107, 107)),
+            (Palette::Default, Series::DataRate | Series::Download) => self.shade(BLUE, RGBColor(77, 171, 247)),
+            (Palette::Default, Series::Upload | Series::PhyRate) => self.shade(GREEN, RGBColor(105, 219, 124)),
+            (Palette::Default, Series::CrossTraffic) => self.shade(RGBColor(255, 165, 0), RGBColor(255, 192, 77)),
+            (Palette::Default, Series::Buffer) => self.shade(BLUE, RGBColor(77, 171, 247)),
+            (Palette::Default, Series::Rebuffer) => self.shade(RED, RGBColor(255, 107, 107)),
+        }
+    }
+
+    /// Returns the color of the `i`th of several overlaid runs.
+    pub fn run_color(&self, i: usize) -> RGBColor {
+        // Okabe-Ito without black and yellow, which disappear on one background or the other
+        const COLORBLIND: [RGBColor; 6] = [
+            RGBColor(0, 114, 178),
+            RGBColor(213, 94, 0),
+            RGBColor(0, 158, 115),
+            RGBColor(204, 121, 167),
+            RGBColor(86, 180, 233),
+            RGBColor(230, 159, 0),
+        ];
+        match self.palette {
+            Palette::Colorblind => COLORBLIND[i % COLORBLIND.len()],
+            Palette::Default => {
+                let RGBAColor(r, g, b, _) = Palette99::pick(i).to_rgba();
+                RGBColor(r, g, b)
+            }
+        }
+    }
+
+    fn shade(&self, light: RGBColor, dark: RGBColor) -> RGBColor {
+        match self.mode {
+            Mode::Light => light,
+            Mode::Dark => dark,
+        }
+    }
+
+    fn caption(&self) -> TextStyle<'static> {
+        ("sans-serif", self.font_size * 2).into_font().color(&self.foreground)
+    }
+
+    fn axis_desc(&self) -> TextStyle<'static> {
+        ("sans-serif", self.font_size + 2).into_font().color(&self.foreground)
+    }
+
+    fn label(&self) -> TextStyle<'static> {
+        ("sans-serif", self.font_size).into_font().color(&self.foreground)
+    }
+}
+
+/// Draws the smoothed latency and effective data rate series, with their averages, into a PNG chart.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `latencies`: Download time of each sample, in seconds.
+/// - `data_rates`: Effective data rate of each sample, in bits per second.
+/// - `marks`: Outliers and samples with other traffic on the interface.
+/// - `x`: Where each sample goes on the x axis.
+/// - `theme`: Colors and font sizes.
+pub fn plot_latency_and_data_rate(
+    path: &Path,
+    latencies: &[f64],
+    data_rates: &[f64],
+    marks: &Marks,
+    x: &SampleAxis,
+    theme: &Theme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Marks { outliers, cross_traffic } = *marks;
+    let latency_color = theme.color(Series::Latency);
+    let data_rate_color = theme.color(Series::DataRate);
+    let outlier_color = theme.color(Series::Outliers);
+    let cross_traffic_color = theme.color(Series::CrossTraffic);
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+
+    let areas = root.split_evenly((2, 1));
+
+    let avg_latency = latencies.iter().sum::<f64>() / latencies.len() as f64;
+    let avg_data_rate = data_rates.iter().sum::<f64>() / data_rates.len() as f64;
+
+    // Short series (e.g. a soak test with only a few summary windows) are smoothed over fewer points
+    let smoothing = theme.smoothing.unwrap_or(5).min(latencies.len()).max(1);
+    let smoothed = if smoothing > 1 { " (Smoothed)" } else { "" };
+    let smoothed_latencies: Vec<f64> = latencies.windows(smoothing).map(|w| w.iter().sum::<f64>() / w.len() as f64).collect();
+    let smoothed_data_rates: Vec<f64> = data_rates.windows(smoothing).map(|w| w.iter().sum::<f64>() / w.len() as f64).collect();
+
+    // Each smoothed value is drawn at the first sample of its window; the axis spans every sample
+    // so that outliers in the tail the smoothing window does not cover can be drawn where they are
+    // Elapsed time starts at zero, so runs of different lengths line up
+    let (x_axis, sample_desc, x) = (x.kind, x.sample_desc, x.positions);
+    let x_start = if x_axis == XAxis::Elapsed { 0.0 } else { x[0] };
+    let x_end = if x[x.len() - 1] > x_start { x[x.len() - 1] } else { x_start + 1.0 };
+    let x_desc = x_axis.desc(sample_desc);
+    let x_label = |value: &f64| x_axis.label(*value);
+
+    // Outliers are drawn at their raw value, so the latency axis must reach them
+    let max_latency = outliers.iter().map(|&i| latencies[i]).fold(smoothed_latencies.iter().cloned().fold(f64::NAN, f64::max), f64::max);
+    let max_data_rate = avg_data_rate * 2.0;
+    // A sample spans from the end of the one before it
+    let shaded: Vec<(f64, f64)> = cross_traffic.iter().map(|&(first, last)| (if first == 0 { x_start } else { x[first - 1] }, x[last])).collect();
+
+    let mut latency_chart = ChartBuilder::on(&areas[0])
+        .caption(format!("Latency per Download{}", smoothed), theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 5)
+        .build_cartesian_2d(x_start..x_end, 0.0..max_latency)?;
+    
+    latency_chart.configure_mesh()
+        .x_desc(&x_desc)
+        .y_desc("Latency (s)")
+        .x_label_formatter(&x_label)
+        .y_label_formatter(&|y| format!("{:.5}", y))
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .bold_line_style(theme.foreground.mix(0.2))
+        .light_line_style(theme.background.mix(0.7))
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    // Shaded first, so the series are drawn over it
+    if !shaded.is_empty() {
+        latency_chart.draw_series(shaded.iter().map(|&(left, right)| Rectangle::new([(left, 0.0), (right, max_latency)], cross_traffic_color.mix(0.25).filled())))?
+        .label("Other traffic on the interface")
+        .legend(move |(x, y)| Rectangle::new([(x - 5, y - 4), (x + 5, y + 4)], cross_traffic_color.mix(0.5).filled()));
+    }
+    
+    latency_chart.draw_series(LineSeries::new(
+        x.iter().cloned().zip(smoothed_latencies.iter().cloned()),
+        &latency_color,
+    ))?
+    .label(format!("Latency (s){}", smoothed))
+    .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], latency_color));
+
+    latency_chart.draw_series(std::iter::once(PathElement::new(
+        [(x_start, avg_latency), (x_end, avg_latency)], 
+        latency_color.mix(0.5).stroke_width(2)
+    )))?
+    .label(format!("Avg Latency: {:.5} s", avg_latency))
+    .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], latency_color.mix(0.5)));
+
+    if !outliers.is_empty() {
+        latency_chart.draw_series(outliers.iter().map(|&i| Circle::new((x[i], latencies[i]), 5, outlier_color.stroke_width(2))))?
+        .label(format!("Outliers ({})", outliers.len()))
+        .legend(move |(x, y)| Circle::new((x, y), 4, outlier_color.stroke_width(2)));
+    }
+
+    latency_chart.configure_series_labels()
+        .border_style(theme.foreground)
+        .background_style(theme.background.mix(0.8))
+        .label_font(theme.label())
+        .draw()?;
+
+    let mut data_rate_chart = ChartBuilder::on(&areas[1])
+        .caption(format!("Effective Data Rate per Download{}", smoothed), theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 5)
+        .build_cartesian_2d(x_start..x_end, 0.0..max_data_rate)?;
+    
+    data_rate_chart.configure_mesh()
+        .x_desc(&x_desc)
+        .y_desc(format!("Data Rate ({})", theme.units.rate_unit()))
+        .x_label_formatter(&x_label)
+        .y_label_formatter(&|y| format!("{:.1}", theme.units.rate_value(*y)))
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .bold_line_style(theme.foreground.mix(0.2))
+        .light_line_style(theme.background.mix(0.7))
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    if !shaded.is_empty() {
+        data_rate_chart.draw_series(shaded.iter().map(|&(left, right)| Rectangle::new([(left, 0.0), (right, max_data_rate)], cross_traffic_color.mix(0.25).filled())))?
+        .label("Other traffic on the interface")
+        .legend(move |(x, y)| Rectangle::new([(x - 5, y - 4), (x + 5, y + 4)], cross_traffic_color.mix(0.5).filled()));
+    }
+    
+    data_rate_chart.draw_series(LineSeries::new(
+        x.iter().cloned().zip(smoothed_data_rates.iter().cloned()),
+        &data_rate_color,
+    ))?
+    .label(format!("Effective Data Rate ({}){}", theme.units.rate_unit(), smoothed))
+    .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], data_rate_color));
+    
+    data_rate_chart.draw_series(std::iter::once(PathElement::new(
+        [(x_start, avg_data_rate), (x_end, avg_data_rate)], 
+        data_rate_color.mix(0.5).stroke_width(2)
+    )))?
+    .label(format!("Avg Data Rate: {}", theme.units.rate(avg_data_rate)))
+    .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], data_rate_color.mix(0.5)));
+
+    if !outliers.is_empty() {
+        data_rate_chart.draw_series(
+            outliers.iter().map(|&i| Circle::new((x[i], data_rates[i].min(max_data_rate)), 5, outlier_color.stroke_width(2))),
+        )?
+        .label(format!("Outliers ({})", outliers.len()))
+        .legend(move |(x, y)| Circle::new((x, y), 4, outlier_color.stroke_width(2)));
+    }
+
+    data_rate_chart.configure_series_labels()
+        .border_style(theme.foreground)
+        .background_style(theme.background.mix(0.8))
+        .label_font(theme.label())
+        .draw()?;
+
+    println!("Refined Latency and Effective Data Rate chart saved as {}", path.display());
+
+    Ok(())
+}
+
+/// Draws the download and upload series of a run in both directions side by side, one chart for the
+/// effective data rate and one for the latency, into a PNG chart.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `download`: Latencies (s) and effective data rates (bps) of the download samples.
+/// - `upload`: Latencies (s) and effective data rates (bps) of the upload samples.
+/// - `theme`: Colors and font sizes.
+pub fn plot_asymmetry(
+    path: &Path,
+    download: (&[f64], &[f64]),
+    upload: (&[f64], &[f64]),
+    theme: &Theme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let download_color = theme.color(Series::Download);
+    let upload_color = theme.color(Series::Upload);
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+
+    let areas = root.split_evenly((2, 1));
+    let samples = download.0.len().max(upload.0.len()).max(2) as i32;
+    let max = |a: &[f64], b: &[f64]| a.iter().chain(b).cloned().fold(0.0, f64::max);
+
+    let mut data_rate_chart = ChartBuilder::on(&areas[0])
+        .caption("Effective Data Rate: Download vs Upload", theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 5)
+        .build_cartesian_2d(1..samples, 0.0..max(download.1, upload.1) * 1.1)?;
+
+    data_rate_chart.configure_mesh()
+        .x_desc("Sample")
+        .y_desc(format!("Data Rate ({})", theme.units.rate_unit()))
+        .y_label_formatter(&|y| format!("{:.1}", theme.units.rate_value(*y)))
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .bold_line_style(theme.foreground.mix(0.2))
+        .light_line_style(theme.background.mix(0.7))
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    data_rate_chart.draw_series(LineSeries::new((1..).zip(download.1.iter().cloned()), &download_color))?
+        .label("Download")
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], download_color));
+    data_rate_chart.draw_series(LineSeries::new((1..).zip(upload.1.iter().cloned()), &upload_color))?
+        .label("Upload")
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], upload_color));
+
+    data_rate_chart.configure_series_labels()
+        .border_style(theme.foreground)
+        .background_style(theme.background.mix(0.8))
+        .label_font(theme.label())
+        .draw()?;
+
+    let mut latency_chart = ChartBuilder::on(&areas[1])
+        .caption("Latency: Download vs Upload", theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 5)
+        .build_cartesian_2d(1..samples, 0.0..max(download.0, upload.0) * 1.1)?;
+
+    latency_chart.configure_mesh()
+        .x_desc("Sample")
+        .y_desc("Latency (s)")
+        .y_label_formatter(&|y| format!("{:.5}", y))
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .bold_line_style(theme.foreground.mix(0.2))
+        .light_line_style(theme.background.mix(0.7))
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    latency_chart.draw_series(LineSeries::new((1..).zip(download.0.iter().cloned()), &download_color))?
+        .label("Download")
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], download_color));
+    latency_chart.draw_series(LineSeries::new((1..).zip(upload.0.iter().cloned()), &upload_color))?
+        .label("Upload")
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], upload_color));
+
+    latency_chart.configure_series_labels()
+        .border_style(theme.foreground)
+        .background_style(theme.background.mix(0.8))
+        .label_font(theme.label())
+        .draw()?;
+
+    println!("Download vs upload chart saved as {}", path.display());
+
+    Ok(())
+}
+
+/// Draws the measured data rate against the Wi-Fi PHY rate, and the signal strength below it, over
+/// the time of the run into a PNG chart, so throughput dips can be matched with rate control decisions.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `measured`: Offset (s) and effective data rate (in the theme's units) of each transfer sample.
+/// - `phy_rate`: Offset (s) and PHY rate (in the theme's units) of each link sample.
+/// - `signal`: Offset (s) and signal strength (dBm) of each link sample.
+/// - `theme`: Colors and font sizes.
+pub fn plot_wifi(
+    path: &Path,
+    measured: &[(f64, f64)],
+    phy_rate: &[(f64, f64)],
+    signal: &[(f64, f64)],
+    theme: &Theme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let data_rate_color = theme.color(Series::DataRate);
+    let phy_rate_color = theme.color(Series::PhyRate);
+    let signal_color = theme.color(Series::Signal);
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+
+    let areas = root.split_evenly((2, 1));
+    let end = measured.iter().chain(phy_rate).chain(signal).map(|&(offset, _)| offset).fold(1.0, f64::max);
+    let max_rate = measured.iter().chain(phy_rate).map(|&(_, rate)| rate).fold(1.0, f64::max) * 1.1;
+
+    let mut rate_chart = ChartBuilder::on(&areas[0])
+        .caption("Effective Data Rate vs Wi-Fi PHY Rate", theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 5)
+        .build_cartesian_2d(0.0..end, 0.0..max_rate)?;
+
+    rate_chart.configure_mesh()
+        .x_desc("Time (s)")
+        .y_desc(format!("Rate ({})", theme.units.rate_unit()))
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .bold_line_style(theme.foreground.mix(0.2))
+        .light_line_style(theme.background.mix(0.7))
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    rate_chart.draw_series(LineSeries::new(measured.iter().cloned(), &data_rate_color))?
+        .label("Effective Data Rate")
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], data_rate_color));
+    rate_chart.draw_series(LineSeries::new(phy_rate.iter().cloned(), &phy_rate_color))?
+        .label("PHY Rate")
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], phy_rate_color));
+
+    rate_chart.configure_series_labels()
+        .border_style(theme.foreground)
+        .background_style(theme.background.mix(0.8))
+        .label_font(theme.label())
+        .draw()?;
+
+    // Signal strength is negative; the axis spans from a little below the weakest to a little above the strongest
+    let weakest = signal.iter().map(|&(_, dbm)| dbm).fold(f64::INFINITY, f64::min);
+    let strongest = signal.iter().map(|&(_, dbm)| dbm).fold(f64::NEG_INFINITY, f64::max);
+    let signal_range = if signal.is_empty() { -100.0..0.0 } else { weakest - 5.0..strongest + 5.0 };
+
+    let mut signal_chart = ChartBuilder::on(&areas[1])
+        .caption("Wi-Fi Signal Strength", theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 5)
+        .build_cartesian_2d(0.0..end, signal_range)?;
+
+    signal_chart.configure_mesh()
+        .x_desc("Time (s)")
+        .y_desc("Signal (dBm)")
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .bold_line_style(theme.foreground.mix(0.2))
+        .light_line_style(theme.background.mix(0.7))
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    signal_chart.draw_series(LineSeries::new(signal.iter().cloned(), &signal_color))?
+        .label("Signal")
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], signal_color));
+
+    signal_chart.configure_series_labels()
+        .border_style(theme.foreground)
+        .background_style(theme.background.mix(0.8))
+        .label_font(theme.label())
+        .draw()?;
+
+    println!("Wi-Fi link chart saved as {}", path.display());
+
+    Ok(())
+}
+
+/// Plots the playout buffer of a simulated stream over the run, with its stalls shaded.
+///
+/// # Arguments
+/// - `levels`: Offsets (s) and buffer levels (s of media).
+/// - `rebuffers`: Start and end offsets (s) of the stalls.
+/// - `startup`: Media time buffered before playback starts or resumes, in seconds.
+/// - `capacity`: Media time the buffer holds at most, in seconds.
+pub fn plot_playout_buffer(
+    path: &Path,
+    levels: &[(f64, f64)],
+    rebuffers: &[(f64, f64)],
+    startup: f64,
+    capacity: f64,
+    theme: &Theme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let buffer_color = theme.color(Series::Buffer);
+    let rebuffer_color = theme.color(Series::Rebuffer);
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+
+    let end = levels.iter().map(|&(offset, _)| offset).fold(1.0, f64::max);
+    let top = capacity * 1.1;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Playout Buffer", theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 5)
+        .build_cartesian_2d(0.0..end, 0.0..top)?;
+
+    chart.configure_mesh()
+        .x_desc("Time (s)")
+        .y_desc("Buffered Media (s)")
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .bold_line_style(theme.foreground.mix(0.2))
+        .light_line_style(theme.background.mix(0.7))
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    if !rebuffers.is_empty() {
+        chart.draw_series(rebuffers.iter().map(|&(start, stop)| Rectangle::new([(start, 0.0), (stop, top)], rebuffer_color.mix(0.25).filled())))?
+            .label("Rebuffering")
+            .legend(move |(x, y)| Rectangle::new([(x - 5, y - 4), (x + 5, y + 4)], rebuffer_color.mix(0.5).filled()));
+    }
+    chart.draw_series(LineSeries::new(levels.iter().cloned(), &buffer_color))?
+        .label("Buffer")
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], buffer_color));
+    let foreground = theme.foreground;
+    chart.draw_series(std::iter::once(PathElement::new([(0.0, startup), (end, startup)], foreground.mix(0.5))))?
+        .label(format!("Startup Threshold ({:.1} s)", startup))
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], foreground.mix(0.5)));
+
+    chart.configure_series_labels()
+        .border_style(theme.foreground)
+        .background_style(theme.background.mix(0.8))
+        .label_font(theme.label())
+        .draw()?;
+
+    println!("Playout buffer chart saved as {}", path.display());
+
+    Ok(())
+}
+
+/// Draws the round-trip time of each ping against the data rate at the time it was sent into a PNG
+/// scatter chart, colored from early (data rate color) to late (latency color) in the run.
+///
+/// A link without queueing problems shows a flat band of points; bufferbloat shows as latency
+/// climbing while throughput stays put, congestion collapse as points moving to high latency and
+/// low throughput late in the run.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `points`: Offset (s), data rate (in the theme's units), and round-trip time (ms) of each ping.
+/// - `theme`: Colors and font sizes.
+pub fn plot_latency_vs_throughput(path: &Path, points: &[(f64, f64, f64)], theme: &Theme) -> Result<(), Box<dyn std::error::Error>> {
+    let early = theme.color(Series::DataRate);
+    let late = theme.color(Series::Latency);
+    let end = points.iter().map(|&(offset, _, _)| offset).fold(0.0, f64::max);
+    let at = move |offset: f64| {
+        let t = if end > 0.0 { offset / end } else { 0.0 };
+        let mix = |from: u8, to: u8| (f64::from(from) + (f64::from(to) - f64::from(from)) * t).round() as u8;
+        RGBColor(mix(early.0, late.0), mix(early.1, late.1), mix(early.2, late.2))
+    };
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+
+    // Scaled to the data, as round-trip times on a LAN are well below a millisecond
+    let max = |values: &mut dyn Iterator<Item = f64>| match values.fold(0.0, f64::max) {
+        max if max > 0.0 => max * 1.1,
+        _ => 1.0,
+    };
+    let max_rate = max(&mut points.iter().map(|&(_, rate, _)| rate));
+    let max_rtt = max(&mut points.iter().map(|&(_, _, rtt)| rtt));
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Latency vs Throughput", theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 5)
+        .build_cartesian_2d(0.0..max_rate, 0.0..max_rtt)?;
+
+    chart.configure_mesh()
+        .x_desc(format!("Data Rate ({})", theme.units.rate_unit()))
+        .y_desc("Round-Trip Time (ms)")
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .bold_line_style(theme.foreground.mix(0.2))
+        .light_line_style(theme.background.mix(0.7))
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    chart.draw_series(points.iter().map(|&(offset, rate, rtt)| Circle::new((rate, rtt), 4, at(offset).filled())))?;
+    // The gradient is explained by its two ends
+    chart.draw_series(std::iter::empty::<Circle<(f64, f64), i32>>())?
+        .label("Start of run")
+        .legend(move |(x, y)| Circle::new((x, y), 4, early.filled()));
+    chart.draw_series(std::iter::empty::<Circle<(f64, f64), i32>>())?
+        .label(format!("End of run ({:.1}s)", end))
+        .legend(move |(x, y)| Circle::new((x, y), 4, late.filled()));
+
+    chart.configure_series_labels()
+        .border_style(theme.foreground)
+        .background_style(theme.background.mix(0.8))
+        .label_font(theme.label())
+        .draw()?;
+
+    println!("Latency vs throughput chart saved as {}", path.display());
+
+    Ok(())
+}
+
+/// One point of a trend chart: a run, or the average of the runs of a day, week, or hour.
+#[derive(Clone, Copy, Debug)]
+pub struct TrendPoint {
+    /// Days since the Unix epoch, or the hour of day (UTC) in the hourly chart.
+    pub at: f64,
+    /// Effective data rate, in bits per second.
+    pub data_rate: f64,
+    /// Average transfer time, in seconds.
+    pub latency: f64,
+}
+
+/// What the x axis of a trend chart shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrendAxis {
+    /// Dates, with points at days since the Unix epoch.
+    Date,
+    /// Hours of the day in UTC, 0 to 23.
+    HourOfDay,
+}
+
+/// Formats the values of a chart axis.
+type AxisLabel<'a> = Box<dyn Fn(&f64) -> String + 'a>;
+
+/// A server in the server comparison chart: its name, data rate (bps), and average transfer time (s).
+pub type ServerBar<'a> = (&'a str, f64, f64);
+
+/// Draws the data rate and latency of many runs over time into a PNG chart: every run as a dot and
+/// the averages of its period as a line, so a slow degradation stands out from run-to-run noise.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `title`: What the chart covers, e.g. the job name; prefixes the captions.
+/// - `runs`: The individual runs, drawn as dots; may be empty.
+/// - `averages`: Averages per period, drawn as a line, labeled `average_label` in the legend.
+/// - `axis`: Whether the points are placed by date or hour of day.
+/// - `theme`: Colors and font sizes.
+pub fn plot_trend(
+    path: &Path,
+    title: &str,
+    runs: &[TrendPoint],
+    averages: (&[TrendPoint], &str),
+    axis: TrendAxis,
+    theme: &Theme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (averages, average_label) = averages;
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+    let areas = root.split_evenly((2, 1));
+
+    let points = || runs.iter().chain(averages);
+    let (x_start, x_end) = match axis {
+        TrendAxis::Date => {
+            let first = points().map(|point| point.at).fold(f64::INFINITY, f64::min).floor();
+            let last = points().map(|point| point.at).fold(f64::NEG_INFINITY, f64::max).ceil();
+            (first, last.max(first + 1.0))
+        }
+        TrendAxis::HourOfDay => (0.0, 23.0),
+    };
+    let (x_desc, x_label): (&str, AxisLabel) = match axis {
+        TrendAxis::Date => ("Date (UTC)", Box::new(|day: &f64| date_of_day(*day))),
+        TrendAxis::HourOfDay => ("Hour of Day (UTC)", Box::new(|hour: &f64| format!("{:02}:00", *hour as u32))),
+    };
+    let max = |value: fn(&TrendPoint) -> f64| match points().map(value).fold(0.0, f64::max) {
+        max if max > 0.0 => max * 1.1,
+        _ => 1.0,
+    };
+
+    for (area, series) in areas.iter().zip([Series::DataRate, Series::Latency]) {
+        let (name, y_desc, value, y_label): (_, _, fn(&TrendPoint) -> f64, AxisLabel) = match series {
+            Series::DataRate => (
+                "Effective Data Rate",
+                format!("Data Rate ({})", theme.units.rate_unit()),
+                |point| point.data_rate,
+                Box::new(|y: &f64| format!("{:.1}", theme.units.rate_value(*y))),
+            ),
+            _ => ("Latency", "Avg Transfer Time (ms)".to_string(), |point| point.latency, Box::new(|y: &f64| format!("{:.3}", y * 1000.0))),
+        };
+        let color = theme.color(series);
+        let mut chart = ChartBuilder::on(area)
+            .caption(format!("{}: {}", title, name), theme.caption())
+            .margin(10)
+            .x_label_area_size(theme.font_size * 10 / 3)
+            .y_label_area_size(theme.font_size * 5)
+            .build_cartesian_2d(x_start..x_end, 0.0..max(value))?;
+
+        chart.configure_mesh()
+            .x_desc(x_desc)
+            .y_desc(y_desc)
+            .x_label_formatter(&x_label)
+            .y_label_formatter(&y_label)
+            .axis_desc_style(theme.axis_desc())
+            .label_style(theme.label())
+            .bold_line_style(theme.foreground.mix(0.2))
+            .light_line_style(theme.background.mix(0.7))
+            .axis_style(theme.foreground)
+            .draw()?;
+
+        if !runs.is_empty() {
+            chart.draw_series(runs.iter().map(|run| Circle::new((run.at, value(run)), 2, color.mix(0.4).filled())))?
+                .label("Runs")
+                .legend(move |(x, y)| Circle::new((x, y), 2, color.mix(0.4).filled()));
+        }
+        chart.draw_series(LineSeries::new(averages.iter().map(|average| (average.at, value(average))), color.stroke_width(2)))?
+            .label(average_label)
+            .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], color.stroke_width(2)));
+        chart.draw_series(averages.iter().map(|average| Circle::new((average.at, value(average)), 4, color.filled())))?;
+
+        chart.configure_series_labels()
+            .border_style(theme.foreground)
+            .background_style(theme.background.mix(0.8))
+            .label_font(theme.label())
+            .draw()?;
+    }
+
+    println!("{} trend chart saved as {}", title, path.display());
+
+    Ok(())
+}
+
+/// Formats days since the Unix epoch as a date, e.g. `2026-10-15`.
+pub fn date_of_day(day: f64) -> String {
+    let time = UNIX_EPOCH + std::time::Duration::from_secs_f64(day.max(0.0) * 86_400.0);
+    humantime::format_rfc3339_seconds(time).to_string()[..10].to_string()
+}
+
+/// Draws the effective data rate and average transfer time of several servers as bars, one per
+/// server, into a PNG chart.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `servers`: The servers, in the order drawn.
+/// - `theme`: Colors and font sizes.
+pub fn plot_servers(path: &Path, servers: &[ServerBar], theme: &Theme) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+    let areas = root.split_evenly((2, 1));
+    let n = servers.len() as i32;
+    let x_label = |x: &SegmentValue<i32>| match x {
+        SegmentValue::CenterOf(i) => servers.get(*i as usize).map_or(String::new(), |server| server.0.to_string()),
+        _ => String::new(),
+    };
+
+    for (area, series) in areas.iter().zip([Series::DataRate, Series::Latency]) {
+        let (name, y_desc, value, y_label): (_, _, fn(&ServerBar) -> f64, AxisLabel) = match series {
+            Series::DataRate => (
+                "Effective Data Rate",
+                format!("Data Rate ({})", theme.units.rate_unit()),
+                |server| server.1,
+                Box::new(|y: &f64| format!("{:.1}", theme.units.rate_value(*y))),
+            ),
+            _ => ("Avg Transfer Time", "Avg Transfer Time (ms)".to_string(), |server| server.2, Box::new(|y: &f64| format!("{:.3}", y * 1000.0))),
+        };
+        let max = match servers.iter().map(value).fold(0.0, f64::max) {
+            max if max > 0.0 => max * 1.1,
+            _ => 1.0,
+        };
+        let color = theme.color(series);
+        let mut chart = ChartBuilder::on(area)
+            .caption(format!("{} by Server", name), theme.caption())
+            .margin(10)
+            .x_label_area_size(theme.font_size * 10 / 3)
+            .y_label_area_size(theme.font_size * 5)
+            .build_cartesian_2d((0..n - 1).into_segmented(), 0.0..max)?;
+
+        chart.configure_mesh()
+            .disable_x_mesh()
+            .x_desc("Server")
+            .y_desc(y_desc)
+            .x_labels(servers.len())
+            .x_label_formatter(&x_label)
+            .y_label_formatter(&y_label)
+            .axis_desc_style(theme.axis_desc())
+            .label_style(theme.label())
+            .bold_line_style(theme.foreground.mix(0.2))
+            .light_line_style(theme.background.mix(0.7))
+            .axis_style(theme.foreground)
+            .draw()?;
+
+        chart.draw_series(servers.iter().zip(0..).map(|(server, i)| {
+            let mut bar = Rectangle::new([(SegmentValue::Exact(i), 0.0), (SegmentValue::Exact(i + 1), value(server))], color.filled());
+            bar.set_margin(0, 0, 20, 20);
+            bar
+        }))?;
+    }
+
+    println!("Server comparison chart saved as {}", path.display());
+
+    Ok(())
+}
+
+/// Draws how the latencies and effective data rates of a run are distributed, as histograms with
+/// their averages, into a PNG chart.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `latencies`: Download time of each sample, in seconds.
+/// - `data_rates`: Effective data rate of each sample, in bits per second.
+/// - `bins`: Number of bars in each histogram.
+/// - `theme`: Colors and font sizes.
+pub fn plot_histograms(path: &Path, latencies: &[f64], data_rates: &[f64], bins: usize, theme: &Theme) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+    let areas = root.split_evenly((2, 1));
+    let bins = bins.max(1);
+
+    for (area, series) in areas.iter().zip([Series::Latency, Series::DataRate]) {
+        let (name, x_desc, samples, x_label): (_, _, _, AxisLabel) = match series {
+            Series::DataRate => (
+                "Effective Data Rate",
+                format!("Data Rate ({})", theme.units.rate_unit()),
+                data_rates,
+                Box::new(|x: &f64| format!("{:.1}", theme.units.rate_value(*x))),
+            ),
+            _ => ("Latency", "Latency (ms)".to_string(), latencies, Box::new(|x: &f64| format!("{:.3}", x * 1000.0))),
+        };
+        let min = samples.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = samples.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        // All samples equal would give zero-width bars
+        let width = if max > min { (max - min) / bins as f64 } else { 1.0 };
+        let mut counts = vec![0u32; bins];
+        for sample in samples {
+            counts[(((sample - min) / width) as usize).min(bins - 1)] += 1;
+        }
+        let highest = counts.iter().copied().max().unwrap_or(0).max(1);
+        let average = samples.iter().sum::<f64>() / samples.len() as f64;
+        let (color, foreground) = (theme.color(series), theme.foreground);
+
+        let mut chart = ChartBuilder::on(area)
+            .caption(format!("{} Distribution", name), theme.caption())
+            .margin(10)
+            .x_label_area_size(theme.font_size * 10 / 3)
+            .y_label_area_size(theme.font_size * 5)
+            .build_cartesian_2d(min..min + width * bins as f64, 0.0..f64::from(highest) * 1.1)?;
+
+        chart.configure_mesh()
+            .disable_x_mesh()
+            .x_desc(x_desc)
+            .y_desc("Samples")
+            .x_label_formatter(&x_label)
+            .y_label_formatter(&|y| format!("{:.0}", y))
+            .axis_desc_style(theme.axis_desc())
+            .label_style(theme.label())
+            .bold_line_style(theme.foreground.mix(0.2))
+            .light_line_style(theme.background.mix(0.7))
+            .axis_style(theme.foreground)
+            .draw()?;
+
+        chart.draw_series(counts.iter().enumerate().map(|(i, &count)| {
+            let left = min + width * i as f64;
+            let mut bar = Rectangle::new([(left, 0.0), (left + width, f64::from(count))], color.filled());
+            bar.set_margin(0, 0, 1, 1);
+            bar
+        }))?;
+
+        chart.draw_series(std::iter::once(PathElement::new(
+            [(average, 0.0), (average, f64::from(highest) * 1.1)],
+            foreground.stroke_width(2),
+        )))?
+        .label(format!("Average: {}", x_label(&average)))
+        .legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], foreground));
+
+        chart.configure_series_labels()
+            .border_style(theme.foreground)
+            .background_style(theme.background.mix(0.8))
+            .label_font(theme.label())
+            .draw()?;
+    }
+
+    println!("Histogram chart saved as {}", path.display());
+
+    Ok(())
+}
+
+/// One run of an overlay chart.
+pub struct OverlayRun<'a> {
+    /// Legend entry of the run, e.g. its start time.
+    pub name: &'a str,
+    /// Seconds from the start of the run to the end of each sample.
+    pub offsets: &'a [f64],
+    /// Download time of each sample, in seconds.
+    pub latencies: &'a [f64],
+    /// Effective data rate of each sample, in bits per second.
+    pub data_rates: &'a [f64],
+}
+
+/// Draws the smoothed latency and effective data rate of several runs over their elapsed time,
+/// one line per run, into a PNG chart.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `runs`: The runs, each with at least one sample.
+/// - `theme`: Colors and font sizes.
+pub fn plot_overlay(path: &Path, runs: &[OverlayRun], theme: &Theme) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+    let areas = root.split_evenly((2, 1));
+
+    let smooth = |values: &[f64]| -> Vec<f64> {
+        let smoothing = theme.smoothing.unwrap_or(5).min(values.len()).max(1);
+        values.windows(smoothing).map(|w| w.iter().sum::<f64>() / w.len() as f64).collect()
+    };
+    let latencies: Vec<Vec<f64>> = runs.iter().map(|run| smooth(run.latencies)).collect();
+    let data_rates: Vec<Vec<f64>> = runs.iter().map(|run| smooth(run.data_rates)).collect();
+    let x_end = runs.iter().flat_map(|run| run.offsets.last()).cloned().fold(0.0, f64::max).max(1e-3);
+    // Too many runs would cover the chart with their legend
+    let legend = runs.len() <= 12;
+    let smoothed_desc = if theme.smoothing == Some(1) { "" } else { " (Smoothed)" };
+
+    for (area, series) in areas.iter().zip([Series::Latency, Series::DataRate]) {
+        let (name, y_desc, smoothed, y_label): (_, _, _, AxisLabel) = match series {
+            Series::DataRate => (
+                "Effective Data Rate",
+                format!("Data Rate ({})", theme.units.rate_unit()),
+                &data_rates,
+                Box::new(|y: &f64| format!("{:.1}", theme.units.rate_value(*y))),
+            ),
+            _ => ("Latency", "Latency (s)".to_string(), &latencies, Box::new(|y: &f64| format!("{:.5}", y))),
+        };
+        let max = match smoothed.iter().flatten().cloned().fold(0.0, f64::max) {
+            max if max > 0.0 => max * 1.1,
+            _ => 1.0,
+        };
+        let mut chart = ChartBuilder::on(area)
+            .caption(format!("{} per Run{}", name, smoothed_desc), theme.caption())
+            .margin(10)
+            .x_label_area_size(theme.font_size * 10 / 3)
+            .y_label_area_size(theme.font_size * 5)
+            .build_cartesian_2d(0.0..x_end, 0.0..max)?;
+
+        chart.configure_mesh()
+            .x_desc("Elapsed Time (s)")
+            .y_desc(y_desc)
+            .x_label_formatter(&|x| format!("{:.3}", x))
+            .y_label_formatter(&y_label)
+            .axis_desc_style(theme.axis_desc())
+            .label_style(theme.label())
+            .bold_line_style(theme.foreground.mix(0.2))
+            .light_line_style(theme.background.mix(0.7))
+            .axis_style(theme.foreground)
+            .draw()?;
+
+        for (i, (run, values)) in runs.iter().zip(smoothed).enumerate() {
+            let color = theme.run_color(i);
+            let line = chart.draw_series(LineSeries::new(run.offsets.iter().cloned().zip(values.iter().cloned()), &color))?;
+            if legend {
+                line.label(run.name).legend(move |(x, y)| PathElement::new([(x - 5, y), (x + 5, y)], color));
+            }
+        }
+
+        if legend {
+            chart.configure_series_labels()
+                .border_style(theme.foreground)
+                .background_style(theme.background.mix(0.8))
+                .label_font(theme.label())
+                .draw()?;
+        }
+    }
+
+    println!("Overlay chart of {} runs saved as {}", runs.len(), path.display());
+
+    Ok(())
+}
+
+/// Draws a matrix of values between nodes into a PNG heatmap, each cell shaded from the background
+/// (zero) to the color of `series` (the largest value) and labeled with its value.
+///
+/// # Arguments
+/// - `path`: File the chart is written to.
+/// - `title`: Caption of the chart.
+/// - `nodes`: Names of the rows (sources) and columns (destinations).
+/// - `values`: Value of every row and column; `None` for the diagonal and failed pairs, which stay empty.
+/// - `format`: Formats a value as its cell label.
+/// - `series`: Series whose color the largest value gets.
+/// - `theme`: Colors and font sizes.
+pub fn plot_heatmap(
+    path: &Path,
+    title: &str,
+    nodes: &[String],
+    values: &[Vec<Option<f64>>],
+    format: impl Fn(f64) -> String,
+    series: Series,
+    theme: &Theme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let n = nodes.len() as i32;
+    let max = values.iter().flatten().flatten().copied().fold(0.0, f64::max);
+    let full = theme.color(series);
+    let shade = |value: f64| {
+        let t = if max > 0.0 { value / max } else { 0.0 };
+        let mix = |from: u8, to: u8| (f64::from(from) + (f64::from(to) - f64::from(from)) * t).round() as u8;
+        RGBColor(mix(theme.background.0, full.0), mix(theme.background.1, full.1), mix(theme.background.2, full.2))
+    };
+    let root = BitMapBackend::new(path, theme.size).into_drawing_area();
+    root.fill(&theme.background)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, theme.caption())
+        .margin(10)
+        .x_label_area_size(theme.font_size * 10 / 3)
+        .y_label_area_size(theme.font_size * 12)
+        .build_cartesian_2d((0..n - 1).into_segmented(), (0..n - 1).into_segmented())?;
+
+    // Rows are drawn from the top, like the table, so the first source is at the top of the y axis
+    let name = |segment: &SegmentValue<i32>| match segment {
+        SegmentValue::CenterOf(i) => nodes.get(*i as usize).cloned().unwrap_or_default(),
+        _ => String::new(),
+    };
+    let row_name = |segment: &SegmentValue<i32>| match segment {
+        SegmentValue::CenterOf(i) => name(&SegmentValue::CenterOf(n - 1 - i)),
+        _ => String::new(),
+    };
+    chart.configure_mesh()
+        .disable_mesh()
+        .x_desc("Destination")
+        .y_desc("Source")
+        .x_labels(nodes.len())
+        .y_labels(nodes.len())
+        .x_label_formatter(&name)
+        .y_label_formatter(&row_name)
+        .axis_desc_style(theme.axis_desc())
+        .label_style(theme.label())
+        .axis_style(theme.foreground)
+        .draw()?;
+
+    let cells = || {
+        values.iter().enumerate().flat_map(|(row, columns)| {
+            columns.iter().enumerate().filter_map(move |(column, value)| value.map(|value| (n - 1 - row as i32, column as i32, value)))
+        })
+    };
+    chart.draw_series(cells().map(|(row, column, value)| {
+        Rectangle::new(
+            [(SegmentValue::Exact(column), SegmentValue::Exact(row)), (SegmentValue::Exact(column + 1), SegmentValue::Exact(row + 1))],
+            shade(value).filled(),
+        )
+    }))?;
+    // Labels switch to the background color on the darker half of the scale to stay readable
+    chart.draw_series(cells().map(|(row, column, value)| {
+        let color = if max > 0.0 && value / max > 0.5 { &theme.background } else { &theme.foreground };
+        Text::new(
+            format(value),
+            (SegmentValue::CenterOf(column), SegmentValue::CenterOf(row)),
+            theme.label().color(color).pos(Pos::new(HPos::Center, VPos::Center)),
+        )
+    }))?;
+
+    println!("{} heatmap saved as {}", title, path.display());
+
+    Ok(())
+}
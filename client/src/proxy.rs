@@ -0,0 +1,176 @@
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::str::FromStr;
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTHENTICATION: u8 = 0;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_ADDRESS_IPV4: u8 = 1;
+const SOCKS_ADDRESS_DOMAIN: u8 = 3;
+const SOCKS_ADDRESS_IPV6: u8 = 4;
+
+/// Upper bound on the HTTP CONNECT response head, so a misbehaving proxy cannot make us buffer forever.
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+/// Protocol spoken with the proxy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyKind {
+    Socks5,
+    HttpConnect,
+}
+
+/// A proxy the test connection is tunneled through, given as `socks5://HOST:PORT` or `http://HOST:PORT`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    /// Address of the proxy as `host:port`.
+    pub address: String,
+}
+
+impl FromStr for Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (kind, address) = if let Some(address) = s.strip_prefix("socks5://").or_else(|| s.strip_prefix("socks5h://")) {
+            (ProxyKind::Socks5, address)
+        } else if let Some(address) = s.strip_prefix("http://") {
+            (ProxyKind::HttpConnect, address)
+        } else {
+            return Err(format!("unsupported proxy '{}', expected socks5://HOST:PORT or http://HOST:PORT", s));
+        };
+        let address = address.trim_end_matches('/');
+        if !address.contains(':') {
+            return Err(format!("proxy '{}' has no port", s));
+        }
+        Ok(Proxy { kind, address: address.to_string() })
+    }
+}
+
+impl fmt::Display for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ProxyKind::Socks5 => write!(f, "socks5://{}", self.address),
+            ProxyKind::HttpConnect => write!(f, "http://{}", self.address),
+        }
+    }
+}
+
+impl Proxy {
+    /// Asks the proxy, over a connection already made to it, to open a tunnel to `target`.
+    ///
+    /// The target name is passed on unresolved, so it is resolved from the proxy's side of the
+    /// network, as it would be for any other client behind the proxy.
+    ///
+    /// # Arguments
+    /// - `stream`: Connection to the proxy.
+    /// - `target`: Address to tunnel to as `host:port`.
+    pub fn tunnel(&self, stream: &mut TcpStream, target: &str) -> io::Result<()> {
+        match self.kind {
+            ProxyKind::Socks5 => socks5_connect(stream, target),
+            ProxyKind::HttpConnect => http_connect(stream, target),
+        }
+    }
+}
+
+fn socks5_connect(stream: &mut TcpStream, target: &str) -> io::Result<()> {
+    stream.write_all(&[SOCKS_VERSION, 1, SOCKS_NO_AUTHENTICATION])?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice)?;
+    if choice != [SOCKS_VERSION, SOCKS_NO_AUTHENTICATION] {
+        return Err(proxy_error("SOCKS5 proxy requires authentication, which is not supported"));
+    }
+
+    let (host, port) = split_host_port(target)?;
+    let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(SOCKS_ADDRESS_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(SOCKS_ADDRESS_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let name = host.as_bytes();
+            if name.len() > usize::from(u8::MAX) {
+                return Err(proxy_error("host name too long for SOCKS5"));
+            }
+            request.push(SOCKS_ADDRESS_DOMAIN);
+            request.push(name.len() as u8);
+            request.extend_from_slice(name);
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        return Err(proxy_error(format!("SOCKS5 proxy could not connect to {}: {}", target, socks5_reply(reply[1]))));
+    }
+    // The address the proxy connected from is of no use, but has to be consumed
+    let bound_len = match reply[3] {
+        SOCKS_ADDRESS_IPV4 => 4,
+        SOCKS_ADDRESS_IPV6 => 16,
+        SOCKS_ADDRESS_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            usize::from(len[0])
+        }
+        other => return Err(proxy_error(format!("SOCKS5 proxy replied with unknown address type {}", other))),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound)
+}
+
+fn socks5_reply(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn http_connect(stream: &mut TcpStream, target: &str) -> io::Result<()> {
+    write!(stream, "CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target)?;
+
+    // Read byte by byte so that nothing past the response head is consumed from the tunnel
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() == MAX_RESPONSE_HEAD {
+            return Err(proxy_error("HTTP proxy response head too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status = head.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(proxy_error(format!("HTTP proxy refused to connect to {}: {}", target, status))),
+    }
+}
+
+/// Splits `host:port`, accepting bracketed IPv6 literals.
+fn split_host_port(target: &str) -> io::Result<(&str, u16)> {
+    let (host, port) = target
+        .rsplit_once(':')
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("'{}' has no port", target)))?;
+    let port = port
+        .parse()
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid port in '{}'", target)))?;
+    Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+}
+
+fn proxy_error<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(ErrorKind::ConnectionRefused, error)
+}
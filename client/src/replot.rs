@@ -0,0 +1,200 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use clap::ValueEnum;
+use crate::html;
+use crate::plot::{self, ChartOptions, Marks, SampleAxis, XAxis};
+use crate::results::RunResult;
+use crate::stats;
+use crate::units::Units;
+
+/// Chart drawn from the saved samples.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ChartType {
+    /// Latency and effective data rate over the run, as a test run draws them.
+    Lines,
+    /// How the latencies and effective data rates are distributed.
+    Histogram,
+    /// Latency and effective data rate over the run as interactive HTML, unsmoothed.
+    Html,
+}
+
+/// Options of the `replot` subcommand.
+#[derive(Clone, clap::Args)]
+pub struct ReplotArgs {
+    /// Per-chunk metrics CSV a run wrote, e.g. `download_metrics.csv`.
+    #[arg(value_name = "METRICS")]
+    metrics: PathBuf,
+
+    /// Chart the rows of this run, when the file holds several; by default the last run in it.
+    #[arg(long, value_name = "ID")]
+    run_id: Option<String>,
+
+    /// Chart the run of this summary written with `--json`, and mark the outliers it recorded.
+    #[arg(long, value_name = "SUMMARY", conflicts_with = "run_id")]
+    summary: Option<PathBuf>,
+
+    /// Kind of chart to draw.
+    #[arg(long, value_name = "TYPE", value_enum, default_value_t = ChartType::Lines)]
+    chart_type: ChartType,
+
+    /// Number of bars in each histogram.
+    #[arg(long, value_name = "N", default_value_t = 30)]
+    bins: usize,
+
+    /// Without `--summary`, mark chunks whose download time exceeds the median by more than this
+    /// many scaled median absolute deviations.
+    #[arg(long, value_name = "K", default_value_t = 3.5, value_parser = crate::positive)]
+    outlier_k: f64,
+
+    /// What the x axis shows: sample numbers, seconds since the start of the run, or UTC time of
+    /// day.
+    #[arg(long, value_name = "AXIS", value_enum, default_value_t = XAxis::Sample)]
+    x_axis: XAxis,
+
+    /// Show data rates in decimal (Mbit/s) or binary (Mibit/s) units.
+    #[arg(long, value_name = "UNITS", value_enum, default_value_t = Units::Si)]
+    units: Units,
+
+    /// File the chart is written to; by default `<METRICS>_replot.png`, or `.html`, next to the
+    /// metrics file.
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    chart: ChartOptions,
+}
+
+/// The samples of one run, read back from its metrics CSV.
+pub struct Samples {
+    /// "Download" or "Upload".
+    pub label: String,
+    /// `None` for a file written with `--per-run-files`, which has no Run ID column.
+    pub run_id: Option<String>,
+    pub latencies: Vec<f64>,
+    pub data_rates: Vec<f64>,
+    pub offsets: Vec<f64>,
+    pub started_at: SystemTime,
+}
+
+/// Draws the chart of a run again from the metrics CSV it wrote, without running a test.
+///
+/// # Returns
+/// - The process exit code: 0, or `EXIT_TEST_ERROR` if the file holds no samples of the run.
+pub fn run(args: &ReplotArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let summary = match &args.summary {
+        Some(path) => Some(RunResult::read_json(path).map_err(|e| format!("{} is not a run summary: {}", path.display(), e))?),
+        None => None,
+    };
+    let run_id = args.run_id.as_deref().or(summary.as_ref().map(|summary| summary.run_id.as_str()));
+    let samples = match read_samples(&args.metrics, run_id)? {
+        Some(samples) => samples,
+        None => {
+            let run = run_id.map(|id| format!(" of run {}", id)).unwrap_or_default();
+            eprintln!("Test error: {} holds no samples{}", args.metrics.display(), run);
+            return Ok(crate::EXIT_TEST_ERROR);
+        }
+    };
+    if let Some(id) = &samples.run_id {
+        println!("Charting {} samples of run {} from {}", samples.latencies.len(), id, args.metrics.display());
+    }
+
+    // A summary's outliers are matched by offset, since in a soak test they number summary windows
+    // rather than chunks
+    let outliers: Vec<usize> = match &summary {
+        Some(summary) => samples
+            .offsets
+            .iter()
+            .enumerate()
+            .filter(|(_, &offset)| summary.outliers.iter().any(|outlier| (outlier.offset_seconds - offset).abs() < 1e-6))
+            .map(|(i, _)| i)
+            .collect(),
+        None => stats::high_outliers(&samples.latencies, args.outlier_k),
+    };
+
+    let extension = if args.chart_type == ChartType::Html { "html" } else { "png" };
+    let path = args.output.clone().unwrap_or_else(|| {
+        let stem = args.metrics.file_stem().unwrap_or_default().to_string_lossy();
+        args.metrics.with_file_name(format!("{}_replot.{}", stem, extension))
+    });
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        crate::output::create_dir(Some(dir))?;
+    }
+
+    let theme = args.chart.theme(args.units);
+    let x_desc = format!("{} Number", samples.label);
+    let positions = args.x_axis.positions(&samples.offsets, samples.started_at);
+    let x = SampleAxis { kind: args.x_axis, positions: &positions, sample_desc: &x_desc };
+    let marks = Marks { outliers: &outliers, cross_traffic: &[] };
+    match args.chart_type {
+        ChartType::Lines => plot::plot_latency_and_data_rate(&path, &samples.latencies, &samples.data_rates, &marks, &x, &theme)?,
+        ChartType::Histogram => plot::plot_histograms(&path, &samples.latencies, &samples.data_rates, args.bins, &theme)?,
+        ChartType::Html => html::write_latency_and_data_rate(&path, &samples.latencies, &samples.data_rates, &marks, &x, &theme, None)?,
+    }
+    Ok(0)
+}
+
+/// Reads the samples of one run from a metrics CSV.
+///
+/// # Arguments
+/// - `path`: Metrics CSV written by a download or upload.
+/// - `run_id`: Run whose rows to read; `None` for the last run in the file. A file written with
+///   `--per-run-files` has no Run ID column and holds one run, which is read whatever the ID.
+///
+/// # Returns
+/// - The samples, or `None` if the file holds no rows of the run.
+fn read_samples(path: &Path, run_id: Option<&str>) -> Result<Option<Samples>, Box<dyn std::error::Error>> {
+    let mut runs = read_runs(path)?;
+    Ok(match run_id {
+        Some(id) => runs.into_iter().find(|run| run.run_id.as_deref().is_none_or(|run_id| run_id == id)),
+        None => runs.pop(),
+    })
+}
+
+/// Reads the samples of every run in a metrics CSV.
+///
+/// # Returns
+/// - The runs in the order their first rows appear in the file.
+pub fn read_runs(path: &Path) -> Result<Vec<Samples>, Box<dyn std::error::Error>> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let not_metrics = || format!("{} is not a per-chunk metrics file", path.display());
+    let time = headers.iter().position(|header| header.ends_with(" Time (s)")).ok_or_else(not_metrics)?;
+    let (rate, offset, timestamp) = match (column("Effective Data Rate (bps)"), column("Offset (s)"), column("Timestamp")) {
+        (Some(rate), Some(offset), Some(timestamp)) => (rate, offset, timestamp),
+        _ => return Err(not_metrics().into()),
+    };
+    let run_column = column("Run ID");
+    let label = headers[time].trim_end_matches(" Time (s)").to_string();
+    let number = |record: &csv::StringRecord, i: usize, line: usize| {
+        record[i].parse::<f64>().map_err(|_| format!("{} line {}: '{}' is not a number", path.display(), line, &record[i]))
+    };
+
+    let mut runs: Vec<Samples> = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        // The header is line 1
+        let line = i + 2;
+        let run_id = run_column.map(|run_column| record[run_column].to_string());
+        let (latency, data_rate, offset) = (number(&record, time, line)?, number(&record, rate, line)?, number(&record, offset, line)?);
+        let run = match runs.iter().rposition(|run| run.run_id == run_id) {
+            Some(run) => &mut runs[run],
+            None => {
+                let at = humantime::parse_rfc3339(&record[timestamp]).map_err(|e| format!("{} line {}: {}", path.display(), line, e))?;
+                runs.push(Samples {
+                    label: label.clone(),
+                    run_id,
+                    latencies: Vec::new(),
+                    data_rates: Vec::new(),
+                    offsets: Vec::new(),
+                    started_at: at - Duration::from_secs_f64(offset.max(0.0)),
+                });
+                runs.last_mut().unwrap()
+            }
+        };
+        run.latencies.push(latency);
+        run.data_rates.push(data_rate);
+        run.offsets.push(offset);
+    }
+    Ok(runs)
+}
@@ -0,0 +1,352 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use crate::results::{RunResult, TunnelComparison};
+use crate::stats::Summary;
+use crate::units::Units;
+use crate::voip;
+
+/// Formats the run summary can be written in besides the console output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// The full summary as JSON, as `--json` writes it.
+    Json,
+    /// A Markdown summary with tables and the charts, for GitHub issues and wikis.
+    Md,
+    /// A PDF report with the summary, methodology, charts, and environment, for readers who will
+    /// not open CSVs.
+    #[cfg(feature = "pdf-report")]
+    Pdf,
+}
+
+impl Format {
+    /// Returns the reporter that writes this format.
+    pub fn reporter(self) -> Box<dyn Reporter> {
+        match self {
+            Format::Json => Box::new(Json),
+            Format::Md => Box::new(Markdown),
+            #[cfg(feature = "pdf-report")]
+            Format::Pdf => Box::new(crate::pdf::Pdf),
+        }
+    }
+}
+
+/// A chart written by a run, which reports link to.
+pub struct Chart {
+    pub title: &'static str,
+    pub path: PathBuf,
+}
+
+/// What a report is written from: the evaluated run and the charts written for it.
+pub struct Report<'a> {
+    pub result: &'a RunResult,
+    pub charts: &'a [Chart],
+    /// Units the data rates and sizes are shown in.
+    pub units: Units,
+}
+
+/// Writes a report of a run in one format.
+pub trait Reporter {
+    /// Extension of the report file, which is named `report.<extension>` unless given a path.
+    fn extension(&self) -> &'static str;
+
+    /// Writes the report to `path`.
+    fn write(&self, report: &Report, path: &Path) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// The full run summary as pretty-printed JSON, readable again as a baseline.
+pub struct Json;
+
+impl Reporter for Json {
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn write(&self, report: &Report, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        report.result.write_json(path)
+    }
+}
+
+/// A Markdown summary: the headline numbers and distributions as tables, the findings as lists,
+/// and the charts as images (PNG) or links (HTML) relative to the report.
+pub struct Markdown;
+
+impl Reporter for Markdown {
+    fn extension(&self) -> &'static str {
+        "md"
+    }
+
+    fn write(&self, report: &Report, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, markdown(report, path.parent().unwrap_or(Path::new(""))))?;
+        Ok(())
+    }
+}
+
+/// Renders the Markdown report, linking the charts relative to `dir`, where the report is written.
+fn markdown(report: &Report, dir: &Path) -> String {
+    let result = report.result;
+    let mut md = String::new();
+    let _ = writeln!(md, "# Bandwidth test: {} from {}\n", result.direction, cell(&result.server));
+    let _ = writeln!(md, "Started {} from {}.\n", result.started_at, cell(&result.local_address));
+
+    md.push_str("| Metric | Value |\n|---|---|\n");
+    for (metric, value) in summary_rows(result, report.units) {
+        let _ = writeln!(md, "| {} | {} |", metric, cell(&value));
+    }
+
+    let rate_name = format!("Data rate ({})", report.units.rate_unit());
+    let distributions = [
+        ("Transfer time (ms)", result.latency_stats.as_ref(), 1000.0),
+        (rate_name.as_str(), result.throughput_stats.as_ref(), 1.0 / report.units.mega()),
+    ];
+    if distributions.iter().any(|(_, stats, _)| stats.is_some()) {
+        md.push_str("\n## Distribution\n\n| | Samples | Mean | Median | Min | p5 | p95 | p99 | Max |\n|---|---|---|---|---|---|---|---|---|\n");
+        for (name, stats, scale) in distributions {
+            if let Some(Summary { samples, mean, median, min, p5, p95, p99, max, .. }) = stats {
+                let _ = write!(md, "| {} | {} |", name, samples);
+                for value in [mean, median, min, p5, p95, p99, max] {
+                    let _ = write!(md, " {:.3} |", value * scale);
+                }
+                md.push('\n');
+            }
+        }
+    }
+
+    if let Some(comparison) = &result.tunnel_comparison {
+        let _ = writeln!(md, "\n## Tunnel overhead\n\n| | Direct | Through {} | Tunnel adds |\n|---|---|---|---|", cell(&comparison.interface));
+        for [metric, direct, tunneled, added] in tunnel_rows(comparison, report.units) {
+            let _ = writeln!(md, "| {} | {} | {} | {} |", metric, direct, tunneled, added);
+        }
+    }
+
+    if !result.recommendations.is_empty() {
+        md.push_str("\n## Recommendations\n\n");
+        for recommendation in &result.recommendations {
+            let _ = writeln!(md, "- {}. {}", recommendation.finding, recommendation.action);
+        }
+    }
+
+    let warnings = warnings(result);
+    if !warnings.is_empty() {
+        md.push_str("\n## Warnings\n\n");
+        for warning in warnings {
+            let _ = writeln!(md, "- {}", warning);
+        }
+    }
+
+    if !report.charts.is_empty() {
+        md.push_str("\n## Charts\n\n");
+        for chart in report.charts {
+            let path = chart.path.strip_prefix(dir).unwrap_or(&chart.path).display().to_string().replace(' ', "%20");
+            // GitHub renders images inline but only links other files
+            if chart.path.extension().is_some_and(|extension| extension == "png") {
+                let _ = writeln!(md, "![{}]({})\n", chart.title, path);
+            } else {
+                let _ = writeln!(md, "- [{}]({})\n", chart.title, path);
+            }
+        }
+    }
+    md
+}
+
+/// The headline numbers of a run, as metric and value, for the summary table of a report.
+pub fn summary_rows(result: &RunResult, units: Units) -> Vec<(&'static str, String)> {
+    let mut rows = vec![
+        ("Effective data rate", units.rate_or_na(result.throughput_bps)),
+        ("Avg transfer time", result.latency_seconds.map_or("n/a".to_string(), |latency| format!("{:.3} ms", latency * 1000.0))),
+        ("Loss", format!("{:.2}%", result.loss_percent)),
+        ("Chunks received", format!("{} of {}", result.chunks_received, result.chunks_expected)),
+        ("Data transferred", units.size(result.total_bytes as f64)),
+    ];
+    if let Some(rtt) = result.rtt_seconds {
+        rows.push(("RTT", format!("{:.3} ms", rtt * 1000.0)));
+    }
+    if let Some(delay) = result.one_way_delay_seconds {
+        rows.push(("One-way delay", format!("{:.3} ms", delay * 1000.0)));
+    }
+    if let Some(cv) = result.throughput_cv {
+        rows.push(("Data rate CV", format!("{:.3}", cv)));
+    }
+    if let Some(shaping) = &result.shaping {
+        rows.push(("Server shaping", format!("{}, {} byte burst", units.rate(shaping.rate_bps as f64), shaping.burst_bytes)));
+    }
+    if let Some(streaming) = &result.streaming {
+        rows.push((
+            "Streaming",
+            match streaming.startup_delay_seconds {
+                Some(delay) => format!(
+                    "{}: started after {:.3} s, {} rebuffers for {:.3} s ({:.2}%)",
+                    units.rate(streaming.bitrate_bps),
+                    delay,
+                    streaming.rebuffers.len(),
+                    streaming.rebuffering_seconds,
+                    streaming.rebuffer_ratio_percent
+                ),
+                None => format!("{}: playback never started", units.rate(streaming.bitrate_bps)),
+            },
+        ));
+    }
+    if let Some(repeat) = &result.repeat {
+        rows.push((
+            "Median over runs",
+            format!(
+                "{}, 95% CI [{:.2}, {:.2}] over {} runs",
+                units.rate(repeat.median_throughput_bps),
+                units.rate_value(repeat.median_ci_low_bps),
+                units.rate_value(repeat.median_ci_high_bps),
+                repeat.throughputs_bps.len()
+            ),
+        ));
+    }
+    if let Some(asymmetry) = &result.asymmetry {
+        rows.push((
+            "Download : upload",
+            format!(
+                "{} : {}{}",
+                units.rate_or_na(asymmetry.download_throughput_bps),
+                units.rate_or_na(asymmetry.upload_throughput_bps),
+                asymmetry.ratio.map(|ratio| format!(" ({:.2}:1)", ratio)).unwrap_or_default()
+            ),
+        ));
+    }
+    if let Some(comparison) = &result.relay_comparison {
+        rows.push((
+            "Via relay",
+            format!(
+                "{}{}",
+                units.rate_or_na(comparison.relayed_throughput_bps),
+                comparison.ratio.map(|ratio| format!(" ({:.1}% of direct)", ratio * 100.0)).unwrap_or_default()
+            ),
+        ));
+    }
+    if let Some(comparison) = &result.tunnel_comparison {
+        rows.push((
+            "Through tunnel",
+            format!(
+                "{} on {}{}",
+                units.rate_or_na(comparison.tunneled_throughput_bps),
+                comparison.interface,
+                comparison.throughput_overhead_percent.map(|overhead| format!(" ({:.1}% overhead)", overhead)).unwrap_or_default()
+            ),
+        ));
+    }
+    for estimate in &result.dispersion {
+        if let Some(rate) = &estimate.rate {
+            rows.push((
+                "Dispersion estimate",
+                format!(
+                    "{} {}, 95% CI [{:.2}, {:.2}], {} confidence{}",
+                    estimate.direction,
+                    units.rate(rate.available_bps),
+                    units.rate_value(rate.ci_low_bps),
+                    units.rate_value(rate.ci_high_bps),
+                    rate.confidence,
+                    match estimate.offload {
+                        Some(offload) if offload.gso => ", sent with UDP GSO",
+                        Some(offload) if offload.gro => ", received with UDP GRO",
+                        _ => "",
+                    }
+                ),
+            ));
+        }
+    }
+    if let Some(call) = &result.voip {
+        rows.push((
+            "VoIP MOS",
+            format!("{:.2} ({}, R-factor {:.1}, {})", call.mos, call.codec, call.r_factor, voip::verdict(call.mos)),
+        ));
+    }
+    if let Some(peer) = &result.peer {
+        rows.push((
+            "Peer-to-peer",
+            format!(
+                "{} with {} ({:.2}% loss{})",
+                units.rate(peer.data_rate_bps),
+                peer.peer_address,
+                peer.loss_percent,
+                peer.target_rate_bps.map(|rate| format!(", paced at {}", units.rate(rate))).unwrap_or_default()
+            ),
+        ));
+    }
+    if let Some(gaming) = &result.gaming {
+        if let (Some(median), Some(p95)) = (gaming.added_median_seconds, gaming.added_p95_seconds) {
+            rows.push((
+                "Gaming",
+                format!(
+                    "{:+.1} ms median, {:+.1} ms p95, {:+.2} points loss under load ({} Hz)",
+                    median * 1000.0,
+                    p95 * 1000.0,
+                    gaming.added_loss_percent,
+                    gaming.rate_hz
+                ),
+            ));
+        }
+    }
+    if let Some(cross_traffic) = result.cross_traffic.as_ref().filter(|cross_traffic| !cross_traffic.flagged.is_empty()) {
+        rows.push((
+            "Cross traffic",
+            format!(
+                "{} of {} intervals, {} of other traffic on {}",
+                cross_traffic.flagged.len(),
+                cross_traffic.intervals,
+                units.size(cross_traffic.other_bytes as f64),
+                cross_traffic.interface
+            ),
+        ));
+    }
+    if let Some(bottleneck) = &result.bottleneck {
+        rows.push(("Bottleneck", bottleneck.verdict.clone()));
+    }
+    if !result.tags.is_empty() {
+        rows.push(("Tags", result.tags.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join(", ")));
+    }
+    rows
+}
+
+/// The direct and tunneled runs side by side, as metric, direct value, tunneled value, and what the
+/// tunnel adds.
+pub fn tunnel_rows(comparison: &TunnelComparison, units: Units) -> Vec<[String; 4]> {
+    let ms = |seconds: f64| format!("{:.3} ms", seconds * 1000.0);
+    let added = |direct: f64, tunneled: f64| format!("{:+.3} ms", (tunneled - direct) * 1000.0);
+    let mut rows = vec![
+        [
+            "Effective data rate".to_string(),
+            units.rate_or_na(comparison.direct_throughput_bps),
+            units.rate_or_na(comparison.tunneled_throughput_bps),
+            comparison.throughput_overhead_percent.map(|overhead| format!("{:+.1}%", -overhead)).unwrap_or_default(),
+        ],
+    ];
+    let pairs = [
+        ("Avg transfer time", comparison.direct_latency_seconds, comparison.tunneled_latency_seconds),
+        ("TCP RTT", comparison.direct_rtt_seconds, comparison.tunneled_rtt_seconds),
+        ("Time to first byte", comparison.direct_first_byte_seconds, comparison.tunneled_first_byte_seconds),
+    ];
+    for (metric, direct, tunneled) in pairs {
+        if let (Some(direct), Some(tunneled)) = (direct, tunneled) {
+            rows.push([metric.to_string(), ms(direct), ms(tunneled), added(direct, tunneled)]);
+        }
+    }
+    rows.push([
+        "Loss".to_string(),
+        format!("{:.2}%", comparison.direct_loss_percent),
+        format!("{:.2}%", comparison.tunneled_loss_percent),
+        format!("{:+.2} pp", comparison.tunneled_loss_percent - comparison.direct_loss_percent),
+    ]);
+    rows
+}
+
+/// The warnings a run raised about the connection, e.g. TCP options that were not negotiated.
+pub fn warnings(result: &RunResult) -> Vec<&String> {
+    result
+        .tcp_options
+        .iter()
+        .flat_map(|options| [&options.window_scaling, &options.sack, &options.timestamps])
+        .filter_map(|negotiation| negotiation.warning.as_ref())
+        .collect()
+}
+
+/// Escapes the characters that would break a table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
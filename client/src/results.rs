@@ -0,0 +1,1081 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::SystemTime;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use common::protocol::{Direction, TokenBucket};
+use common::tcp_info::TcpInfo;
+use crate::burst::BurstStats;
+use crate::sequence::SequenceCounts;
+use crate::stats::Summary;
+use crate::traceroute::TraceMode;
+use crate::voip::Codec;
+
+/// Summary of a single test run, as written to and read from the JSON result document.
+#[derive(Default, Serialize, Deserialize)]
+pub struct RunResult {
+    /// Which way the test data flowed.
+    #[serde(default)]
+    pub direction: Direction,
+    /// Wall-clock start of the run, in RFC 3339 format (UTC).
+    #[serde(default)]
+    pub started_at: String,
+    /// Identifies the run's rows in metrics files shared by several runs.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub run_id: String,
+    /// `--tag`s the run was labelled with.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// Address of the test server as given on the command line.
+    #[serde(default)]
+    pub server: String,
+    /// Local address the data connection was made from.
+    #[serde(default)]
+    pub local_address: String,
+    /// Network interface the connection was bound to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// DSCP code point the test traffic was marked with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dscp: Option<u8>,
+    /// TTL / hop limit the test traffic was sent with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u8>,
+    /// Number of chunks the server was expected to send.
+    pub chunks_expected: usize,
+    /// Number of chunks fully received.
+    pub chunks_received: usize,
+    /// Total payload received, in bytes.
+    pub total_bytes: u64,
+    /// Average effective data rate over the whole transfer, in bits per second, unless no data
+    /// arrived to time.
+    pub throughput_bps: Option<f64>,
+    /// Average per-chunk download time, in seconds, unless no chunk arrived.
+    pub latency_seconds: Option<f64>,
+    /// Percentage of the expected data that was not received.
+    pub loss_percent: f64,
+    /// Distribution of the per-chunk download times (per summary window in a soak test), in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_stats: Option<Summary>,
+    /// Distribution of the per-chunk data rates (per summary window in a soak test), in bits per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throughput_stats: Option<Summary>,
+    /// Samples with an unusually long download time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outliers: Vec<Outlier>,
+    /// Coefficient of variation (stddev / mean) of the data rate after the ramp-up; lower is steadier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throughput_cv: Option<f64>,
+    /// Comparison with the opposite direction when both were measured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asymmetry: Option<Asymmetry>,
+    /// Spread over all runs when the test was repeated; the rest of the result is the median run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<RepeatSummary>,
+    /// Bandwidth-Delay Product at the measured data rate, in bits, if there was one.
+    pub bdp_bits: Option<f64>,
+    /// Smoothed RTT reported by the kernel at the end of the run, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_seconds: Option<f64>,
+    /// Largest window the client's socket buffer allowed at the end of the run, in bytes: the
+    /// receive window for a download, the send window for an upload.
+    #[serde(default)]
+    pub receive_window_bytes: u64,
+    /// Theoretical TCP throughput ceiling given the window and RTT, in bits per second.
+    pub tcp_throughput_bps: f64,
+    /// Whether the data rate came close to the window ceiling.
+    #[serde(default)]
+    pub window_limited: bool,
+    /// Per-burst statistics when the run used a burst traffic pattern.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bursts: Vec<BurstStats>,
+    /// Token bucket the server shaped the download with, if the client asked for one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shaping: Option<TokenBucket>,
+    /// How the chunks were merged into samples when their size adapted to the data rate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    /// Playback of the download as a constant-bitrate stream, when one was simulated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streaming: Option<Streaming>,
+    /// Diagnosis if the transfer was aborted because it stopped making progress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stall: Option<Stall>,
+    /// Whether the run ended because the server stopped answering TCP keepalive probes, rather than
+    /// stalling or failing otherwise.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keepalive_drop: bool,
+    /// Whether the run ended because the server fell silent, sending neither data nor heartbeats.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub heartbeat_lost: bool,
+    /// Connections that dropped during the run and were re-established; data on either side of each
+    /// is not continuous.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reconnects: Vec<Reconnect>,
+    /// Most likely limit of the transfer, attributed from CPU use, disk time, and socket queues.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bottleneck: Option<Bottleneck>,
+    /// Details of a real file transfer, when a file was downloaded instead of synthetic chunks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileTransfer>,
+    /// Number of received chunks whose checksum or seeded content did not match, when integrity
+    /// checks were enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub corrupted_chunks: Option<usize>,
+    /// Seed of the pseudo-random payload, if one was used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_seed: Option<u64>,
+    /// Missing, duplicated, and reordered chunks detected from the sequence numbers.
+    #[serde(default)]
+    pub sequence: SequenceCounts,
+    /// Average delay from the server sending a chunk to its first byte arriving, in seconds. Only
+    /// meaningful when both clocks are synchronized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub one_way_delay_seconds: Option<f64>,
+    /// ICMP echo round trips to the server before the transfer started, as an idle reference.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_idle: Option<PingSummary>,
+    /// ICMP echo round trips to the server while the transfer was running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_loaded: Option<PingSummary>,
+    /// UDP echo round trips to the server before the transfer started, or of a latency-only run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_echo_idle: Option<EchoSummary>,
+    /// UDP echo round trips to the server while the transfer was running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_echo_loaded: Option<EchoSummary>,
+    /// Bandwidth estimated from the dispersion of UDP packet trains, per direction, in a dispersion
+    /// run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dispersion: Vec<DispersionEstimate>,
+    /// Quality of a simulated call, in a VoIP run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voip: Option<VoipCall>,
+    /// Latency and loss of game traffic with and without the transfer, in a gaming run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gaming: Option<Gaming>,
+    /// The test against another client, in a peer-to-peer run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<PeerTest>,
+    /// Exact reads and writes on the test connection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_io: Option<SocketIo>,
+    /// Whether the server ended the transfer with its end-of-test frame; absent for soak tests,
+    /// which the client ends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_confirmed_end: Option<bool>,
+    /// Path to the server traced before the run, so results can be told apart by route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traceroute: Option<Traceroute>,
+    /// Time the system resolver took to look up the server's name, per record type, when the server
+    /// was given as a hostname.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<DnsLookup>,
+    /// How long each stage of establishing the test session took.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup: Option<ConnectionSetup>,
+    /// How the server was chosen from a server list, if one was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_selection: Option<ServerSelection>,
+    /// Public address reported by the STUN server, if one was queried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stun: Option<StunMapping>,
+    /// Proxy the connection was tunneled through, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// SSH tunnel the connection was forwarded through, if any; `server` is then the local end of
+    /// the forward.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_tunnel: Option<SshTunnel>,
+    /// Server the test was relayed through, if any; `server` is then the final destination.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay: Option<String>,
+    /// Comparison with the same test relayed through another server, when one was measured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_comparison: Option<RelayComparison>,
+    /// Comparison with the same test through a tunnel or VPN interface, when one was measured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel_comparison: Option<TunnelComparison>,
+    /// The object transferred when testing an S3-compatible endpoint instead of a test server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_storage: Option<ObjectStorage>,
+    /// What the interface carried during the run, compared with the test's own traffic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface_counters: Option<InterfaceCounters>,
+    /// Traffic on the interface besides the test's own, sampled during the run when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cross_traffic: Option<CrossTraffic>,
+    /// Wi-Fi link state sampled during the run, when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wifi: Option<WifiSummary>,
+    /// Energy the host consumed during the run, when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy: Option<Energy>,
+    /// Packet capture of the test connection, when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture: Option<CaptureSummary>,
+    /// Retransmissions and congestion window changes recorded by the kernel, when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_events: Option<TcpEventSummary>,
+    /// Kernel state of the test socket before, during, and after the run, when requested.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub socket_snapshots: Vec<SocketSnapshot>,
+    /// Changes that should raise the data rate, derived from what limited the run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recommendations: Vec<Recommendation>,
+    /// Window scaling, SACK, and timestamps as negotiated on the test connection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_options: Option<TcpOptions>,
+}
+
+/// Kernel TCP events of our side of the connection, summarized; the events themselves are in the
+/// TCP events CSV.
+#[derive(Serialize, Deserialize)]
+pub struct TcpEventSummary {
+    pub retransmits: u64,
+    /// Congestion window or slow-start threshold changes.
+    pub window_changes: u64,
+    /// Congestion window in segments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_cwnd: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cwnd: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_cwnd: Option<u32>,
+    /// Lowest slow-start threshold set by a loss, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_ssthresh: Option<u32>,
+    pub events_csv: String,
+}
+
+/// What the packet capture of a run recorded.
+#[derive(Default, Serialize, Deserialize)]
+pub struct CaptureSummary {
+    /// The pcap file.
+    pub file: String,
+    /// Bytes kept of every packet.
+    pub snaplen: u32,
+    /// Packets of the test connection seen.
+    pub packets: u64,
+    /// Packets written to the file; fewer than seen once the size limit is reached.
+    pub packets_written: u64,
+    pub file_bytes: u64,
+    /// The file reached its size limit, so later packets are missing from it.
+    pub limit_reached: bool,
+    /// Packets the kernel dropped because the capture fell behind.
+    pub dropped: u64,
+    /// Options of the SYN we sent, if the capture saw it in full.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub syn: Option<SynOptions>,
+    /// Options of the server's SYN-ACK, as it arrived here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub syn_ack: Option<SynOptions>,
+}
+
+/// TCP options of a handshake packet.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SynOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mss: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_scale: Option<u8>,
+    pub sack_permitted: bool,
+    pub timestamps: bool,
+}
+
+/// Which of the TCP options that throughput depends on the handshake negotiated.
+#[derive(Serialize, Deserialize)]
+pub struct TcpOptions {
+    pub window_scaling: OptionNegotiation,
+    pub sack: OptionNegotiation,
+    pub timestamps: OptionNegotiation,
+    /// Window scale shift we advertised, and the server's, when scaling was negotiated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rcv_wscale: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snd_wscale: Option<u8>,
+}
+
+/// What is known about the negotiation of one TCP option.
+#[derive(Default, Serialize, Deserialize)]
+pub struct OptionNegotiation {
+    /// Both sides agreed to use it; unknown where the OS does not report it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub negotiated: Option<bool>,
+    /// This host's settings enable it, so our SYN offers it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled_here: Option<bool>,
+    /// Our SYN carried it, as captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offered: Option<bool>,
+    /// The SYN-ACK that arrived carried it, as captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answered: Option<bool>,
+    /// Why the option is missing, when this host asked for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// The kernel's state of the test socket at one point, as `ss -tmio` shows it.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SocketSnapshot {
+    /// "before" the request is sent, "during" the transfer, or "after" it, before the connection is closed.
+    pub stage: String,
+    pub timestamp: String,
+    /// TCP state, e.g. "ESTABLISHED" or "TIME-WAIT".
+    pub state: String,
+    /// Bytes received but not read by us yet.
+    pub recv_q: u32,
+    /// Bytes sent but not acknowledged by the server yet.
+    pub send_q: u32,
+    /// Pending timer, when it fires, and how often it has already: "on,204ms,0".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timer: Option<String>,
+    pub uid: u32,
+    pub inode: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub congestion_control: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<SocketMemory>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_info: Option<TcpInfo>,
+}
+
+/// Socket memory accounting (`skmem` in `ss -m`), in bytes.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SocketMemory {
+    pub rmem_alloc: u32,
+    pub rcvbuf: u32,
+    pub wmem_alloc: u32,
+    pub sndbuf: u32,
+    pub fwd_alloc: u32,
+    pub wmem_queued: u32,
+    pub optmem: u32,
+    pub backlog: u32,
+    /// Packets dropped before they were queued on the socket.
+    pub drops: u32,
+}
+
+/// Energy consumed during a run, for characterizing embedded and mobile devices.
+#[derive(Serialize, Deserialize)]
+pub struct Energy {
+    /// What was measured: "RAPL" for the CPU packages, or the battery.
+    pub source: String,
+    pub joules: f64,
+    /// Joules per gigabyte (10^9 bytes) of test data, if any arrived.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub joules_per_gb: Option<f64>,
+}
+
+/// Wi-Fi link samples of a run, summarized; the samples themselves are in the Wi-Fi CSV.
+#[derive(Serialize, Deserialize)]
+pub struct WifiSummary {
+    pub interface: String,
+    pub samples: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_signal_dbm: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_signal_dbm: Option<i32>,
+    /// Average PHY rate in the direction of the test, in Mbit/s.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_phy_rate_mbps: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_phy_rate_mbps: Option<f64>,
+    /// Frames we retransmitted during the run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_retries: Option<u64>,
+    /// Frames we gave up on after all retries during the run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_failed: Option<u64>,
+    /// Channels the link was on, in order, listing each change.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<u32>,
+}
+
+/// Interface counters sampled before and after the transfer, in the direction of the test.
+///
+/// Traffic beyond the test data and its estimated headers comes from retransmissions or from other
+/// applications on the host sharing the interface.
+#[derive(Serialize, Deserialize)]
+pub struct InterfaceCounters {
+    pub interface: String,
+    /// Bytes the test sent or received through the socket, including chunk framing.
+    pub application_bytes: u64,
+    /// Bytes the interface counted in the direction of the test, including link, IP, and TCP headers.
+    pub interface_bytes: u64,
+    pub packets: u64,
+    /// How much more the interface carried than the application, in percent.
+    pub overhead_percent: f64,
+    /// Header bytes expected for the packets counted.
+    pub header_bytes_estimate: u64,
+    /// Bytes neither the test data nor the estimated headers account for.
+    pub unexplained_bytes: u64,
+    /// Bytes the interface counted in the other direction, mostly acknowledgements.
+    pub reverse_bytes: u64,
+}
+
+/// Other traffic on the test interface during the run, from its counters sampled at a fixed
+/// interval against the test's own bytes.
+#[derive(Serialize, Deserialize)]
+pub struct CrossTraffic {
+    pub interface: String,
+    pub interval_seconds: f64,
+    /// Share of the test's own bytes other traffic had to reach for an interval to be flagged.
+    pub threshold_percent: f64,
+    pub intervals: usize,
+    /// Bytes in either direction that neither the test nor the estimated headers account for.
+    pub other_bytes: u64,
+    /// Intervals with significant other traffic, whose samples are marked in the charts.
+    pub flagged: Vec<CrossTrafficInterval>,
+    /// Samples left out of the latency and data rate distributions because they overlap a flagged
+    /// interval, when excluding them was requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excluded_samples: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CrossTrafficInterval {
+    pub start_offset_seconds: f64,
+    pub end_offset_seconds: f64,
+    pub other_bytes: u64,
+    pub other_bps: f64,
+    /// Bytes the test itself moved in the interval.
+    pub test_bytes: u64,
+}
+
+/// An object transferred in parts against an S3-compatible endpoint; each part is one sample.
+#[derive(Serialize, Deserialize)]
+pub struct ObjectStorage {
+    pub bucket: String,
+    pub key: String,
+    /// Size of each ranged GET or uploaded part, in bytes.
+    pub part_size: usize,
+    /// Parts transferred at once, each over its own connection.
+    pub concurrency: usize,
+    pub parts: usize,
+}
+
+/// What limited the transfer on the client, with the measurements the verdict is based on.
+#[derive(Serialize, Deserialize)]
+pub struct Bottleneck {
+    /// Human-readable attribution, e.g. "receive path was CPU-bound (the client kept its 2 cores
+    /// 87% busy)".
+    pub verdict: String,
+    /// CPU time used by the client during the transfer, as a percentage of one core.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<f64>,
+    /// Cores the client could run on, which the CPU time is judged against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cores: Option<usize>,
+    /// Share of the transfer spent writing to disk, in percent, when a file was written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_percent: Option<f64>,
+    /// Average bytes queued in our socket after each chunk: unread for a download, unsent for an upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_queue_bytes: Option<f64>,
+    /// Size of the socket buffer the queue was sampled from, in bytes.
+    pub socket_buffer_bytes: u64,
+}
+
+impl Bottleneck {
+    /// CPU time used by the client as a percentage of all the cores it could run on.
+    pub fn cpu_load_percent(&self) -> Option<f64> {
+        Some(self.cpu_percent? / self.cores? as f64)
+    }
+
+    /// Whether the client used nearly all the CPU time it had.
+    pub fn cpu_bound(&self) -> bool {
+        self.cpu_load_percent().is_some_and(|load| load >= crate::bottleneck::CPU_BOUND_FRACTION * 100.0)
+    }
+}
+
+/// A tuning change suggested by the evidence of a run.
+#[derive(Serialize, Deserialize)]
+pub struct Recommendation {
+    /// What the run showed, e.g. "The receive window (...) limited the data rate".
+    pub finding: String,
+    /// What to change, with the command or option to do it.
+    pub action: String,
+}
+
+/// A file downloaded from the server's disk, with the client's disk phase timed apart from the network.
+#[derive(Serialize, Deserialize)]
+pub struct FileTransfer {
+    /// Name of the file under the server's file root.
+    pub name: String,
+    pub bytes: u64,
+    /// Whether the server's end-of-test frame arrived at the end of the file.
+    pub complete: bool,
+    /// Where the file was written, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Time spent writing the file and flushing it to disk, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_write_seconds: Option<f64>,
+    /// Rate the file was written to disk at, in bits per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_write_bps: Option<f64>,
+}
+
+/// A dropped connection the test resumed from.
+#[derive(Serialize, Deserialize)]
+pub struct Reconnect {
+    /// Chunks completed before the connection dropped.
+    pub after_chunks: usize,
+    /// When the connection dropped, in seconds from the start of the run.
+    pub offset_seconds: f64,
+    /// Why the connection was considered lost.
+    pub error: String,
+    /// Connection attempts it took to resume.
+    pub attempts: u32,
+    /// Time from the drop until the test resumed, in seconds.
+    pub downtime_seconds: f64,
+}
+
+/// Download and upload measured together.
+#[derive(Serialize, Deserialize)]
+pub struct Asymmetry {
+    /// Average data rate of each direction, in bits per second, unless no data arrived in it.
+    pub download_throughput_bps: Option<f64>,
+    pub upload_throughput_bps: Option<f64>,
+    /// Download data rate divided by upload data rate, if both were measured.
+    pub ratio: Option<f64>,
+    /// Average chunk transfer time of each direction, in seconds, unless no chunk arrived in it.
+    pub download_latency_seconds: Option<f64>,
+    pub upload_latency_seconds: Option<f64>,
+    /// Average one-way delay of each direction while loaded, in seconds, if measured: from the
+    /// sender stamping a chunk to the receiver reading its first bytes, by the receiver's clock.
+    pub download_one_way_delay_seconds: Option<f64>,
+    pub upload_one_way_delay_seconds: Option<f64>,
+}
+
+impl Asymmetry {
+    pub fn new(download: &RunResult, upload: &RunResult) -> Self {
+        Asymmetry {
+            download_throughput_bps: download.throughput_bps,
+            upload_throughput_bps: upload.throughput_bps,
+            ratio: download.throughput_bps.zip(upload.throughput_bps).map(|(download, upload)| download / upload),
+            download_latency_seconds: download.latency_seconds,
+            upload_latency_seconds: upload.latency_seconds,
+            download_one_way_delay_seconds: download.one_way_delay_seconds,
+            upload_one_way_delay_seconds: upload.one_way_delay_seconds,
+        }
+    }
+}
+
+/// The direct path to the server measured against the path through a relay.
+#[derive(Serialize, Deserialize)]
+pub struct RelayComparison {
+    /// Server the relayed run went through.
+    pub relay: String,
+    /// Average data rate of each path, in bits per second, unless no data arrived on it.
+    pub direct_throughput_bps: Option<f64>,
+    pub relayed_throughput_bps: Option<f64>,
+    /// Relayed data rate divided by direct data rate, if both were measured; below one the
+    /// detour costs throughput.
+    pub ratio: Option<f64>,
+    /// Average chunk transfer time of each path, in seconds, unless no chunk arrived on it.
+    pub direct_latency_seconds: Option<f64>,
+    pub relayed_latency_seconds: Option<f64>,
+}
+
+impl RelayComparison {
+    pub fn new(relay: &str, direct: &RunResult, relayed: &RunResult) -> Self {
+        RelayComparison {
+            relay: relay.to_string(),
+            direct_throughput_bps: direct.throughput_bps,
+            relayed_throughput_bps: relayed.throughput_bps,
+            ratio: relayed.throughput_bps.zip(direct.throughput_bps).map(|(relayed, direct)| relayed / direct),
+            direct_latency_seconds: direct.latency_seconds,
+            relayed_latency_seconds: relayed.latency_seconds,
+        }
+    }
+}
+
+/// The direct path to the server measured against the path through a tunnel or VPN interface.
+#[derive(Serialize, Deserialize)]
+pub struct TunnelComparison {
+    /// Interface the tunneled run was bound to.
+    pub interface: String,
+    /// Server the tunneled run connected to, when it was given apart from the direct run's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// Average data rate of each path, in bits per second, unless no data arrived on it.
+    pub direct_throughput_bps: Option<f64>,
+    pub tunneled_throughput_bps: Option<f64>,
+    /// Share of the direct data rate lost in the tunnel, in percent, if both paths were
+    /// measured; negative if the tunnel was faster.
+    pub throughput_overhead_percent: Option<f64>,
+    /// Average chunk transfer time of each path, in seconds, unless no chunk arrived on it.
+    pub direct_latency_seconds: Option<f64>,
+    pub tunneled_latency_seconds: Option<f64>,
+    /// TCP round-trip time of each path, in seconds, where the OS reported it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_rtt_seconds: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunneled_rtt_seconds: Option<f64>,
+    /// Time to the first byte of data on each path, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_first_byte_seconds: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunneled_first_byte_seconds: Option<f64>,
+    pub direct_loss_percent: f64,
+    pub tunneled_loss_percent: f64,
+}
+
+impl TunnelComparison {
+    pub fn new(interface: &str, server: Option<&str>, direct: &RunResult, tunneled: &RunResult) -> Self {
+        let first_byte = |result: &RunResult| result.setup.as_ref().and_then(|setup| setup.first_byte_seconds);
+        TunnelComparison {
+            interface: interface.to_string(),
+            server: server.map(str::to_string),
+            direct_throughput_bps: direct.throughput_bps,
+            tunneled_throughput_bps: tunneled.throughput_bps,
+            throughput_overhead_percent: tunneled
+                .throughput_bps
+                .zip(direct.throughput_bps)
+                .map(|(tunneled, direct)| (1.0 - tunneled / direct) * 100.0),
+            direct_latency_seconds: direct.latency_seconds,
+            tunneled_latency_seconds: tunneled.latency_seconds,
+            direct_rtt_seconds: direct.rtt_seconds,
+            tunneled_rtt_seconds: tunneled.rtt_seconds,
+            direct_first_byte_seconds: first_byte(direct),
+            tunneled_first_byte_seconds: first_byte(tunneled),
+            direct_loss_percent: direct.loss_percent,
+            tunneled_loss_percent: tunneled.loss_percent,
+        }
+    }
+}
+
+/// Throughput across the runs of a repeated test.
+#[derive(Serialize, Deserialize)]
+pub struct RepeatSummary {
+    /// Average effective data rate of every run that received data, in order, in bits per second.
+    pub throughputs_bps: Vec<f64>,
+    pub median_throughput_bps: f64,
+    /// Bootstrap 95% confidence interval of the median, in bits per second.
+    pub median_ci_low_bps: f64,
+    pub median_ci_high_bps: f64,
+}
+
+/// Outcome of a series of ICMP echo requests.
+#[derive(Serialize, Deserialize)]
+pub struct PingSummary {
+    pub sent: usize,
+    pub received: usize,
+    pub loss_percent: f64,
+    /// Distribution of the round-trip times, in seconds, if any reply arrived.
+    pub rtt: Option<Summary>,
+}
+
+/// Reads and writes on the test connection, as counted by the client.
+#[derive(Serialize, Deserialize)]
+pub struct SocketIo {
+    /// Bytes read, chunk headers and acknowledgements included.
+    pub bytes_read: u64,
+    pub reads: u64,
+    /// Reads that returned fewer bytes than asked for, i.e. data arrived in smaller pieces.
+    pub short_reads: u64,
+    /// Bytes written, chunk headers included; the test request is not counted.
+    pub bytes_written: u64,
+    pub writes: u64,
+    /// Writes the socket accepted only part of.
+    pub short_writes: u64,
+    /// Payload bytes of the chunk the connection ended in, counted in `total_bytes`.
+    pub partial_chunk_bytes: u64,
+}
+
+/// Outcome of a series of UDP echo probes.
+#[derive(Serialize, Deserialize)]
+pub struct EchoSummary {
+    pub sent: usize,
+    pub received: usize,
+    pub loss_percent: f64,
+    /// Distribution of the round-trip times, in seconds, if any probe returned.
+    pub rtt: Option<Summary>,
+    /// Mean difference between consecutive round-trip times, in seconds.
+    pub jitter_seconds: Option<f64>,
+}
+
+/// A simulated call through the UDP echo service, rated with the E-model of ITU-T G.107.
+#[derive(Serialize, Deserialize)]
+pub struct VoipCall {
+    pub codec: Codec,
+    pub packets_sent: usize,
+    pub packets_received: usize,
+    /// UDP payload of each packet, RTP header included, in bytes.
+    pub packet_bytes: usize,
+    pub loss_percent: f64,
+    /// How much burstier the losses were than random loss at the same rate; 1 for random loss.
+    pub burst_ratio: f64,
+    /// Distribution of the round-trip times, in seconds, if any packet returned.
+    pub rtt: Option<Summary>,
+    /// Interarrival jitter of the returned packets as RFC 3550 defines it, in seconds.
+    pub jitter_seconds: f64,
+    /// Estimated one-way delay from speaker to listener, in seconds, if any packet returned.
+    pub mouth_to_ear_seconds: Option<f64>,
+    /// Transmission rating from 0 to 100.
+    pub r_factor: f64,
+    /// Mean opinion score from 1 to 4.5.
+    pub mos: f64,
+}
+
+/// Small, frequent packets like a game's through the UDP echo service, sent first on an idle link
+/// and then during a transfer.
+#[derive(Serialize, Deserialize)]
+pub struct Gaming {
+    /// Packets sent per second.
+    pub rate_hz: u32,
+    /// UDP payload of each packet, in bytes.
+    pub packet_bytes: usize,
+    pub idle: EchoSummary,
+    pub loaded: EchoSummary,
+    /// Median round-trip time under load less the idle one, in seconds, if packets returned in
+    /// both phases.
+    pub added_median_seconds: Option<f64>,
+    /// Same for the 95th percentile, in seconds.
+    pub added_p95_seconds: Option<f64>,
+    /// Loss under load less the idle loss, in percentage points.
+    pub added_loss_percent: f64,
+}
+
+/// Bandwidth of one direction estimated from how far the path spread out trains of UDP packets
+/// sent back-to-back.
+#[derive(Serialize, Deserialize)]
+pub struct DispersionEstimate {
+    pub direction: Direction,
+    pub trains_sent: usize,
+    /// Trains that arrived complete and with a measurable spread.
+    pub trains_used: usize,
+    pub packets_per_train: u16,
+    /// UDP payload of each packet, in bytes.
+    pub packet_bytes: usize,
+    /// The estimate, if any train could be used.
+    pub rate: Option<DispersionRate>,
+    /// Segmentation offload used for the trains, if it was requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offload: Option<UdpOffload>,
+}
+
+/// UDP segmentation offload of a dispersion estimate. Offload changes what the trains measure: GSO
+/// hands the kernel or NIC a whole train to pace, and GRO merges packets that arrive together,
+/// which then share one arrival time.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct UdpOffload {
+    /// Whether upload trains were sent with GSO (UDP_SEGMENT).
+    pub gso: bool,
+    /// Whether download trains were received with GRO.
+    pub gro: bool,
+    /// Packets that arrived merged with others by GRO.
+    pub coalesced_packets: usize,
+}
+
+impl fmt::Display for UdpOffload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.gso, self.gro) {
+            (true, _) => write!(f, "GSO active; trains were segmented by the kernel or NIC, so they left at its pace"),
+            (false, true) => write!(
+                f,
+                "GRO active, {} packets coalesced; coalesced packets share an arrival time, so the spread of their trains is understated",
+                self.coalesced_packets
+            ),
+            (false, false) => write!(f, "inactive; trains were sent and received packet by packet"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DispersionRate {
+    /// Median rate at which the trains arrived, in bits per second. Cross traffic spreads a train
+    /// out, so this falls between the bandwidth left over by other traffic and the capacity.
+    pub available_bps: f64,
+    /// 95% confidence interval of the median, in bits per second.
+    pub ci_low_bps: f64,
+    pub ci_high_bps: f64,
+    /// Median rate of the first two packets of each train, which are least affected by cross
+    /// traffic: an estimate of the capacity of the narrowest link, in bits per second.
+    pub pair_capacity_bps: f64,
+    pub confidence: Confidence,
+}
+
+/// How far a dispersion estimate can be relied on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::High => "high",
+            Confidence::Medium => "medium",
+            Confidence::Low => "low",
+        })
+    }
+}
+
+/// A test directly between two clients, each possibly behind a NAT, over the UDP path hole punching
+/// opened between them.
+#[derive(Serialize, Deserialize)]
+pub struct PeerTest {
+    pub session: String,
+    pub local_address: String,
+    /// Address the other client's traffic came from.
+    pub peer_address: String,
+    /// Whether the server's rendezvous introduced the clients, rather than a given address.
+    pub rendezvous: bool,
+    /// Time hole punching took until both clients heard from each other, in seconds.
+    pub punch_seconds: f64,
+    /// Whether this client sent the data; the receiver's counts are reported on both sides.
+    pub sender: bool,
+    /// UDP payload of each data packet, in bytes.
+    pub packet_bytes: usize,
+    /// Rate the sender paced its packets at, in bits per second, unless the receiver missed it.
+    pub target_rate_bps: Option<f64>,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    /// Data rate the receiver saw, in bits per second.
+    pub data_rate_bps: f64,
+    pub loss_percent: f64,
+}
+
+/// The public address a STUN server saw our UDP traffic come from.
+#[derive(Serialize, Deserialize)]
+pub struct StunMapping {
+    pub server: String,
+    pub local_address: String,
+    pub mapped_address: String,
+}
+
+impl StunMapping {
+    /// Whether a NAT rewrote the address or port on the way to the STUN server.
+    pub fn behind_nat(&self) -> bool {
+        self.local_address != self.mapped_address
+    }
+}
+
+/// The server picked from a list of candidates.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ServerSelection {
+    pub chosen: String,
+    pub candidates: Vec<Candidate>,
+}
+
+/// The SSH tunnel a run's connection was forwarded through.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SshTunnel {
+    /// SSH host, as `[USER@]HOST[:PORT]`.
+    pub destination: String,
+    /// Server address as seen from the SSH host, which the forward connects to.
+    pub target: String,
+    /// Time ssh took to log in and start forwarding, in seconds.
+    pub setup_seconds: f64,
+}
+
+/// A listed server and how quickly it could be reached.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub server: String,
+    /// Fastest TCP handshake to the server, in seconds, or `None` if it could not be reached.
+    pub rtt_seconds: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Stages of establishing the test session, in seconds.
+#[derive(Serialize, Deserialize)]
+pub struct ConnectionSetup {
+    /// Resolving the server address for the connection.
+    pub resolve_seconds: f64,
+    /// The TCP handshake, with the proxy when connecting through one.
+    pub connect_seconds: f64,
+    /// Having the proxy open the tunnel to the server (its handshake plus its own connection to the
+    /// server), if connecting through one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_seconds: Option<f64>,
+    /// From sending the test request to the first byte of the server's response (in an upload, the
+    /// first acknowledgement), if any arrived.
+    pub first_byte_seconds: Option<f64>,
+    /// Whether the request went in the SYN, when TCP Fast Open was requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fast_open: Option<FastOpen>,
+    /// The race between IPv6 and IPv4, when the server name has addresses of both.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub happy_eyeballs: Option<HappyEyeballs>,
+}
+
+/// What TCP Fast Open did for a connection. With it, `connect_seconds` ends before the handshake
+/// and `first_byte_seconds` covers both.
+#[derive(Serialize, Deserialize)]
+pub struct FastOpen {
+    /// The server acknowledged the data in our SYN.
+    pub used: bool,
+    /// Round trips saved on setup: the one the request no longer waits for the handshake.
+    pub round_trips_saved: u32,
+    /// Time saved, estimated as the connection's lowest RTT.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saved_seconds: Option<f64>,
+}
+
+/// How the Happy Eyeballs race between the server's IPv6 and IPv4 addresses went.
+#[derive(Serialize, Deserialize)]
+pub struct HappyEyeballs {
+    /// Address the test connected to.
+    pub winner: SocketAddr,
+    /// The winning attempt's TCP handshake, in seconds.
+    pub handshake_seconds: f64,
+    /// Connection attempts started until one connected.
+    pub attempts: usize,
+    /// First address of the other family whose attempt finished, if any did in time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other: Option<SocketAddr>,
+    /// Its TCP handshake, in seconds, if it connected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other_handshake_seconds: Option<f64>,
+    /// How much longer its handshake took than the winner's; negative when it would have been
+    /// faster but started too late to win.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin_seconds: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other_error: Option<String>,
+}
+
+/// A timed name lookup of one record type.
+#[derive(Serialize, Deserialize)]
+pub struct DnsLookup {
+    /// `A` or `AAAA`.
+    pub record_type: String,
+    pub seconds: f64,
+    pub addresses: Vec<IpAddr>,
+    /// Resolver error, e.g. when the name has no records of this type.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Hops on the way to the server.
+#[derive(Serialize, Deserialize)]
+pub struct Traceroute {
+    pub mode: TraceMode,
+    pub hops: Vec<Hop>,
+    /// Whether the last hop is the server; otherwise the trace gave up at the hop limit.
+    pub reached: bool,
+}
+
+/// One TTL step of a traceroute.
+#[derive(Serialize, Deserialize)]
+pub struct Hop {
+    pub ttl: u8,
+    /// Address that answered the probes, or `None` if none was answered.
+    pub address: Option<IpAddr>,
+    /// Round-trip time of every answered probe, in seconds.
+    pub rtts_seconds: Vec<f64>,
+}
+
+/// A sample flagged by outlier detection.
+#[derive(Serialize, Deserialize)]
+pub struct Outlier {
+    /// Chunk number, or summary window number in a soak test, starting at 1.
+    pub sample: usize,
+    /// Time from the start of the run to the end of the sample, in seconds.
+    pub offset_seconds: f64,
+    /// Wall-clock time of the end of the sample, in RFC 3339 format (UTC).
+    pub timestamp: String,
+    pub latency_seconds: f64,
+}
+
+/// Samples merged from small chunks with `--adaptive-chunks`, each sized to take about the target
+/// time at the data rate measured before it.
+#[derive(Serialize, Deserialize)]
+pub struct AdaptiveSampling {
+    /// Size of the chunks the server sent, in bytes.
+    pub chunk_bytes: usize,
+    /// Time each sample was sized to take, in seconds.
+    pub target_seconds: f64,
+    pub samples: usize,
+    pub min_sample_bytes: usize,
+    pub max_sample_bytes: usize,
+    /// Average time the samples took, in seconds.
+    pub mean_sample_seconds: f64,
+}
+
+/// A download played out as a constant-bitrate stream through a player's buffer.
+#[derive(Serialize, Deserialize)]
+pub struct Streaming {
+    /// Bitrate of the stream, in bits per second.
+    pub bitrate_bps: f64,
+    /// Media time buffered before playback starts or resumes, in seconds.
+    pub startup_seconds: f64,
+    /// Media time the buffer holds at most, in seconds.
+    pub buffer_seconds: f64,
+    /// Time from the start of the transfer until playback started, in seconds; `None` if it never
+    /// did.
+    pub startup_delay_seconds: Option<f64>,
+    /// Media time played, in seconds.
+    pub played_seconds: f64,
+    /// Stalls after playback started, while the buffer refilled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rebuffers: Vec<Rebuffer>,
+    /// Total time playback was stalled, in seconds.
+    pub rebuffering_seconds: f64,
+    /// Share of the time since playback started that it was stalled.
+    pub rebuffer_ratio_percent: f64,
+    /// Average and lowest buffer level once playback started, in seconds of media.
+    pub mean_buffer_seconds: Option<f64>,
+    pub min_buffer_seconds: Option<f64>,
+}
+
+/// A stall of a simulated stream.
+#[derive(Serialize, Deserialize)]
+pub struct Rebuffer {
+    /// Offset from the start of the transfer at which the buffer ran dry, in seconds.
+    pub offset_seconds: f64,
+    /// How long playback waited for the buffer to refill, in seconds.
+    pub duration_seconds: f64,
+}
+
+/// Where and how a stalled transfer was aborted.
+#[derive(Serialize, Deserialize)]
+pub struct Stall {
+    /// Bytes received before the transfer stalled.
+    pub after_bytes: u64,
+    /// How long no data arrived before the transfer was aborted, in seconds.
+    pub timeout_seconds: f64,
+    /// Kernel TCP state at the moment the stall was detected, where available.
+    pub tcp_info: Option<TcpInfo>,
+}
+
+impl RunResult {
+    /// Writes the result as pretty-printed JSON to `path`.
+    pub fn write_json(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Reads a result previously written with `write_json`.
+    pub fn read_json(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// Generates an ID for a run started at `started_at`: the start time to the second, so IDs sort
+/// by start, and a random suffix that keeps runs started in the same second apart, e.g.
+/// `20261015T052218Z-3fa9c1`.
+pub fn new_run_id(started_at: SystemTime) -> String {
+    format!("{}-{:06x}", compact_timestamp(started_at), rand::thread_rng().gen_range(0..0x100_0000u32))
+}
+
+/// Formats `at` as a basic-format ISO 8601 UTC timestamp to the second, e.g. `20261015T052218Z`,
+/// which is safe in file names.
+pub fn compact_timestamp(at: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(at).to_string().chars().filter(|c| *c != '-' && *c != ':').collect()
+}
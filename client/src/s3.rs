@@ -0,0 +1,472 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use common::payload;
+use common::protocol::Direction;
+use crate::connection;
+use crate::html;
+use crate::plot::{self, Marks, SampleAxis};
+use crate::report::Chart;
+use crate::results::{self, ObjectStorage, RunResult};
+use crate::sigv4::{self, Credentials};
+use crate::soak::RotatingCsv;
+use crate::transfer::{self, Outcome, Outputs};
+use crate::{chart_theme, connect_options, output, Args};
+
+/// Hex SHA-256 of an empty body.
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Upper bound on a response head, so a misbehaving endpoint cannot make us buffer forever.
+const MAX_HEADER_LINES: usize = 200;
+
+/// An S3-compatible endpoint and the object tested against it.
+struct Target {
+    /// `host:port` of the endpoint.
+    authority: String,
+    /// Path of the object, path-style and URI-encoded: `/bucket/key`.
+    path: String,
+    region: String,
+    credentials: Credentials,
+}
+
+/// A part transferred by a worker.
+struct Part {
+    number: usize,
+    bytes: usize,
+    /// When the request was sent, relative to the start of the run.
+    started: Duration,
+    /// Time from sending the request to the end of the response.
+    elapsed: Duration,
+    /// ETag of an uploaded part, needed to complete the upload.
+    etag: Option<String>,
+}
+
+/// Measures multi-part GET or PUT throughput against an S3-compatible endpoint.
+///
+/// A download fetches the object in ranged GETs of the part size; an upload creates a multipart
+/// upload of `--s3-parts` parts and completes it. `--s3-concurrency` workers, each on its own
+/// connection, transfer the parts, and every part is one sample in the usual metrics, CSV, and chart.
+///
+/// # Returns
+/// - The run summary, with the data rate taken over the wall-clock time of all parts together.
+pub fn run_test(args: &Args, outputs: &Outputs, direction: Direction) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let target = target(args)?;
+    let part_size = args.s3_part_size as usize;
+    let concurrency = args.s3_concurrency as usize;
+
+    let mut control = HttpConnection::open(&target.authority, args)?;
+    let local_address = control.stream.get_ref().local_addr()?;
+    println!("Connected to S3 endpoint {} from {}", target.authority, local_address);
+
+    let (parts, upload_id, payload) = match direction {
+        Direction::Download => {
+            let size = object_size(&mut control, &target)?;
+            println!("Object {} is {} bytes", target.path, size);
+            (size.div_ceil(part_size as u64), None, Vec::new())
+        }
+        Direction::Upload => {
+            let upload_id = create_upload(&mut control, &target)?;
+            let mut payload = vec![0u8; part_size];
+            if let Some(seed) = args.payload_seed.or_else(|| if args.random_payload { Some(rand::random()) } else { None }) {
+                payload::fill_seeded(&mut payload, seed);
+            }
+            (u64::from(args.s3_parts), Some(upload_id), payload)
+        }
+    };
+    let parts = parts as usize;
+    println!("Transferring {} parts of up to {} bytes with {} workers", parts, part_size, concurrency);
+
+    let started_at = SystemTime::now();
+    let run_id = results::new_run_id(started_at);
+    let tags = output::tags(args);
+    let run_start = Instant::now();
+    let next = Arc::new(AtomicUsize::new(1));
+    let payload = Arc::new(payload);
+    let payload_hash = Arc::new(sigv4::hex(&sigv4::sha256(&payload)));
+    let target = Arc::new(target);
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut workers = Vec::new();
+    for _ in 0..concurrency.min(parts.max(1)) {
+        let worker = Worker {
+            connection: HttpConnection::open(&target.authority, args)?,
+            target: Arc::clone(&target),
+            next: Arc::clone(&next),
+            parts,
+            part_size,
+            upload_id: upload_id.clone(),
+            payload: Arc::clone(&payload),
+            payload_hash: Arc::clone(&payload_hash),
+            run_start,
+            done: done_tx.clone(),
+        };
+        workers.push(thread::spawn(move || worker.run()));
+    }
+    drop(done_tx);
+
+    let label = match direction {
+        Direction::Download => "GET",
+        Direction::Upload => "PUT",
+    };
+    let mut wtr = RotatingCsv::new(
+        &outputs.metrics_csv,
+        &["Part", &format!("{} Time (s)", label), "Effective Data Rate (bps)", "Offset (s)", "Timestamp"],
+        None,
+        (!args.per_run_files).then_some(run_id.as_str()),
+        &tags,
+    )?;
+    let mut completed = Vec::new();
+    for part in done_rx {
+        let seconds = part.elapsed.as_secs_f64();
+        let rate = part.bytes as f64 * 8.0 / seconds;
+        let offset = part.started + part.elapsed;
+        println!("Part {}: {} Time: {:.3}s, Effective Data Rate: {:.2} bps", part.number, label, seconds, rate);
+        wtr.write_record(&[
+            part.number.to_string(),
+            seconds.to_string(),
+            rate.to_string(),
+            offset.as_secs_f64().to_string(),
+            humantime::format_rfc3339_micros(started_at + offset).to_string(),
+        ])?;
+        completed.push(part);
+    }
+    let wall_time = run_start.elapsed();
+    wtr.flush()?;
+    wtr.report(label);
+
+    let mut transfer_error = None;
+    for worker in workers {
+        match worker.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                eprintln!("Worker stopped: {}", e);
+                transfer_error.get_or_insert(e);
+            }
+            Err(_) => return Err("S3 worker thread panicked".into()),
+        }
+    }
+    if let Some(upload_id) = &upload_id {
+        let finished = if transfer_error.is_none() {
+            complete_upload(&mut control, &target, upload_id, &mut completed)
+        } else {
+            abort_upload(&mut control, &target, upload_id)
+        };
+        if let Err(e) = finished {
+            eprintln!("Finishing the multipart upload failed: {}", e);
+            transfer_error.get_or_insert(e);
+        }
+    }
+
+    // In completion order, so the samples follow each other in time on the chart
+    completed.sort_by_key(|part| part.started + part.elapsed);
+    let latencies: Vec<f64> = completed.iter().map(|part| part.elapsed.as_secs_f64()).collect();
+    let data_rates: Vec<f64> = completed.iter().map(|part| part.bytes as f64 * 8.0 / part.elapsed.as_secs_f64()).collect();
+    let offsets: Vec<f64> = completed.iter().map(|part| (part.started + part.elapsed).as_secs_f64()).collect();
+    let total_bytes: usize = completed.iter().map(|part| part.bytes).sum();
+    // Parts overlap, so the aggregate rate is taken over the wall-clock time rather than per part
+    let throughput = total_bytes as f64 * 8.0 / wall_time.as_secs_f64();
+    let avg_latency = if latencies.is_empty() { None } else { Some(latencies.iter().sum::<f64>() / latencies.len() as f64) };
+    let loss_percent = (parts - completed.len()) as f64 / parts.max(1) as f64 * 100.0;
+
+    println!("Total Data Transferred: {}", args.units.size(total_bytes as f64));
+    println!("Aggregate Data Rate: {:.2} bps ({}) over {:.3}s with {} workers", throughput, args.units.rate(throughput), wall_time.as_secs_f64(), concurrency);
+    let analysis = transfer::analyze_samples(label, "Part", &latencies, &data_rates, &offsets, started_at, args.outlier_k, &[]);
+    let mut charts = Vec::new();
+    if latencies.is_empty() {
+        println!("No parts completed, skipping chart");
+    } else {
+        let positions = args.x_axis.positions(&offsets, started_at);
+        let x = SampleAxis { kind: args.x_axis, positions: &positions, sample_desc: "Part Number" };
+        let marks = Marks { outliers: &analysis.outlier_indices, cross_traffic: &[] };
+        plot::plot_latency_and_data_rate(&outputs.chart, &latencies, &data_rates, &marks, &x, &chart_theme(args))?;
+        charts.push(Chart { title: "Latency and effective data rate", path: outputs.chart.clone() });
+        if args.html {
+            html::write_latency_and_data_rate(
+                &outputs.html_chart,
+                &latencies,
+                &data_rates,
+                &marks,
+                &x,
+                &chart_theme(args),
+                args.plotly_js.as_deref(),
+            )?;
+            charts.push(Chart { title: "Interactive latency and effective data rate", path: outputs.html_chart.clone() });
+        }
+    }
+
+    let result = RunResult {
+        direction,
+        started_at: humantime::format_rfc3339_micros(started_at).to_string(),
+        run_id,
+        tags,
+        server: args.s3_endpoint.clone().unwrap_or_default(),
+        local_address: local_address.to_string(),
+        interface: args.interface.clone(),
+        chunks_expected: parts,
+        chunks_received: completed.len(),
+        total_bytes: total_bytes as u64,
+        throughput_bps: Some(throughput),
+        latency_seconds: avg_latency,
+        loss_percent,
+        latency_stats: analysis.latency_stats,
+        throughput_stats: analysis.throughput_stats,
+        outliers: analysis.outliers,
+        throughput_cv: analysis.throughput_cv,
+        proxy: args.proxy.as_ref().map(|proxy| proxy.to_string()),
+        object_storage: Some(ObjectStorage {
+            bucket: args.s3_bucket.clone().unwrap_or_default(),
+            key: args.s3_key.clone(),
+            part_size,
+            concurrency,
+            parts,
+        }),
+        ..RunResult::default()
+    };
+    Ok(Outcome { result, transfer_error, latencies, data_rates, offsets, charts })
+}
+
+/// Resolves the endpoint, object path, and credentials from the command line and environment.
+fn target(args: &Args) -> Result<Target, Box<dyn std::error::Error>> {
+    let endpoint = args.s3_endpoint.as_deref().unwrap_or_default();
+    let authority = match endpoint.strip_prefix("http://") {
+        Some(authority) => authority.trim_end_matches('/'),
+        None if endpoint.starts_with("https://") => {
+            return Err("TLS is not supported; use an http:// endpoint or a local TLS-terminating proxy".into())
+        }
+        None => return Err(format!("S3 endpoint '{}' must start with http://", endpoint).into()),
+    };
+    let authority = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+    let bucket = args.s3_bucket.as_deref().ok_or("--s3-endpoint requires --s3-bucket")?;
+    let access_key = std::env::var("AWS_ACCESS_KEY_ID").map_err(|_| "set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY for the S3 endpoint")?;
+    let secret_key = std::env::var("AWS_SECRET_ACCESS_KEY").map_err(|_| "set AWS_SECRET_ACCESS_KEY for the S3 endpoint")?;
+    Ok(Target {
+        authority,
+        path: format!("/{}/{}", sigv4::uri_encode(bucket, true), sigv4::uri_encode(&args.s3_key, false)),
+        region: args.s3_region.clone(),
+        credentials: Credentials { access_key, secret_key, session_token: std::env::var("AWS_SESSION_TOKEN").ok() },
+    })
+}
+
+/// Transfers parts until none are left, each on the worker's own connection.
+struct Worker {
+    connection: HttpConnection,
+    target: Arc<Target>,
+    /// Number of the next part to transfer, shared by all workers; parts are numbered from 1.
+    next: Arc<AtomicUsize>,
+    parts: usize,
+    part_size: usize,
+    /// ID of the multipart upload for an upload, `None` for a download.
+    upload_id: Option<String>,
+    payload: Arc<Vec<u8>>,
+    payload_hash: Arc<String>,
+    run_start: Instant,
+    done: Sender<Part>,
+}
+
+impl Worker {
+    fn run(mut self) -> io::Result<()> {
+        loop {
+            let number = self.next.fetch_add(1, Ordering::Relaxed);
+            if number > self.parts {
+                return Ok(());
+            }
+            let started = self.run_start.elapsed();
+            let (bytes, etag) = match &self.upload_id {
+                None => {
+                    let first = (number - 1) as u64 * self.part_size as u64;
+                    let range = format!("bytes={}-{}", first, first + self.part_size as u64 - 1);
+                    let response =
+                        self.connection.send(&self.target, "GET", &[], &[("range", &range)], &[], EMPTY_SHA256)?.expect_success()?;
+                    (response.body.len(), None)
+                }
+                Some(upload_id) => {
+                    let number = number.to_string();
+                    let query = [("partNumber", number.as_str()), ("uploadId", upload_id.as_str())];
+                    let response =
+                        self.connection.send(&self.target, "PUT", &query, &[], &self.payload, &self.payload_hash)?.expect_success()?;
+                    (self.payload.len(), response.header("etag").map(str::to_string))
+                }
+            };
+            let elapsed = self.run_start.elapsed() - started;
+            if self.done.send(Part { number, bytes, started, elapsed, etag }).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn object_size(connection: &mut HttpConnection, target: &Target) -> io::Result<u64> {
+    let response = connection.send(target, "HEAD", &[], &[], &[], EMPTY_SHA256)?.expect_success()?;
+    response
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "HEAD response has no Content-Length"))
+}
+
+fn create_upload(connection: &mut HttpConnection, target: &Target) -> io::Result<String> {
+    let response = connection.send(target, "POST", &[("uploads", "")], &[], &[], EMPTY_SHA256)?.expect_success()?;
+    xml_element(&response.body, "UploadId").ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "no UploadId in the response"))
+}
+
+fn complete_upload(connection: &mut HttpConnection, target: &Target, upload_id: &str, parts: &mut [Part]) -> io::Result<()> {
+    parts.sort_by_key(|part| part.number);
+    let mut body = String::from("<CompleteMultipartUpload>");
+    for part in parts.iter() {
+        let etag = part.etag.as_deref().unwrap_or_default();
+        body.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", part.number, etag));
+    }
+    body.push_str("</CompleteMultipartUpload>");
+    let hash = sigv4::hex(&sigv4::sha256(body.as_bytes()));
+    let response = connection.send(target, "POST", &[("uploadId", upload_id)], &[], body.as_bytes(), &hash)?.expect_success()?;
+    // S3 can report a failed completion with a 200 status and an error document
+    match xml_element(&response.body, "Code") {
+        Some(code) => Err(io::Error::other(format!("completing the upload failed: {}", code))),
+        None => Ok(()),
+    }
+}
+
+fn abort_upload(connection: &mut HttpConnection, target: &Target, upload_id: &str) -> io::Result<()> {
+    connection.send(target, "DELETE", &[("uploadId", upload_id)], &[], &[], EMPTY_SHA256)?.expect_success().map(|_| ())
+}
+
+/// Returns the text of the first `<name>` element in an XML document.
+fn xml_element(document: &[u8], name: &str) -> Option<String> {
+    let document = String::from_utf8_lossy(document);
+    let start = document.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + document[start..].find(&format!("</{}>", name))?;
+    Some(document[start..end].to_string())
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// Returns the value of a header, matching its name case-insensitively.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Turns an error status into an error carrying the service's error code.
+    fn expect_success(self) -> io::Result<Self> {
+        if (200..300).contains(&self.status) {
+            return Ok(self);
+        }
+        let code = xml_element(&self.body, "Code").map(|code| format!(" ({})", code)).unwrap_or_default();
+        Err(io::Error::other(format!("S3 request failed with status {}{}", self.status, code)))
+    }
+}
+
+/// A persistent HTTP/1.1 connection to the endpoint.
+struct HttpConnection {
+    stream: BufReader<TcpStream>,
+}
+
+impl HttpConnection {
+    fn open(authority: &str, args: &Args) -> io::Result<Self> {
+        let (stream, _) = connection::connect(authority, &connect_options(args))?;
+        Ok(HttpConnection { stream: BufReader::new(stream) })
+    }
+
+    /// Sends a signed request for the target object and reads the response.
+    ///
+    /// # Arguments
+    /// - `query`: Query parameters, not yet encoded.
+    /// - `extra_headers`: Headers to send and sign besides the ones every request carries.
+    /// - `payload_hash`: Hex SHA-256 of `body`.
+    fn send(
+        &mut self,
+        target: &Target,
+        method: &str,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, &str)],
+        body: &[u8],
+        payload_hash: &str,
+    ) -> io::Result<Response> {
+        let amz_date: String = humantime::format_rfc3339_seconds(SystemTime::now()).to_string().chars().filter(|c| *c != '-' && *c != ':').collect();
+        let mut headers = vec![("host", target.authority.as_str()), ("x-amz-content-sha256", payload_hash), ("x-amz-date", amz_date.as_str())];
+        if let Some(token) = &target.credentials.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        headers.extend_from_slice(extra_headers);
+        let signed = sigv4::Request { method, path: &target.path, query, headers: &headers, payload_hash };
+        let authorization = sigv4::authorization(&signed, &target.credentials, &amz_date, &target.region);
+
+        let query = sigv4::canonical_query(query);
+        let mut head = format!("{} {}{}{} HTTP/1.1\r\n", method, target.path, if query.is_empty() { "" } else { "?" }, query);
+        for (name, value) in &headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("authorization: {}\r\ncontent-length: {}\r\n\r\n", authorization, body.len()));
+        let stream = self.stream.get_mut();
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+
+        self.read_response(method == "HEAD")
+    }
+
+    fn read_response(&mut self, head_only: bool) -> io::Result<Response> {
+        let status_line = self.read_line()?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("malformed HTTP status line '{}'", status_line)))?;
+        let mut headers = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADER_LINES {
+                return Err(io::Error::new(ErrorKind::InvalidData, "HTTP response head too long"));
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        let mut response = Response { status, headers, body: Vec::new() };
+        if head_only {
+            return Ok(response);
+        }
+        if response.header("transfer-encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked")) {
+            response.body = self.read_chunked()?;
+        } else if let Some(length) = response.header("content-length") {
+            let length: usize = length.parse().map_err(|_| io::Error::new(ErrorKind::InvalidData, "invalid Content-Length"))?;
+            response.body = vec![0u8; length];
+            self.stream.read_exact(&mut response.body)?;
+        }
+        Ok(response)
+    }
+
+    fn read_chunked(&mut self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            let size = usize::from_str_radix(line.split(';').next().unwrap_or_default().trim(), 16)
+                .map_err(|_| io::Error::new(ErrorKind::InvalidData, "invalid chunk size"))?;
+            if size == 0 {
+                // Skip any trailers up to the blank line ending the body
+                while !self.read_line()?.is_empty() {}
+                return Ok(body);
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            self.stream.read_exact(&mut body[start..])?;
+            self.read_line()?;
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
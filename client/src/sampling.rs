@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+use crate::results::AdaptiveSampling;
+
+/// Size of the chunks requested from the server with `--adaptive-chunks`: small enough that one
+/// takes about 130 ms at 1 Mbit/s, so even the slowest links are sampled finely.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Factor by which a sample may be larger than the one before it.
+const MAX_GROWTH: usize = 4;
+
+/// One measurement sample, merged from consecutive chunks.
+pub struct Sample {
+    /// Time the chunks took to transfer, as they are timed on their own.
+    pub duration: Duration,
+    pub bytes: usize,
+    /// When the last chunk of the sample was complete.
+    pub end: Instant,
+}
+
+/// Merges consecutive chunks into samples that each take about a target duration, sizing every
+/// sample from the data rate of the one before it: a few chunks on a slow link, thousands on a
+/// fast one.
+///
+/// A sample grows at most `MAX_GROWTH` times over the one before, since a short sample can catch a
+/// burst that is far faster than the link, and it ends early once it has taken the target time.
+pub struct AdaptiveSampler {
+    target: Duration,
+    chunk_size: usize,
+    /// Bytes after which the current sample is complete.
+    sample_bytes: usize,
+    bytes: usize,
+    duration: Duration,
+    end: Option<Instant>,
+    samples: usize,
+    sample_seconds_sum: f64,
+    min_sample_bytes: usize,
+    max_sample_bytes: usize,
+}
+
+impl AdaptiveSampler {
+    /// Starts with a sample of a single chunk, since nothing is known about the rate yet.
+    pub fn new(target: Duration, chunk_size: usize) -> Self {
+        AdaptiveSampler {
+            target,
+            chunk_size,
+            sample_bytes: chunk_size,
+            bytes: 0,
+            duration: Duration::ZERO,
+            end: None,
+            samples: 0,
+            sample_seconds_sum: 0.0,
+            min_sample_bytes: usize::MAX,
+            max_sample_bytes: 0,
+        }
+    }
+
+    /// Adds a chunk to the current sample.
+    ///
+    /// # Returns
+    /// - The sample, if the chunk completes it.
+    pub fn record(&mut self, duration: Duration, bytes: usize, end: Instant) -> Option<Sample> {
+        self.duration += duration;
+        self.bytes += bytes;
+        self.end = Some(end);
+        if self.bytes < self.sample_bytes && self.duration < self.target {
+            return None;
+        }
+        let sample = self.take();
+        if let Some(sample) = &sample {
+            // Rounded to whole chunks, since a sample can only end at a chunk boundary
+            let rate = sample.bytes as f64 / sample.duration.as_secs_f64().max(f64::EPSILON);
+            let chunks = (rate * self.target.as_secs_f64() / self.chunk_size as f64).round().max(1.0);
+            let bytes = (chunks as usize).saturating_mul(self.chunk_size);
+            self.sample_bytes = bytes.min(sample.bytes.saturating_mul(MAX_GROWTH));
+        }
+        sample
+    }
+
+    /// Ends the run, completing the last sample with whatever chunks it has.
+    pub fn finish(&mut self) -> Option<Sample> {
+        self.take()
+    }
+
+    /// How the chunks were merged, once the run is over.
+    pub fn summary(&self) -> AdaptiveSampling {
+        AdaptiveSampling {
+            chunk_bytes: self.chunk_size,
+            target_seconds: self.target.as_secs_f64(),
+            samples: self.samples,
+            min_sample_bytes: if self.samples == 0 { 0 } else { self.min_sample_bytes },
+            max_sample_bytes: self.max_sample_bytes,
+            mean_sample_seconds: if self.samples == 0 { 0.0 } else { self.sample_seconds_sum / self.samples as f64 },
+        }
+    }
+
+    fn take(&mut self) -> Option<Sample> {
+        let end = self.end.take()?;
+        let (duration, bytes) = (std::mem::take(&mut self.duration), std::mem::take(&mut self.bytes));
+        if bytes == 0 {
+            return None;
+        }
+        let sample = Sample { duration, bytes, end };
+        self.samples += 1;
+        self.sample_seconds_sum += sample.duration.as_secs_f64();
+        self.min_sample_bytes = self.min_sample_bytes.min(sample.bytes);
+        self.max_sample_bytes = self.max_sample_bytes.max(sample.bytes);
+        Some(sample)
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::anomaly::{self, Monitor};
+use crate::coordinator;
+use crate::results::RunResult;
+use crate::Args;
+
+/// Results the API returns when the request does not give a limit.
+const DEFAULT_LIMIT: usize = 100;
+/// How far ahead a schedule is searched for its next run; covers leap days.
+const SEARCH_MINUTES: u64 = 5 * 366 * 24 * 60;
+
+/// A named test run on a schedule, as given in the schedule file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JobConfig {
+    pub name: String,
+    /// Cron expression in UTC: minute, hour, day of month, month, and day of week.
+    pub schedule: String,
+    /// Client options of the test, as on the command line.
+    pub args: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    jobs: Vec<JobConfig>,
+}
+
+/// One run of a job, as kept in the history file.
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    pub job: String,
+    pub started_at: String,
+    pub exit_code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<RunResult>,
+}
+
+/// A job ready to run: its configuration, parsed options, schedule, and next run.
+struct Job {
+    config: JobConfig,
+    args: Args,
+    cron: Cron,
+    next: SystemTime,
+    /// Moving averages of the job's completed runs, if anomalies are flagged.
+    monitor: Option<Monitor>,
+}
+
+/// Runs the jobs of the schedule file `config` forever, one at a time, appending every run to
+/// `args.history` and serving the jobs and their history on `args.api` if given.
+///
+/// Jobs due at the same time run one after another, and runs missed while another job was running
+/// are skipped rather than caught up on. With `args.anomaly_sigmas`, every job's moving averages
+/// are learned from its runs in the history first, and each new run is judged against them.
+pub fn run(args: &Args, config: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut jobs = load(config)?;
+    let history = args.history.clone();
+    if let Some(sigmas) = args.anomaly_sigmas {
+        let settings = anomaly::Settings { sigmas, alpha: args.anomaly_alpha, warmup: args.anomaly_warmup };
+        learn(&mut jobs, settings, &history)?;
+    }
+    // The API reads the job list from here, with the next run the scheduler has picked for each
+    let published: Arc<Mutex<Vec<serde_json::Value>>> = Arc::new(Mutex::new(Vec::new()));
+    if let Some(address) = args.api {
+        serve_api(address, Arc::clone(&published), history.clone())?;
+    }
+    println!("Scheduler running {} jobs, history in {}", jobs.len(), history.display());
+
+    loop {
+        *published.lock().unwrap_or_else(PoisonError::into_inner) = jobs.iter().map(describe).collect();
+        let job = jobs.iter_mut().min_by_key(|job| job.next).ok_or("the schedule has no jobs")?;
+        thread::sleep(job.next.duration_since(SystemTime::now()).unwrap_or_default());
+
+        let started_at = SystemTime::now();
+        println!("=== Job {} at {} ===", job.config.name, humantime::format_rfc3339_seconds(started_at));
+        let report = coordinator::run_test(Args { monitor: job.monitor.clone(), ..job.args.clone() });
+        println!("Job {} finished with exit code {}", job.config.name, report.exit_code);
+        if let (Some(monitor), Some(result), None) = (&mut job.monitor, &report.result, &report.error) {
+            monitor.update(result);
+        }
+        let record = Record {
+            job: job.config.name.clone(),
+            started_at: humantime::format_rfc3339_micros(started_at).to_string(),
+            exit_code: report.exit_code,
+            error: report.error,
+            result: report.result,
+        };
+        if let Err(e) = append(&history, &record) {
+            eprintln!("Failed to record job {} in {}: {}", job.config.name, history.display(), e);
+        }
+        job.next = job.cron.next_after(SystemTime::now()).ok_or("schedule has no next run")?;
+    }
+}
+
+/// Reads the schedule file and checks every job's schedule and options before anything runs.
+fn load(path: &str) -> Result<Vec<Job>, Box<dyn std::error::Error>> {
+    let config: Config = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|e| format!("{} is not a valid schedule file: {}", path, e))?;
+    let mut names = HashSet::new();
+    let now = SystemTime::now();
+    let mut jobs = Vec::new();
+    for config in config.jobs {
+        if !names.insert(config.name.clone()) {
+            return Err(format!("job {} is defined twice", config.name).into());
+        }
+        let cron: Cron = config.schedule.parse().map_err(|e| format!("job {}: {}", config.name, e))?;
+        let next = cron.next_after(now).ok_or_else(|| format!("job {}: '{}' never matches a date", config.name, config.schedule))?;
+        let args = coordinator::parse_test(config.args.clone()).map_err(|e| format!("job {}: {}", config.name, e))?;
+        jobs.push(Job { config, args, cron, next, monitor: None });
+    }
+    Ok(jobs)
+}
+
+/// Sets up anomaly detection for every job, learning from the job's completed runs in the history
+/// in the order they ran.
+fn learn(jobs: &mut [Job], settings: anomaly::Settings, history: &Path) -> io::Result<()> {
+    let mut records = read_history(history)?;
+    records.reverse();
+    for job in jobs {
+        let mut monitor = Monitor::new(settings);
+        for record in records.iter().filter(|record| record.job == job.config.name && record.error.is_none()) {
+            if let Some(result) = &record.result {
+                monitor.update(result);
+            }
+        }
+        job.monitor = Some(monitor);
+    }
+    Ok(())
+}
+
+fn describe(job: &Job) -> serde_json::Value {
+    json!({
+        "name": job.config.name,
+        "schedule": job.config.schedule,
+        "args": job.config.args,
+        "next_run": humantime::format_rfc3339_seconds(job.next).to_string(),
+    })
+}
+
+fn append(history: &Path, record: &Record) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(history)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Reads the history, newest first, skipping lines that are not records (e.g. one cut short by a crash).
+pub fn read_history(history: &Path) -> io::Result<Vec<Record>> {
+    let file = match File::open(history) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(record) = serde_json::from_str(&line?) {
+            records.push(record);
+        }
+    }
+    records.reverse();
+    Ok(records)
+}
+
+/// A cron schedule with minute resolution, evaluated in UTC.
+///
+/// Every field takes `*`, values, ranges (`1-5`), steps (`*/15`, `0-30/10`), and lists of them
+/// (`0,30`); Sunday is 0 or 7 in the day of week.
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Both day fields are restricted, so a day matching either is enough, as in cron.
+    either_day: bool,
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("'{}' is not a cron expression of five fields: minute hour day month weekday", s));
+        }
+        let weekdays = field(fields[4], 0, 7)?;
+        Ok(Cron {
+            minutes: field(fields[0], 0, 59)?,
+            hours: field(fields[1], 0, 23)?,
+            days: field(fields[2], 1, 31)?,
+            months: field(fields[3], 1, 12)?,
+            // Both 0 and 7 are Sunday
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            either_day: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+        })
+    }
+}
+
+impl Cron {
+    /// Returns the first minute after `time` the schedule matches.
+    fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let bit = |set: u64, value: u64| set & 1 << value != 0;
+        let mut minute = time.duration_since(UNIX_EPOCH).ok()?.as_secs() / 60 + 1;
+        let end = minute + SEARCH_MINUTES;
+        while minute < end {
+            let days = minute / (24 * 60);
+            let (month, day) = month_and_day(days);
+            let (day_ok, weekday_ok) = (bit(self.days, day), bit(self.weekdays, (days + 4) % 7));
+            let date_ok = if self.either_day { day_ok || weekday_ok } else { day_ok && weekday_ok };
+            if !bit(self.months, month) || !date_ok {
+                minute = (days + 1) * 24 * 60;
+            } else if !bit(self.hours, minute / 60 % 24) {
+                minute = (minute / 60 + 1) * 60;
+            } else if !bit(self.minutes, minute % 60) {
+                minute += 1;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(minute * 60));
+            }
+        }
+        None
+    }
+}
+
+/// Parses one cron field into a set of values, bit `n` standing for value `n`.
+fn field(text: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut set = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step: &u64| step > 0).ok_or_else(|| format!("'{}' has no valid step", part))?),
+            None => (part, 1),
+        };
+        let number = |value: &str| -> Result<u64, String> {
+            value.parse().ok().filter(|value| (min..=max).contains(value)).ok_or_else(|| format!("'{}' is not a value in {}-{}", value, min, max))
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            // "5/15" runs from 5 to the end of the range
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        for value in (first..=last).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+/// Converts days since the Unix epoch to the month (1-12) and day of the month (1-31).
+fn month_and_day(days: u64) -> (u64, u64) {
+    // The civil-from-days algorithm, counting years from March so leap days come last
+    let days = days + 719_468;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    (month, day)
+}
+
+/// Serves the job list and the history over HTTP from a background thread:
+///
+/// - `GET /jobs`: every job with its schedule, options, and next run.
+/// - `GET /results`: the recorded runs, newest first; `?job=NAME` selects one job's runs and
+///   `&limit=N` the number returned (default 100).
+fn serve_api(address: SocketAddr, jobs: Arc<Mutex<Vec<serde_json::Value>>>, history: PathBuf) -> io::Result<()> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to listen for API requests on {}: {}", address, e)))?;
+    println!("Results API listening on http://{}", address);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (jobs, history) = (Arc::clone(&jobs), history.clone());
+            thread::spawn(move || {
+                if let Err(e) = answer(stream, &jobs, &history) {
+                    eprintln!("API request failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn answer(stream: TcpStream, jobs: &Mutex<Vec<serde_json::Value>>, history: &Path) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are read and ignored, so the client is not reset when the connection closes
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut fields = request_line.split_whitespace();
+    let (method, target) = (fields.next().unwrap_or_default(), fields.next().unwrap_or_default());
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let parameter = |name: &str| {
+        query.split('&').filter_map(|pair| pair.split_once('=')).find(|(key, _)| *key == name).map(|(_, value)| percent_decode(value))
+    };
+    let (status, body) = match (method, path) {
+        ("GET", "/jobs") => ("200 OK", json!(*jobs.lock().unwrap_or_else(PoisonError::into_inner))),
+        ("GET", "/results") => match parameter("limit").map_or(Ok(DEFAULT_LIMIT), |limit| limit.parse()) {
+            Ok(limit) => {
+                let job = parameter("job");
+                let records: Vec<Record> =
+                    read_history(history)?.into_iter().filter(|record| job.as_ref().is_none_or(|job| &record.job == job)).take(limit).collect();
+                ("200 OK", json!(records))
+            }
+            Err(_) => ("400 Bad Request", json!({ "error": "limit is not a number" })),
+        },
+        (_, "/jobs" | "/results") => ("405 Method Not Allowed", json!({ "error": "only GET is supported" })),
+        _ => ("404 Not Found", json!({ "error": "unknown path; try /jobs or /results" })),
+    };
+
+    let body = serde_json::to_string_pretty(&body)?;
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Decodes `%XX` escapes and `+` in a query parameter.
+fn percent_decode(value: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(if byte == b'+' { b' ' } else { byte });
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
@@ -0,0 +1,80 @@
+use std::collections::BTreeSet;
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on the number of individually remembered missing sequence numbers, so a corrupted
+/// header claiming a huge jump cannot exhaust memory. Gaps beyond it are still counted.
+const MAX_TRACKED_MISSING: usize = 1 << 20;
+
+/// How a chunk's sequence number relates to the chunks received before it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arrival {
+    /// The next expected chunk.
+    InOrder,
+    /// Arrived after this many later-numbered chunks were skipped.
+    Gap(u64),
+    /// Filled an earlier gap.
+    Reordered,
+    /// Was already received.
+    Duplicate,
+}
+
+/// Counts of sequence anomalies over a transfer.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct SequenceCounts {
+    /// Chunks skipped in the sequence and never received.
+    pub missing: u64,
+    /// Chunks received more than once.
+    pub duplicated: u64,
+    /// Chunks received after a later-numbered chunk.
+    pub reordered: u64,
+}
+
+/// Tracks the sequence numbers of received chunks to detect missing, duplicated, and reordered data.
+#[derive(Default)]
+pub struct SequenceTracker {
+    next: u64,
+    missing: BTreeSet<u64>,
+    untracked_missing: u64,
+    duplicated: u64,
+    reordered: u64,
+}
+
+impl SequenceTracker {
+    /// Returns the sequence number expected next, one past the highest received so far.
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    /// Records the arrival of chunk `sequence`.
+    pub fn record(&mut self, sequence: u64) -> Arrival {
+        if sequence == self.next {
+            self.next += 1;
+            Arrival::InOrder
+        } else if sequence > self.next {
+            let skipped = sequence - self.next;
+            for missing in self.next..sequence {
+                if self.missing.len() == MAX_TRACKED_MISSING {
+                    self.untracked_missing += sequence - missing;
+                    break;
+                }
+                self.missing.insert(missing);
+            }
+            self.next = sequence + 1;
+            Arrival::Gap(skipped)
+        } else if self.missing.remove(&sequence) {
+            self.reordered += 1;
+            Arrival::Reordered
+        } else {
+            self.duplicated += 1;
+            Arrival::Duplicate
+        }
+    }
+
+    pub fn counts(&self) -> SequenceCounts {
+        SequenceCounts {
+            missing: self.missing.len() as u64 + self.untracked_missing,
+            duplicated: self.duplicated,
+            reordered: self.reordered,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use common::mdns::{self, Advertisement};
+use crate::connection::{self, ConnectOptions};
+use crate::results::{Candidate, ServerSelection};
+
+/// TCP handshakes timed per candidate; the fastest one counts, as the others may have queued behind
+/// unrelated traffic.
+const PROBES_PER_SERVER: usize = 3;
+
+/// Reads a server list: one `host:port` per line, with blank lines and `#` comments ignored.
+///
+/// # Arguments
+/// - `source`: Path of a local file, or an `http://` URL to fetch it from.
+pub fn load(source: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let text = match source.strip_prefix("http://") {
+        Some(location) => fetch(location)?,
+        None if source.starts_with("https://") => return Err("server lists can only be fetched over plain http://".into()),
+        None => fs::read_to_string(source)?,
+    };
+    let servers: Vec<String> = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if servers.is_empty() {
+        return Err(format!("no servers listed in {}", source).into());
+    }
+    Ok(servers)
+}
+
+/// Fetches a document with a minimal HTTP/1.0 GET.
+fn fetch(location: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let (authority, path) = match location.find('/') {
+        Some(slash) => location.split_at(slash),
+        None => (location, "/"),
+    };
+    let address = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", path, authority)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("fetching the server list failed: {}", status).into());
+    }
+    Ok(body.to_string())
+}
+
+/// Measures the TCP handshake time to every server and picks the one with the lowest.
+///
+/// # Returns
+/// - The selection, or an error if no server could be reached.
+pub fn select_nearest(servers: &[String], options: &ConnectOptions) -> Result<ServerSelection, Box<dyn std::error::Error>> {
+    let candidates: Vec<Candidate> = servers.iter().map(|server| probe(server, options)).collect();
+    let chosen = candidates
+        .iter()
+        .filter_map(|candidate| candidate.rtt_seconds.map(|rtt| (candidate, rtt)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(candidate, _)| candidate.server.clone())
+        .ok_or("none of the listed servers could be reached")?;
+    Ok(ServerSelection { chosen, candidates })
+}
+
+fn probe(server: &str, options: &ConnectOptions) -> Candidate {
+    let mut best: Option<f64> = None;
+    let mut error = None;
+    for _ in 0..PROBES_PER_SERVER {
+        match connection::connect(server, options) {
+            Ok((_, timings)) => {
+                // Through a proxy, the tunnel setup is what differs between candidates
+                let rtt = (timings.connect + timings.proxy.unwrap_or_default()).as_secs_f64();
+                best = Some(best.map_or(rtt, |best| best.min(rtt)));
+            }
+            Err(e) => error = Some(e.to_string()),
+        }
+    }
+    Candidate { server: server.to_string(), rtt_seconds: best, error: if best.is_none() { error } else { None } }
+}
+
+/// Asks the local network for test servers over mDNS and collects the answers.
+///
+/// # Arguments
+/// - `wait`: How long to collect answers for.
+pub fn discover(wait: Duration) -> io::Result<Vec<Advertisement>> {
+    let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))?;
+    // Sent from an ephemeral port, so responders answer us directly
+    socket.send_to(&mdns::query(), SocketAddr::from((mdns::GROUP, mdns::PORT)))?;
+
+    let deadline = Instant::now() + wait;
+    let mut found: Vec<Advertisement> = Vec::new();
+    let mut packet = [0u8; 9000];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(found);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match socket.recv(&mut packet) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(found),
+            Err(e) => return Err(e),
+        };
+        for advertisement in mdns::parse_response(&packet[..len]) {
+            if !found.contains(&advertisement) {
+                found.push(advertisement);
+            }
+        }
+    }
+}
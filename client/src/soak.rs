@@ -0,0 +1,322 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use csv::Writer;
+
+/// Number of short summary windows that make up one long summary window (1 minute vs 5 minutes).
+const LONG_WINDOW_FACTOR: u32 = 5;
+
+/// Running aggregate of the chunks received in one summary window.
+struct Window {
+    length: Duration,
+    started: Instant,
+    chunks: usize,
+    bytes: usize,
+    latency_sum: f64,
+    latency_min: f64,
+    latency_max: f64,
+}
+
+impl Window {
+    fn new(length: Duration, started: Instant) -> Self {
+        Window { length, started, chunks: 0, bytes: 0, latency_sum: 0.0, latency_min: f64::INFINITY, latency_max: 0.0 }
+    }
+
+    fn add(&mut self, latency: f64, bytes: usize) {
+        self.chunks += 1;
+        self.bytes += bytes;
+        self.latency_sum += latency;
+        self.latency_min = self.latency_min.min(latency);
+        self.latency_max = self.latency_max.max(latency);
+    }
+}
+
+/// Aggregated statistics of one summary window.
+pub struct Summary {
+    /// Length of the window in seconds.
+    pub window_seconds: u64,
+    /// Offset of the end of the window from the start of the run, in seconds.
+    pub end_offset_seconds: f64,
+    /// Wall-clock time of the end of the window.
+    pub end_time: SystemTime,
+    pub chunks: usize,
+    pub bytes: usize,
+    /// Effective data rate over the window, in bits per second.
+    pub throughput_bps: f64,
+    pub avg_latency_seconds: f64,
+    pub min_latency_seconds: f64,
+    pub max_latency_seconds: f64,
+}
+
+/// Aggregates chunks into rolling short (default 1 minute) and long (5 times as long) summaries,
+/// printing each summary and appending it to a CSV file as its window closes.
+///
+/// Only the aggregates are kept, so memory use stays constant however long the run lasts.
+pub struct RollingSummaries {
+    run_start: Instant,
+    started_at: SystemTime,
+    label: &'static str,
+    /// Tag values that lead every row.
+    tags: Vec<String>,
+    short: Window,
+    long: Window,
+    writer: Writer<File>,
+}
+
+impl RollingSummaries {
+    /// Creates the summary CSV at `path`.
+    ///
+    /// # Arguments
+    /// - `path`: File the summaries are written to.
+    /// - `short_window`: Length of the short summary window; the long window is five times as long.
+    /// - `run_start`: Start of the run, used for the window offsets.
+    /// - `started_at`: Wall-clock time of `run_start`, used for the window timestamps.
+    /// - `label`: Name of the transfer direction, e.g. `Download`, used in the column names and output.
+    /// - `tags`: Tags of the run, whose keys become the first columns.
+    pub fn new(
+        path: &Path,
+        short_window: Duration,
+        run_start: Instant,
+        started_at: SystemTime,
+        label: &'static str,
+        tags: &BTreeMap<String, String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut writer = Writer::from_path(path)?;
+        writer.write_record(tags.keys().cloned().chain([
+            "Window (s)".to_string(),
+            "End Offset (s)".to_string(),
+            "Chunks".to_string(),
+            "Bytes".to_string(),
+            "Effective Data Rate (bps)".to_string(),
+            format!("Avg {} Time (s)", label),
+            format!("Min {} Time (s)", label),
+            format!("Max {} Time (s)", label),
+            "End Timestamp".to_string(),
+        ]))?;
+
+        Ok(RollingSummaries {
+            run_start,
+            started_at,
+            label,
+            tags: tags.values().cloned().collect(),
+            short: Window::new(short_window, run_start),
+            long: Window::new(short_window * LONG_WINDOW_FACTOR, run_start),
+            writer,
+        })
+    }
+
+    /// Adds a chunk to the current windows and closes any window whose time is up.
+    ///
+    /// # Returns
+    /// - The summary of the short window if this chunk closed it, for plotting.
+    pub fn record(&mut self, now: Instant, latency: f64, bytes: usize) -> Result<Option<Summary>, Box<dyn std::error::Error>> {
+        self.short.add(latency, bytes);
+        self.long.add(latency, bytes);
+
+        let mut closed = None;
+        if now.duration_since(self.short.started) >= self.short.length {
+            closed = Some(close(&mut self.short, &mut self.writer, self.run_start, self.started_at, self.label, &self.tags, now)?);
+        }
+        if now.duration_since(self.long.started) >= self.long.length {
+            close(&mut self.long, &mut self.writer, self.run_start, self.started_at, self.label, &self.tags, now)?;
+        }
+
+        Ok(closed)
+    }
+
+    /// Closes the partially filled windows at the end of the run.
+    ///
+    /// # Returns
+    /// - The summary of the last short window, if it received any chunks.
+    pub fn finish(mut self, now: Instant) -> Result<Option<Summary>, Box<dyn std::error::Error>> {
+        let mut last = None;
+        if self.short.chunks > 0 {
+            last = Some(close(&mut self.short, &mut self.writer, self.run_start, self.started_at, self.label, &self.tags, now)?);
+        }
+        if self.long.chunks > 0 {
+            close(&mut self.long, &mut self.writer, self.run_start, self.started_at, self.label, &self.tags, now)?;
+        }
+        self.writer.flush()?;
+        Ok(last)
+    }
+}
+
+fn close(
+    window: &mut Window,
+    writer: &mut Writer<File>,
+    run_start: Instant,
+    started_at: SystemTime,
+    label: &str,
+    tags: &[String],
+    now: Instant,
+) -> Result<Summary, Box<dyn std::error::Error>> {
+    let end_offset = now.duration_since(run_start);
+    let summary = Summary {
+        window_seconds: window.length.as_secs(),
+        end_offset_seconds: end_offset.as_secs_f64(),
+        end_time: started_at + end_offset,
+        chunks: window.chunks,
+        bytes: window.bytes,
+        throughput_bps: window.bytes as f64 * 8.0 / window.latency_sum,
+        avg_latency_seconds: window.latency_sum / window.chunks as f64,
+        min_latency_seconds: window.latency_min,
+        max_latency_seconds: window.latency_max,
+    };
+
+    writer.write_record(tags.iter().cloned().chain([
+        summary.window_seconds.to_string(),
+        summary.end_offset_seconds.to_string(),
+        summary.chunks.to_string(),
+        summary.bytes.to_string(),
+        summary.throughput_bps.to_string(),
+        summary.avg_latency_seconds.to_string(),
+        summary.min_latency_seconds.to_string(),
+        summary.max_latency_seconds.to_string(),
+        humantime::format_rfc3339_micros(summary.end_time).to_string(),
+    ]))?;
+    writer.flush()?;
+    println!(
+        "[{}s window @ {:.0}s] {} chunks, Effective Data Rate: {:.2} bps, {} Time avg/min/max: {:.5}/{:.5}/{:.5}s",
+        summary.window_seconds,
+        summary.end_offset_seconds,
+        summary.chunks,
+        summary.throughput_bps,
+        label,
+        summary.avg_latency_seconds,
+        summary.min_latency_seconds,
+        summary.max_latency_seconds
+    );
+
+    *window = Window::new(window.length, now);
+    Ok(summary)
+}
+
+/// CSV writer that starts a new numbered file (`name.1.csv`, `name.2.csv`, ...) at a fixed interval,
+/// so multi-hour runs do not produce a single unbounded file.
+///
+/// Given a run ID, rows are appended to the files under a leading "Run ID" column instead of
+/// replacing them, so one file collects the samples of many runs. The run's tags follow as columns
+/// of their own.
+pub struct RotatingCsv {
+    base: PathBuf,
+    header: Vec<String>,
+    rotate_after: Option<Duration>,
+    run_id: Option<String>,
+    /// Run ID and tag values that lead every row.
+    leading: Vec<String>,
+    segment: usize,
+    opened: Instant,
+    writer: Writer<File>,
+}
+
+impl RotatingCsv {
+    /// Opens the first file.
+    ///
+    /// # Arguments
+    /// - `base`: Path of the CSV file; with rotation the segment number is inserted before the extension.
+    /// - `header`: Header row written at the top of every file.
+    /// - `rotate_after`: How long to write to one file before starting the next, or `None` to never rotate.
+    /// - `run_id`: Run the rows belong to, to append them keyed by it; `None` to overwrite the file.
+    /// - `tags`: Tags of the run, whose keys become columns.
+    pub fn new(
+        base: &Path,
+        header: &[&str],
+        rotate_after: Option<Duration>,
+        run_id: Option<&str>,
+        tags: &BTreeMap<String, String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut full_header: Vec<String> = run_id.map(|_| "Run ID".to_string()).into_iter().chain(tags.keys().cloned()).collect();
+        full_header.extend(header.iter().map(|h| h.to_string()));
+        let leading = run_id.map(str::to_string).into_iter().chain(tags.values().cloned()).collect();
+        let segment = 1;
+        let writer = open_segment(base, &full_header, rotate_after.map(|_| segment), run_id.is_some())?;
+
+        Ok(RotatingCsv {
+            base: base.to_path_buf(),
+            header: full_header,
+            rotate_after,
+            run_id: run_id.map(str::to_string),
+            leading,
+            segment,
+            opened: Instant::now(),
+            writer,
+        })
+    }
+
+    /// Writes one row, rotating to a new file first if the current one is due.
+    pub fn write_record(&mut self, record: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(rotate_after) = self.rotate_after {
+            if self.opened.elapsed() >= rotate_after {
+                self.writer.flush()?;
+                self.segment += 1;
+                self.writer = open_segment(&self.base, &self.header, Some(self.segment), self.run_id.is_some())?;
+                self.opened = Instant::now();
+                println!("Rotated per-chunk metrics to {}", segment_path(&self.base, Some(self.segment)).display());
+            }
+        }
+        self.writer.write_record(self.leading.iter().chain(record))?;
+        Ok(())
+    }
+
+    /// Flushes the current file.
+    pub fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Path of the file currently being written.
+    pub fn current_path(&self) -> PathBuf {
+        segment_path(&self.base, self.rotate_after.map(|_| self.segment))
+    }
+
+    /// Reports where the rows went, e.g. "Download metrics saved to download_metrics.csv".
+    pub fn report(&self, label: &str) {
+        match &self.run_id {
+            Some(run_id) => println!("{} metrics appended to {} as run {}", label, self.current_path().display(), run_id),
+            None => println!("{} metrics saved to {}", label, self.current_path().display()),
+        }
+    }
+}
+
+/// Opens a segment and writes the header, unless appending to a file that already has it. A file
+/// with other columns, e.g. from a run in the other direction, is not appended to.
+fn open_segment(base: &Path, header: &[String], segment: Option<usize>, append: bool) -> Result<Writer<File>, Box<dyn std::error::Error>> {
+    let path = segment_path(base, segment);
+    if !append {
+        let mut writer = Writer::from_path(path)?;
+        writer.write_record(header)?;
+        return Ok(writer);
+    }
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if file.metadata()?.len() > 0 {
+        let mut first_line = String::new();
+        BufReader::new(File::open(&path)?).read_line(&mut first_line)?;
+        if first_line.trim_end() != header.join(",") {
+            return Err(format!(
+                "{} has other columns than this run writes; move it away, or pass --per-run-files to overwrite it",
+                path.display()
+            )
+            .into());
+        }
+        return Ok(Writer::from_writer(file));
+    }
+    let mut writer = Writer::from_writer(file);
+    writer.write_record(header)?;
+    Ok(writer)
+}
+
+fn segment_path(base: &Path, segment: Option<usize>) -> PathBuf {
+    match segment {
+        Some(segment) => {
+            let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+            let file_name = match base.extension() {
+                Some(ext) => format!("{}.{}.{}", stem, segment, ext.to_string_lossy()),
+                None => format!("{}.{}", stem, segment),
+            };
+            base.with_file_name(file_name)
+        }
+        None => base.to_path_buf(),
+    }
+}
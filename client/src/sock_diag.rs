@@ -0,0 +1,246 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::results::SocketSnapshot;
+
+/// Takes a snapshot of the TCP socket from `local` to `remote`, as `ss -tmi` would show it.
+///
+/// # Arguments
+/// - `stage`: When the snapshot is taken, e.g. "before", recorded with it.
+///
+/// # Returns
+/// - The snapshot, or `None` if the kernel no longer has the socket.
+#[cfg(target_os = "linux")]
+pub fn snapshot(local: SocketAddr, remote: SocketAddr, stage: &str) -> io::Result<Option<SocketSnapshot>> {
+    use std::time::SystemTime;
+
+    sys::query(local, remote).map(|reply| {
+        reply.map(|socket| SocketSnapshot {
+            stage: stage.to_string(),
+            timestamp: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+            ..socket
+        })
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn snapshot(_local: SocketAddr, _remote: SocketAddr, _stage: &str) -> io::Result<Option<SocketSnapshot>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "socket snapshots are only available on Linux"))
+}
+
+/// Takes snapshots of the test socket from a separate thread while a transfer runs.
+pub struct Background {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Vec<SocketSnapshot>>>,
+}
+
+impl Background {
+    /// Starts taking a "during" snapshot of the socket every `interval` until `finish` is called.
+    /// Snapshots that fail are skipped.
+    pub fn start(local: SocketAddr, remote: SocketAddr, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut snapshots = Vec::new();
+            // The first one after a full interval, so it shows the transfer under way
+            thread::sleep(interval);
+            while !stopped.load(Ordering::Relaxed) {
+                let started = Instant::now();
+                if let Ok(Some(snapshot)) = snapshot(local, remote, "during") {
+                    snapshots.push(snapshot);
+                }
+                thread::sleep(interval.saturating_sub(started.elapsed()));
+            }
+            snapshots
+        });
+        Background { stop, handle: Some(handle) }
+    }
+
+    /// Stops taking snapshots and waits for the thread, which finishes the current interval first.
+    pub fn finish(mut self) -> Vec<SocketSnapshot> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.take().and_then(|handle| handle.join().ok()).unwrap_or_default()
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::net::{IpAddr, SocketAddr};
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+    use common::tcp_info;
+    use crate::results::{SocketMemory, SocketSnapshot};
+
+    /// `SOCK_DIAG_BY_FAMILY` (linux/sock_diag.h).
+    const SOCK_DIAG_BY_FAMILY: u16 = 20;
+    const NLMSG_ERROR: u16 = 2;
+    const NLMSG_HDRLEN: usize = 16;
+    /// `struct inet_diag_msg`, ahead of the attributes of a reply.
+    const INET_DIAG_MSG_LEN: usize = 72;
+    /// Attributes of a reply (linux/inet_diag.h).
+    const INET_DIAG_INFO: u16 = 2;
+    const INET_DIAG_CONG: u16 = 4;
+    const INET_DIAG_SKMEMINFO: u16 = 7;
+    /// Matches a socket whatever its cookie.
+    const INET_DIAG_NOCOOKIE: u32 = !0;
+
+    /// Looks up the socket from `local` to `remote` by its exact address, which finds it in
+    /// TIME-WAIT too.
+    pub fn query(local: SocketAddr, remote: SocketAddr) -> io::Result<Option<SocketSnapshot>> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_SOCK_DIAG) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let request = request(local, remote);
+        let mut kernel: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        kernel.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let sent = unsafe {
+            libc::sendto(
+                socket.as_raw_fd(),
+                request.as_ptr() as *const libc::c_void,
+                request.len(),
+                0,
+                &kernel as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut reply = vec![0u8; 16384];
+        let received = unsafe { libc::recv(socket.as_raw_fd(), reply.as_mut_ptr() as *mut libc::c_void, reply.len(), 0) };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        reply.truncate(received as usize);
+        if reply.len() < NLMSG_HDRLEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated sock_diag reply"));
+        }
+        let length = (u32_at(&reply, 0) as usize).min(reply.len());
+        match u16::from_ne_bytes([reply[4], reply[5]]) {
+            NLMSG_ERROR => {
+                let errno = -(u32_at(&reply, NLMSG_HDRLEN) as i32);
+                if errno == libc::ENOENT {
+                    Ok(None)
+                } else {
+                    Err(io::Error::from_raw_os_error(errno))
+                }
+            }
+            SOCK_DIAG_BY_FAMILY => parse(&reply[NLMSG_HDRLEN..length]).map(Some),
+            other => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected sock_diag reply type {}", other))),
+        }
+    }
+
+    /// Builds a request for one socket with its TCP_INFO, congestion control, and memory.
+    fn request(local: SocketAddr, remote: SocketAddr) -> Vec<u8> {
+        let family = if local.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 } as u8;
+        let extensions = [INET_DIAG_INFO, INET_DIAG_CONG, INET_DIAG_SKMEMINFO].iter().fold(0u8, |bits, &kind| bits | 1 << (kind - 1));
+        let mut message = Vec::with_capacity(NLMSG_HDRLEN + 56);
+        // struct nlmsghdr, its length filled in last
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+        message.extend_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+        message.extend_from_slice(&1u32.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        // struct inet_diag_req_v2
+        message.extend_from_slice(&[family, libc::IPPROTO_TCP as u8, extensions, 0]);
+        message.extend_from_slice(&u32::MAX.to_ne_bytes());
+        // struct inet_diag_sockid: ports and addresses in network byte order
+        message.extend_from_slice(&local.port().to_be_bytes());
+        message.extend_from_slice(&remote.port().to_be_bytes());
+        message.extend_from_slice(&address(local.ip()));
+        message.extend_from_slice(&address(remote.ip()));
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&INET_DIAG_NOCOOKIE.to_ne_bytes());
+        message.extend_from_slice(&INET_DIAG_NOCOOKIE.to_ne_bytes());
+        let length = message.len() as u32;
+        message[..4].copy_from_slice(&length.to_ne_bytes());
+        message
+    }
+
+    fn address(ip: IpAddr) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        match ip {
+            IpAddr::V4(ip) => bytes[..4].copy_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => bytes.copy_from_slice(&ip.octets()),
+        }
+        bytes
+    }
+
+    /// Parses a `struct inet_diag_msg` and the attributes that follow it.
+    fn parse(message: &[u8]) -> io::Result<SocketSnapshot> {
+        if message.len() < INET_DIAG_MSG_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated inet_diag_msg"));
+        }
+        let (state, timer, retransmits) = (message[1], message[2], message[3]);
+        let expires_ms = u32_at(message, 52);
+        let mut snapshot = SocketSnapshot {
+            state: tcp_info::TcpInfo { state, ..Default::default() }.state_name().to_string(),
+            recv_q: u32_at(message, 56),
+            send_q: u32_at(message, 60),
+            uid: u32_at(message, 64),
+            inode: u32_at(message, 68),
+            // As `ss -o` shows it: the pending timer, when it fires, and how often it has
+            timer: match timer {
+                1 => Some("on"),
+                2 => Some("keepalive"),
+                3 => Some("timewait"),
+                4 => Some("persist"),
+                _ => None,
+            }
+            .map(|name| format!("{},{}ms,{}", name, expires_ms, retransmits)),
+            ..SocketSnapshot::default()
+        };
+
+        let mut attributes = &message[INET_DIAG_MSG_LEN..];
+        while attributes.len() >= 4 {
+            let length = u16::from_ne_bytes([attributes[0], attributes[1]]) as usize;
+            let kind = u16::from_ne_bytes([attributes[2], attributes[3]]);
+            if length < 4 || length > attributes.len() {
+                break;
+            }
+            let payload = &attributes[4..length];
+            match kind {
+                INET_DIAG_INFO => snapshot.tcp_info = Some(tcp_info::from_bytes(payload)),
+                INET_DIAG_CONG => {
+                    let name = payload.split(|&byte| byte == 0).next().unwrap_or_default();
+                    snapshot.congestion_control = Some(String::from_utf8_lossy(name).into_owned());
+                }
+                INET_DIAG_SKMEMINFO => {
+                    let value = |index: usize| if payload.len() >= index * 4 + 4 { u32_at(payload, index * 4) } else { 0 };
+                    snapshot.memory = Some(SocketMemory {
+                        rmem_alloc: value(0),
+                        rcvbuf: value(1),
+                        wmem_alloc: value(2),
+                        sndbuf: value(3),
+                        fwd_alloc: value(4),
+                        wmem_queued: value(5),
+                        optmem: value(6),
+                        backlog: value(7),
+                        drops: value(8),
+                    });
+                }
+                _ => {}
+            }
+            // Attributes are padded to four bytes
+            attributes = &attributes[((length + 3) & !3).min(attributes.len())..];
+        }
+        Ok(snapshot)
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+}
@@ -0,0 +1,89 @@
+//! Forwarding the test connection through the system's OpenSSH client, for servers that can only
+//! be reached over SSH.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the local port is checked while ssh logs in.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A local port forwarded with `ssh -L` to an address as seen from the SSH host; ssh is stopped when
+/// the tunnel is dropped.
+pub struct Tunnel {
+    child: Child,
+    /// Local end of the forward, which the client connects to instead of the server.
+    pub local: SocketAddr,
+}
+
+impl Tunnel {
+    /// Starts `ssh -N -L` forwarding a free local port to `target`, and waits until ssh listens on
+    /// it.
+    ///
+    /// ssh reads `~/.ssh/config` and uses the keys and agent as it does on the command line, and
+    /// asks for a password or passphrase on the terminal if it needs one.
+    ///
+    /// # Arguments
+    /// - `destination`: SSH host as `[USER@]HOST[:PORT]`.
+    /// - `target`: Address the SSH host connects the forward to, e.g. `127.0.0.1:7878` for a
+    ///   server on the host itself.
+    /// - `bind`: Local address ssh connects from.
+    /// - `connect_timeout`: How long ssh waits for the TCP handshake with the SSH host.
+    /// - `login_timeout`: How long to wait for ssh to log in and listen.
+    pub fn open(
+        destination: &str,
+        target: &str,
+        bind: Option<IpAddr>,
+        connect_timeout: Option<Duration>,
+        login_timeout: Duration,
+    ) -> io::Result<Tunnel> {
+        // Taken from the OS's free ports and released again for ssh to listen on
+        let local = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.local_addr()?;
+        let mut command = Command::new("ssh");
+        // Without ExitOnForwardFailure ssh would stay logged in without the forward
+        command.args(["-N", "-o", "ExitOnForwardFailure=yes", "-L"]).arg(format!("{}:{}", local, target));
+        if let Some(bind) = bind {
+            command.arg("-b").arg(bind.to_string());
+        }
+        if let Some(timeout) = connect_timeout {
+            command.arg("-o").arg(format!("ConnectTimeout={}", timeout.as_secs().max(1)));
+        }
+        let child = command
+            .arg(format!("ssh://{}", destination))
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot run ssh: {}", e)))?;
+
+        let tunnel = Tunnel { child, local };
+        tunnel.wait_for_forward(destination, login_timeout)
+    }
+
+    /// Waits until ssh listens on the local port, which it does once it has logged in.
+    fn wait_for_forward(mut self, destination: &str, timeout: Duration) -> io::Result<Tunnel> {
+        let started = Instant::now();
+        // Connecting to the port would already open a connection to the server, so it is only
+        // checked whether the port is taken
+        while TcpListener::bind(self.local).is_ok() {
+            if let Some(status) = self.child.try_wait()? {
+                return Err(io::Error::other(format!("ssh to {} exited ({}) before forwarding", destination, status)));
+            }
+            if started.elapsed() >= timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("ssh to {} did not forward within {:?}", destination, timeout),
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(self)
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
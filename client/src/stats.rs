@@ -0,0 +1,127 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Descriptive statistics of a series of samples.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Summary {
+    pub samples: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// Sample standard deviation; zero for a single sample.
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p5: f64,
+    pub p25: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl Summary {
+    /// Computes the statistics of `samples`.
+    ///
+    /// # Returns
+    /// - The summary, or `None` if there are no samples.
+    pub fn of(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let variance = if sorted.len() > 1 { sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0) } else { 0.0 };
+
+        Some(Summary {
+            samples: sorted.len(),
+            mean,
+            median: percentile(&sorted, 50.0),
+            stddev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            p5: percentile(&sorted, 5.0),
+            p25: percentile(&sorted, 25.0),
+            p75: percentile(&sorted, 75.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+        })
+    }
+}
+
+/// Returns the `p`th percentile of the non-empty, ascending `sorted` samples, interpolating
+/// linearly between the two closest ranks.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// Scale factor that makes the median absolute deviation a consistent estimator of the standard
+/// deviation for normally distributed samples.
+const MAD_SCALE: f64 = 1.4826;
+
+/// Finds samples that lie more than `k` scaled median absolute deviations above the median.
+///
+/// The median and MAD are barely moved by the outliers themselves, unlike the mean and standard
+/// deviation. If more than half of the samples are identical the MAD is zero, and the mean absolute
+/// deviation from the median is used instead.
+///
+/// # Returns
+/// - The indices of the outlying samples, in ascending order.
+pub fn high_outliers(samples: &[f64], k: f64) -> Vec<usize> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = percentile(&sorted, 50.0);
+
+    let mut deviations: Vec<f64> = sorted.iter().map(|x| (x - median).abs()).collect();
+    deviations.sort_by(|a, b| a.total_cmp(b));
+    let mut spread = percentile(&deviations, 50.0) * MAD_SCALE;
+    if spread == 0.0 {
+        spread = deviations.iter().sum::<f64>() / deviations.len() as f64 * (std::f64::consts::PI / 2.0).sqrt();
+    }
+    if spread == 0.0 {
+        return Vec::new();
+    }
+
+    let limit = median + k * spread;
+    samples.iter().enumerate().filter(|(_, x)| **x > limit).map(|(i, _)| i).collect()
+}
+
+/// Estimates a confidence interval for the median of `samples` with the percentile bootstrap.
+///
+/// The resampling is seeded, so the same samples always give the same interval.
+///
+/// # Arguments
+/// - `samples`: The observed values, e.g. one throughput per run.
+/// - `confidence`: Coverage of the interval, e.g. 0.95.
+/// - `resamples`: Number of bootstrap resamples.
+///
+/// # Returns
+/// - The lower and upper bound, or `None` if there are no samples.
+pub fn bootstrap_median_ci(samples: &[f64], confidence: f64, resamples: usize) -> Option<(f64, f64)> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut resample = vec![0.0; samples.len()];
+    let mut medians: Vec<f64> = (0..resamples)
+        .map(|_| {
+            for value in resample.iter_mut() {
+                *value = samples[rng.gen_range(0..samples.len())];
+            }
+            resample.sort_by(|a, b| a.total_cmp(b));
+            percentile(&resample, 50.0)
+        })
+        .collect();
+    medians.sort_by(|a, b| a.total_cmp(b));
+
+    let tail = (1.0 - confidence) / 2.0 * 100.0;
+    Some((percentile(&medians, tail), percentile(&medians, 100.0 - tail)))
+}
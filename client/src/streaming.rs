@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+use crate::results::{Rebuffer, Streaming};
+
+/// Minimum time between two recorded buffer levels, so small chunks do not flood the chart.
+const POINT_INTERVAL: f64 = 0.1;
+
+/// Playout buffer of a video player streaming at a constant bitrate.
+///
+/// Received data fills the buffer with the media time it holds. Playback starts once the startup
+/// threshold is buffered and drains the buffer in real time; when it runs dry, playback stalls
+/// until the threshold is buffered again. A full buffer holds the player off reading, as a real
+/// player stops fetching segments, which leaves the rest of the data queued on the path.
+pub struct Player {
+    bitrate_bps: f64,
+    startup: f64,
+    capacity: f64,
+    run_start: Instant,
+    /// Media time buffered as of `updated`, in seconds.
+    buffered: f64,
+    updated: Instant,
+    playing: bool,
+    startup_delay: Option<f64>,
+    /// Offset at which the buffer ran dry, while playback waits for it to refill.
+    rebuffering_since: Option<f64>,
+    rebuffers: Vec<Rebuffer>,
+    played: f64,
+    /// Buffer level over the run, as offset and media time in seconds.
+    points: Vec<(f64, f64)>,
+}
+
+impl Player {
+    /// # Arguments
+    /// - `bitrate_bps`: Bitrate of the stream, in bits per second.
+    /// - `startup`: Media time buffered before playback starts, and resumes after a stall.
+    /// - `capacity`: Media time the buffer holds at most.
+    /// - `run_start`: When the transfer started, which offsets are measured from.
+    pub fn new(bitrate_bps: u64, startup: Duration, capacity: Duration, run_start: Instant) -> Self {
+        Player {
+            bitrate_bps: bitrate_bps as f64,
+            startup: startup.as_secs_f64(),
+            capacity: capacity.as_secs_f64(),
+            run_start,
+            buffered: 0.0,
+            updated: run_start,
+            playing: false,
+            startup_delay: None,
+            rebuffering_since: None,
+            rebuffers: Vec::new(),
+            played: 0.0,
+            points: vec![(0.0, 0.0)],
+        }
+    }
+
+    /// Returns how long to wait before reading `bytes` more, until the buffer has room for them.
+    ///
+    /// The wait never outlasts what is buffered, so a chunk larger than the free space cannot
+    /// stall playback.
+    pub fn wait_for_room(&mut self, bytes: usize, now: Instant) -> Duration {
+        self.play_until(now);
+        let excess = self.buffered + self.media_seconds(bytes) - self.capacity;
+        if self.playing && excess > 0.0 {
+            Duration::from_secs_f64(excess.min(self.buffered))
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Adds a received chunk of `bytes` to the buffer.
+    pub fn receive(&mut self, bytes: usize, now: Instant) {
+        self.play_until(now);
+        self.buffered += self.media_seconds(bytes);
+        let offset = self.offset(now);
+        if !self.playing && self.buffered >= self.startup {
+            self.playing = true;
+            match self.rebuffering_since.take() {
+                Some(since) => self.rebuffers.push(Rebuffer { offset_seconds: since, duration_seconds: offset - since }),
+                None => self.startup_delay = Some(offset),
+            }
+        }
+        if self.points.last().is_none_or(|&(last, _)| offset - last >= POINT_INTERVAL) {
+            self.points.push((offset, self.buffered));
+        }
+    }
+
+    /// Ends the stream at `now`; a stall still going on counts up to then.
+    ///
+    /// # Returns
+    /// - The summary of the playback, and the buffer level over the run.
+    pub fn finish(mut self, now: Instant) -> (Streaming, Vec<(f64, f64)>) {
+        self.play_until(now);
+        let end = self.offset(now);
+        if let Some(since) = self.rebuffering_since.take() {
+            self.rebuffers.push(Rebuffer { offset_seconds: since, duration_seconds: end - since });
+        }
+        self.points.push((end, self.buffered));
+
+        let rebuffering_seconds = self.rebuffers.iter().map(|rebuffer| rebuffer.duration_seconds).fold(0.0, |sum, duration| sum + duration);
+        // Only the levels once playback started say how close it came to stalling
+        let levels: Vec<f64> = match self.startup_delay {
+            Some(delay) => self.points.iter().filter(|&&(offset, _)| offset >= delay).map(|&(_, level)| level).collect(),
+            None => Vec::new(),
+        };
+        let streaming = Streaming {
+            bitrate_bps: self.bitrate_bps,
+            startup_seconds: self.startup,
+            buffer_seconds: self.capacity,
+            startup_delay_seconds: self.startup_delay,
+            played_seconds: self.played,
+            rebuffering_seconds,
+            rebuffer_ratio_percent: if self.played + rebuffering_seconds > 0.0 {
+                rebuffering_seconds / (self.played + rebuffering_seconds) * 100.0
+            } else {
+                0.0
+            },
+            mean_buffer_seconds: (!levels.is_empty()).then(|| levels.iter().sum::<f64>() / levels.len() as f64),
+            min_buffer_seconds: levels.iter().cloned().reduce(f64::min),
+            rebuffers: self.rebuffers,
+        };
+        (streaming, self.points)
+    }
+
+    fn media_seconds(&self, bytes: usize) -> f64 {
+        bytes as f64 * 8.0 / self.bitrate_bps
+    }
+
+    fn offset(&self, at: Instant) -> f64 {
+        at.saturating_duration_since(self.run_start).as_secs_f64()
+    }
+
+    /// Plays the buffer down to `now`, stalling where it runs dry.
+    fn play_until(&mut self, now: Instant) {
+        let since = self.updated;
+        let elapsed = now.saturating_duration_since(since).as_secs_f64();
+        self.updated = self.updated.max(now);
+        if !self.playing {
+            return;
+        }
+        if self.buffered > elapsed {
+            self.buffered -= elapsed;
+            self.played += elapsed;
+            return;
+        }
+        let dry = self.offset(since) + self.buffered;
+        self.played += self.buffered;
+        self.buffered = 0.0;
+        self.playing = false;
+        self.rebuffering_since = Some(dry);
+        self.points.push((dry, 0.0));
+    }
+}
@@ -0,0 +1,121 @@
+use std::convert::TryInto;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+use crate::connection;
+use crate::results::StunMapping;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const ATTRIBUTE_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTRIBUTE_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+/// Requests sent before giving up; STUN runs over UDP, so a request or its answer may be lost.
+const ATTEMPTS: usize = 3;
+const ATTEMPT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Asks a STUN server (RFC 5389) which public address and port our UDP traffic appears to come from.
+///
+/// Comparing the answer with the local address shows whether the host is behind a NAT, which
+/// decides whether peers can reach it directly.
+///
+/// # Arguments
+/// - `server`: STUN server as `host:port`, e.g. `stun.l.google.com:19302`.
+/// - `bind`: Local address to send from, if any.
+pub fn query(server: &str, bind: Option<IpAddr>) -> io::Result<StunMapping> {
+    let target = connection::resolve(server, bind, None)?;
+    let local = match bind {
+        Some(bind) => SocketAddr::new(bind, 0),
+        None if target.is_ipv4() => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        None => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    };
+    query_from(&UdpSocket::bind(local)?, server, target)
+}
+
+/// Asks the STUN server `server` at `target` from an existing socket, e.g. the one a peer-to-peer
+/// test punches through, since a NAT may map every local port differently.
+pub fn query_from(socket: &UdpSocket, server: &str, target: SocketAddr) -> io::Result<StunMapping> {
+    socket.set_read_timeout(Some(ATTEMPT_TIMEOUT))?;
+
+    let transaction: [u8; 12] = rand::random();
+    let mut request = Vec::with_capacity(HEADER_LEN);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction);
+
+    let mut response = [0u8; 1500];
+    for _ in 0..ATTEMPTS {
+        socket.send_to(&request, target)?;
+        let len = match socket.recv_from(&mut response) {
+            Ok((len, sender)) if sender == target => len,
+            Ok(_) => continue,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(mapped) = parse_response(&response[..len], &transaction) {
+            return Ok(StunMapping {
+                server: server.to_string(),
+                local_address: socket.local_addr()?.to_string(),
+                mapped_address: mapped.to_string(),
+            });
+        }
+    }
+    Err(io::Error::new(ErrorKind::TimedOut, format!("no answer from STUN server {}", server)))
+}
+
+/// Extracts the mapped address from a binding success response to our transaction.
+fn parse_response(packet: &[u8], transaction: &[u8; 12]) -> Option<SocketAddr> {
+    let kind = u16::from_be_bytes(packet.get(0..2)?.try_into().ok()?);
+    if kind != BINDING_SUCCESS || packet.get(8..20)? != transaction {
+        return None;
+    }
+    let length = usize::from(u16::from_be_bytes(packet.get(2..4)?.try_into().ok()?));
+    let attributes = packet.get(HEADER_LEN..HEADER_LEN + length)?;
+
+    let mut offset = 0;
+    let mut mapped = None;
+    while offset + 4 <= attributes.len() {
+        let kind = u16::from_be_bytes(attributes[offset..offset + 2].try_into().ok()?);
+        let len = usize::from(u16::from_be_bytes(attributes[offset + 2..offset + 4].try_into().ok()?));
+        let value = attributes.get(offset + 4..offset + 4 + len)?;
+        match kind {
+            // Preferred, since NATs that rewrite addresses in payloads cannot mangle it
+            ATTRIBUTE_XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction)),
+            ATTRIBUTE_MAPPED_ADDRESS => mapped = parse_address(value, None),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of four bytes
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+    mapped
+}
+
+/// Decodes a (XOR-)MAPPED-ADDRESS value; `transaction` is given for the XOR variant.
+fn parse_address(value: &[u8], transaction: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    let family = *value.get(1)?;
+    let mut port = u16::from_be_bytes(value.get(2..4)?.try_into().ok()?);
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+    let mut mask = [0u8; 16];
+    if let Some(transaction) = transaction {
+        port ^= (MAGIC_COOKIE >> 16) as u16;
+        mask[..4].copy_from_slice(&cookie);
+        mask[4..].copy_from_slice(transaction);
+    }
+    let ip = match family {
+        0x01 => {
+            let mut octets: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+            octets.iter_mut().zip(&mask).for_each(|(byte, mask)| *byte ^= mask);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        0x02 => {
+            let mut octets: [u8; 16] = value.get(4..20)?.try_into().ok()?;
+            octets.iter_mut().zip(&mask).for_each(|(byte, mask)| *byte ^= mask);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
@@ -0,0 +1,162 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use clap::ValueEnum;
+use crate::anomaly::Anomaly;
+use crate::notify::Status;
+use crate::report;
+use crate::results::RunResult;
+use crate::thresholds::Violation;
+use crate::units::Units;
+
+/// When the console summary is colored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// When printing to a terminal and `NO_COLOR` is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether the summary is colored under this choice.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // https://no-color.org: any non-empty value disables color
+            ColorChoice::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+        }
+    }
+}
+
+/// ANSI styles of the summary.
+#[derive(Clone, Copy)]
+enum Style {
+    Bold,
+    Pass,
+    Fail,
+    Warn,
+}
+
+/// Applies styles, or leaves the text plain when color is off.
+struct Painter {
+    color: bool,
+}
+
+impl Painter {
+    fn paint(&self, style: Style, text: &str) -> String {
+        if !self.color {
+            return text.to_string();
+        }
+        let code = match style {
+            Style::Bold => "1",
+            Style::Pass => "1;32",
+            Style::Fail => "1;31",
+            Style::Warn => "33",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+/// What the summary table shows besides the run itself.
+pub struct Verdict<'a> {
+    pub status: Status,
+    pub violations: &'a [Violation],
+    /// Metrics that regressed against the baseline.
+    pub regressions: &'a [&'static str],
+    /// Metrics that deviated from the moving average of a scheduled job.
+    pub anomalies: &'a [Anomaly],
+    /// Whether any threshold was given, so a pass can say what it passed.
+    pub thresholds_set: bool,
+}
+
+/// Prints the run's headline numbers as a table, marking the metrics that missed a threshold,
+/// regressed against the baseline, or deviated from the job's recent runs, followed by the
+/// anomalies of the run and its outcome.
+///
+/// # Arguments
+/// - `color`: Whether to color the failures red, the anomalies yellow, and a pass green.
+pub fn print(result: &RunResult, units: Units, verdict: &Verdict, color: bool) {
+    let painter = Painter { color };
+    let rows = report::summary_rows(result, units);
+    let width = rows.iter().map(|(metric, _)| metric.len()).max().unwrap_or(0);
+
+    println!("{}", painter.paint(Style::Bold, "=== Summary ==="));
+    for (metric, value) in &rows {
+        let failed = verdict.violations.iter().any(|violation| row_of(violation.metric) == Some(metric));
+        let regressed = verdict.regressions.iter().any(|regression| row_of(regression) == Some(metric));
+        let deviated = verdict.anomalies.iter().any(|anomaly| row_of(anomaly.metric) == Some(metric));
+        let cells = format!("  {:<width$}  {}", metric, value, width = width);
+        match (failed, regressed) {
+            (true, _) => println!("{}", painter.paint(Style::Fail, &format!("{}  FAIL", cells))),
+            (false, true) => println!("{}", painter.paint(Style::Fail, &format!("{}  REGRESSION", cells))),
+            _ if deviated => println!("{}", painter.paint(Style::Fail, &format!("{}  ANOMALY", cells))),
+            // Other traffic on the interface is only listed when some was flagged
+            _ if *metric == "Cross traffic" => println!("{}", painter.paint(Style::Warn, &cells)),
+            _ => println!("{}", cells),
+        }
+    }
+
+    for anomaly in anomalies(result) {
+        println!("{}", painter.paint(Style::Warn, &format!("  ! {}", anomaly)));
+    }
+    for anomaly in verdict.anomalies {
+        println!("{}", painter.paint(Style::Fail, &format!("ANOMALY: {}", anomaly)));
+    }
+    for violation in verdict.violations {
+        println!("{}", painter.paint(Style::Fail, &format!("FAIL: {}", violation)));
+    }
+    match verdict.status {
+        Status::Passed if verdict.thresholds_set => println!("{}", painter.paint(Style::Pass, "PASS: all thresholds met")),
+        Status::Passed => {}
+        Status::ThresholdFailed if verdict.violations.is_empty() => {
+            println!("{}", painter.paint(Style::Fail, &format!("FAIL: regressed against the baseline: {}", verdict.regressions.join(", "))))
+        }
+        Status::ThresholdFailed => {}
+        Status::Incomplete => println!("{}", painter.paint(Style::Fail, "INCOMPLETE: the transfer was cut short")),
+        Status::Anomalous => {}
+    }
+}
+
+/// The summary row a threshold or baseline metric, e.g. `throughput (bps)`, belongs to.
+fn row_of(metric: &str) -> Option<&'static str> {
+    let metric = metric.to_ascii_lowercase();
+    if metric.starts_with("throughput") {
+        Some("Effective data rate")
+    } else if metric.starts_with("latency") {
+        Some("Avg transfer time")
+    } else if metric.starts_with("loss") {
+        Some("Loss")
+    } else {
+        None
+    }
+}
+
+/// Things that went wrong during the run without failing it, worth a second look.
+fn anomalies(result: &RunResult) -> Vec<String> {
+    let mut anomalies = Vec::new();
+    if !result.outliers.is_empty() {
+        anomalies.push(format!("{} outlier samples", result.outliers.len()));
+    }
+    if result.stall.is_some() {
+        anomalies.push("the transfer stalled".to_string());
+    }
+    if result.keepalive_drop {
+        anomalies.push("the server stopped answering keepalive probes".to_string());
+    }
+    if result.heartbeat_lost {
+        anomalies.push("the server fell silent".to_string());
+    }
+    if !result.reconnects.is_empty() {
+        anomalies.push(format!("{} reconnects", result.reconnects.len()));
+    }
+    if let Some(corrupted) = result.corrupted_chunks.filter(|&corrupted| corrupted > 0) {
+        anomalies.push(format!("{} corrupted chunks", corrupted));
+    }
+    let sequence = &result.sequence;
+    if sequence.missing + sequence.duplicated + sequence.reordered > 0 {
+        anomalies.push(format!("{} missing, {} duplicated, {} reordered chunks", sequence.missing, sequence.duplicated, sequence.reordered));
+    }
+    anomalies.extend(report::warnings(result).into_iter().cloned());
+    anomalies
+}
@@ -0,0 +1,71 @@
+use common::tcp_info::TcpInfo;
+use crate::results::{CaptureSummary, OptionNegotiation, SynOptions, TcpOptions};
+use crate::tuning::HostSettings;
+
+/// Works out which of window scaling, SACK, and timestamps the handshake negotiated, and for each
+/// one this host asked for but did not get, where it was lost.
+///
+/// # Arguments
+/// - `info`: The kernel's view of the connection, which records the negotiated options.
+/// - `host`: This host's settings, which decide what our SYN offers.
+/// - `capture`: The packet capture, if one ran, with the options the handshake packets carried.
+pub fn verify(info: &TcpInfo, host: &HostSettings, capture: Option<&CaptureSummary>) -> TcpOptions {
+    // Windows reports only whether timestamps are in use
+    let reported = |flag: u8| (!cfg!(windows) || flag == TcpInfo::OPTION_TIMESTAMPS).then_some(info.options & flag != 0);
+    let check = |name: &str, sysctl: &str, flag: u8, enabled_here: Option<bool>, carried: fn(&SynOptions) -> bool| {
+        let mut negotiation = OptionNegotiation {
+            negotiated: reported(flag),
+            enabled_here,
+            offered: capture.and_then(|capture| capture.syn.as_ref()).map(carried),
+            answered: capture.and_then(|capture| capture.syn_ack.as_ref()).map(carried),
+            warning: None,
+        };
+        if negotiation.negotiated == Some(false) {
+            negotiation.warning = Some(if enabled_here == Some(false) {
+                format!("{}: disabled on this host; enable it with sysctl -w net.ipv4.{}=1", name, sysctl)
+            } else if negotiation.offered == Some(false) {
+                format!("{}: missing from the SYN this host sent", name)
+            } else if negotiation.answered == Some(false) {
+                format!("{}: missing from the server's SYN-ACK; the server has it disabled, or a middlebox on the path stripped it", name)
+            } else {
+                format!("{}: not negotiated; the server has it disabled, or a middlebox on the path stripped it", name)
+            });
+        }
+        negotiation
+    };
+
+    let window_scaling = check("Window scaling", "tcp_window_scaling", TcpInfo::OPTION_WSCALE, host.window_scaling, |syn| syn.window_scale.is_some());
+    let scaled = window_scaling.negotiated == Some(true);
+    TcpOptions {
+        window_scaling,
+        sack: check("SACK", "tcp_sack", TcpInfo::OPTION_SACK, host.sack, |syn| syn.sack_permitted),
+        timestamps: check("Timestamps", "tcp_timestamps", TcpInfo::OPTION_TIMESTAMPS, host.timestamps, |syn| syn.timestamps),
+        rcv_wscale: scaled.then_some(info.rcv_wscale),
+        snd_wscale: scaled.then_some(info.snd_wscale),
+    }
+}
+
+/// Prints the negotiated options on one line, then a warning for each one that is missing.
+pub fn print(options: &TcpOptions) {
+    let state = |negotiation: &OptionNegotiation| match negotiation.negotiated {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "unknown",
+    };
+    let shifts = match (options.rcv_wscale, options.snd_wscale) {
+        (Some(ours), Some(theirs)) => format!(" (shift {} here, {} at the server)", ours, theirs),
+        _ => String::new(),
+    };
+    println!(
+        "TCP Options: window scaling {}{}, SACK {}, timestamps {}",
+        state(&options.window_scaling),
+        shifts,
+        state(&options.sack),
+        state(&options.timestamps)
+    );
+    for negotiation in [&options.window_scaling, &options.sack, &options.timestamps] {
+        if let Some(warning) = &negotiation.warning {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+use crate::results::TcpEventSummary;
+
+/// Where tracefs is mounted, on current kernels and on older ones that only have it under debugfs.
+#[cfg(target_os = "linux")]
+const TRACEFS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+/// The tracepoints recorded: every retransmitted segment, and the sender state on every ACK received.
+const EVENTS: [&str; 2] = ["tcp/tcp_retransmit_skb", "tcp/tcp_probe"];
+/// Ring buffer per CPU, holding the events the reader has not caught up with.
+#[cfg(target_os = "linux")]
+const BUFFER_KB: u32 = 4096;
+/// How often the reader checks for new events.
+#[cfg(target_os = "linux")]
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+/// The slow-start threshold before the first loss, which is not a threshold at all.
+const INFINITE_SSTHRESH: u32 = 0x7fff_ffff;
+
+/// What happened to the connection at one point of the run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    Retransmit,
+    /// The congestion window or slow-start threshold changed.
+    Window,
+}
+
+/// A kernel TCP event of the test connection.
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    /// Seconds since the start of the run, on the same timeline as the per-sample offsets.
+    pub offset: f64,
+    pub kind: EventKind,
+    /// Congestion window in segments, as of the last ACK (unknown before the first one).
+    pub cwnd: Option<u32>,
+    pub ssthresh: Option<u32>,
+    /// Smoothed RTT in microseconds.
+    pub srtt_us: Option<u32>,
+    /// Send window the peer advertised, in bytes.
+    pub snd_wnd: Option<u32>,
+}
+
+/// Records the retransmissions and congestion window changes of the test connection from the
+/// kernel's `tcp` tracepoints.
+///
+/// Events are filtered by the kernel on the connection's ports and timestamped by it with
+/// microsecond resolution, in a tracing instance of our own, so other tracing on the host is not
+/// disturbed. They describe our side of the connection as a sender: for a download, the server's
+/// retransmissions and window are on the server and do not show here. Requires Linux, tracefs, and root.
+pub struct Tracer {
+    instance: PathBuf,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<io::Result<Vec<Event>>>>,
+}
+
+impl Tracer {
+    /// Starts recording the events of the connection from `local_port` to `remote_port`.
+    ///
+    /// # Arguments
+    /// - `run_start`: Start of the run, which event offsets are measured from.
+    #[cfg(target_os = "linux")]
+    pub fn start(local_port: u16, remote_port: u16, run_start: Instant) -> io::Result<Self> {
+        use std::fs::OpenOptions;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::{process, thread};
+
+        let root = TRACEFS.iter().map(Path::new).find(|root| root.join("instances").is_dir()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "tracefs is not mounted; mount it with `mount -t tracefs nodev /sys/kernel/tracing`")
+        })?;
+        let instance = root.join("instances").join(format!("bwtest-{}", process::id()));
+        fs::create_dir(&instance).map_err(|e| io::Error::new(e.kind(), format!("failed to create tracing instance {}: {}", instance.display(), e)))?;
+        // From here on the instance is removed again by Drop, whatever fails
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut tracer = Tracer { instance, stop, handle: None };
+        tracer.write("trace_clock", "mono")?;
+        tracer.write("buffer_size_kb", &BUFFER_KB.to_string())?;
+        // Both events carry the socket's own ports in host byte order
+        let filter = format!("sport == {} && dport == {}", local_port, remote_port);
+        for event in EVENTS {
+            tracer.write(&format!("events/{}/filter", event), &filter)?;
+            tracer.write(&format!("events/{}/enable", event), "1")?;
+        }
+
+        // Monotonic time of the run start, to place the kernel's timestamps on the run's timeline
+        let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+        let start = now.tv_sec as f64 + now.tv_nsec as f64 * 1e-9 - run_start.elapsed().as_secs_f64();
+        let pipe = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(tracer.instance.join("trace_pipe"))?;
+        let stopped = Arc::clone(&tracer.stop);
+        tracer.handle = Some(thread::spawn(move || read_events(pipe, &stopped, start)));
+        Ok(tracer)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn start(_local_port: u16, _remote_port: u16, _run_start: Instant) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "kernel TCP events are only available on Linux"))
+    }
+
+    /// Stops recording and returns the events, after reading those still buffered.
+    pub fn finish(mut self) -> io::Result<Vec<Event>> {
+        self.disable();
+        self.stop.store(true, Ordering::Relaxed);
+        let handle = self.handle.take().ok_or_else(|| io::Error::other("tracer already finished"))?;
+        handle.join().map_err(|_| io::Error::other("TCP event reader panicked"))?
+    }
+
+    fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        fs::write(self.instance.join(file), value)
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to set {} of the tracing instance: {}", file, e)))
+    }
+
+    fn disable(&self) {
+        for event in EVENTS {
+            let _ = self.write(&format!("events/{}/enable", event), "0");
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.disable();
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        // The kernel refuses to remove an instance that is still open or has events enabled
+        let _ = fs::remove_dir(&self.instance);
+    }
+}
+
+/// Reads events from the instance's pipe until `stop` is set and the pipe is drained. Probes that
+/// leave the window unchanged, the vast majority, are dropped.
+#[cfg(target_os = "linux")]
+fn read_events(pipe: fs::File, stop: &AtomicBool, start: f64) -> io::Result<Vec<Event>> {
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+
+    let mut reader = BufReader::new(pipe);
+    let mut events = Vec::new();
+    let mut line = String::new();
+    let mut last: Option<Event> = None;
+    loop {
+        match reader.read_line(&mut line) {
+            Ok(_) if line.ends_with('\n') => {
+                if let Some(mut event) = parse(&line, start) {
+                    // Retransmit events carry no window of their own
+                    if event.kind == EventKind::Retransmit {
+                        if let Some(last) = last {
+                            event = Event { cwnd: last.cwnd, ssthresh: last.ssthresh, srtt_us: last.srtt_us, snd_wnd: last.snd_wnd, ..event };
+                        }
+                        events.push(event);
+                    } else if last.is_none_or(|last| (last.cwnd, last.ssthresh) != (event.cwnd, event.ssthresh)) {
+                        events.push(event);
+                    }
+                    if event.kind == EventKind::Window {
+                        last = Some(event);
+                    }
+                }
+                line.clear();
+                continue;
+            }
+            // Nothing new, or part of a line that the next read completes
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        if stop.load(Ordering::Relaxed) {
+            return Ok(events);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Parses a line of `trace_pipe` output, e.g.
+/// `  client-123 [002] ..s1. 5061.181516: tcp_probe: family=AF_INET ... snd_cwnd=10 ssthresh=2147483647 ...`.
+#[cfg(target_os = "linux")]
+fn parse(line: &str, start: f64) -> Option<Event> {
+    let (kind, name) = vec![(EventKind::Retransmit, ": tcp_retransmit_skb: "), (EventKind::Window, ": tcp_probe: ")]
+        .into_iter()
+        .find(|(_, name)| line.contains(name))?;
+    let (head, fields) = line.split_once(name)?;
+    let timestamp: f64 = head.split_whitespace().last()?.parse().ok()?;
+    let field = |key: &str| {
+        fields.split_whitespace().find_map(|pair| pair.strip_prefix(key).and_then(|value| value.strip_prefix('='))).and_then(|value| value.parse().ok())
+    };
+    Some(Event {
+        offset: timestamp - start,
+        kind,
+        cwnd: field("snd_cwnd"),
+        ssthresh: field("ssthresh"),
+        srtt_us: field("srtt"),
+        snd_wnd: field("snd_wnd"),
+    })
+}
+
+/// Summarizes the events of a run.
+pub fn summarize(events: &[Event], csv: &Path) -> TcpEventSummary {
+    let windows: Vec<u32> = events.iter().filter(|event| event.kind == EventKind::Window).filter_map(|event| event.cwnd).collect();
+    TcpEventSummary {
+        retransmits: events.iter().filter(|event| event.kind == EventKind::Retransmit).count() as u64,
+        window_changes: windows.len() as u64,
+        min_cwnd: windows.iter().copied().min(),
+        max_cwnd: windows.iter().copied().max(),
+        final_cwnd: windows.last().copied(),
+        min_ssthresh: events.iter().filter_map(|event| event.ssthresh).filter(|&ssthresh| ssthresh < INFINITE_SSTHRESH).min(),
+        events_csv: csv.display().to_string(),
+    }
+}
+
+/// Writes the events to a CSV file, on the same timeline as the per-sample metrics, after a column
+/// for each of the run's tags.
+pub fn write_csv(events: &[Event], path: &Path, tags: &BTreeMap<String, String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(tags.keys().map(String::as_str).chain(["Offset (s)", "Event", "Cwnd (segments)", "Ssthresh (segments)", "SRTT (us)", "Send Window (bytes)"]))?;
+    let optional = |value: Option<u32>| value.map(|value| value.to_string()).unwrap_or_default();
+    for event in events {
+        let kind = match event.kind {
+            EventKind::Retransmit => "retransmit",
+            EventKind::Window => "window",
+        };
+        // The threshold before the first loss is left empty rather than shown as 2^31
+        let ssthresh = event.ssthresh.filter(|&ssthresh| ssthresh < INFINITE_SSTHRESH);
+        wtr.write_record(tags.values().cloned().chain([
+            format!("{:.6}", event.offset),
+            kind.to_string(),
+            optional(event.cwnd),
+            optional(ssthresh),
+            optional(event.srtt_us),
+            optional(event.snd_wnd),
+        ]))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
@@ -0,0 +1,73 @@
+use std::fmt;
+use serde::Serialize;
+
+/// Pass/fail limits checked against the results of a run.
+///
+/// Every limit is optional; an unset limit is never violated.
+pub struct Thresholds {
+    /// Minimum acceptable average effective data rate, in bits per second.
+    pub min_throughput_bps: Option<f64>,
+    /// Maximum acceptable average per-chunk download time, in seconds.
+    pub max_latency_seconds: Option<f64>,
+    /// Maximum acceptable share of expected data that was not received, in percent.
+    pub max_loss_percent: Option<f64>,
+}
+
+/// A single threshold that the run did not meet.
+#[derive(Serialize)]
+pub struct Violation {
+    pub metric: &'static str,
+    /// The measured value, absent if the run had none, like the latency of a run that received
+    /// no chunk.
+    pub measured: Option<f64>,
+    pub limit: f64,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.measured {
+            Some(measured) => write!(f, "{} was {:.5}, limit {:.5}", self.metric, measured, self.limit),
+            None => write!(f, "{} was n/a, limit {:.5}", self.metric, self.limit),
+        }
+    }
+}
+
+impl Thresholds {
+    /// Returns true if at least one limit was configured.
+    pub fn is_set(&self) -> bool {
+        self.min_throughput_bps.is_some() || self.max_latency_seconds.is_some() || self.max_loss_percent.is_some()
+    }
+
+    /// Checks the measured values against the configured limits.
+    ///
+    /// # Arguments
+    /// - `throughput_bps`: Average effective data rate in bits per second, if any data arrived.
+    /// - `latency_seconds`: Average per-chunk download time in seconds, if any chunk arrived.
+    /// - `loss_percent`: Percentage of the expected data that was not received.
+    ///
+    /// # Returns
+    /// - The list of violated thresholds, empty if the run passed.
+    pub fn evaluate(&self, throughput_bps: Option<f64>, latency_seconds: Option<f64>, loss_percent: f64) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        if let Some(limit) = self.min_throughput_bps {
+            // Nothing received fails a minimum rather than being skipped
+            if throughput_bps.is_none_or(|throughput| throughput < limit) {
+                violations.push(Violation { metric: "throughput (bps)", measured: throughput_bps, limit });
+            }
+        }
+        if let Some(limit) = self.max_latency_seconds {
+            // Without a chunk there is no latency to meet the limit with
+            if latency_seconds.is_none_or(|latency| latency > limit) {
+                violations.push(Violation { metric: "latency (s)", measured: latency_seconds, limit });
+            }
+        }
+        if let Some(limit) = self.max_loss_percent {
+            if loss_percent > limit {
+                violations.push(Violation { metric: "loss (%)", measured: Some(loss_percent), limit });
+            }
+        }
+
+        violations
+    }
+}
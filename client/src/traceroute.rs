@@ -0,0 +1,232 @@
+use std::convert::TryInto;
+use std::io::{self, ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use crate::ping;
+use crate::results::{Hop, Traceroute};
+
+/// Probes sent per hop, as in the classic traceroute.
+const PROBES_PER_HOP: usize = 3;
+
+/// How long to wait for the answer to a single probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// First destination port of UDP probes, as in the classic traceroute.
+const UDP_BASE_PORT: u16 = 33434;
+
+/// How often a pending TCP connect is checked while waiting for ICMP answers.
+const TCP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMP_DESTINATION_UNREACHABLE: u8 = 3;
+
+/// Kind of packet used to probe each hop; some networks only let one of them through.
+#[derive(Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceMode {
+    /// UDP datagrams to high ports, answered by the server with port unreachable.
+    Udp,
+    /// ICMP echo requests, answered by the server with echo replies.
+    Icmp,
+    /// TCP SYNs to the test port, answered by the server with SYN-ACK or reset.
+    Tcp,
+}
+
+/// What a probe was sent as, to recognise the ICMP answers that quote it.
+enum Probe {
+    Udp { source_port: u16, destination_port: u16 },
+    Icmp { identifier: u16, sequence: u16 },
+    Tcp { source_port: u16, socket: Socket },
+}
+
+/// Who answered a probe, and whether the path ends there.
+struct Answer {
+    address: Ipv4Addr,
+    last: bool,
+}
+
+/// Traces the path to `target` by sending probes with increasing TTLs and collecting the ICMP time
+/// exceeded messages of the routers on the way.
+///
+/// Reading ICMP errors requires a raw socket (root or CAP_NET_RAW). Only IPv4 is supported.
+///
+/// # Arguments
+/// - `target`: The test server; TCP probes go to its port.
+/// - `bind`: Local address to send from, if any.
+/// - `mode`: Kind of probe to send.
+/// - `max_hops`: Largest TTL to try before giving up.
+pub fn trace(target: SocketAddr, bind: Option<IpAddr>, mode: TraceMode, max_hops: u8) -> io::Result<Traceroute> {
+    let target_ip = match target.ip() {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(_) => return Err(io::Error::new(ErrorKind::Unsupported, "traceroute supports only IPv4 servers")),
+    };
+    let receiver = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
+    let source = SocketAddr::new(bind.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)), 0);
+    let udp = match mode {
+        TraceMode::Udp => {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.bind(&source.into())?;
+            Some(socket)
+        }
+        _ => None,
+    };
+    let identifier: u16 = rand::random();
+
+    let mut hops = Vec::new();
+    let mut ended = false;
+    let mut sequence: u16 = 0;
+    for ttl in 1..=max_hops {
+        let mut hop = Hop { ttl, address: None, rtts_seconds: Vec::new() };
+        for _ in 0..PROBES_PER_HOP {
+            sequence = sequence.wrapping_add(1);
+            let sent = Instant::now();
+            let probe = match mode {
+                TraceMode::Udp => {
+                    let socket = udp.as_ref().expect("UDP traces open a UDP socket");
+                    let destination_port = UDP_BASE_PORT.wrapping_add(sequence);
+                    socket.set_ttl(u32::from(ttl))?;
+                    socket.send_to(&[0u8; 32], &SocketAddr::new(target.ip(), destination_port).into())?;
+                    Probe::Udp { source_port: local_port(socket)?, destination_port }
+                }
+                TraceMode::Icmp => {
+                    receiver.set_ttl(u32::from(ttl))?;
+                    receiver.send_to(&ping::echo_request(false, identifier, sequence), &SocketAddr::new(target.ip(), 0).into())?;
+                    Probe::Icmp { identifier, sequence }
+                }
+                TraceMode::Tcp => {
+                    let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))?;
+                    socket.bind(&source.into())?;
+                    socket.set_ttl(u32::from(ttl))?;
+                    socket.set_nonblocking(true)?;
+                    // Completes or fails asynchronously; the outcome is polled while waiting
+                    let _ = socket.connect(&target.into());
+                    Probe::Tcp { source_port: local_port(&socket)?, socket }
+                }
+            };
+
+            if let Some(answer) = wait_for_answer(&receiver, &probe, target_ip, sent + PROBE_TIMEOUT)? {
+                hop.rtts_seconds.push(sent.elapsed().as_secs_f64());
+                hop.address.get_or_insert(IpAddr::V4(answer.address));
+                ended |= answer.last;
+            }
+        }
+        hops.push(hop);
+        if ended {
+            break;
+        }
+    }
+
+    let reached = hops.last().is_some_and(|hop: &Hop| hop.address == Some(target.ip()));
+    Ok(Traceroute { mode, hops, reached })
+}
+
+fn local_port(socket: &Socket) -> io::Result<u16> {
+    socket
+        .local_addr()?
+        .as_socket()
+        .map(|addr| addr.port())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "probe socket has no IP address"))
+}
+
+/// Reads ICMP messages until one answers `probe` or the deadline passes.
+fn wait_for_answer(receiver: &Socket, probe: &Probe, target: Ipv4Addr, deadline: Instant) -> io::Result<Option<Answer>> {
+    let mut packet = [0u8; 1500];
+    loop {
+        if let Probe::Tcp { socket, .. } = probe {
+            // A SYN-ACK or a reset both mean the probe reached the server
+            let refused = matches!(socket.take_error()?, Some(e) if e.kind() == ErrorKind::ConnectionRefused);
+            if refused || socket.peer_addr().is_ok() {
+                return Ok(Some(Answer { address: target, last: true }));
+            }
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        let wait = match probe {
+            Probe::Tcp { .. } => remaining.min(TCP_POLL_INTERVAL),
+            _ => remaining,
+        };
+        receiver.set_read_timeout(Some(wait))?;
+        let len = match (&*receiver).read(&mut packet) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(answer) = parse_answer(&packet[..len], probe, target) {
+            return Ok(Some(answer));
+        }
+    }
+}
+
+/// Recognises an IPv4 packet carrying an ICMP answer to `probe`.
+fn parse_answer(packet: &[u8], probe: &Probe, target: Ipv4Addr) -> Option<Answer> {
+    let (source, icmp) = ip_payload(packet)?;
+    let (kind, code) = (*icmp.first()?, *icmp.get(1)?);
+
+    if kind == ping::ECHO_REPLY_V4 {
+        let matches = matches!(probe, Probe::Icmp { identifier, sequence }
+            if icmp.get(4..6)? == identifier.to_be_bytes() && icmp.get(6..8)? == sequence.to_be_bytes());
+        return if matches { Some(Answer { address: source, last: true }) } else { None };
+    }
+    if kind != ICMP_TIME_EXCEEDED && kind != ICMP_DESTINATION_UNREACHABLE {
+        return None;
+    }
+
+    // Errors quote the IP header and the first 8 bytes of the packet that caused them
+    let quoted = icmp.get(8..)?;
+    let (_, transport) = ip_payload(quoted)?;
+    let protocol = *quoted.get(9)?;
+    if quoted.get(16..20)? != target.octets() {
+        return None;
+    }
+    let quotes_probe = match probe {
+        Probe::Udp { source_port, destination_port } => {
+            protocol == 17 && transport.get(0..2)? == source_port.to_be_bytes() && transport.get(2..4)? == destination_port.to_be_bytes()
+        }
+        Probe::Icmp { identifier, sequence } => {
+            protocol == 1 && transport.get(4..6)? == identifier.to_be_bytes() && transport.get(6..8)? == sequence.to_be_bytes()
+        }
+        Probe::Tcp { source_port, .. } => protocol == 6 && transport.get(0..2)? == source_port.to_be_bytes(),
+    };
+    if !quotes_probe {
+        return None;
+    }
+
+    // Anything but a TTL expiry in transit ends the trace: port unreachable from the server is the
+    // expected end of a UDP trace, other unreachables mean the path goes no further
+    let last = !(kind == ICMP_TIME_EXCEEDED && code == 0);
+    Some(Answer { address: source, last })
+}
+
+/// Splits an IPv4 packet into its source address and payload.
+fn ip_payload(packet: &[u8]) -> Option<(Ipv4Addr, &[u8])> {
+    let header_len = usize::from(packet.first()? & 0x0f) * 4;
+    let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+    Some((Ipv4Addr::from(source), packet.get(header_len..)?))
+}
+
+/// Prints the trace one hop per line, like traceroute does.
+pub fn print(traceroute: &Traceroute) {
+    for hop in &traceroute.hops {
+        let mut line = format!("{:>3}", hop.ttl);
+        if let Some(address) = hop.address {
+            line.push_str(&format!("  {}", address));
+        }
+        for rtt in &hop.rtts_seconds {
+            line.push_str(&format!("  {:.3} ms", rtt * 1000.0));
+        }
+        for _ in hop.rtts_seconds.len()..PROBES_PER_HOP {
+            line.push_str("  *");
+        }
+        println!("{}", line);
+    }
+    if !traceroute.reached {
+        println!("Server not reached after {} hops", traceroute.hops.len());
+    }
+}
+
@@ -0,0 +1,1955 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use common::buffer::AlignedBuffer;
+use common::frame::{self, ChunkHeader, EndOfTest, Heartbeat};
+use common::heartbeat;
+use common::netif;
+use common::payload;
+use common::protocol::{BurstPattern, Direction, Reply, TestRequest, TokenBucket, PROTOCOL_VERSION, REPLY_VERSION};
+use common::sockopt;
+use common::tcp_info::{self, TcpInfo};
+use csv::Writer;
+use crate::bottleneck;
+use crate::burst::{self, ChunkTiming};
+use crate::capture::Capture;
+use crate::connection::{self, Family};
+use crate::cross_traffic;
+use crate::dns;
+use crate::echo;
+use crate::energy;
+use crate::html;
+#[cfg(feature = "fault-injection")]
+use crate::fault;
+use crate::metrics;
+use crate::mqtt;
+use crate::netdev;
+use crate::ping::{self, PingSeries, Pinger};
+use crate::plot::{self, Marks, SampleAxis};
+use crate::report::Chart;
+use crate::results::{self, ConnectionSetup, EchoSummary, Energy, FastOpen, FileTransfer, HappyEyeballs, InterfaceCounters, Outlier, PingSummary, Reconnect, RunResult, SocketIo, Stall, Streaming, WifiSummary};
+use crate::s3;
+use crate::sampling::{self, AdaptiveSampler, Sample};
+use crate::sequence::{Arrival, SequenceTracker};
+use crate::sock_diag;
+use crate::soak::{RollingSummaries, RotatingCsv};
+use crate::stats::{self, Summary};
+use crate::streaming::Player;
+use crate::stun;
+use crate::tcp_options;
+use crate::tcp_trace::{self, Tracer};
+use crate::traceroute;
+use crate::tuning;
+use crate::wifi;
+use crate::wire::IoCounters;
+use crate::units::Units;
+use crate::{chart_theme, connect_options, output, Args};
+use socket2::SockRef;
+
+/// RTT assumed for the BDP and TCP throughput estimates when the kernel does not report one.
+const ASSUMED_RTT_SECONDS: f64 = 0.2;
+
+/// Share of the measured window ceiling above which a transfer is reported as window-limited.
+const WINDOW_LIMITED_RATIO: f64 = 0.9;
+
+/// Share of the samples at the start of a run that are treated as ramp-up (TCP slow start, buffer
+/// auto-tuning) and left out of the stability score.
+const RAMP_UP_FRACTION: f64 = 0.1;
+
+/// Number of ICMP echo requests sent before the transfer for the idle reference.
+const IDLE_PINGS: usize = 5;
+
+/// Number of UDP echo probes sent before the transfer for the idle reference; probes are sent
+/// more often than pings, so more of them fit in the same time.
+const IDLE_PROBES: usize = 20;
+
+/// Why a connection was lost when the kernel gave up on it after unanswered keepalive probes.
+const KEEPALIVE_DROP: &str = "the server stopped answering keepalive probes";
+
+/// Why a run ends when the server's heartbeats stop.
+const HEARTBEAT_LOST: &str = "the server sent neither data nor heartbeats";
+
+/// Longest wait between reconnect attempts, however often the backoff has doubled.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Smallest default token bucket for --shape-rate-bps: one full-size Ethernet frame.
+const SHAPING_MIN_BURST: u64 = 1500;
+
+/// Share of the test data that interface traffic may exceed the test and its headers by before it
+/// is reported as retransmissions or cross-traffic.
+const UNEXPLAINED_TRAFFIC_RATIO: f64 = 0.05;
+
+/// Files written by a single test run.
+pub struct Outputs {
+    /// Per-chunk metrics CSV.
+    pub metrics_csv: PathBuf,
+    /// Rolling summaries CSV of a soak test.
+    pub summary_csv: PathBuf,
+    /// Latency and data rate chart.
+    pub chart: PathBuf,
+    /// Interactive latency and data rate chart.
+    pub html_chart: PathBuf,
+    /// Wi-Fi link samples CSV.
+    pub wifi_csv: PathBuf,
+    /// Wi-Fi link chart.
+    pub wifi_chart: PathBuf,
+    /// Ping round-trip time against data rate chart.
+    pub scatter_chart: PathBuf,
+    /// Packet capture of the test connection.
+    pub capture: PathBuf,
+    /// Kernel TCP events CSV.
+    pub tcp_events_csv: PathBuf,
+    /// Playout buffer levels CSV of a simulated stream.
+    pub stream_csv: PathBuf,
+    /// Playout buffer chart of a simulated stream.
+    pub stream_chart: PathBuf,
+}
+
+impl Outputs {
+    /// Returns the output paths for a run in `direction`, with `_<suffix>` appended to every file
+    /// name if a suffix is given so that several runs in one invocation do not overwrite each other.
+    /// The `--output-dir` and `--name-template` apply to every path.
+    pub fn new(args: &Args, direction: Direction, suffix: Option<&str>) -> Self {
+        let name = |stem: &str, extension: &str| match suffix {
+            Some(suffix) => output::path(args, Some(direction), format!("{}_{}.{}", stem, suffix, extension)),
+            None => output::path(args, Some(direction), format!("{}.{}", stem, extension)),
+        };
+        match direction {
+            Direction::Download => Outputs {
+                metrics_csv: name("download_metrics", "csv"),
+                summary_csv: name("download_summary", "csv"),
+                chart: name("latency_data_rate", "png"),
+                html_chart: name("latency_data_rate", "html"),
+                wifi_csv: name("download_wifi", "csv"),
+                wifi_chart: name("wifi_link", "png"),
+                scatter_chart: name("latency_vs_throughput", "png"),
+                capture: name("download_capture", "pcap"),
+                tcp_events_csv: name("download_tcp_events", "csv"),
+                stream_csv: name("stream_buffer", "csv"),
+                stream_chart: name("stream_buffer", "png"),
+            },
+            Direction::Upload => Outputs {
+                metrics_csv: name("upload_metrics", "csv"),
+                summary_csv: name("upload_summary", "csv"),
+                chart: name("latency_data_rate_upload", "png"),
+                html_chart: name("latency_data_rate_upload", "html"),
+                wifi_csv: name("upload_wifi", "csv"),
+                wifi_chart: name("wifi_link_upload", "png"),
+                scatter_chart: name("latency_vs_throughput_upload", "png"),
+                capture: name("upload_capture", "pcap"),
+                tcp_events_csv: name("upload_tcp_events", "csv"),
+                stream_csv: name("stream_buffer_upload", "csv"),
+                stream_chart: name("stream_buffer_upload", "png"),
+            },
+        }
+    }
+}
+
+/// Result of one test run.
+pub struct Outcome {
+    pub result: RunResult,
+    /// The error that cut the transfer short, if any. The result still covers the data received before it.
+    pub transfer_error: Option<io::Error>,
+    /// Per-sample download times, in seconds, as plotted.
+    pub latencies: Vec<f64>,
+    /// Per-sample effective data rates, in bits per second, as plotted.
+    pub data_rates: Vec<f64>,
+    /// Time from the start of the run to the end of each sample, in seconds.
+    pub offsets: Vec<f64>,
+    /// Charts written for the run, for the reports.
+    pub charts: Vec<Chart>,
+}
+
+/// Connects to the server, runs one test in `direction`, and writes its CSV and chart outputs.
+///
+/// Uploads are sent continuously from a separate thread and timed by the server's acknowledgements,
+/// so like downloads they are measured where the data arrives, plus the constant return trip of
+/// the acknowledgement.
+///
+/// # Arguments
+/// - `args`: Command line options describing the test.
+/// - `outputs`: Where to write the per-run files.
+/// - `direction`: Which way the data flows.
+///
+/// # Returns
+/// - The run summary, together with the error that ended the transfer early, if any.
+pub fn run_test(args: &Args, outputs: &Outputs, direction: Direction) -> Result<Outcome, Box<dyn std::error::Error>> {
+    if args.s3_endpoint.is_some() {
+        return s3::run_test(args, outputs, direction);
+    }
+    // Through a relay, the connection and everything measured on its path ends at the relay
+    let peer = args.relay.as_deref().unwrap_or(&args.server);
+    let dns = match dns::hostname(peer) {
+        Some(host) => dns::time_lookups(host),
+        None => Vec::new(),
+    };
+    for lookup in &dns {
+        match &lookup.error {
+            Some(e) => println!("DNS {} lookup: {:.3} ms ({})", lookup.record_type, lookup.seconds * 1000.0, e),
+            None => println!(
+                "DNS {} lookup: {:.3} ms ({} addresses)",
+                lookup.record_type,
+                lookup.seconds * 1000.0,
+                lookup.addresses.len()
+            ),
+        }
+    }
+    let stun = args.stun.as_deref().and_then(|server| match stun::query(server, args.bind) {
+        Ok(mapping) => {
+            println!(
+                "STUN: local {}, public {}{}",
+                mapping.local_address,
+                mapping.mapped_address,
+                if mapping.behind_nat() { " (behind NAT)" } else { "" }
+            );
+            Some(mapping)
+        }
+        Err(e) => {
+            eprintln!("STUN query failed, continuing without it: {}", e);
+            None
+        }
+    });
+    // Traced before connecting, since the server gives up on a connection that sends no request
+    let traceroute = match args.traceroute {
+        Some(mode) => {
+            let traced = connection::resolve(peer, args.bind, args.family)
+                .and_then(|target| traceroute::trace(target, args.bind, mode, args.traceroute_max_hops));
+            match traced {
+                Ok(traced) => {
+                    println!("Path to the server:");
+                    traceroute::print(&traced);
+                    Some(traced)
+                }
+                Err(e) => {
+                    eprintln!("Traceroute failed, continuing without it: {}", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    // Started before connecting, so the handshake and its negotiated options are in the capture
+    let capture = if args.capture {
+        let started = capture_address(args, peer)
+            .and_then(|remote| Capture::start(&outputs.capture, remote, args.capture_snaplen, args.capture_max_mb * 1_000_000));
+        match started {
+            Ok(capture) => Some(capture),
+            Err(e) => {
+                eprintln!("Packet capture unavailable, continuing without it: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let (mut stream, connect_timings) = connection::connect(peer, &connect_options(args))?;
+    let local_address = stream.local_addr()?;
+    if let Some(proxy) = &args.proxy {
+        println!("Tunneled through proxy {}", proxy);
+    }
+    match &args.relay {
+        Some(relay) => println!("Connected to relay {}, forwarding to {}...", relay, args.server),
+        None => println!("Connected to the server..."),
+    }
+    match &args.interface {
+        Some(interface) => println!("Testing from {} via interface {}", local_address, interface),
+        None => println!("Testing from {}", local_address),
+    }
+    if let Some(keepalive) = &connect_options(args).keepalive {
+        println!("TCP keepalive: {}", keepalive);
+    }
+
+    let burst = match (args.burst_on_ms, args.burst_off_ms) {
+        (Some(on), Some(off)) => Some(BurstPattern { on: Duration::from_millis(on), off: Duration::from_millis(off) }),
+        _ => None,
+    };
+    let shape = args.shape_rate_bps.map(|rate_bps| TokenBucket {
+        rate_bps,
+        // 10 ms at the rate, in bytes
+        burst_bytes: args.shape_burst_bytes.unwrap_or((rate_bps / 800).max(SHAPING_MIN_BURST)),
+    });
+    if let Some(shape) = &shape {
+        println!("Server shaping: token bucket at {}, {} byte burst", args.units.rate(shape.rate_bps as f64), shape.burst_bytes);
+    }
+    // A soak test streams until the deadline, so the server is asked for an unbounded transfer
+    let soak_duration = args.duration.map(Duration::from_secs_f64);
+    let defaults = TestRequest::default();
+    // Adaptive samples are merged from small chunks, as many as make up the default transfer
+    let chunk_size = if args.adaptive_chunks { sampling::CHUNK_SIZE } else { defaults.chunk_size };
+    // A player reads at most about a second of media at a time, so a full buffer never holds the
+    // server off for long
+    let chunk_size = match args.stream_bitrate {
+        Some(bitrate) => chunk_size.min(((bitrate / 8) as usize).max(sampling::CHUNK_SIZE)),
+        None => chunk_size,
+    };
+    let chunk_count = if soak_duration.is_some() || args.file.is_some() {
+        0
+    } else {
+        (defaults.chunk_count * defaults.chunk_size).div_ceil(chunk_size)
+    };
+    let payload_seed = args.payload_seed.or_else(|| if args.random_payload { Some(rand::random()) } else { None });
+    if let Some(seed) = payload_seed {
+        println!("Payload seed: {}", seed);
+    }
+    let request = TestRequest {
+        direction,
+        chunk_size,
+        chunk_count,
+        burst,
+        dscp: args.dscp,
+        ttl: args.ttl,
+        checksum: args.verify,
+        payload_seed,
+        file: args.file.clone(),
+        relay: args.relay.as_ref().map(|_| args.server.clone()),
+        heartbeat: args.heartbeat_ms.map(Duration::from_millis),
+        shape,
+        ..TestRequest::default()
+    };
+    // The idle reference is taken before the request is sent, while the link is still quiet
+    let ping_interval = Duration::from_millis(args.ping_interval_ms);
+    let mut pinger = None;
+    let mut ping_idle = None;
+    if args.ping {
+        match Pinger::new(connect_timings.peer.ip()) {
+            Ok(mut idle_pinger) => {
+                ping_idle = Some(ping::series(&mut idle_pinger, IDLE_PINGS, ping_interval).summary());
+                pinger = Some(idle_pinger);
+            }
+            Err(e) => eprintln!("ICMP ping unavailable, continuing without it: {}", e),
+        }
+    }
+    let echo_interval = Duration::from_millis(args.udp_echo_interval_ms);
+    let mut prober = None;
+    let mut udp_echo_idle = None;
+    if let Some(port) = args.udp_echo {
+        match echo::Prober::new(SocketAddr::new(connect_timings.peer.ip(), port), Some(local_address.ip())) {
+            Ok(mut idle_prober) => {
+                udp_echo_idle = Some(echo::series(&mut idle_prober, IDLE_PROBES, echo_interval).summary());
+                prober = Some(idle_prober);
+            }
+            Err(e) => eprintln!("UDP echo unavailable, continuing without it: {}", e),
+        }
+    }
+    let wifi_interface = if args.wifi {
+        match test_interface(args, local_address.ip()).and_then(|name| wifi::link_state(&name).map(|_| name)) {
+            Ok(name) => Some(name),
+            Err(e) => {
+                eprintln!("Wi-Fi metrics unavailable, continuing without them: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    // Sampled before the request, so the counters cover everything the test puts on the wire
+    let counters_at_start =
+        test_interface(args, local_address.ip()).and_then(|name| netdev::read(&name).map(|counters| (name, counters)));
+    let remote_address = connect_timings.peer;
+    let mut socket_snapshots = Vec::new();
+    if args.socket_snapshots {
+        match sock_diag::snapshot(local_address, remote_address, "before") {
+            Ok(snapshot) => socket_snapshots.extend(snapshot),
+            Err(e) => eprintln!("Socket snapshots unavailable, continuing without them: {}", e),
+        }
+    }
+    request.write_to(&mut stream)?;
+    let request_sent = Instant::now();
+    let mut first_byte = None;
+
+    // A read that makes no progress for this long is a stall; the idle gap between bursts is expected,
+    // unless the server fills it with heartbeats
+    let stall_timeout = if let Some(interval) = request.heartbeat {
+        Some(heartbeat::timeout(interval))
+    } else if args.stall_timeout > 0.0 {
+        let idle = request.burst.map_or(Duration::ZERO, |burst| burst.off);
+        Some(Duration::from_secs_f64(args.stall_timeout) + idle)
+    } else {
+        None
+    };
+    stream.set_read_timeout(stall_timeout)?;
+    stream.set_write_timeout(stall_timeout)?;
+    read_reply(&mut stream)?;
+    // The options are settled by the handshake, whatever happens to the connection later; with
+    // Fast Open, the handshake is only over once the reply has arrived
+    let handshake_info = tcp_info::tcp_info(&stream).ok();
+    // Kept to query the kernel's TCP state if the transfer stalls
+    let mut probe = stream.try_clone()?;
+
+    #[cfg(feature = "fault-injection")]
+    if args.fault.is_active() {
+        println!("Fault injection enabled");
+    }
+    // Uploads only read acknowledgements here, so their faults apply per acknowledgement
+    let read_chunk_size = match direction {
+        Direction::Download => ChunkHeader::LEN + request.chunk_size,
+        Direction::Upload => ChunkHeader::LEN,
+    };
+    let mut stream = inject_faults(stream, args, read_chunk_size);
+
+    // A single page-aligned buffer is reused for every chunk so it is never reallocated or re-zeroed
+    let mut buffer = AlignedBuffer::new(request.chunk_size, args.hugepages);
+    if args.hugepages && !buffer.is_hugepage_backed() {
+        eprintln!("Hugepages unavailable, falling back to regular pages");
+    }
+    // The content every chunk must have, so seeded payloads can be verified without checksums;
+    // uploads send it instead and leave checking it to the server
+    let expected_payload = payload_seed.map(|seed| {
+        let mut expected = vec![0u8; request.chunk_size];
+        payload::fill_seeded(&mut expected, seed);
+        expected
+    });
+    let upload_checksum = match direction {
+        Direction::Upload => {
+            if let Some(expected) = &expected_payload {
+                buffer.copy_from_slice(expected);
+            }
+            ChunkHeader::checksum_of(&buffer)
+        }
+        Direction::Download => 0,
+    };
+    let verifying = request.checksum || (direction == Direction::Download && expected_payload.is_some());
+    let label = match direction {
+        Direction::Download => "Download",
+        Direction::Upload => "Upload",
+    };
+    let mut total_data_transferred = 0;
+    let mut total_time = Duration::new(0, 0);
+
+    let run_id = results::new_run_id(SystemTime::now());
+    let tags = output::tags(args);
+    let rotate_after = soak_duration.map(|_| Duration::from_secs_f64(args.rotate_after));
+    let mut wtr = RotatingCsv::new(
+        &outputs.metrics_csv,
+        &["Chunk", &format!("{} Time (s)", label), "Effective Data Rate (bps)", "Offset (s)", "Timestamp"],
+        rotate_after,
+        (!args.per_run_files).then_some(run_id.as_str()),
+        &tags,
+    )?;
+
+    // Sample timestamps are the wall-clock start plus the monotonic offset, so a clock step during
+    // the run cannot reorder them
+    let run_start = Instant::now();
+    let tracer = if args.tcp_events {
+        match Tracer::start(local_address.port(), remote_address.port(), run_start) {
+            Ok(tracer) => Some(tracer),
+            Err(e) => {
+                eprintln!("Kernel TCP events unavailable, continuing without them: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let started_at = SystemTime::now();
+    let background_ping = pinger.map(|pinger| ping::Background::start(pinger, ping_interval));
+    let background_echo = prober.map(|prober| echo::Background::start(prober, echo_interval));
+    let background_wifi = wifi_interface
+        .clone()
+        .map(|name| wifi::Background::start(name, Duration::from_millis(args.wifi_interval_ms), run_start));
+    // Only once the first snapshot showed that the kernel answers
+    let background_snapshots = (!socket_snapshots.is_empty()).then(|| {
+        let interval = Duration::from_secs_f64(args.socket_snapshot_interval);
+        sock_diag::Background::start(local_address, remote_address, interval)
+    });
+    let mqtt_samples = crate::mqtt_broker(args).map(|broker| {
+        let interval = Duration::from_secs_f64(args.mqtt_interval);
+        mqtt::Sampler::start(broker, format!("{}/samples", crate::mqtt_topic(args)), interval, direction, started_at, tags.clone())
+    });
+    let mut sampler = bottleneck::Sampler::start();
+    let energy_meter = if args.energy {
+        match energy::Meter::start() {
+            Ok(meter) => Some(meter),
+            Err(e) => {
+                eprintln!("Energy measurement unavailable, continuing without it: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut summaries = match soak_duration {
+        Some(_) => Some(RollingSummaries::new(
+            &outputs.summary_csv,
+            Duration::from_secs_f64(args.summary_interval),
+            run_start,
+            started_at,
+            label,
+            &tags,
+        )?),
+        None => None,
+    };
+
+    // In a soak test these hold one entry per short summary window instead of one per chunk,
+    // which keeps memory bounded on multi-hour runs
+    let mut latencies = Vec::new();
+    let mut data_rates = Vec::new();
+    let mut offsets = Vec::new();
+    let mut chunk_timings = Vec::new();
+    let mut chunks_received = 0;
+    let mut latency_sum = 0.0;
+    let mut transfer_error = None;
+    let mut stall = None;
+    let mut corrupted_chunks = 0;
+    let mut sequence = SequenceTracker::default();
+    let mut one_way_delay_sum = 0.0;
+    let mut reconnects = Vec::new();
+    let mut keepalive_drop = false;
+    let mut heartbeat_lost = false;
+    let mut end_of_test = None;
+    let mut output_file = match &args.output_file {
+        Some(path) => Some(File::create(path)?),
+        None => None,
+    };
+    let mut disk_write_time = Duration::ZERO;
+    let mut reconnect_budget = args.reconnect;
+
+    let upload_payload = match direction {
+        Direction::Upload => Some(buffer.to_vec()),
+        Direction::Download => None,
+    };
+    let io_counters = Arc::new(IoCounters::default());
+    let mut uploader = match &upload_payload {
+        Some(payload) => Some(start_uploader(&probe, payload, &request, upload_checksum, &io_counters)?),
+        None => None,
+    };
+    let mut heartbeats = start_heartbeats(&probe, &request, &io_counters)?;
+    let cross_traffic_interval = Duration::from_millis(args.cross_traffic_interval_ms);
+    let background_cross_traffic = if args.cross_traffic {
+        match test_interface(args, local_address.ip()).and_then(|name| netdev::read(&name).map(|_| name)) {
+            Ok(name) => {
+                let stream = probe.try_clone()?;
+                let background =
+                    cross_traffic::Background::start(name.clone(), local_address.ip(), stream, direction, Arc::clone(&io_counters), cross_traffic_interval, run_start);
+                Some((name, background))
+            }
+            Err(e) => {
+                eprintln!("Cross-traffic detection unavailable, continuing without it: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    // Payload bytes and time of a download chunk the connection ends in
+    let mut cut_off = (0, Duration::ZERO);
+
+    let mut adaptive = args
+        .adaptive_chunks
+        .then(|| AdaptiveSampler::new(Duration::from_millis(args.adaptive_chunk_ms), request.chunk_size));
+    let sample_name = if adaptive.is_some() { "Sample" } else { "Chunk" };
+    let mut player = args.stream_bitrate.map(|bitrate| {
+        println!("Streaming at {} with a {} s buffer", args.units.rate(bitrate as f64), args.stream_buffer);
+        Player::new(bitrate, Duration::from_secs_f64(args.stream_startup), Duration::from_secs_f64(args.stream_buffer), run_start)
+    });
+    let mut samples_recorded = 0;
+    let mut record_sample = |sample: Sample| -> Result<(), Box<dyn std::error::Error>> {
+        samples_recorded += 1;
+        let download_time = sample.duration.as_secs_f64();
+        let effective_data_rate = sample.bytes as f64 * 8.0 / download_time;
+        let offset = sample.end - run_start;
+        if let Some(samples) = &mqtt_samples {
+            samples.record(offset.as_secs_f64(), download_time, sample.bytes);
+        }
+        wtr.write_record(&[
+            samples_recorded.to_string(),
+            download_time.to_string(),
+            effective_data_rate.to_string(),
+            offset.as_secs_f64().to_string(),
+            humantime::format_rfc3339_micros(started_at + offset).to_string(),
+        ])?;
+        match summaries.as_mut() {
+            Some(summaries) => {
+                if let Some(summary) = summaries.record(sample.end, download_time, sample.bytes)? {
+                    latencies.push(summary.avg_latency_seconds);
+                    data_rates.push(summary.throughput_bps);
+                    offsets.push(summary.end_offset_seconds);
+                }
+            }
+            None => {
+                latencies.push(download_time);
+                data_rates.push(effective_data_rate);
+                offsets.push(offset.as_secs_f64());
+                println!(
+                    "{} {}: {} Time: {:.2}s, Effective Data Rate: {:.2} bps",
+                    sample_name, samples_recorded, label, download_time, effective_data_rate
+                );
+            }
+        }
+        Ok(())
+    };
+
+    loop {
+        let i = chunks_received + 1;
+        let done = match soak_duration {
+            Some(duration) => run_start.elapsed() >= duration,
+            // A file transfer ends with the server's end-of-test frame
+            None if request.file.is_some() => false,
+            None => i > request.chunk_count,
+        };
+        if done {
+            break;
+        }
+        if let Some(player) = player.as_mut() {
+            let wait = player.wait_for_room(request.chunk_size, Instant::now());
+            if !wait.is_zero() {
+                thread::sleep(wait);
+            }
+        }
+
+        let start = Instant::now();
+        // Keep the chunks received so far if the connection fails; they still count towards the results
+        let read_before = io_counters.bytes_read();
+        let received = match &uploader {
+            None => read_chunk(&mut stream, &mut buffer, &io_counters),
+            Some((_, sent)) => read_ack(&mut stream, sent, start, buffer.len(), &io_counters),
+        };
+        if received.is_err() && uploader.is_none() {
+            let read = (io_counters.bytes_read() - read_before) as usize;
+            cut_off = (read.saturating_sub(ChunkHeader::LEN), start.elapsed());
+        }
+        let chunk = match received {
+            Ok(chunk) => chunk,
+            Err(e) if reconnect_budget > 0 => {
+                let dropped = Instant::now();
+                let cause = if args.keepalive && sockopt::is_keepalive_timeout(&e) {
+                    KEEPALIVE_DROP.to_string()
+                } else if request.heartbeat.is_some() && is_timeout(&e) {
+                    HEARTBEAT_LOST.to_string()
+                } else {
+                    e.to_string()
+                };
+                eprintln!("Connection lost after {} chunks: {}", chunks_received, cause);
+                // The old uploader gives up once its connection is shut down
+                let _ = probe.shutdown(Shutdown::Both);
+                if let Some((handle, _)) = uploader.take() {
+                    let _ = handle.join();
+                }
+                // Chunks in flight when the connection dropped are requested again
+                let resumed = TestRequest {
+                    chunk_count: if request.chunk_count == 0 { 0 } else { request.chunk_count - chunks_received },
+                    first_sequence: sequence.next(),
+                    ..request.clone()
+                };
+                let attempts_before = reconnect_budget;
+                match reconnect(args, peer, &resumed, stall_timeout, &mut reconnect_budget) {
+                    Ok(resumed_stream) => {
+                        let attempts = attempts_before - reconnect_budget;
+                        println!("Reconnected after {} attempts, resuming at chunk {}", attempts, i);
+                        probe = resumed_stream.try_clone()?;
+                        stream = inject_faults(resumed_stream, args, read_chunk_size);
+                        if let Some(payload) = &upload_payload {
+                            uploader = Some(start_uploader(&probe, payload, &resumed, upload_checksum, &io_counters)?);
+                        }
+                        heartbeats = start_heartbeats(&probe, &resumed, &io_counters)?;
+                        // The chunk is sent again in full
+                        cut_off = (0, Duration::ZERO);
+                        reconnects.push(Reconnect {
+                            after_chunks: chunks_received,
+                            offset_seconds: (dropped - run_start).as_secs_f64(),
+                            error: cause,
+                            attempts,
+                            downtime_seconds: dropped.elapsed().as_secs_f64(),
+                        });
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Giving up after {} reconnect attempts: {}", args.reconnect, e);
+                        transfer_error = Some(e);
+                        break;
+                    }
+                }
+            }
+            Err(e) if args.keepalive && sockopt::is_keepalive_timeout(&e) => {
+                eprintln!("Connection dropped after {} chunks: {}", chunks_received, KEEPALIVE_DROP);
+                keepalive_drop = true;
+                transfer_error = Some(io::Error::new(e.kind(), KEEPALIVE_DROP));
+                break;
+            }
+            Err(e) if request.heartbeat.is_some() && is_timeout(&e) => {
+                let timeout = stall_timeout.unwrap_or_default().as_secs_f64();
+                eprintln!("Connection dropped after {} chunks: {} for {:.1}s", chunks_received, HEARTBEAT_LOST, timeout);
+                heartbeat_lost = true;
+                transfer_error = Some(io::Error::new(e.kind(), format!("{} for {:.1}s", HEARTBEAT_LOST, timeout)));
+                break;
+            }
+            Err(e) if is_timeout(&e) => {
+                let timeout = stall_timeout.unwrap_or_default();
+                let tcp_info = tcp_info::tcp_info(&probe).ok();
+                eprintln!(
+                    "Stalled after {} bytes: no progress for {:.1}s",
+                    total_data_transferred,
+                    timeout.as_secs_f64()
+                );
+                if let Some(tcp_info) = &tcp_info {
+                    eprintln!("Last TCP state: {}", tcp_info);
+                }
+                stall = Some(Stall { after_bytes: total_data_transferred as u64, timeout_seconds: timeout.as_secs_f64(), tcp_info });
+                transfer_error = Some(io::Error::new(io::ErrorKind::TimedOut, format!("stalled after {} bytes", total_data_transferred)));
+                break;
+            }
+            Err(e) => {
+                eprintln!("Transfer stopped after {} chunks: {}", chunks_received, e);
+                transfer_error = Some(e);
+                break;
+            }
+        };
+        let end = Instant::now();
+        sampler.sample_queue(&probe, direction);
+        // Downloads respond with the first chunk, uploads with the first acknowledgement
+        first_byte.get_or_insert(match direction {
+            Direction::Download => chunk.first_byte,
+            Direction::Upload => end,
+        });
+        if let Some(end) = EndOfTest::from_header(&chunk.header) {
+            // Files end wherever the file does; other transfers only end early if the server gives up
+            if request.file.is_none() {
+                let error = format!("the server ended the test after {} of {} chunks", chunks_received, request.chunk_count);
+                eprintln!("Transfer stopped: {}", error);
+                transfer_error = Some(io::Error::other(error));
+            }
+            end_of_test = Some(end);
+            break;
+        }
+        let payload = &buffer[..chunk.header.length as usize];
+        // Written after the chunk is timed, so the disk does not count as network time
+        if let Some(output) = output_file.as_mut() {
+            let write_start = Instant::now();
+            output.write_all(payload)?;
+            disk_write_time += write_start.elapsed();
+        }
+        // Verified after the chunk is timed, so hashing does not count as transfer time
+        let mut corrupted = false;
+        match direction {
+            Direction::Download => {
+                if request.checksum && !chunk.header.verify(payload) {
+                    eprintln!("Chunk {}: checksum mismatch", i);
+                    corrupted = true;
+                }
+                if expected_payload.as_ref().is_some_and(|expected| payload != &expected[..payload.len()]) {
+                    eprintln!("Chunk {}: payload differs from the seeded content", i);
+                    corrupted = true;
+                }
+            }
+            // The acknowledgement carries the checksum of what the server received
+            Direction::Upload => {
+                if request.checksum && chunk.header.checksum != upload_checksum {
+                    eprintln!("Chunk {}: checksum mismatch at the server", i);
+                    corrupted = true;
+                }
+            }
+        }
+        if corrupted {
+            corrupted_chunks += 1;
+        }
+        match sequence.record(chunk.header.sequence) {
+            Arrival::InOrder => {}
+            Arrival::Gap(skipped) => eprintln!("Chunk {}: {} chunks missing before sequence {}", i, skipped, chunk.header.sequence),
+            Arrival::Reordered => eprintln!("Chunk {}: sequence {} arrived out of order", i, chunk.header.sequence),
+            Arrival::Duplicate => eprintln!("Chunk {}: sequence {} is a duplicate", i, chunk.header.sequence),
+        }
+        one_way_delay_sum += chunk.one_way_delay_seconds;
+        if request.burst.is_some() {
+            chunk_timings.push(ChunkTiming { first_byte: chunk.first_byte, end, bytes: payload.len() });
+        }
+
+        // In burst mode the wait for the next burst is idle time, not download time
+        let duration = if request.burst.is_some() { end - chunk.first_byte } else { end - start };
+        total_time += duration;
+        total_data_transferred += payload.len();
+
+        chunks_received += 1;
+        latency_sum += duration.as_secs_f64();
+        if let Some(player) = player.as_mut() {
+            player.receive(payload.len(), end);
+        }
+
+        let sample = match adaptive.as_mut() {
+            Some(adaptive) => adaptive.record(duration, payload.len(), end),
+            None => Some(Sample { duration, bytes: payload.len(), end }),
+        };
+        if let Some(sample) = sample {
+            record_sample(sample)?;
+        }
+    }
+    // The chunks of an unfinished sample were transferred, so they still make one
+    if let Some(sample) = adaptive.as_mut().and_then(AdaptiveSampler::finish) {
+        record_sample(sample)?;
+    }
+    let adaptive_sampling = adaptive.map(|adaptive| adaptive.summary());
+    let playback = player.map(|player| player.finish(Instant::now()));
+
+    // Of the connection the run ended on, before either side starts closing it
+    let after_snapshot = match (&background_snapshots, probe.local_addr()) {
+        (Some(_), Ok(local)) => sock_diag::snapshot(local, remote_address, "after").ok().flatten(),
+        _ => None,
+    };
+
+    // Closing our side once the heartbeats stop leaves the server none to discard unread
+    if let Some(heartbeats) = heartbeats.take() {
+        let _ = heartbeats.stop();
+        let _ = probe.shutdown(Shutdown::Write);
+    }
+    // With every chunk transferred, the server confirms that it meant to stop there
+    if transfer_error.is_none() && soak_duration.is_none() && end_of_test.is_none() {
+        match read_end_of_test(&mut stream, &io_counters) {
+            Ok(end) => end_of_test = Some(end),
+            Err(e) => eprintln!("The server did not confirm the end of the test: {}", e),
+        }
+    }
+    if let Some(end) = &end_of_test {
+        if end.next_sequence != sequence.next() {
+            eprintln!(
+                "The server ended the test before sequence {}, but the next chunk received would have been {}",
+                end.next_sequence,
+                sequence.next()
+            );
+        }
+    }
+
+    if let Some((handle, _)) = uploader {
+        // Ends a soak upload, and unblocks the sender if the transfer failed
+        let _ = probe.shutdown(Shutdown::Write);
+        match handle.join() {
+            Ok(Err(e)) if transfer_error.is_none() && chunks_received < request.chunk_count => transfer_error = Some(e),
+            Ok(_) => {}
+            Err(_) => return Err("upload thread panicked".into()),
+        }
+    }
+    // Whatever arrived of the chunk the connection ended in was transferred, so it counts
+    let (cut_off_bytes, cut_off_time) = cut_off;
+    if transfer_error.is_some() && cut_off_bytes > 0 {
+        println!("Partial chunk: {} bytes received before the connection ended", cut_off_bytes);
+        if let Some(output) = output_file.as_mut() {
+            output.write_all(&buffer[..cut_off_bytes])?;
+        }
+        total_data_transferred += cut_off_bytes;
+        total_time += cut_off_time;
+    }
+    let socket_io = io_counters.snapshot(if transfer_error.is_some() { cut_off_bytes as u64 } else { 0 });
+
+    let loaded_pings = background_ping.map(|background| background.finish());
+    let ping_loaded = loaded_pings.as_ref().map(|series| series.summary());
+    let udp_echo_loaded = background_echo.map(|background| background.finish().summary());
+    let energy_source = energy_meter.as_ref().map(|meter| meter.source().to_string());
+    let joules = energy_meter.map(|meter| meter.finish());
+    let wifi_samples = background_wifi.map(|background| background.finish()).unwrap_or_default();
+    let cross_traffic_intervals = background_cross_traffic.map(|(name, background)| (name, background.finish()));
+    let tcp_events = tracer.map(|tracer| tracer.finish());
+    if let Some(samples) = mqtt_samples {
+        samples.finish();
+    }
+    if let Some(background) = background_snapshots {
+        socket_snapshots.extend(background.finish());
+        socket_snapshots.extend(after_snapshot);
+    }
+    let capture = capture.and_then(|capture| match capture.finish() {
+        Ok(summary) => Some(summary),
+        Err(e) => {
+            eprintln!("Packet capture failed: {}", e);
+            None
+        }
+    });
+
+    wtr.flush()?;
+    wtr.report(label);
+    if let Some(summaries) = summaries {
+        if let Some(summary) = summaries.finish(Instant::now())? {
+            latencies.push(summary.avg_latency_seconds);
+            data_rates.push(summary.throughput_bps);
+            offsets.push(summary.end_offset_seconds);
+        }
+        println!("Rolling summaries saved to {}", outputs.summary_csv.display());
+    }
+
+    let total_data_bits = total_data_transferred as f64 * 8.0;
+    let total_time_seconds = total_time.as_secs_f64();
+    // Without any data timed there is no rate to report, rather than NaN
+    let avg_effective_data_rate = if total_time_seconds > 0.0 {
+        Some(metrics::calculate_effective_data_rate(total_data_bits, total_time_seconds))
+    } else {
+        None
+    };
+    // Read at the end of the run, once receive buffer auto-tuning has grown the window
+    let tcp_state = tcp_info::tcp_info(&probe).ok();
+    let measured_rtt = tcp_state.filter(|info| info.rtt > 0).map(|info| info.rtt as f64 / 1_000_000.0);
+    let rtt_seconds = measured_rtt.unwrap_or(ASSUMED_RTT_SECONDS);
+    // A download is bounded by the window we advertise, an upload by what our send buffer keeps in
+    // flight, scaled by the shift the server advertised
+    let window_scale = match &tcp_state {
+        // Windows does not report the shifts, and scales windows unless tuning is disabled
+        Some(_) if cfg!(windows) => Some(metrics::MAX_WINDOW_SCALE),
+        Some(info) if info.options & TcpInfo::OPTION_WSCALE == 0 => None,
+        Some(info) if direction == Direction::Upload => Some(info.snd_wscale),
+        Some(info) => Some(info.rcv_wscale),
+        // Without TCP_INFO only the buffer size is known
+        None => Some(metrics::MAX_WINDOW_SCALE),
+    };
+    let (window_name, buffer_name, socket_buffer) = match direction {
+        Direction::Download => ("Receive Window", "SO_RCVBUF", SockRef::from(&probe).recv_buffer_size()?),
+        Direction::Upload => ("Send Window", "SO_SNDBUF", SockRef::from(&probe).send_buffer_size()?),
+    };
+    let socket_buffer = socket_buffer as u64;
+    if !socket_snapshots.is_empty() {
+        let (first, last) = (&socket_snapshots[0], &socket_snapshots[socket_snapshots.len() - 1]);
+        println!(
+            "Socket Snapshots: {} recorded, from {} {} to {} {}",
+            socket_snapshots.len(),
+            first.state,
+            first.stage,
+            last.state,
+            last.stage
+        );
+    }
+    let window = metrics::max_window(socket_buffer, window_scale);
+    let bdp = avg_effective_data_rate.map(|rate| metrics::calculate_bdp(rate, rtt_seconds));
+    let tcp_throughput = metrics::calculate_tcp_throughput(window as f64 * 8.0, rtt_seconds);
+    let window_limited = avg_effective_data_rate.is_some_and(|rate| rate >= tcp_throughput * WINDOW_LIMITED_RATIO);
+    let avg_latency = if chunks_received > 0 { Some(latency_sum / chunks_received as f64) } else { None };
+    let loss_percent = match soak_duration {
+        // Without a fixed amount of data, loss is the share of the planned duration that was not covered
+        Some(duration) if transfer_error.is_some() => {
+            (1.0 - run_start.elapsed().as_secs_f64() / duration.as_secs_f64()).max(0.0) * 100.0
+        }
+        Some(_) => 0.0,
+        // The size of the rest of an incomplete file is unknown, so it counts as lost entirely
+        None if request.file.is_some() => {
+            if end_of_test.is_some() {
+                0.0
+            } else {
+                100.0
+            }
+        }
+        None => {
+            let expected_data = request.chunk_count * request.chunk_size;
+            (expected_data - total_data_transferred) as f64 / expected_data as f64 * 100.0
+        }
+    };
+
+    println!("Total Data Transferred: {}", args.units.size(total_data_transferred as f64));
+    match avg_effective_data_rate {
+        Some(rate) => println!("Average Effective Data Rate: {:.2} bps ({})", rate, args.units.rate(rate)),
+        None => println!("Average Effective Data Rate: n/a (no data arrived)"),
+    }
+    if let Some(adaptive) = &adaptive_sampling {
+        println!(
+            "Adaptive Chunks: {} samples of {} to {} from {} chunks, {:.3} s on average (target {:.3} s)",
+            adaptive.samples,
+            args.units.size(adaptive.min_sample_bytes as f64),
+            args.units.size(adaptive.max_sample_bytes as f64),
+            args.units.size(adaptive.chunk_bytes as f64),
+            adaptive.mean_sample_seconds,
+            adaptive.target_seconds
+        );
+    }
+    match bdp {
+        Some(bdp) => println!("Calculated BDP: {:.2} bits", bdp),
+        None => println!("Calculated BDP: n/a"),
+    }
+    match measured_rtt {
+        Some(rtt) => println!("RTT: {:.3} ms", rtt * 1000.0),
+        None => println!("RTT: {:.3} ms (assumed, not reported by the OS)", rtt_seconds * 1000.0),
+    }
+    match window_scale {
+        Some(shift) => println!("{}: {} bytes ({} {} bytes, window scale {})", window_name, window, buffer_name, socket_buffer, shift),
+        None => println!("{}: {} bytes ({} {} bytes, window scaling not negotiated)", window_name, window, buffer_name, socket_buffer),
+    }
+    let host_settings = tuning::HostSettings::read();
+    let tcp_options = handshake_info.as_ref().map(|info| tcp_options::verify(info, &host_settings, capture.as_ref()));
+    if let Some(options) = &tcp_options {
+        tcp_options::print(options);
+    }
+    println!("TCP Throughput: {:.2} bps", tcp_throughput);
+    if window_limited {
+        println!(
+            "Window-limited: the data rate reached {:.0}% of the window ceiling; a larger {} may increase it",
+            avg_effective_data_rate.unwrap_or_default() / tcp_throughput * 100.0,
+            buffer_name
+        );
+    }
+    let sequence = sequence.counts();
+    println!(
+        "Sequence: {} missing, {} duplicated, {} reordered",
+        sequence.missing, sequence.duplicated, sequence.reordered
+    );
+    print_socket_io(&socket_io);
+    // Soak tests end on the client's schedule, so the server has nothing to confirm
+    let server_confirmed_end = soak_duration.is_none().then_some(end_of_test.is_some());
+    match &end_of_test {
+        Some(end) => println!("End of Test: confirmed by the server, {} bytes on the last connection", end.bytes),
+        None if server_confirmed_end.is_some() => println!("End of Test: not confirmed by the server"),
+        None => {}
+    }
+    let one_way_delay = if chunks_received > 0 { Some(one_way_delay_sum / chunks_received as f64) } else { None };
+    if let Some(delay) = one_way_delay {
+        println!("Average One-Way Delay: {:.3} ms (assumes synchronized clocks)", delay * 1000.0);
+    }
+    let file = match &request.file {
+        Some(name) => {
+            // Flushed to the disk so the write time covers the storage, not just the page cache
+            if let Some(output) = &output_file {
+                let sync_start = Instant::now();
+                output.sync_all()?;
+                disk_write_time += sync_start.elapsed();
+            }
+            let disk_write_seconds = output_file.as_ref().map(|_| disk_write_time.as_secs_f64());
+            let disk_write_bps = disk_write_seconds.map(|seconds| total_data_bits / seconds);
+            println!(
+                "File {}: {} bytes{}",
+                name,
+                total_data_transferred,
+                if end_of_test.is_some() { "" } else { " (incomplete)" }
+            );
+            if let (Some(path), Some(seconds), Some(rate)) = (&args.output_file, disk_write_seconds, disk_write_bps) {
+                println!("Disk Write: {:.3}s ({:.2} bps) to {}", seconds, rate, path.display());
+                if avg_effective_data_rate.is_some_and(|network| rate < network) {
+                    println!("The disk was slower than the network; storage, not the network, limits this transfer");
+                }
+            }
+            Some(FileTransfer {
+                name: name.clone(),
+                bytes: total_data_transferred as u64,
+                complete: end_of_test.is_some(),
+                output: args.output_file.as_ref().map(|path| path.display().to_string()),
+                disk_write_seconds,
+                disk_write_bps,
+            })
+        }
+        None => None,
+    };
+    let disk_time = output_file.as_ref().map(|_| disk_write_time);
+    let bottleneck = sampler.finish(direction, total_data_transferred as u64, socket_buffer, disk_time, tcp_state.as_ref());
+    println!("Bottleneck: {}", bottleneck.verdict);
+    let evidence = tuning::Evidence {
+        direction,
+        throughput_bps: avg_effective_data_rate,
+        rtt_seconds: measured_rtt,
+        window_limited,
+        socket_buffer,
+        requested_buffer: args.socket_buffer,
+        tcp_info: tcp_state.as_ref(),
+        bottleneck: &bottleneck,
+        verifying,
+    };
+    let recommendations = tuning::recommend(&evidence, &host_settings);
+    for recommendation in &recommendations {
+        println!("Recommendation: {}. {}", recommendation.finding, recommendation.action);
+    }
+    // Chunk headers included, as counted on the socket in the direction of the test
+    let application_bytes = match direction {
+        Direction::Download => socket_io.bytes_read,
+        Direction::Upload => socket_io.bytes_written,
+    };
+    let counters_delta = counters_at_start.and_then(|(name, start)| netdev::read(&name).map(|end| (name, end.since(&start))));
+    let interface_counters = match counters_delta {
+        Ok((name, delta)) => {
+            let counters = interface_counters(&name, direction, &delta, application_bytes, local_address.ip());
+            println!(
+                "Interface {}: {} bytes in {} packets for {} bytes of test traffic ({:+.2}%, about {:.2}% expected for headers); {} bytes the other way",
+                counters.interface,
+                counters.interface_bytes,
+                counters.packets,
+                counters.application_bytes,
+                counters.overhead_percent,
+                counters.header_bytes_estimate as f64 / application_bytes.max(1) as f64 * 100.0,
+                counters.reverse_bytes
+            );
+            if counters.unexplained_bytes as f64 > application_bytes as f64 * UNEXPLAINED_TRAFFIC_RATIO {
+                println!(
+                    "The interface carried {} bytes more than the test and its headers account for: retransmissions, or traffic from other applications on this host",
+                    counters.unexplained_bytes
+                );
+            }
+            Some(counters)
+        }
+        Err(e) => {
+            println!("Interface counters unavailable: {}", e);
+            None
+        }
+    };
+    let energy = match (energy_source, joules) {
+        (Some(source), Some(Ok(joules))) => {
+            let gigabytes = total_data_transferred as f64 / 1_000_000_000.0;
+            let joules_per_gb = if gigabytes > 0.0 { Some(joules / gigabytes) } else { None };
+            match joules_per_gb {
+                Some(per_gb) => println!("Energy: {:.2} J ({:.2} J/GB, {})", joules, per_gb, source),
+                None => println!("Energy: {:.2} J ({})", joules, source),
+            }
+            Some(Energy { source, joules, joules_per_gb })
+        }
+        (_, Some(Err(e))) => {
+            eprintln!("Energy measurement failed: {}", e);
+            None
+        }
+        _ => None,
+    };
+    if let Some(capture) = &capture {
+        println!(
+            "Packet Capture: {} of {} packets written to {} ({} bytes){}{}",
+            capture.packets_written,
+            capture.packets,
+            capture.file,
+            capture.file_bytes,
+            if capture.limit_reached { ", size limit reached" } else { "" },
+            if capture.dropped > 0 { format!(", {} dropped by the kernel", capture.dropped) } else { String::new() }
+        );
+    }
+    let tcp_events = match tcp_events {
+        Some(Ok(events)) => {
+            tcp_trace::write_csv(&events, &outputs.tcp_events_csv, &tags)?;
+            let summary = tcp_trace::summarize(&events, &outputs.tcp_events_csv);
+            let cwnd = match (summary.min_cwnd, summary.max_cwnd, summary.final_cwnd) {
+                (Some(min), Some(max), Some(last)) => format!("cwnd {}-{} segments, {} at the end", min, max, last),
+                _ => "no ACKs seen".to_string(),
+            };
+            println!(
+                "Kernel TCP Events: {} retransmits, {} window changes, {}{}; saved to {}",
+                summary.retransmits,
+                summary.window_changes,
+                cwnd,
+                summary.min_ssthresh.map(|ssthresh| format!(", ssthresh down to {}", ssthresh)).unwrap_or_default(),
+                summary.events_csv
+            );
+            Some(summary)
+        }
+        Some(Err(e)) => {
+            eprintln!("Kernel TCP events failed: {}", e);
+            None
+        }
+        None => None,
+    };
+    let setup = ConnectionSetup {
+        resolve_seconds: connect_timings.resolve.as_secs_f64(),
+        connect_seconds: connect_timings.connect.as_secs_f64(),
+        proxy_seconds: connect_timings.proxy.map(|proxy| proxy.as_secs_f64()),
+        first_byte_seconds: first_byte.map(|first_byte| (first_byte - request_sent).as_secs_f64()),
+        fast_open: handshake_info.filter(|_| args.fast_open).map(|info| {
+            let used = info.options & TcpInfo::OPTION_SYN_DATA != 0;
+            let min_rtt = tcp_state.map_or(info.min_rtt, |state| state.min_rtt);
+            FastOpen {
+                used,
+                round_trips_saved: u32::from(used),
+                saved_seconds: (used && min_rtt > 0).then(|| min_rtt as f64 / 1_000_000.0),
+            }
+        }),
+        happy_eyeballs: connect_timings.race.map(connection::Race::report),
+    };
+    print!(
+        "Connection Setup: resolve {:.3} ms, TCP connect {:.3} ms",
+        setup.resolve_seconds * 1000.0,
+        setup.connect_seconds * 1000.0
+    );
+    if let Some(seconds) = setup.proxy_seconds {
+        print!(", proxy tunnel {:.3} ms", seconds * 1000.0);
+    }
+    match setup.first_byte_seconds {
+        Some(seconds) => println!(", first byte {:.3} ms", seconds * 1000.0),
+        None => println!(", no response"),
+    }
+    match &setup.fast_open {
+        Some(FastOpen { used: true, saved_seconds, .. }) => println!(
+            "TCP Fast Open: request sent in the SYN, saving 1 round trip{}",
+            saved_seconds.map(|seconds| format!(" (about {:.3} ms)", seconds * 1000.0)).unwrap_or_default()
+        ),
+        Some(_) => println!(
+            "TCP Fast Open: not used; the first connection to a server only fetches its cookie (try --repeat), and the server needs --fast-open"
+        ),
+        None => {}
+    }
+    if let Some(race) = &setup.happy_eyeballs {
+        print_happy_eyeballs(race);
+    }
+    if let Some(summary) = &ping_idle {
+        print_ping("idle", summary);
+    }
+    if let Some(summary) = &ping_loaded {
+        print_ping("under load", summary);
+    }
+    if let Some(summary) = &udp_echo_idle {
+        print_echo("idle", summary);
+    }
+    if let Some(summary) = &udp_echo_loaded {
+        print_echo("under load", summary);
+    }
+    if verifying {
+        println!("Integrity: {} of {} chunks corrupted", corrupted_chunks, chunks_received);
+    }
+
+    let mut cross_traffic = cross_traffic_intervals
+        .map(|(name, intervals)| cross_traffic::summarize(&name, &intervals, cross_traffic_interval, args.cross_traffic_threshold));
+    let affected = cross_traffic.as_ref().map(|summary| cross_traffic::affected_samples(summary, &offsets)).unwrap_or_default();
+    if let Some(summary) = cross_traffic.as_mut() {
+        println!(
+            "Cross Traffic: {} of {} intervals on {} carried other traffic above {}% of the test's ({} in total)",
+            summary.flagged.len(),
+            summary.intervals,
+            summary.interface,
+            summary.threshold_percent,
+            args.units.size(summary.other_bytes as f64)
+        );
+        for flagged in &summary.flagged {
+            println!(
+                "  {:.3}-{:.3}s: {} of other traffic, {} bytes beside {} of the test's",
+                flagged.start_offset_seconds,
+                flagged.end_offset_seconds,
+                args.units.rate(flagged.other_bps),
+                flagged.other_bytes,
+                flagged.test_bytes
+            );
+        }
+        if args.exclude_cross_traffic {
+            let excluded = affected.iter().filter(|&&affected| affected).count();
+            println!("Excluding {} of {} samples that overlap other traffic from the distributions", excluded, affected.len());
+            summary.excluded_samples = Some(excluded);
+        }
+    }
+    let excluded = if args.exclude_cross_traffic { &affected[..] } else { &[] };
+    let sample_name = if soak_duration.is_some() {
+        "Window"
+    } else if adaptive_sampling.is_some() {
+        "Sample"
+    } else {
+        "Chunk"
+    };
+    let analysis = analyze_samples(label, sample_name, &latencies, &data_rates, &offsets, started_at, args.outlier_k, excluded);
+    let wifi = match &wifi_interface {
+        Some(name) => Some(report_wifi(args, name, direction, &wifi_samples, (&offsets, &data_rates), outputs, started_at)?),
+        None => None,
+    };
+    let streaming = match playback {
+        Some((streaming, levels)) => Some(report_streaming(args, streaming, &levels, outputs, started_at)?),
+        None => None,
+    };
+
+    let bursts = match &request.burst {
+        // Gaps longer than half the idle time can only come from the server pausing between bursts
+        Some(pattern) => burst::split_bursts(&chunk_timings, pattern.off / 2),
+        None => Vec::new(),
+    };
+    for (i, burst) in bursts.iter().enumerate() {
+        print!(
+            "Burst {}: {} chunks, Throughput: {:.2} bps, First Chunk Latency: {:.5}s",
+            i + 1,
+            burst.chunks,
+            burst.throughput_bps,
+            burst.first_chunk_latency_seconds
+        );
+        match (burst.steady_latency_seconds, burst.recovery_ratio()) {
+            (Some(steady), Some(ratio)) => println!(", Steady Latency: {:.5}s ({:.2}x)", steady, ratio),
+            _ => println!(),
+        }
+    }
+
+    let mut charts = Vec::new();
+    if latencies.is_empty() {
+        println!("No data received, skipping chart");
+    } else {
+        let x_desc = if soak_duration.is_some() { "Summary Window".to_string() } else { format!("{} Number", label) };
+        let positions = args.x_axis.positions(&offsets, started_at);
+        let x = SampleAxis { kind: args.x_axis, positions: &positions, sample_desc: &x_desc };
+        let cross_traffic_spans = cross_traffic::spans(&affected);
+        let marks = Marks { outliers: &analysis.outlier_indices, cross_traffic: &cross_traffic_spans };
+        plot::plot_latency_and_data_rate(&outputs.chart, &latencies, &data_rates, &marks, &x, &chart_theme(args))?;
+        charts.push(Chart { title: "Latency and effective data rate", path: outputs.chart.clone() });
+        if args.html {
+            html::write_latency_and_data_rate(
+                &outputs.html_chart,
+                &latencies,
+                &data_rates,
+                &marks,
+                &x,
+                &chart_theme(args),
+                args.plotly_js.as_deref(),
+            )?;
+            charts.push(Chart { title: "Interactive latency and effective data rate", path: outputs.html_chart.clone() });
+        }
+        if let Some(series) = loaded_pings.as_ref().filter(|series| !series.rtts.is_empty()) {
+            let points = latency_under_load(series, run_start, &latencies, &data_rates, &offsets, args.units);
+            plot::plot_latency_vs_throughput(&outputs.scatter_chart, &points, &chart_theme(args))?;
+            charts.push(Chart { title: "Ping RTT against effective data rate", path: outputs.scatter_chart.clone() });
+        }
+    }
+    if wifi.as_ref().is_some_and(|wifi| wifi.samples > 0) {
+        charts.push(Chart { title: "Wi-Fi link", path: outputs.wifi_chart.clone() });
+    }
+    if streaming.is_some() {
+        charts.push(Chart { title: "Playout buffer", path: outputs.stream_chart.clone() });
+    }
+
+    let result = RunResult {
+        direction,
+        started_at: humantime::format_rfc3339_micros(started_at).to_string(),
+        run_id,
+        tags,
+        server: args.server.clone(),
+        local_address: local_address.to_string(),
+        interface: args.interface.clone(),
+        dscp: args.dscp,
+        ttl: args.ttl,
+        chunks_expected: request.chunk_count,
+        chunks_received,
+        total_bytes: total_data_transferred as u64,
+        throughput_bps: avg_effective_data_rate,
+        latency_seconds: avg_latency,
+        loss_percent,
+        latency_stats: analysis.latency_stats,
+        throughput_stats: analysis.throughput_stats,
+        outliers: analysis.outliers,
+        throughput_cv: analysis.throughput_cv,
+        repeat: None,
+        asymmetry: None,
+        bdp_bits: bdp,
+        rtt_seconds: measured_rtt,
+        receive_window_bytes: window,
+        tcp_throughput_bps: tcp_throughput,
+        window_limited,
+        bursts,
+        shaping: request.shape,
+        adaptive_sampling,
+        streaming,
+        stall,
+        keepalive_drop,
+        heartbeat_lost,
+        reconnects,
+        bottleneck: Some(bottleneck),
+        file,
+        corrupted_chunks: if verifying { Some(corrupted_chunks) } else { None },
+        payload_seed,
+        sequence,
+        one_way_delay_seconds: one_way_delay,
+        ping_idle,
+        ping_loaded,
+        udp_echo_idle,
+        udp_echo_loaded,
+        dispersion: Vec::new(),
+        voip: None,
+        gaming: None,
+        peer: None,
+        socket_io: Some(socket_io),
+        server_confirmed_end,
+        traceroute,
+        dns,
+        setup: Some(setup),
+        server_selection: args.server_selection.clone(),
+        stun,
+        proxy: args.proxy.as_ref().map(|proxy| proxy.to_string()),
+        ssh_tunnel: args.ssh_tunnel.clone(),
+        relay: args.relay.clone(),
+        relay_comparison: None,
+        tunnel_comparison: None,
+        object_storage: None,
+        interface_counters,
+        cross_traffic,
+        wifi,
+        energy,
+        capture,
+        tcp_events,
+        socket_snapshots,
+        recommendations,
+        tcp_options,
+    };
+
+    Ok(Outcome { result, transfer_error, latencies, data_rates, offsets, charts })
+}
+
+/// Distribution, stability, and outliers of the per-sample times and data rates of a run.
+pub struct SampleAnalysis {
+    pub latency_stats: Option<Summary>,
+    pub throughput_stats: Option<Summary>,
+    /// Coefficient of variation of the data rate after the ramp-up.
+    pub throughput_cv: Option<f64>,
+    /// Indices of the samples that took unusually long, as highlighted in the chart.
+    pub outlier_indices: Vec<usize>,
+    pub outliers: Vec<Outlier>,
+}
+
+/// Summarizes the samples of a run and prints the summary.
+///
+/// # Arguments
+/// - `label`: What a sample's time measures, e.g. "Download".
+/// - `sample_name`: What a sample is, e.g. "Chunk", for the outlier listing.
+/// - `latencies`, `data_rates`, `offsets`: Time, data rate, and offset from the start of the run of
+///   every sample, in seconds and bits per second.
+/// - `started_at`: Wall-clock start of the run, to timestamp outliers.
+/// - `outlier_k`: Outlier threshold in MADs above the median.
+/// - `excluded`: Whether each sample is left out of the analysis; samples keep their numbers, as
+///   in the charts. Empty to analyze every sample.
+#[allow(clippy::too_many_arguments)]
+pub fn analyze_samples(
+    label: &str,
+    sample_name: &str,
+    latencies: &[f64],
+    data_rates: &[f64],
+    offsets: &[f64],
+    started_at: SystemTime,
+    outlier_k: f64,
+    excluded: &[bool],
+) -> SampleAnalysis {
+    let kept: Vec<usize> = (0..latencies.len()).filter(|&i| !excluded.get(i).copied().unwrap_or(false)).collect();
+    let kept_latencies: Vec<f64> = kept.iter().map(|&i| latencies[i]).collect();
+    let kept_data_rates: Vec<f64> = kept.iter().map(|&i| data_rates[i]).collect();
+    let latency_stats = Summary::of(&kept_latencies);
+    let throughput_stats = Summary::of(&kept_data_rates);
+    if let Some(stats) = &latency_stats {
+        print_summary(&format!("{} Time (s)", label), stats, 5);
+    }
+    if let Some(stats) = &throughput_stats {
+        print_summary("Effective Data Rate (bps)", stats, 2);
+    }
+
+    // Stability is judged on the steady state only; the ramp-up would make every run look unstable
+    let ramp_up = (kept_data_rates.len() as f64 * RAMP_UP_FRACTION).floor() as usize;
+    let throughput_cv = Summary::of(&kept_data_rates[ramp_up..]).map(|steady| steady.stddev / steady.mean);
+    if let Some(cv) = throughput_cv {
+        println!(
+            "Throughput Stability: coefficient of variation {:.2}% over samples {}-{}",
+            cv * 100.0,
+            kept[ramp_up] + 1,
+            kept[kept.len() - 1] + 1
+        );
+    }
+
+    let outlier_indices: Vec<usize> = stats::high_outliers(&kept_latencies, outlier_k).into_iter().map(|i| kept[i]).collect();
+    if !outlier_indices.is_empty() {
+        println!("Outliers ({} time above median + {} x MAD):", label.to_lowercase(), outlier_k);
+    }
+    let outliers: Vec<Outlier> = outlier_indices
+        .iter()
+        .map(|&i| {
+            let timestamp = humantime::format_rfc3339_micros(started_at + Duration::from_secs_f64(offsets[i])).to_string();
+            println!("  {} {} at {:.3}s ({}): {:.5}s", sample_name, i + 1, offsets[i], timestamp, latencies[i]);
+            Outlier { sample: i + 1, offset_seconds: offsets[i], timestamp, latency_seconds: latencies[i] }
+        })
+        .collect();
+
+    SampleAnalysis { latency_stats, throughput_stats, throughput_cv, outlier_indices, outliers }
+}
+
+fn print_ping(phase: &str, summary: &PingSummary) {
+    match &summary.rtt {
+        Some(rtt) => println!(
+            "ICMP Ping ({}): {}/{} replies, {:.1}% loss, RTT min/avg/max {:.3}/{:.3}/{:.3} ms",
+            phase,
+            summary.received,
+            summary.sent,
+            summary.loss_percent,
+            rtt.min * 1000.0,
+            rtt.mean * 1000.0,
+            rtt.max * 1000.0
+        ),
+        None => println!("ICMP Ping ({}): no replies to {} requests", phase, summary.sent),
+    }
+}
+
+fn print_socket_io(io: &SocketIo) {
+    println!(
+        "Socket I/O: {} bytes read in {} reads ({} short, {} bytes on average), {} bytes written in {} writes ({} short)",
+        io.bytes_read,
+        io.reads,
+        io.short_reads,
+        io.bytes_read.checked_div(io.reads).unwrap_or_default(),
+        io.bytes_written,
+        io.writes,
+        io.short_writes
+    );
+}
+
+pub fn print_echo(phase: &str, summary: &EchoSummary) {
+    match (&summary.rtt, summary.jitter_seconds) {
+        (Some(rtt), jitter) => println!(
+            "UDP Echo ({}): {}/{} replies, {:.1}% loss, RTT min/avg/max {:.3}/{:.3}/{:.3} ms, jitter {}",
+            phase,
+            summary.received,
+            summary.sent,
+            summary.loss_percent,
+            rtt.min * 1000.0,
+            rtt.mean * 1000.0,
+            rtt.max * 1000.0,
+            jitter.map_or("n/a".to_string(), |jitter| format!("{:.3} ms", jitter * 1000.0))
+        ),
+        (None, _) => println!("UDP Echo ({}): no replies to {} probes", phase, summary.sent),
+    }
+}
+
+fn print_summary(metric: &str, stats: &Summary, precision: usize) {
+    let value = |x: f64| format!("{:.*}", precision, x);
+    println!(
+        "{}: mean {}, median {}, stddev {}, min {}, max {}, p5 {}, p25 {}, p75 {}, p95 {}, p99 {}",
+        metric,
+        value(stats.mean),
+        value(stats.median),
+        value(stats.stddev),
+        value(stats.min),
+        value(stats.max),
+        value(stats.p5),
+        value(stats.p25),
+        value(stats.p75),
+        value(stats.p95),
+        value(stats.p99)
+    );
+}
+
+/// Waits for the server to accept the test request, explaining why it did not otherwise.
+fn read_reply<R: Read>(stream: &mut R) -> io::Result<()> {
+    match Reply::read_from(stream) {
+        Ok(Reply::Accepted { version }) if version >= REPLY_VERSION => Ok(()),
+        Ok(Reply::Accepted { version }) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the server answered at protocol version {}, but this client needs {} or later", version, REPLY_VERSION),
+        )),
+        Ok(Reply::Rejected { reason }) => Err(io::Error::other(format!("the server rejected the test: {}", reason))),
+        Ok(Reply::QuotaExceeded { quota, remaining, reset_in }) => Err(io::Error::other(format!(
+            "the server's daily quota for this address cannot cover the test: {} of {} bytes left, reset in {}",
+            remaining,
+            quota,
+            humantime::format_duration(reset_in)
+        ))),
+        // Servers from before versioning close the connection on the version they do not know
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(io::Error::new(
+            e.kind(),
+            format!(
+                "the server closed the connection without answering the test request; it may be too old for this client (protocol version {})",
+                PROTOCOL_VERSION
+            ),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// Connects again after the connection dropped and sends `request`, retrying with exponential
+/// backoff while the reconnect budget lasts.
+///
+/// # Arguments
+/// - `peer`: Address to connect to (the server, or the relay).
+/// - `request`: The test request for the remaining chunks.
+/// - `budget`: Reconnect attempts left for the run, decremented for every attempt made.
+fn reconnect(args: &Args, peer: &str, request: &TestRequest, stall_timeout: Option<Duration>, budget: &mut u32) -> io::Result<TcpStream> {
+    let mut backoff = Duration::from_millis(args.reconnect_backoff_ms);
+    loop {
+        thread::sleep(backoff);
+        *budget -= 1;
+        let attempt = connection::connect(peer, &connect_options(args)).and_then(|(mut stream, _)| {
+            stream.set_read_timeout(stall_timeout)?;
+            stream.set_write_timeout(stall_timeout)?;
+            request.write_to(&mut stream)?;
+            read_reply(&mut stream)?;
+            Ok(stream)
+        });
+        match attempt {
+            Ok(stream) => return Ok(stream),
+            Err(e) if *budget == 0 => return Err(e),
+            Err(e) => {
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                eprintln!("Reconnect failed, retrying in {:.1}s: {}", backoff.as_secs_f64(), e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+fn inject_faults(stream: TcpStream, args: &Args, read_chunk_size: usize) -> fault::FaultInjector<TcpStream> {
+    fault::FaultInjector::new(stream, &args.fault, read_chunk_size)
+}
+
+#[cfg(not(feature = "fault-injection"))]
+fn inject_faults(stream: TcpStream, _args: &Args, _read_chunk_size: usize) -> TcpStream {
+    stream
+}
+
+/// The thread sending upload chunks, and the start times of the chunks it has sent.
+type Uploader = (thread::JoinHandle<io::Result<()>>, Receiver<SendTime>);
+
+/// Starts sending the chunks of `request` on a clone of `stream`.
+fn start_uploader(stream: &TcpStream, payload: &[u8], request: &TestRequest, checksum: u64, io: &Arc<IoCounters>) -> io::Result<Uploader> {
+    let (sent_tx, sent_rx) = mpsc::channel();
+    let writer = stream.try_clone()?;
+    let payload = payload.to_vec();
+    let request = request.clone();
+    let io = Arc::clone(io);
+    let handle = thread::spawn(move || send_chunks(writer, &payload, &request, checksum, sent_tx, &io));
+    Ok((handle, sent_rx))
+}
+
+/// Starts sending heartbeats on the otherwise idle upstream direction of a download, if the request
+/// asks for them.
+fn start_heartbeats(stream: &TcpStream, request: &TestRequest, io: &Arc<IoCounters>) -> io::Result<Option<heartbeat::Sender>> {
+    match (request.heartbeat, request.direction) {
+        (Some(interval), Direction::Download) => {
+            let mut writer = stream.try_clone()?;
+            let io = Arc::clone(io);
+            Ok(Some(heartbeat::Sender::start(interval, move |frame| io.write_all(&mut writer, frame))))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Returns true if `e` is a read timing out, which the platforms report with different error kinds.
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// A chunk transferred in either direction.
+struct Chunk {
+    /// The header of a downloaded chunk, or the server's acknowledgement of an uploaded one.
+    header: ChunkHeader,
+    /// When the first byte of the chunk arrived (or was sent), so idle time before it can be told
+    /// apart from transfer time.
+    first_byte: Instant,
+    /// Time from the sender starting the chunk to its first byte arriving. Only meaningful if the
+    /// server's and our clocks are synchronized.
+    one_way_delay_seconds: f64,
+}
+
+/// Reads one chunk header and its payload, which is stored at the start of `buffer`.
+///
+/// Heartbeats the server sends between bursts are skipped, so the chunk starts at its own first byte.
+fn read_chunk<R: Read>(stream: &mut R, buffer: &mut [u8], io: &IoCounters) -> io::Result<Chunk> {
+    let (header, first_byte, first_byte_ns) = loop {
+        let mut header = [0u8; ChunkHeader::LEN];
+        let received = match io.read_some(stream, &mut header)? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection without ending the test")),
+            n => n,
+        };
+        let first_byte = Instant::now();
+        let first_byte_ns = frame::unix_time_ns();
+        io.read_full(stream, &mut header[received..])?;
+        let header = ChunkHeader::decode(&header);
+        if Heartbeat::from_header(&header).is_none() {
+            break (header, first_byte, first_byte_ns);
+        }
+    };
+
+    if EndOfTest::from_header(&header).is_some() {
+        return Ok(Chunk { header, first_byte, one_way_delay_seconds: 0.0 });
+    }
+    let length = header.length as usize;
+    if length > buffer.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("chunk {} claims {} bytes, more than the requested {}", header.sequence, length, buffer.len()),
+        ));
+    }
+    io.read_full(stream, &mut buffer[..length])?;
+    let one_way_delay_seconds = (first_byte_ns as f64 - header.send_time_ns as f64) / 1e9;
+    Ok(Chunk { header, first_byte, one_way_delay_seconds })
+}
+
+/// Reads the frame the server ends a complete transfer with.
+fn read_end_of_test<R: Read>(stream: &mut R, io: &IoCounters) -> io::Result<EndOfTest> {
+    let header = loop {
+        let mut frame = [0u8; ChunkHeader::LEN];
+        io.read_full(stream, &mut frame).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => io::Error::new(e.kind(), "the connection closed without it"),
+            _ => e,
+        })?;
+        let header = ChunkHeader::decode(&frame);
+        if Heartbeat::from_header(&header).is_none() {
+            break header;
+        }
+    };
+    EndOfTest::from_header(&header).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("chunk {} arrived after the last requested one", header.sequence))
+    })
+}
+
+/// When an uploaded chunk started to be sent, by the monotonic and the wall clock (nanoseconds
+/// since the Unix epoch).
+type SendTime = (Instant, u64);
+
+/// Sends upload chunks with `payload` back to back, reporting each chunk's start on `sent`.
+///
+/// Sends the request's chunk count numbered from its first sequence, or until the connection is shut
+/// down if the count is zero, following its burst pattern if one is given, like the server does for
+/// downloads.
+fn send_chunks(mut stream: TcpStream, payload: &[u8], request: &TestRequest, checksum: u64, sent: Sender<SendTime>, io: &IoCounters) -> io::Result<()> {
+    let (count, first_sequence) = (request.chunk_count, request.first_sequence);
+    let mut burst_start = Instant::now();
+    let mut sequence = first_sequence;
+    while count == 0 || sequence < first_sequence + count as u64 {
+        if let Some(burst) = &request.burst {
+            if burst_start.elapsed() >= burst.on {
+                match request.heartbeat {
+                    Some(interval) => heartbeat::sleep(burst.off, interval, |frame| io.write_all(&mut stream, frame))?,
+                    None => thread::sleep(burst.off),
+                }
+                burst_start = Instant::now();
+            }
+        }
+
+        let header = ChunkHeader { sequence, length: payload.len() as u32, send_time_ns: frame::unix_time_ns(), checksum };
+        if sent.send((Instant::now(), header.send_time_ns)).is_err() {
+            // The receiving side has finished
+            return Ok(());
+        }
+        io.write_all(&mut stream, &header.encode())?;
+        io.write_all(&mut stream, payload)?;
+        sequence += 1;
+    }
+    Ok(())
+}
+
+/// Reads the server's acknowledgement of the next uploaded chunk.
+///
+/// # Arguments
+/// - `stream`: The connection the acknowledgements arrive on.
+/// - `sent`: Start times of the uploaded chunks, in order.
+/// - `waiting_since`: When we started waiting for this acknowledgement; the chunk counts as started
+///   no earlier, as for a download.
+/// - `chunk_size`: Size of the uploaded chunks, which the server cannot have received more of.
+/// - `io`: Counts the reads.
+fn read_ack<R: Read>(stream: &mut R, sent: &Receiver<SendTime>, waiting_since: Instant, chunk_size: usize, io: &IoCounters) -> io::Result<Chunk> {
+    // The server's heartbeats fill the time between acknowledgements
+    let ack = loop {
+        let mut ack = [0u8; ChunkHeader::LEN];
+        io.read_full(stream, &mut ack)?;
+        let ack = ChunkHeader::decode(&ack);
+        if Heartbeat::from_header(&ack).is_none() {
+            break ack;
+        }
+    };
+    if EndOfTest::from_header(&ack).is_some() {
+        return Ok(Chunk { header: ack, first_byte: Instant::now(), one_way_delay_seconds: 0.0 });
+    }
+    if ack.length as usize > chunk_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("server acknowledged {} bytes of chunk {}, more than were sent", ack.length, ack.sequence),
+        ));
+    }
+    let (send_start, send_time_ns) =
+        sent.recv().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "acknowledgement for a chunk that was never sent"))?;
+
+    Ok(Chunk {
+        header: ack,
+        first_byte: send_start.max(waiting_since),
+        // The server stamps the acknowledgement with when the chunk's first bytes reached it, so
+        // this is one way, not the round trip of the acknowledgement
+        one_way_delay_seconds: (ack.send_time_ns as f64 - send_time_ns as f64) / 1e9,
+    })
+}
+
+/// Pairs each ping sent during the run with the data rate at the time: the rate over the samples
+/// completed before the next ping was sent, or of the sample in progress if none completed.
+///
+/// Returns the offset (s), data rate (in `units`), and round-trip time (ms) of every answered ping.
+fn latency_under_load(pings: &PingSeries, run_start: Instant, latencies: &[f64], data_rates: &[f64], offsets: &[f64], units: Units) -> Vec<(f64, f64, f64)> {
+    let sent: Vec<f64> = pings.sent_at.iter().map(|&sent_at| sent_at.saturating_duration_since(run_start).as_secs_f64()).collect();
+    let mut points = Vec::new();
+    for (i, (&start, &rtt)) in sent.iter().zip(&pings.rtts).enumerate() {
+        let end = sent.get(i + 1).copied().unwrap_or(f64::INFINITY);
+        // Samples complete in order, so their offsets are sorted
+        let first = offsets.partition_point(|&offset| offset < start);
+        let last = offsets.partition_point(|&offset| offset < end);
+        let rate = if first == last {
+            match data_rates.get(first) {
+                Some(&rate) => rate,
+                None => continue,
+            }
+        } else {
+            // Weighted by time, so the rate is the bits moved over the time spent moving them
+            let bits: f64 = (first..last).map(|j| data_rates[j] * latencies[j]).sum();
+            let time: f64 = latencies[first..last].iter().sum();
+            if time > 0.0 { bits / time } else { continue }
+        };
+        points.push((start, units.rate_value(rate), rtt * 1000.0));
+    }
+    points
+}
+
+/// Compares what an interface carried during the run in the direction of the test with what the
+/// test itself sent or received.
+fn interface_counters(interface: &str, direction: Direction, delta: &netdev::Counters, application_bytes: u64, local: IpAddr) -> InterfaceCounters {
+    let (interface_bytes, packets, reverse_bytes) = match direction {
+        Direction::Download => (delta.rx_bytes, delta.rx_packets, delta.tx_bytes),
+        Direction::Upload => (delta.tx_bytes, delta.tx_packets, delta.rx_bytes),
+    };
+    let header_bytes_estimate = packets * netdev::header_bytes_per_packet(local);
+    InterfaceCounters {
+        interface: interface.to_string(),
+        application_bytes,
+        interface_bytes,
+        packets,
+        overhead_percent: (interface_bytes as f64 / application_bytes.max(1) as f64 - 1.0) * 100.0,
+        header_bytes_estimate,
+        unexplained_bytes: interface_bytes.saturating_sub(application_bytes + header_bytes_estimate),
+        reverse_bytes,
+    }
+}
+
+/// The address the test connection's packets go to: the proxy when tunneling through one, the
+/// server (or relay) otherwise.
+fn capture_address(args: &Args, peer: &str) -> io::Result<SocketAddr> {
+    match &args.proxy {
+        Some(proxy) => connection::resolve(&proxy.address, args.bind, args.family),
+        None => connection::resolve(peer, args.bind, args.family),
+    }
+}
+
+fn print_happy_eyeballs(race: &HappyEyeballs) {
+    let family = Family::of(race.winner);
+    print!(
+        "Happy Eyeballs: {} won with {} in {:.3} ms after {} attempt{}",
+        family,
+        race.winner,
+        race.handshake_seconds * 1000.0,
+        race.attempts,
+        if race.attempts == 1 { "" } else { "s" }
+    );
+    match (race.other, race.margin_seconds, &race.other_error) {
+        (Some(other), Some(margin), _) => println!(
+            "; {} {} {} {:.3} ms",
+            Family::of(other),
+            other,
+            if margin >= 0.0 { "was slower by" } else { "started too late, though faster by" },
+            margin.abs() * 1000.0
+        ),
+        (Some(other), _, Some(error)) => println!("; {} {} failed: {}", Family::of(other), other, error),
+        _ => println!("; {} did not connect in time", if family == Family::Ipv4 { Family::Ipv6 } else { Family::Ipv4 }),
+    }
+}
+
+/// The interface the test runs over: the `--interface` device, or the one holding the local address.
+fn test_interface(args: &Args, local: IpAddr) -> io::Result<String> {
+    match &args.interface {
+        Some(interface) => Ok(interface.clone()),
+        None => netif::interface_with_address(local),
+    }
+}
+
+/// Writes the Wi-Fi link samples of a run to CSV, charts them against the measured data rate, and
+/// prints their summary.
+///
+/// # Arguments
+/// - `throughput`: End offsets (s) and effective data rates (bps) of the transfer samples.
+fn report_wifi(
+    args: &Args,
+    interface: &str,
+    direction: Direction,
+    samples: &[wifi::Sample],
+    throughput: (&[f64], &[f64]),
+    outputs: &Outputs,
+    started_at: SystemTime,
+) -> Result<WifiSummary, Box<dyn std::error::Error>> {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    let tags = output::tags(args);
+    let mut wtr = Writer::from_path(&outputs.wifi_csv)?;
+    wtr.write_record(tags.keys().map(String::as_str).chain([
+        "Offset (s)",
+        "Timestamp",
+        "Signal (dBm)",
+        "TX Bitrate (Mbit/s)",
+        "RX Bitrate (Mbit/s)",
+        "TX Retries",
+        "TX Failed",
+        "Channel",
+        "Frequency (MHz)",
+    ]))?;
+    for sample in samples {
+        let link = &sample.link;
+        wtr.write_record(tags.values().cloned().chain([
+            sample.offset.as_secs_f64().to_string(),
+            humantime::format_rfc3339_micros(started_at + sample.offset).to_string(),
+            optional(link.signal_dbm.map(|value| value.to_string())),
+            optional(link.tx_bitrate_mbps.map(|value| value.to_string())),
+            optional(link.rx_bitrate_mbps.map(|value| value.to_string())),
+            optional(link.tx_retries.map(|value| value.to_string())),
+            optional(link.tx_failed.map(|value| value.to_string())),
+            optional(link.channel.map(|value| value.to_string())),
+            optional(link.frequency_mhz.map(|value| value.to_string())),
+        ]))?;
+    }
+    wtr.flush()?;
+    println!("Wi-Fi link samples saved to {}", outputs.wifi_csv.display());
+
+    // Rate control picks the PHY rate per direction; the one that matters is where the data flows
+    let phy_rate = |link: &wifi::LinkState| match direction {
+        Direction::Download => link.rx_bitrate_mbps,
+        Direction::Upload => link.tx_bitrate_mbps,
+    };
+    let summary = wifi::summarize(interface, samples, phy_rate);
+    if samples.is_empty() {
+        println!("Wi-Fi {}: no samples", interface);
+    } else {
+        let points = |value: &dyn Fn(&wifi::LinkState) -> Option<f64>| -> Vec<(f64, f64)> {
+            samples.iter().filter_map(|sample| value(&sample.link).map(|value| (sample.offset.as_secs_f64(), value))).collect()
+        };
+        let units = args.units;
+        let measured: Vec<(f64, f64)> = throughput.0.iter().zip(throughput.1).map(|(&offset, &rate)| (offset, units.rate_value(rate))).collect();
+        // The driver reports PHY rates in Mbit/s
+        let phy_rate_points: Vec<(f64, f64)> = points(&phy_rate).into_iter().map(|(offset, mbps)| (offset, units.rate_value(mbps * 1_000_000.0))).collect();
+        plot::plot_wifi(&outputs.wifi_chart, &measured, &phy_rate_points, &points(&|link| link.signal_dbm.map(f64::from)), &chart_theme(args))?;
+        print!("Wi-Fi {}: {} samples", interface, summary.samples);
+        if let (Some(avg), Some(min)) = (summary.avg_signal_dbm, summary.min_signal_dbm) {
+            print!(", signal {:.1} dBm average, {} dBm worst", avg, min);
+        }
+        if let (Some(avg), Some(min)) = (summary.avg_phy_rate_mbps, summary.min_phy_rate_mbps) {
+            print!(", PHY rate {} average, {} lowest", units.rate(avg * 1_000_000.0), units.rate(min * 1_000_000.0));
+        }
+        if let Some(retries) = summary.tx_retries {
+            print!(", {} TX retries", retries);
+        }
+        if !summary.channels.is_empty() {
+            let channels: Vec<String> = summary.channels.iter().map(|channel| channel.to_string()).collect();
+            print!(", channel {}", channels.join(" -> "));
+        }
+        println!();
+    }
+    Ok(summary)
+}
+
+/// Writes the buffer levels of a simulated stream to CSV and charts them, and prints how playback
+/// went.
+///
+/// # Arguments
+/// - `levels`: Offsets (s) and buffer levels (s of media) over the run.
+fn report_streaming(
+    args: &Args,
+    streaming: Streaming,
+    levels: &[(f64, f64)],
+    outputs: &Outputs,
+    started_at: SystemTime,
+) -> Result<Streaming, Box<dyn std::error::Error>> {
+    let tags = output::tags(args);
+    let mut wtr = Writer::from_path(&outputs.stream_csv)?;
+    wtr.write_record(tags.keys().map(String::as_str).chain(["Offset (s)", "Timestamp", "Buffer (s)"]))?;
+    for &(offset, level) in levels {
+        wtr.write_record(tags.values().cloned().chain([
+            offset.to_string(),
+            humantime::format_rfc3339_micros(started_at + Duration::from_secs_f64(offset)).to_string(),
+            level.to_string(),
+        ]))?;
+    }
+    wtr.flush()?;
+    println!("Playout buffer levels saved to {}", outputs.stream_csv.display());
+    let rebuffers: Vec<(f64, f64)> =
+        streaming.rebuffers.iter().map(|rebuffer| (rebuffer.offset_seconds, rebuffer.offset_seconds + rebuffer.duration_seconds)).collect();
+    plot::plot_playout_buffer(&outputs.stream_chart, levels, &rebuffers, streaming.startup_seconds, streaming.buffer_seconds, &chart_theme(args))?;
+
+    let rate = args.units.rate(streaming.bitrate_bps);
+    match streaming.startup_delay_seconds {
+        Some(delay) => {
+            print!(
+                "Streaming at {}: playback started after {:.3} s, {} rebuffers for {:.3} s ({:.2}% of the time)",
+                rate,
+                delay,
+                streaming.rebuffers.len(),
+                streaming.rebuffering_seconds,
+                streaming.rebuffer_ratio_percent
+            );
+            if let (Some(mean), Some(min)) = (streaming.mean_buffer_seconds, streaming.min_buffer_seconds) {
+                print!(", buffer {:.1} s on average, {:.1} s lowest", mean, min);
+            }
+            println!();
+        }
+        None => println!("Streaming at {}: playback never started, the {:.1} s startup buffer never filled", rate, streaming.startup_seconds),
+    }
+    for (i, rebuffer) in streaming.rebuffers.iter().enumerate() {
+        println!("Rebuffer {}: at {:.3} s for {:.3} s", i + 1, rebuffer.offset_seconds, rebuffer.duration_seconds);
+    }
+    Ok(streaming)
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use clap::ValueEnum;
+use crate::output;
+use crate::plot::{self, ChartOptions, TrendAxis, TrendPoint};
+use crate::results::RunResult;
+use crate::scheduler;
+use crate::units::Units;
+
+/// Options of the `trend` subcommand.
+#[derive(Clone, clap::Args)]
+pub struct TrendArgs {
+    /// History file the scheduler appended its runs to.
+    #[arg(value_name = "FILE")]
+    history: PathBuf,
+
+    /// Only chart the runs of this job.
+    #[arg(long, value_name = "NAME")]
+    job: Option<String>,
+
+    /// Period the runs are averaged over, besides the hour of the day.
+    #[arg(long, value_name = "PERIOD", value_enum, default_value_t = Bucket::Day)]
+    bucket: Bucket,
+
+    /// Show data rates in decimal (Mbit/s) or binary (Mibit/s) units.
+    #[arg(long, value_name = "UNITS", value_enum, default_value_t = Units::Si)]
+    units: Units,
+
+    /// Write the charts into this directory, creating it if needed.
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    chart: ChartOptions,
+}
+
+/// Period the runs of a trend chart are averaged over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Bucket {
+    Day,
+    /// Weeks starting on Monday.
+    Week,
+}
+
+impl Bucket {
+    /// The first day of the period containing `day`, in days since the Unix epoch.
+    fn start(self, day: f64) -> f64 {
+        let day = day.floor();
+        match self {
+            Bucket::Day => day,
+            // The epoch was a Thursday, three days after the Monday its week started on
+            Bucket::Week => ((day + 3.0) / 7.0).floor() * 7.0 - 3.0,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Bucket::Day => "Daily average",
+            Bucket::Week => "Weekly average",
+        }
+    }
+}
+
+/// Charts how the runs kept in the scheduler's history file developed over time, averaged per day or
+/// week and per hour of the day, so a slow degradation shows up that no single run reveals.
+///
+/// Runs that failed before producing a result, or received no chunk, are left out, so outages do
+/// not pull the averages down; they are counted instead.
+///
+/// # Returns
+/// - The process exit code: 0, or `EXIT_TEST_ERROR` if the history holds no runs to chart.
+pub fn run(args: &TrendArgs) -> Result<i32, Box<dyn std::error::Error>> {
+    let bucket = args.bucket;
+    let mut records = scheduler::read_history(&args.history)?;
+    records.reverse();
+
+    let mut runs = Vec::new();
+    let mut failed = 0;
+    for record in records.iter().filter(|record| args.job.as_ref().is_none_or(|job| &record.job == job)) {
+        let (data_rate, latency) = match (&record.error, &record.result) {
+            (None, Some(RunResult { throughput_bps: Some(data_rate), latency_seconds: Some(latency), .. })) => (*data_rate, *latency),
+            _ => {
+                failed += 1;
+                continue;
+            }
+        };
+        let started_at = match humantime::parse_rfc3339_weak(&record.started_at) {
+            Ok(started_at) => started_at,
+            Err(e) => {
+                eprintln!("Skipping a run of {} with start time '{}': {}", record.job, record.started_at, e);
+                continue;
+            }
+        };
+        let seconds = started_at.duration_since(UNIX_EPOCH)?.as_secs_f64();
+        runs.push(TrendPoint { at: seconds / 86_400.0, data_rate, latency });
+    }
+    if runs.is_empty() {
+        eprintln!("Test error: {} holds no completed runs{}", args.history.display(), job_filter(args));
+        return Ok(crate::EXIT_TEST_ERROR);
+    }
+
+    let periods = average(&runs, |run| bucket.start(run.at));
+    let hours = average(&runs, |run| (run.at.fract() * 24.0).floor());
+
+    let title = args.job.as_deref().unwrap_or("All jobs");
+    println!("=== Trend of {} ({} runs, {} failed) ===", title, runs.len(), failed);
+    print_table(if bucket == Bucket::Day { "Day" } else { "Week of" }, &periods, plot::date_of_day, args.units);
+    print_table("Hour (UTC)", &hours, |at| format!("{:02}:00", at as u32), args.units);
+    if let [(first, _), .., (last, _)] = periods.as_slice() {
+        println!(
+            "From {} to {}: data rate {:+.1}%, transfer time {:+.1}%",
+            plot::date_of_day(first.at),
+            plot::date_of_day(last.at),
+            change(first.data_rate, last.data_rate),
+            change(first.latency, last.latency)
+        );
+    }
+
+    output::create_dir(args.output_dir.as_deref())?;
+    let output = |name: &str| args.output_dir.as_ref().map_or_else(|| PathBuf::from(name), |dir| dir.join(name));
+    let theme = args.chart.theme(args.units);
+    let periods: Vec<TrendPoint> = periods.into_iter().map(|(average, _)| average).collect();
+    let hours: Vec<TrendPoint> = hours.into_iter().map(|(average, _)| average).collect();
+    plot::plot_trend(&output("trend.png"), title, &runs, (&periods, bucket.label()), TrendAxis::Date, &theme)?;
+    plot::plot_trend(&output("trend_by_hour.png"), title, &[], (&hours, "Hourly average"), TrendAxis::HourOfDay, &theme)?;
+
+    Ok(0)
+}
+
+/// Averages `runs` grouped by `key`, in the order of their keys, each with its number of runs.
+/// The averages are placed at their key.
+fn average(runs: &[TrendPoint], key: impl Fn(&TrendPoint) -> f64) -> Vec<(TrendPoint, usize)> {
+    let mut groups: BTreeMap<i64, Vec<&TrendPoint>> = BTreeMap::new();
+    for run in runs {
+        groups.entry(key(run) as i64).or_default().push(run);
+    }
+    groups
+        .into_iter()
+        .map(|(key, runs)| {
+            let count = runs.len();
+            let mean = |value: fn(&TrendPoint) -> f64| runs.iter().map(|run| value(run)).sum::<f64>() / count as f64;
+            (TrendPoint { at: key as f64, data_rate: mean(|run| run.data_rate), latency: mean(|run| run.latency) }, count)
+        })
+        .collect()
+}
+
+fn print_table(heading: &str, rows: &[(TrendPoint, usize)], label: impl Fn(f64) -> String, units: Units) {
+    println!("{:<12} {:>6} {:>20} {:>18}", heading, "Runs", "Data Rate", "Transfer Time");
+    for (average, count) in rows {
+        println!(
+            "{:<12} {:>6} {:>20} {:>15.3} ms",
+            label(average.at),
+            count,
+            units.rate(average.data_rate),
+            average.latency * 1000.0
+        );
+    }
+}
+
+/// Relative change from `first` to `last`, in percent.
+fn change(first: f64, last: f64) -> f64 {
+    if first == 0.0 {
+        0.0
+    } else {
+        (last - first) / first * 100.0
+    }
+}
+
+fn job_filter(args: &TrendArgs) -> String {
+    args.job.as_ref().map(|job| format!(" of job {}", job)).unwrap_or_default()
+}
@@ -0,0 +1,203 @@
+use std::convert::TryFrom;
+use std::fs;
+use common::protocol::Direction;
+use common::tcp_info::TcpInfo;
+use crate::bottleneck::{DISK_BOUND_FRACTION, SENDER_LIMITED_FRACTION};
+use crate::results::{Bottleneck, Recommendation};
+
+/// Share of the bytes sent that were retransmitted above which loss is worth acting on.
+const RETRANSMIT_FRACTION: f64 = 0.01;
+/// How much faster than the measured rate a recommended buffer should allow, so the window stops
+/// being the limit rather than moving it just out of the way.
+const HEADROOM: f64 = 4.0;
+/// Smallest buffer recommended, in bytes.
+const MIN_BUFFER: u64 = 4 * 1024 * 1024;
+/// Congestion control algorithms that back off on loss alone, which BBR does not.
+const LOSS_BASED: [&str; 3] = ["cubic", "reno", "htcp"];
+
+/// What a run showed about its limits, for `recommend`.
+pub struct Evidence<'a> {
+    pub direction: Direction,
+    /// Average data rate in bits per second, if any data arrived.
+    pub throughput_bps: Option<f64>,
+    /// Measured RTT in seconds, if the OS reported one.
+    pub rtt_seconds: Option<f64>,
+    /// The data rate came close to the window ceiling.
+    pub window_limited: bool,
+    /// The socket buffer the window was bounded by, as the OS reports it.
+    pub socket_buffer: u64,
+    /// Buffer size requested with `--socket-buffer`, which turns off auto-tuning.
+    pub requested_buffer: Option<usize>,
+    pub tcp_info: Option<&'a TcpInfo>,
+    pub bottleneck: &'a Bottleneck,
+    /// Received data was checked against checksums or a seeded payload.
+    pub verifying: bool,
+}
+
+/// Network settings of this host that limit TCP, as far as they could be read (Linux `/proc/sys`).
+#[derive(Default)]
+pub struct HostSettings {
+    pub rmem_max: Option<u64>,
+    pub wmem_max: Option<u64>,
+    /// Minimum, default, and maximum of the auto-tuned receive buffer.
+    pub tcp_rmem: Option<[u64; 3]>,
+    pub tcp_wmem: Option<[u64; 3]>,
+    pub congestion_control: Option<String>,
+    pub available_congestion_control: Vec<String>,
+    pub window_scaling: Option<bool>,
+    pub sack: Option<bool>,
+    /// Linux also accepts 2, timestamps without the random per-connection offset.
+    pub timestamps: Option<bool>,
+}
+
+impl HostSettings {
+    /// Reads the settings; those that cannot be read, e.g. on other platforms, are left unset.
+    pub fn read() -> Self {
+        let read = |name: &str| fs::read_to_string(format!("/proc/sys/net/{}", name)).ok().map(|value| value.trim().to_string());
+        let triple = |name: &str| {
+            let values: Vec<u64> = read(name)?.split_whitespace().filter_map(|value| value.parse().ok()).collect();
+            <[u64; 3]>::try_from(values).ok()
+        };
+        HostSettings {
+            rmem_max: read("core/rmem_max").and_then(|value| value.parse().ok()),
+            wmem_max: read("core/wmem_max").and_then(|value| value.parse().ok()),
+            tcp_rmem: triple("ipv4/tcp_rmem"),
+            tcp_wmem: triple("ipv4/tcp_wmem"),
+            congestion_control: read("ipv4/tcp_congestion_control"),
+            available_congestion_control: read("ipv4/tcp_available_congestion_control")
+                .map(|names| names.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+            window_scaling: read("ipv4/tcp_window_scaling").map(|value| value != "0"),
+            sack: read("ipv4/tcp_sack").map(|value| value != "0"),
+            timestamps: read("ipv4/tcp_timestamps").map(|value| value != "0"),
+        }
+    }
+}
+
+/// Turns the evidence of a run into concrete changes that should raise the data rate, most
+/// important first.
+///
+/// # Arguments
+/// - `evidence`: What the run showed.
+/// - `host`: This host's settings, which the actions are worded against.
+///
+/// # Returns
+/// - The recommendations; none if nothing on this side of the connection held the run back.
+pub fn recommend(evidence: &Evidence, host: &HostSettings) -> Vec<Recommendation> {
+    let mut recommendations = Vec::new();
+    let busy = evidence.tcp_info.map_or(0, |info| info.busy_time) as f64;
+    let share = |time: u64| if busy > 0.0 { time as f64 / busy } else { 0.0 };
+
+    if evidence.bottleneck.cpu_bound() {
+        let cores = evidence.bottleneck.cores.unwrap_or(1);
+        let load = evidence.bottleneck.cpu_load_percent().unwrap_or_default();
+        let action = if evidence.verifying {
+            "Drop --verify / --payload-seed for throughput runs; checking every byte costs CPU time the transfer needs".to_string()
+        } else {
+            "Spread the load over more cores: run several clients at once, e.g. agents on this host started with --coordinate, and add up their rates".to_string()
+        };
+        recommendations.push(Recommendation { finding: format!("The client kept its {} cores {:.0}% busy, so the CPU limited the run", cores, load), action });
+    }
+
+    // Without window scaling no buffer beyond 64 KB counts, so nothing else would help
+    if evidence.tcp_info.is_some_and(|info| !cfg!(windows) && info.options & TcpInfo::OPTION_WSCALE == 0) {
+        let action = if host.window_scaling == Some(false) {
+            "Enable it on this host: sysctl -w net.ipv4.tcp_window_scaling=1".to_string()
+        } else {
+            "It is enabled here, so the server or a middlebox on the path stripped it; check net.ipv4.tcp_window_scaling on the server and any firewall or proxy in between".to_string()
+        };
+        recommendations.push(Recommendation {
+            finding: "Window scaling was not negotiated, capping the window at 64 KB".to_string(),
+            action,
+        });
+        return recommendations;
+    }
+
+    let rwnd_limited = evidence.tcp_info.is_some_and(|info| share(info.rwnd_limited) >= SENDER_LIMITED_FRACTION);
+    let sndbuf_limited = evidence.tcp_info.is_some_and(|info| share(info.sndbuf_limited) >= SENDER_LIMITED_FRACTION);
+    if evidence.direction == Direction::Upload && rwnd_limited {
+        let target = target_buffer(evidence);
+        recommendations.push(Recommendation {
+            finding: format!(
+                "The server's receive window limited the upload for {:.0}% of the time data was in flight",
+                share(evidence.tcp_info.map_or(0, |info| info.rwnd_limited)) * 100.0
+            ),
+            action: format!("Raise the server's receive buffer limit: sysctl -w net.ipv4.tcp_rmem=\"4096 131072 {}\" on the server", target),
+        });
+    } else if evidence.window_limited || (evidence.direction == Direction::Upload && sndbuf_limited) {
+        recommendations.push(buffer_recommendation(evidence, host));
+    }
+
+    // Only the sender sees its retransmissions, so a download's are on the server
+    if let Some(info) = evidence.tcp_info.filter(|info| evidence.direction == Direction::Upload && info.bytes_sent > 0) {
+        let retransmitted = info.bytes_retrans as f64 / info.bytes_sent as f64;
+        let algorithm = host.congestion_control.as_deref().unwrap_or_default();
+        if retransmitted >= RETRANSMIT_FRACTION && LOSS_BASED.contains(&algorithm) {
+            let load = if host.available_congestion_control.iter().any(|name| name == "bbr") { "" } else { "modprobe tcp_bbr && " };
+            recommendations.push(Recommendation {
+                finding: format!(
+                    "{:.1}% of the bytes sent were retransmitted, and {} backs off on every loss",
+                    retransmitted * 100.0,
+                    algorithm
+                ),
+                action: format!(
+                    "Switch to BBR, which paces to the measured bandwidth instead: {}sysctl -w net.core.default_qdisc=fq net.ipv4.tcp_congestion_control=bbr",
+                    load
+                ),
+            });
+        }
+    }
+
+    if let Some(disk) = evidence.bottleneck.disk_percent.filter(|disk| *disk >= DISK_BOUND_FRACTION * 100.0) {
+        recommendations.push(Recommendation {
+            finding: format!("Writing to disk took {:.0}% of the run", disk),
+            action: "Write --output-file to faster storage, or leave it out to measure the network alone".to_string(),
+        });
+    }
+    recommendations
+}
+
+/// Recommends a larger buffer on our side, through the setting that bounds it: `--socket-buffer`
+/// and the core limit if one was requested, the auto-tuning maximum otherwise.
+fn buffer_recommendation(evidence: &Evidence, host: &HostSettings) -> Recommendation {
+    let target = target_buffer(evidence);
+    let (finding, auto_tuned, core_max, auto_name, core_name) = match evidence.direction {
+        Direction::Download => ("receive window", host.tcp_rmem, host.rmem_max, "net.ipv4.tcp_rmem", "net.core.rmem_max"),
+        Direction::Upload => ("send buffer", host.tcp_wmem, host.wmem_max, "net.ipv4.tcp_wmem", "net.core.wmem_max"),
+    };
+    let finding = format!(
+        "The {} ({} bytes of buffer) limited the data rate{}",
+        finding,
+        evidence.socket_buffer,
+        evidence.rtt_seconds.map(|rtt| format!(" at {:.3} ms RTT", rtt * 1000.0)).unwrap_or_default()
+    );
+    let action = match evidence.requested_buffer {
+        // An explicit size is capped at the core limit, and the kernel doubles what it grants
+        Some(_) if core_max.is_some_and(|max| max * 2 < target) => format!(
+            "Raise the core limit and the requested size: sysctl -w {}={} and --socket-buffer {}",
+            core_name,
+            target,
+            target / 2
+        ),
+        Some(_) => format!("Request a larger buffer: --socket-buffer {}, or leave it out so auto-tuning can grow it", target / 2),
+        None => match auto_tuned {
+            Some([min, default, max]) if max < target => {
+                format!("Raise the auto-tuning limit: sysctl -w {}=\"{} {} {}\"", auto_name, min, default, target)
+            }
+            // The limit allows more, so the window had not grown yet
+            Some(_) => "Auto-tuning allows a larger window than the run reached; run longer (--duration) so it can grow".to_string(),
+            None => format!("Set a larger buffer with --socket-buffer {}", target / 2),
+        },
+    };
+    Recommendation { finding, action }
+}
+
+/// Buffer size, in bytes, that would let the window carry `HEADROOM` times the measured rate at the
+/// measured RTT, counting the half Linux keeps for overhead, rounded up to a power of two.
+fn target_buffer(evidence: &Evidence) -> u64 {
+    let window = match (evidence.rtt_seconds, evidence.throughput_bps) {
+        (Some(rtt), Some(throughput)) => throughput * HEADROOM * rtt / 8.0,
+        _ => evidence.socket_buffer as f64 * HEADROOM / 2.0,
+    };
+    ((window * 2.0) as u64).max(evidence.socket_buffer * 2).max(MIN_BUFFER).next_power_of_two()
+}
@@ -0,0 +1,59 @@
+use clap::ValueEnum;
+
+/// How data rates and sizes are shown in the console, reports, and charts. The CSV files and the
+/// JSON summary keep bits per second and bytes either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Units {
+    /// Decimal prefixes, as ISPs quote their plans: 1 Mbit/s is 1,000,000 bit/s, 1 MB is
+    /// 1,000,000 bytes.
+    #[default]
+    Si,
+    /// Binary prefixes: 1 Mibit/s is 1,048,576 bit/s, 1 MiB is 1,048,576 bytes.
+    Iec,
+}
+
+impl Units {
+    /// Bits or bytes in one mega- or mebi- unit.
+    pub fn mega(self) -> f64 {
+        match self {
+            Units::Si => 1_000_000.0,
+            Units::Iec => 1_048_576.0,
+        }
+    }
+
+    /// Unit of data rates, `Mbit/s` or `Mibit/s`.
+    pub fn rate_unit(self) -> &'static str {
+        match self {
+            Units::Si => "Mbit/s",
+            Units::Iec => "Mibit/s",
+        }
+    }
+
+    /// Unit of data sizes, `MB` or `MiB`.
+    pub fn size_unit(self) -> &'static str {
+        match self {
+            Units::Si => "MB",
+            Units::Iec => "MiB",
+        }
+    }
+
+    /// Converts a data rate in bits per second to `rate_unit`.
+    pub fn rate_value(self, bps: f64) -> f64 {
+        bps / self.mega()
+    }
+
+    /// Formats a data rate in bits per second, e.g. `94.12 Mbit/s`.
+    pub fn rate(self, bps: f64) -> String {
+        format!("{:.2} {}", self.rate_value(bps), self.rate_unit())
+    }
+
+    /// Formats a data rate as `rate` does, or `n/a` if none was measured.
+    pub fn rate_or_na(self, bps: Option<f64>) -> String {
+        bps.map_or("n/a".to_string(), |bps| self.rate(bps))
+    }
+
+    /// Formats a size in bytes, e.g. `100.00 MB`.
+    pub fn size(self, bytes: f64) -> String {
+        format!("{:.2} {}", bytes / self.mega(), self.size_unit())
+    }
+}
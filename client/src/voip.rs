@@ -0,0 +1,202 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::echo;
+use crate::results::VoipCall;
+use crate::stats::Summary;
+
+/// Time of speech each packet carries, which gives the 50 packets per second of a typical call.
+pub const PACKET_INTERVAL: Duration = Duration::from_millis(20);
+
+/// RTP header in front of the voice payload of every packet.
+const RTP_HEADER_LEN: usize = 12;
+
+/// Transmission rating of a connection without impairments, R0 - Is with the default values of
+/// ITU-T G.107.
+const R_DEFAULT: f64 = 93.2;
+
+/// Codec the simulated call is encoded with, which sets the packet size and how the codec itself
+/// and lost packets impair the call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Codec {
+    /// G.711 at 64 kbit/s with packet loss concealment, as most calls to phones are.
+    #[default]
+    G711,
+    /// G.729A at 8 kbit/s, common on VoIP links short of bandwidth.
+    G729,
+}
+
+impl Codec {
+    /// Voice payload of one packet, in bytes.
+    fn payload_len(self) -> usize {
+        match self {
+            Codec::G711 => 160,
+            Codec::G729 => 20,
+        }
+    }
+
+    /// Equipment impairment factor Ie and packet-loss robustness Bpl, from ITU-T G.113.
+    fn impairment(self) -> (f64, f64) {
+        match self {
+            Codec::G711 => (0.0, 25.1),
+            Codec::G729 => (11.0, 19.0),
+        }
+    }
+
+    /// Time the encoder looks ahead of the frame, in seconds.
+    fn lookahead_seconds(self) -> f64 {
+        match self {
+            Codec::G711 => 0.0,
+            Codec::G729 => 0.005,
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Codec::G711 => "G.711",
+            Codec::G729 => "G.729A",
+        })
+    }
+}
+
+/// Places a simulated call of `duration` through the server's echo service at `target`: a
+/// packet the size of one voice frame every 20 ms, echoed back so both directions carry it.
+pub fn call(target: SocketAddr, bind: Option<IpAddr>, codec: Codec, duration: Duration) -> io::Result<VoipCall> {
+    let packets = ((duration.as_secs_f64() / PACKET_INTERVAL.as_secs_f64()).round() as usize).max(1);
+    let packet_bytes = RTP_HEADER_LEN + codec.payload_len();
+    let rtts = echo::paced(target, bind, packets, PACKET_INTERVAL, packet_bytes)?;
+    Ok(rate(codec, packet_bytes, &rtts))
+}
+
+/// Rates the call from the round trips of its packets with the E-model of ITU-T G.107.
+///
+/// The one-way delay is half the round trip, plus the 20 ms a packet's speech takes to record,
+/// the codec's lookahead, and a jitter buffer holding twice the jitter. Lost packets impair the
+/// call more when they come in bursts, which the burst ratio of ITU-T G.113 accounts for.
+fn rate(codec: Codec, packet_bytes: usize, rtts: &[Option<f64>]) -> VoipCall {
+    let returned: Vec<f64> = rtts.iter().flatten().cloned().collect();
+    let sent = rtts.len();
+    let loss = if sent > 0 { (sent - returned.len()) as f64 / sent as f64 } else { 0.0 };
+    // Interarrival jitter of RFC 3550, which smooths the change in transit time over 16 packets
+    let jitter = returned.windows(2).fold(0.0, |jitter, pair| jitter + ((pair[1] - pair[0]).abs() - jitter) / 16.0);
+    let rtt = Summary::of(&returned);
+
+    let mouth_to_ear = rtt.as_ref().map(|rtt| {
+        rtt.mean / 2.0 + PACKET_INTERVAL.as_secs_f64() + codec.lookahead_seconds() + 2.0 * jitter
+    });
+    let burst_ratio = burst_ratio(rtts, loss);
+    let (r_factor, mos) = match mouth_to_ear {
+        Some(delay) => {
+            // Delay impairment Id as approximated by Cole and Rosenbluth for the default echo
+            // loss, in milliseconds
+            let d = delay * 1000.0;
+            let delay_impairment = 0.024 * d + if d > 177.3 { 0.11 * (d - 177.3) } else { 0.0 };
+            let (ie, bpl) = codec.impairment();
+            let ppl = loss * 100.0;
+            let equipment_impairment = ie + (95.0 - ie) * ppl / (ppl / burst_ratio + bpl);
+            let r = R_DEFAULT - delay_impairment - equipment_impairment;
+            (r, mos(r))
+        }
+        // A call none of whose packets arrived is no call at all
+        None => (0.0, 1.0),
+    };
+
+    VoipCall {
+        codec,
+        packets_sent: sent,
+        packets_received: returned.len(),
+        packet_bytes,
+        loss_percent: loss * 100.0,
+        burst_ratio,
+        rtt,
+        jitter_seconds: jitter,
+        mouth_to_ear_seconds: mouth_to_ear,
+        r_factor,
+        mos,
+    }
+}
+
+/// Ratio of the observed loss bursts to those of random loss at the same rate, 1/(p + q) in
+/// ITU-T G.113: p is the chance of losing a packet after one arrived, q of one arriving after a
+/// loss.
+fn burst_ratio(rtts: &[Option<f64>], loss: f64) -> f64 {
+    let (mut arrived, mut lost, mut arrived_then_lost, mut lost_then_arrived) = (0, 0, 0, 0);
+    for pair in rtts.windows(2) {
+        match (pair[0].is_some(), pair[1].is_some()) {
+            (true, next) => {
+                arrived += 1;
+                if !next {
+                    arrived_then_lost += 1;
+                }
+            }
+            (false, next) => {
+                lost += 1;
+                if next {
+                    lost_then_arrived += 1;
+                }
+            }
+        }
+    }
+    if loss == 0.0 || arrived == 0 || lost == 0 {
+        return 1.0;
+    }
+    let p = arrived_then_lost as f64 / arrived as f64;
+    let q = lost_then_arrived as f64 / lost as f64;
+    if p + q > 0.0 {
+        1.0 / (p + q)
+    } else {
+        1.0
+    }
+}
+
+/// Mean opinion score of a transmission rating, from 1 (bad) to 4.5 (best a handset allows).
+fn mos(r: f64) -> f64 {
+    if r <= 0.0 {
+        1.0
+    } else if r >= 100.0 {
+        4.5
+    } else {
+        1.0 + 0.035 * r + r * (r - 60.0) * (100.0 - r) * 7.0e-6
+    }
+}
+
+/// How users rate a call of this MOS, in the categories of ITU-T G.107.
+pub fn verdict(mos: f64) -> &'static str {
+    if mos >= 4.34 {
+        "very satisfied"
+    } else if mos >= 4.03 {
+        "satisfied"
+    } else if mos >= 3.6 {
+        "some users dissatisfied"
+    } else if mos >= 3.1 {
+        "many users dissatisfied"
+    } else if mos >= 2.58 {
+        "nearly all users dissatisfied"
+    } else {
+        "not recommended"
+    }
+}
+
+pub fn print(call: &VoipCall) {
+    println!(
+        "VoIP ({}): {} of {} packets returned ({:.2}% loss, burst ratio {:.2})",
+        call.codec, call.packets_received, call.packets_sent, call.loss_percent, call.burst_ratio
+    );
+    if let (Some(rtt), Some(delay)) = (&call.rtt, call.mouth_to_ear_seconds) {
+        println!(
+            "VoIP RTT: mean {:.3} ms, p95 {:.3} ms, max {:.3} ms; jitter {:.3} ms; mouth-to-ear delay {:.1} ms",
+            rtt.mean * 1000.0,
+            rtt.p95 * 1000.0,
+            rtt.max * 1000.0,
+            call.jitter_seconds * 1000.0,
+            delay * 1000.0
+        );
+    }
+    println!("VoIP MOS: {:.2} (R-factor {:.1}, {})", call.mos, call.r_factor, verdict(call.mos));
+}
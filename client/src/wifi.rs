@@ -0,0 +1,149 @@
+use std::io;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::results::WifiSummary;
+
+/// State of a Wi-Fi link as reported by nl80211, through `iw`.
+///
+/// Every field is optional, as drivers differ in what they report.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkState {
+    pub signal_dbm: Option<i32>,
+    /// PHY rate chosen by rate control for frames we send, in Mbit/s.
+    pub tx_bitrate_mbps: Option<f64>,
+    /// PHY rate of the last frames received from the access point, in Mbit/s.
+    pub rx_bitrate_mbps: Option<f64>,
+    /// Retransmitted frames since association; only our own transmissions are counted.
+    pub tx_retries: Option<u64>,
+    /// Frames given up on after all retries, since association.
+    pub tx_failed: Option<u64>,
+    pub channel: Option<u32>,
+    pub frequency_mhz: Option<u32>,
+}
+
+/// A link state sampled during the run.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    /// When the sample was taken, relative to the start of the run.
+    pub offset: Duration,
+    pub link: LinkState,
+}
+
+/// Reads the current link state of a wireless interface with `iw`.
+///
+/// Requires Linux and the `iw` tool; fails if the interface is not associated with an access point.
+pub fn link_state(interface: &str) -> io::Result<LinkState> {
+    let mut state = LinkState::default();
+    let stations = iw(&["dev", interface, "station", "dump"])?;
+    if !stations.starts_with("Station") {
+        return Err(io::Error::new(io::ErrorKind::NotConnected, format!("{} is not associated with an access point", interface)));
+    }
+    // A client in managed mode has a single station, its access point
+    for line in stations.lines().skip(1).take_while(|line| !line.starts_with("Station")) {
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        let first = value.split_whitespace().next().unwrap_or_default();
+        match key {
+            "signal" => state.signal_dbm = first.parse().ok(),
+            "tx bitrate" => state.tx_bitrate_mbps = first.parse().ok(),
+            "rx bitrate" => state.rx_bitrate_mbps = first.parse().ok(),
+            "tx retries" => state.tx_retries = first.parse().ok(),
+            "tx failed" => state.tx_failed = first.parse().ok(),
+            _ => {}
+        }
+    }
+    // "channel 36 (5180 MHz), width: 80 MHz, center1: 5210 MHz"
+    let info = iw(&["dev", interface, "info"])?;
+    if let Some(channel) = info.lines().map(str::trim).find_map(|line| line.strip_prefix("channel ")) {
+        let mut fields = channel.split_whitespace();
+        state.channel = fields.next().and_then(|channel| channel.parse().ok());
+        state.frequency_mhz = fields.next().and_then(|frequency| frequency.trim_start_matches('(').parse().ok());
+    }
+    Ok(state)
+}
+
+/// Summarizes the samples of a run.
+///
+/// # Arguments
+/// - `phy_rate`: Picks the PHY rate that matters for the run, e.g. the receive rate for a download.
+pub fn summarize(interface: &str, samples: &[Sample], phy_rate: impl Fn(&LinkState) -> Option<f64>) -> WifiSummary {
+    let signals: Vec<i32> = samples.iter().filter_map(|sample| sample.link.signal_dbm).collect();
+    let rates: Vec<f64> = samples.iter().filter_map(|sample| phy_rate(&sample.link)).collect();
+    let average = |values: &[f64]| if values.is_empty() { None } else { Some(values.iter().sum::<f64>() / values.len() as f64) };
+    // The counters run since association, so the run's share is the growth from the first sample
+    let growth = |counter: fn(&LinkState) -> Option<u64>| {
+        let first = samples.iter().find_map(|sample| counter(&sample.link))?;
+        let last = samples.iter().rev().find_map(|sample| counter(&sample.link))?;
+        Some(last.saturating_sub(first))
+    };
+    let mut channels: Vec<u32> = samples.iter().filter_map(|sample| sample.link.channel).collect();
+    channels.dedup();
+    WifiSummary {
+        interface: interface.to_string(),
+        samples: samples.len(),
+        avg_signal_dbm: average(&signals.iter().map(|&signal| f64::from(signal)).collect::<Vec<f64>>()),
+        min_signal_dbm: signals.iter().copied().min(),
+        avg_phy_rate_mbps: average(&rates),
+        min_phy_rate_mbps: rates.iter().copied().reduce(f64::min),
+        tx_retries: growth(|link| link.tx_retries),
+        tx_failed: growth(|link| link.tx_failed),
+        channels,
+    }
+}
+
+fn iw(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("iw").args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(io::Error::other(format!(
+            "iw {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).lines().next().unwrap_or_default()
+        )))
+    }
+}
+
+/// Samples the link state of an interface from a separate thread while a transfer runs.
+pub struct Background {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Vec<Sample>>>,
+}
+
+impl Background {
+    /// Starts sampling `interface` every `interval` until `finish` is called, timing the samples
+    /// from `run_start`. Samples that fail, e.g. while the link reassociates, are skipped.
+    pub fn start(interface: String, interval: Duration, run_start: Instant) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut samples = Vec::new();
+            while !stopped.load(Ordering::Relaxed) {
+                let started = Instant::now();
+                if let Ok(link) = link_state(&interface) {
+                    samples.push(Sample { offset: started - run_start, link });
+                }
+                thread::sleep(interval.saturating_sub(started.elapsed()));
+            }
+            samples
+        });
+        Background { stop, handle: Some(handle) }
+    }
+
+    /// Stops sampling and waits for the thread, which finishes the current interval first.
+    pub fn finish(mut self) -> Vec<Sample> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.take().and_then(|handle| handle.join().ok()).unwrap_or_default()
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,103 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::results::SocketIo;
+
+/// Counts the bytes and system calls of every read and write on the test connection, so totals are
+/// exactly what moved through the socket rather than inferred from whole chunks.
+///
+/// Shared between the receiving loop and the upload thread, hence the atomics.
+#[derive(Default)]
+pub struct IoCounters {
+    bytes_read: AtomicU64,
+    reads: AtomicU64,
+    /// Reads that returned fewer bytes than asked for.
+    short_reads: AtomicU64,
+    bytes_written: AtomicU64,
+    writes: AtomicU64,
+    /// Writes the socket accepted only part of.
+    short_writes: AtomicU64,
+}
+
+impl IoCounters {
+    /// Fills `buffer` with one or more reads.
+    ///
+    /// Unlike `read_exact`, every read is counted, and a connection that closes part way through
+    /// reports how far it got.
+    pub fn read_full<R: Read>(&self, stream: &mut R, buffer: &mut [u8]) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            filled += match self.read_some(stream, &mut buffer[filled..])? {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("connection closed after {} of {} bytes", filled, buffer.len()),
+                    ))
+                }
+                n => n,
+            };
+        }
+        Ok(())
+    }
+
+    /// Reads what is available into `buffer` with a single counted read, retrying if interrupted.
+    ///
+    /// # Returns
+    /// - The number of bytes read, zero at the end of the stream.
+    pub fn read_some<R: Read>(&self, stream: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match stream.read(buffer) {
+                Ok(n) => {
+                    self.reads.fetch_add(1, Ordering::Relaxed);
+                    self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+                    if n > 0 && n < buffer.len() {
+                        self.short_reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Writes all of `data`, continuing after short writes and counting each one.
+    pub fn write_all<W: Write>(&self, stream: &mut W, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            match stream.write(data) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, format!("connection accepted none of {} bytes", data.len()))),
+                Ok(n) => {
+                    self.writes.fetch_add(1, Ordering::Relaxed);
+                    self.bytes_written.fetch_add(n as u64, Ordering::Relaxed);
+                    if n < data.len() {
+                        self.short_writes.fetch_add(1, Ordering::Relaxed);
+                    }
+                    data = &data[n..];
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Returns the counts so far, with the payload bytes of a chunk the transfer ended in.
+    pub fn snapshot(&self, partial_chunk_bytes: u64) -> SocketIo {
+        SocketIo {
+            bytes_read: self.bytes_read(),
+            reads: self.reads.load(Ordering::Relaxed),
+            short_reads: self.short_reads.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written(),
+            writes: self.writes.load(Ordering::Relaxed),
+            short_writes: self.short_writes.load(Ordering::Relaxed),
+            partial_chunk_bytes,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::slice;
+
+/// Size of a regular memory page.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Size of a (transparent) huge page on x86_64 and aarch64 Linux.
+pub const HUGEPAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// A page-aligned buffer for the data plane.
+///
+/// The buffer is allocated once, zeroed by the kernel rather than in userspace, and is meant to be
+/// reused for every chunk of a transfer so the tester does not compete with the network for memory
+/// bandwidth. When hugepages are requested the allocation is rounded up to a whole hugepage and
+/// backed by `MAP_HUGETLB` if reserved hugepages are available, falling back to transparent hugepages.
+pub struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    mapped_len: usize,
+    hugepages: bool,
+}
+
+// The buffer owns its mapping exclusively, just like a `Vec<u8>`.
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// Allocates a zeroed, page-aligned buffer of `len` bytes.
+    ///
+    /// # Arguments
+    /// - `len`: Usable length of the buffer in bytes.
+    /// - `hugepages`: Whether to try backing the buffer with hugepages.
+    pub fn new(len: usize, hugepages: bool) -> Self {
+        let granularity = if hugepages { HUGEPAGE_SIZE } else { PAGE_SIZE };
+        let mapped_len = round_up(len.max(1), granularity);
+        let (ptr, hugepages) = map(mapped_len, hugepages);
+
+        AlignedBuffer { ptr, len, mapped_len, hugepages }
+    }
+
+    /// Returns true if the buffer ended up backed by hugepages (explicit or transparent).
+    pub fn is_hugepage_backed(&self) -> bool {
+        self.hugepages
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unmap(self.ptr, self.mapped_len);
+    }
+}
+
+fn round_up(len: usize, granularity: usize) -> usize {
+    len.div_ceil(granularity) * granularity
+}
+
+#[cfg(unix)]
+fn map(len: usize, hugepages: bool) -> (NonNull<u8>, bool) {
+    let prot = libc::PROT_READ | libc::PROT_WRITE;
+    let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+
+    #[cfg(target_os = "linux")]
+    if hugepages {
+        // Explicit hugepages only succeed if the administrator reserved some (vm.nr_hugepages).
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, prot, flags | libc::MAP_HUGETLB, -1, 0) };
+        if ptr != libc::MAP_FAILED {
+            return (NonNull::new(ptr as *mut u8).expect("mmap returned null"), true);
+        }
+    }
+
+    let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, prot, flags, -1, 0) };
+    if ptr == libc::MAP_FAILED {
+        panic!("Failed to map {} byte buffer: {}", len, std::io::Error::last_os_error());
+    }
+
+    // Ask for transparent hugepages before the first touch so the kernel can back the range with them.
+    #[cfg(target_os = "linux")]
+    let hugepages = hugepages && unsafe { libc::madvise(ptr, len, libc::MADV_HUGEPAGE) } == 0;
+    #[cfg(not(target_os = "linux"))]
+    let hugepages = { let _ = hugepages; false };
+
+    (NonNull::new(ptr as *mut u8).expect("mmap returned null"), hugepages)
+}
+
+#[cfg(unix)]
+fn unmap(ptr: NonNull<u8>, len: usize) {
+    unsafe {
+        libc::munmap(ptr.as_ptr() as *mut libc::c_void, len);
+    }
+}
+
+#[cfg(not(unix))]
+fn map(len: usize, _hugepages: bool) -> (NonNull<u8>, bool) {
+    let layout = std::alloc::Layout::from_size_align(len, PAGE_SIZE).expect("Invalid buffer layout");
+    let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+    (NonNull::new(ptr).unwrap_or_else(|| std::alloc::handle_alloc_error(layout)), false)
+}
+
+#[cfg(not(unix))]
+fn unmap(ptr: NonNull<u8>, len: usize) {
+    let layout = std::alloc::Layout::from_size_align(len, PAGE_SIZE).expect("Invalid buffer layout");
+    unsafe { std::alloc::dealloc(ptr.as_ptr(), layout) };
+}
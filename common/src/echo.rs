@@ -0,0 +1,161 @@
+use std::convert::TryInto;
+
+/// Datagram the client sends to the server's UDP echo port, which returns it unchanged.
+///
+/// Fields are encoded in network byte order after a 4-byte magic, which keeps the server from
+/// reflecting stray traffic. A probe may be padded to the size of the traffic it stands in for,
+/// e.g. a voice packet; the padding is returned with it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Probe {
+    /// Position of the probe in its series, starting at zero.
+    pub sequence: u64,
+    /// Time the client sent the probe, in nanoseconds on a clock of the client's choosing.
+    pub sent_ns: u64,
+}
+
+impl Probe {
+    /// Size of the encoded probe in bytes.
+    pub const LEN: usize = 20;
+    const MAGIC: [u8; 4] = *b"BWEC";
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0..4].copy_from_slice(&Self::MAGIC);
+        bytes[4..12].copy_from_slice(&self.sequence.to_be_bytes());
+        bytes[12..20].copy_from_slice(&self.sent_ns.to_be_bytes());
+        bytes
+    }
+
+    /// Encodes the probe followed by zeros up to `len` bytes, or unpadded if `len` is shorter.
+    pub fn encode_padded(&self, len: usize) -> Vec<u8> {
+        let mut bytes = self.encode().to_vec();
+        bytes.resize(len.max(Self::LEN), 0);
+        bytes
+    }
+
+    /// Decodes a received datagram, or returns `None` if it is not a probe.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::LEN || bytes[0..4] != Self::MAGIC {
+            return None;
+        }
+        Some(Probe {
+            sequence: u64::from_be_bytes(bytes[4..12].try_into().unwrap()),
+            sent_ns: u64::from_be_bytes(bytes[12..20].try_into().unwrap()),
+        })
+    }
+}
+
+/// Most packets the server sends in reply to one train request, which bounds the traffic a single
+/// datagram can draw from it.
+pub const MAX_TRAIN_PACKETS: u16 = 64;
+/// Largest train packet, the UDP payload that fits a 1500-byte IPv4 MTU unfragmented.
+pub const MAX_TRAIN_PACKET_LEN: usize = 1472;
+
+/// Asks the server's echo port to send a train of packets back-to-back to the requester, whose
+/// spread on arrival shows the download bandwidth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrainRequest {
+    /// Identifies the train among the client's trains.
+    pub train: u32,
+    /// Number of packets, capped at `MAX_TRAIN_PACKETS`.
+    pub packets: u16,
+    /// Size of each packet in bytes, between `TrainPacket::HEADER_LEN` and `MAX_TRAIN_PACKET_LEN`.
+    pub len: u16,
+}
+
+impl TrainRequest {
+    pub const LEN: usize = 12;
+    const MAGIC: [u8; 4] = *b"BWTQ";
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0..4].copy_from_slice(&Self::MAGIC);
+        bytes[4..8].copy_from_slice(&self.train.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.packets.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.len.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LEN || bytes[0..4] != Self::MAGIC {
+            return None;
+        }
+        Some(TrainRequest {
+            train: u32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+            packets: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+            len: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+        })
+    }
+}
+
+/// One packet of a train, sent by the server for a download train and by the client for an upload
+/// train. The header is followed by padding up to the train's packet size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrainPacket {
+    pub train: u32,
+    /// Position of the packet in its train, starting at zero.
+    pub index: u16,
+    /// Number of packets in the train.
+    pub packets: u16,
+}
+
+impl TrainPacket {
+    pub const HEADER_LEN: usize = 12;
+    const MAGIC: [u8; 4] = *b"BWTP";
+
+    /// Encodes the packet padded with zeros to `len` bytes, or to the header if `len` is shorter.
+    pub fn encode(&self, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len.max(Self::HEADER_LEN)];
+        bytes[0..4].copy_from_slice(&Self::MAGIC);
+        bytes[4..8].copy_from_slice(&self.train.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.index.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.packets.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::HEADER_LEN || bytes[0..4] != Self::MAGIC {
+            return None;
+        }
+        Some(TrainPacket {
+            train: u32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+            index: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+            packets: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+        })
+    }
+}
+
+/// The server's reply to each packet of an upload train: when the packet arrived, so the client
+/// sees the spread the path gave the train on its way up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrainStamp {
+    pub train: u32,
+    pub index: u16,
+    /// Arrival time in nanoseconds on a clock of the server; only differences are meaningful.
+    pub received_ns: u64,
+}
+
+impl TrainStamp {
+    pub const LEN: usize = 18;
+    const MAGIC: [u8; 4] = *b"BWTS";
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0..4].copy_from_slice(&Self::MAGIC);
+        bytes[4..8].copy_from_slice(&self.train.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.index.to_be_bytes());
+        bytes[10..18].copy_from_slice(&self.received_ns.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LEN || bytes[0..4] != Self::MAGIC {
+            return None;
+        }
+        Some(TrainStamp {
+            train: u32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+            index: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+            received_ns: u64::from_be_bytes(bytes[10..18].try_into().unwrap()),
+        })
+    }
+}
@@ -0,0 +1,257 @@
+//! Extended TCP statistics (RFC 4898) from the Windows IP Helper API, which report what
+//! `SIO_TCP_INFO` leaves out: the slow start threshold, RTT variance, the retransmission timer, and
+//! how long the sender was held back by the receive window, the congestion window, or the
+//! application.
+
+use std::io;
+use std::net::SocketAddr;
+
+/// `TCP_ESTATS_TYPE` values (tcpestats.h).
+const TCP_CONNECTION_ESTATS_SND_CONG: u32 = 2;
+const TCP_CONNECTION_ESTATS_PATH: u32 = 3;
+/// `MIB_TCP_STATE_ESTAB`; the state of the row is not matched, but it must be a valid one.
+const MIB_TCP_STATE_ESTAB: u32 = 5;
+
+/// `MIB_TCPROW` (tcpmib.h): addresses and ports in network byte order.
+#[repr(C)]
+struct TcpRow {
+    state: u32,
+    local_addr: [u8; 4],
+    local_port: u32,
+    remote_addr: [u8; 4],
+    remote_port: u32,
+}
+
+/// `MIB_TCP6ROW` (tcpmib.h).
+#[repr(C)]
+struct Tcp6Row {
+    state: u32,
+    local_addr: [u8; 16],
+    local_scope_id: u32,
+    local_port: u32,
+    remote_addr: [u8; 16],
+    remote_scope_id: u32,
+    remote_port: u32,
+}
+
+/// `TCP_ESTATS_SND_CONG_RW_v0` and `TCP_ESTATS_PATH_RW_v0`.
+#[repr(C)]
+struct EnableCollection {
+    enable: u8,
+}
+
+/// `TCP_ESTATS_SND_CONG_ROD_v0`: times in milliseconds, windows in bytes.
+#[repr(C)]
+#[derive(Default)]
+struct SndCongRod {
+    lim_trans_rwin: u32,
+    lim_time_rwin: u32,
+    lim_bytes_rwin: usize,
+    lim_trans_cwnd: u32,
+    lim_time_cwnd: u32,
+    lim_bytes_cwnd: usize,
+    lim_trans_snd: u32,
+    lim_time_snd: u32,
+    lim_bytes_snd: usize,
+    slow_start: u32,
+    cong_avoid: u32,
+    other_reductions: u32,
+    cur_cwnd: u32,
+    max_ss_cwnd: u32,
+    max_ca_cwnd: u32,
+    cur_ssthresh: u32,
+    max_ssthresh: u32,
+    min_ssthresh: u32,
+}
+
+/// `TCP_ESTATS_PATH_ROD_v0`: times in milliseconds.
+#[repr(C)]
+#[derive(Default)]
+struct PathRod {
+    fast_retran: u32,
+    timeouts: u32,
+    subsequent_timeouts: u32,
+    cur_timeout_count: u32,
+    abrupt_timeouts: u32,
+    pkts_retrans: u32,
+    bytes_retrans: u32,
+    dup_acks_in: u32,
+    sacks_rcvd: u32,
+    sack_blocks_rcvd: u32,
+    cong_signals: u32,
+    pre_cong_sum_cwnd: u32,
+    pre_cong_sum_rtt: u32,
+    post_cong_sum_rtt: u32,
+    post_cong_count_rtt: u32,
+    ecn_signals: u32,
+    ece_rcvd: u32,
+    send_stall: u32,
+    quench_rcvd: u32,
+    retran_thresh: u32,
+    snd_dup_ack_episodes: u32,
+    sum_bytes_reordered: u32,
+    non_recov_da: u32,
+    non_recov_da_episodes: u32,
+    ack_after_fr: u32,
+    dsack_dups: u32,
+    sample_rtt: u32,
+    smoothed_rtt: u32,
+    rtt_var: u32,
+    max_rtt: u32,
+    min_rtt: u32,
+    sum_rtt: u32,
+    count_rtt: u32,
+    cur_rto: u32,
+    max_rto: u32,
+    min_rto: u32,
+    cur_mss: u32,
+    max_mss: u32,
+    min_mss: u32,
+    spurious_rto_detections: u32,
+}
+
+#[link(name = "iphlpapi")]
+extern "system" {
+    fn SetPerTcpConnectionEStats(row: *const u8, kind: u32, rw: *const u8, rw_version: u32, rw_size: u32, offset: u32) -> u32;
+    fn GetPerTcpConnectionEStats(
+        row: *const u8,
+        kind: u32,
+        rw: *mut u8,
+        rw_version: u32,
+        rw_size: u32,
+        ros: *mut u8,
+        ros_version: u32,
+        ros_size: u32,
+        rod: *mut u8,
+        rod_version: u32,
+        rod_size: u32,
+    ) -> u32;
+    fn SetPerTcp6ConnectionEStats(row: *const u8, kind: u32, rw: *const u8, rw_version: u32, rw_size: u32, offset: u32) -> u32;
+    fn GetPerTcp6ConnectionEStats(
+        row: *const u8,
+        kind: u32,
+        rw: *mut u8,
+        rw_version: u32,
+        rw_size: u32,
+        ros: *mut u8,
+        ros_version: u32,
+        ros_size: u32,
+        rod: *mut u8,
+        rod_version: u32,
+        rod_size: u32,
+    ) -> u32;
+}
+
+/// What the extended statistics add to `SIO_TCP_INFO`, converted to the units of `TcpInfo`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Estats {
+    /// Slow start threshold, in bytes.
+    pub ssthresh: u32,
+    /// RTT variance and retransmission timeout, in microseconds.
+    pub rttvar: u32,
+    pub rto: u32,
+    /// Unrecovered retransmission timeouts.
+    pub timeouts: u32,
+    /// Retransmitted segments.
+    pub retransmitted: u32,
+    /// Time the sender was limited by the receive window, the congestion window, and the
+    /// application or send buffer, in microseconds.
+    pub rwnd_limited: u64,
+    pub cwnd_limited: u64,
+    pub sndbuf_limited: u64,
+}
+
+/// The connection from `local` to `peer`, as a `MIB_TCPROW` or `MIB_TCP6ROW` in bytes, and whether
+/// it is IPv6.
+fn row(local: SocketAddr, peer: SocketAddr) -> (Vec<u8>, bool) {
+    // The port goes in the low 16 bits in network byte order, as it lies in memory
+    let port = |address: SocketAddr| u32::from(u16::from_ne_bytes(address.port().to_be_bytes()));
+    let bytes = |row: *const u8, size: usize| unsafe { std::slice::from_raw_parts(row, size).to_vec() };
+    match (local, peer) {
+        (SocketAddr::V4(local), SocketAddr::V4(peer)) => {
+            let row = TcpRow {
+                state: MIB_TCP_STATE_ESTAB,
+                local_addr: local.ip().octets(),
+                local_port: port(SocketAddr::V4(local)),
+                remote_addr: peer.ip().octets(),
+                remote_port: port(SocketAddr::V4(peer)),
+            };
+            (bytes(&row as *const TcpRow as *const u8, std::mem::size_of::<TcpRow>()), false)
+        }
+        _ => {
+            let v6 = |address: SocketAddr| match address {
+                SocketAddr::V4(address) => (address.ip().to_ipv6_mapped().octets(), 0),
+                SocketAddr::V6(address) => (address.ip().octets(), address.scope_id()),
+            };
+            let ((local_addr, local_scope_id), (remote_addr, remote_scope_id)) = (v6(local), v6(peer));
+            let row = Tcp6Row {
+                state: MIB_TCP_STATE_ESTAB,
+                local_addr,
+                local_scope_id,
+                local_port: port(local),
+                remote_addr,
+                remote_scope_id,
+                remote_port: port(peer),
+            };
+            (bytes(&row as *const Tcp6Row as *const u8, std::mem::size_of::<Tcp6Row>()), true)
+        }
+    }
+}
+
+/// Turns on collection of one kind of statistics for the connection; it has no effect when it is
+/// already on.
+fn enable(row: &[u8], v6: bool, kind: u32) -> io::Result<()> {
+    let rw = EnableCollection { enable: 1 };
+    let rw = &rw as *const EnableCollection as *const u8;
+    let size = std::mem::size_of::<EnableCollection>() as u32;
+    let error = unsafe {
+        if v6 {
+            SetPerTcp6ConnectionEStats(row.as_ptr(), kind, rw, 0, size, 0)
+        } else {
+            SetPerTcpConnectionEStats(row.as_ptr(), kind, rw, 0, size, 0)
+        }
+    };
+    // The IP Helper functions return their error code rather than setting the last error
+    if error == 0 { Ok(()) } else { Err(io::Error::from_raw_os_error(error as i32)) }
+}
+
+/// Reads the dynamic statistics (`ROD`) of one kind into `rod`.
+fn read<T>(row: &[u8], v6: bool, kind: u32, rod: &mut T) -> io::Result<()> {
+    let (rod, size) = (rod as *mut T as *mut u8, std::mem::size_of::<T>() as u32);
+    let null = std::ptr::null_mut();
+    let error = unsafe {
+        if v6 {
+            GetPerTcp6ConnectionEStats(row.as_ptr(), kind, null, 0, 0, null, 0, 0, rod, 0, size)
+        } else {
+            GetPerTcpConnectionEStats(row.as_ptr(), kind, null, 0, 0, null, 0, 0, rod, 0, size)
+        }
+    };
+    if error == 0 { Ok(()) } else { Err(io::Error::from_raw_os_error(error as i32)) }
+}
+
+/// Reads the extended statistics of the connection from `local` to `peer`, turning their
+/// collection on first.
+///
+/// Collection starts with the first call for a connection, so the counters cover the time since
+/// then. Turning it on takes administrator rights; without them this fails with access denied.
+pub fn estats(local: SocketAddr, peer: SocketAddr) -> io::Result<Estats> {
+    let (row, v6) = row(local, peer);
+    enable(&row, v6, TCP_CONNECTION_ESTATS_SND_CONG)?;
+    enable(&row, v6, TCP_CONNECTION_ESTATS_PATH)?;
+    let mut congestion = SndCongRod::default();
+    read(&row, v6, TCP_CONNECTION_ESTATS_SND_CONG, &mut congestion)?;
+    let mut path = PathRod::default();
+    read(&row, v6, TCP_CONNECTION_ESTATS_PATH, &mut path)?;
+
+    let micros = |ms: u32| u64::from(ms) * 1000;
+    Ok(Estats {
+        ssthresh: congestion.cur_ssthresh,
+        rttvar: path.rtt_var.saturating_mul(1000),
+        rto: path.cur_rto.saturating_mul(1000),
+        timeouts: path.cur_timeout_count,
+        retransmitted: path.pkts_retrans,
+        rwnd_limited: micros(congestion.lim_time_rwin),
+        cwnd_limited: micros(congestion.lim_time_cwnd),
+        sndbuf_limited: micros(congestion.lim_time_snd),
+    })
+}
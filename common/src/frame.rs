@@ -0,0 +1,114 @@
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Header the server puts in front of every chunk.
+///
+/// Fields are encoded in network byte order, in declaration order.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChunkHeader {
+    /// Position of the chunk in the transfer, starting at zero.
+    pub sequence: u64,
+    /// Length of the payload following the header, in bytes.
+    pub length: u32,
+    /// Wall-clock time the server started sending the chunk, in nanoseconds since the Unix epoch.
+    pub send_time_ns: u64,
+    /// XXH3-64 hash of the payload, or zero if the client did not ask for checksums.
+    pub checksum: u64,
+}
+
+impl ChunkHeader {
+    /// Size of the encoded header in bytes.
+    pub const LEN: usize = 28;
+
+    /// Returns the XXH3-64 hash of `payload`, as carried in `checksum`.
+    pub fn checksum_of(payload: &[u8]) -> u64 {
+        xxh3_64(payload)
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0..8].copy_from_slice(&self.sequence.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.length.to_be_bytes());
+        bytes[12..20].copy_from_slice(&self.send_time_ns.to_be_bytes());
+        bytes[20..28].copy_from_slice(&self.checksum.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::LEN]) -> Self {
+        let u64_at = |offset: usize| u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        ChunkHeader {
+            sequence: u64_at(0),
+            length: u32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+            send_time_ns: u64_at(12),
+            checksum: u64_at(20),
+        }
+    }
+
+    /// Returns true if `payload` matches the checksum in the header.
+    pub fn verify(&self, payload: &[u8]) -> bool {
+        xxh3_64(payload) == self.checksum
+    }
+}
+
+/// Frame the server sends after the last chunk of a download, or the last acknowledgement of an
+/// upload, so the client can tell a transfer the server finished from one it abandoned.
+///
+/// It is encoded as a chunk header whose length is `LENGTH`, which no chunk can have, with the
+/// next sequence number in `sequence`, the payload bytes the server sent or received on this
+/// connection in `checksum`, and the time it finished in `send_time_ns`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EndOfTest {
+    /// Sequence number following the last chunk, i.e. the first sequence plus the chunks transferred.
+    pub next_sequence: u64,
+    /// Payload bytes transferred on this connection.
+    pub bytes: u64,
+    /// Wall-clock time the server finished, in nanoseconds since the Unix epoch.
+    pub finish_time_ns: u64,
+}
+
+impl EndOfTest {
+    /// Chunk length that marks the frame.
+    pub const LENGTH: u32 = u32::MAX;
+
+    pub fn encode(&self) -> [u8; ChunkHeader::LEN] {
+        ChunkHeader { sequence: self.next_sequence, length: Self::LENGTH, send_time_ns: self.finish_time_ns, checksum: self.bytes }.encode()
+    }
+
+    /// Returns the frame if `header` is one rather than the header of a chunk.
+    pub fn from_header(header: &ChunkHeader) -> Option<Self> {
+        if header.length != Self::LENGTH {
+            return None;
+        }
+        Some(EndOfTest { next_sequence: header.sequence, bytes: header.checksum, finish_time_ns: header.send_time_ns })
+    }
+}
+
+/// Frame sent on a direction of the connection that would otherwise be silent, so the peer can tell
+/// a side that is idle from one that is gone (see `heartbeat`).
+///
+/// It is encoded as a chunk header whose length is `LENGTH`, with the time it was sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Heartbeat {
+    /// Wall-clock time the heartbeat was sent, in nanoseconds since the Unix epoch.
+    pub send_time_ns: u64,
+}
+
+impl Heartbeat {
+    /// Chunk length that marks the frame.
+    pub const LENGTH: u32 = u32::MAX - 1;
+
+    pub fn encode(&self) -> [u8; ChunkHeader::LEN] {
+        ChunkHeader { sequence: 0, length: Self::LENGTH, send_time_ns: self.send_time_ns, checksum: 0 }.encode()
+    }
+
+    /// Returns the frame if `header` is one rather than the header of a chunk.
+    pub fn from_header(header: &ChunkHeader) -> Option<Self> {
+        (header.length == Self::LENGTH).then_some(Heartbeat { send_time_ns: header.send_time_ns })
+    }
+}
+
+/// Returns the current wall-clock time in nanoseconds since the Unix epoch.
+pub fn unix_time_ns() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
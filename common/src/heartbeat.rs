@@ -0,0 +1,88 @@
+//! Heartbeats on the idle direction of a test connection.
+//!
+//! During a transfer only one direction carries data. The receiving side sends a heartbeat every
+//! interval on the other direction, and the sending side sends them while it pauses between
+//! bursts, so neither side goes quiet for longer than the interval while its peer is alive. A side
+//! that hears nothing for `timeout(interval)` can end the session instead of waiting for TCP to
+//! notice a half-open connection, which can take minutes.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::frame::{self, Heartbeat};
+
+/// Heartbeats that may go missing before the peer is considered gone.
+pub const MISSED_HEARTBEATS: u32 = 3;
+
+/// How long to hear nothing from a peer sending heartbeats every `interval` before giving up on it.
+pub fn timeout(interval: Duration) -> Duration {
+    interval * MISSED_HEARTBEATS
+}
+
+/// Sleeps for `duration`, sending a heartbeat every `interval` meanwhile.
+///
+/// # Arguments
+/// - `send`: Writes an encoded frame to the connection.
+pub fn sleep<F: FnMut(&[u8]) -> io::Result<()>>(duration: Duration, interval: Duration, mut send: F) -> io::Result<()> {
+    let until = Instant::now() + duration;
+    loop {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(());
+        }
+        thread::sleep(left.min(interval));
+        if Instant::now() < until {
+            send(&Heartbeat { send_time_ns: frame::unix_time_ns() }.encode())?;
+        }
+    }
+}
+
+/// Sends heartbeats from a separate thread until stopped.
+pub struct Sender {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Sender {
+    /// Starts sending a heartbeat every `interval` with `send`, which writes an encoded frame to the
+    /// connection. Sending stops at the first write that fails.
+    pub fn start<F>(interval: Duration, mut send: F) -> Self
+    where
+        F: FnMut(&[u8]) -> io::Result<()> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let handle = thread::spawn(move || loop {
+            thread::park_timeout(interval);
+            if stopped.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            send(&Heartbeat { send_time_ns: frame::unix_time_ns() }.encode())?;
+        });
+        Sender { stop, handle: Some(handle) }
+    }
+
+    /// Stops sending and waits for the thread.
+    ///
+    /// # Returns
+    /// - The write error sending stopped at, if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.signal_stop();
+        self.handle.take().map_or(Ok(()), |handle| handle.join().unwrap_or(Ok(())))
+    }
+
+    fn signal_stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = &self.handle {
+            handle.thread().unpark();
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.signal_stop();
+    }
+}
@@ -0,0 +1,19 @@
+//! Code shared between the bandwidth test server and client.
+
+pub mod buffer;
+pub mod echo;
+#[cfg(windows)]
+mod estats;
+pub mod frame;
+pub mod heartbeat;
+pub mod mdns;
+pub mod mmsg;
+pub mod netif;
+pub mod numa;
+pub mod payload;
+pub mod peer;
+pub mod protocol;
+pub mod sockopt;
+pub mod tcp_info;
+#[cfg(windows)]
+mod winsock;
@@ -0,0 +1,239 @@
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Multicast group and port mDNS queries are sent to.
+pub const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const PORT: u16 = 5353;
+
+/// DNS-SD service type of the test server.
+pub const SERVICE: &str = "_bwtest._tcp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on the class of a question to ask for a unicast reply, and on the class of a record to flush
+/// older cached copies.
+const CLASS_TOP_BIT: u16 = 0x8000;
+/// Header flags of a response that is authoritative.
+const FLAGS_RESPONSE: u16 = 0x8400;
+/// How long receivers may cache the records, in seconds.
+const RECORD_TTL: u32 = 120;
+
+/// A test server found on the network.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Advertisement {
+    /// Service instance name, e.g. `lab-server._bwtest._tcp.local`.
+    pub instance: String,
+    /// Host name the service runs on, e.g. `lab-server.local`.
+    pub host: String,
+    pub port: u16,
+    /// Addresses of the host included in the response.
+    pub addresses: Vec<IpAddr>,
+}
+
+/// Builds a query for all test servers.
+pub fn query() -> Vec<u8> {
+    let mut packet = header(0, 0, 1, 0, 0);
+    write_name(&mut packet, SERVICE);
+    put_u16(&mut packet, TYPE_PTR);
+    put_u16(&mut packet, CLASS_IN | CLASS_TOP_BIT);
+    packet
+}
+
+/// Returns the ID of `packet` if it is a query asking for test servers.
+pub fn service_query_id(packet: &[u8]) -> Option<u16> {
+    let id = get_u16(packet, 0)?;
+    let flags = get_u16(packet, 2)?;
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+    let questions = get_u16(packet, 4)?;
+    let mut offset = 12;
+    for _ in 0..questions {
+        let (name, next) = read_name(packet, offset)?;
+        let kind = get_u16(packet, next)?;
+        offset = next + 4;
+        if name.eq_ignore_ascii_case(SERVICE) && (kind == TYPE_PTR || kind == TYPE_ANY) {
+            return Some(id);
+        }
+    }
+    None
+}
+
+/// Builds the response advertising a test server, answering the query with the given ID.
+///
+/// # Arguments
+/// - `id`: ID of the query being answered (zero for multicast queries).
+/// - `name`: Name of the server, used as the instance and host label, e.g. the hostname.
+/// - `port`: TCP port the server listens on.
+/// - `address`: Address the querier can reach the server at.
+pub fn response(id: u16, name: &str, port: u16, address: IpAddr) -> Vec<u8> {
+    let instance = format!("{}.{}", name, SERVICE);
+    let host = format!("{}.local", name);
+    let mut packet = header(id, FLAGS_RESPONSE, 0, 1, 3);
+
+    record(&mut packet, SERVICE, TYPE_PTR, CLASS_IN, |rdata| write_name(rdata, &instance));
+
+    let unique = CLASS_IN | CLASS_TOP_BIT;
+    record(&mut packet, &instance, TYPE_SRV, unique, |rdata| {
+        put_u16(rdata, 0); // priority
+        put_u16(rdata, 0); // weight
+        put_u16(rdata, port);
+        write_name(rdata, &host);
+    });
+    // An empty TXT record is a single zero-length string
+    record(&mut packet, &instance, TYPE_TXT, unique, |rdata| rdata.push(0));
+    match address {
+        IpAddr::V4(address) => record(&mut packet, &host, TYPE_A, unique, |rdata| rdata.extend_from_slice(&address.octets())),
+        IpAddr::V6(address) => record(&mut packet, &host, TYPE_AAAA, unique, |rdata| rdata.extend_from_slice(&address.octets())),
+    }
+    packet
+}
+
+/// Extracts the test servers advertised in a response.
+pub fn parse_response(packet: &[u8]) -> Vec<Advertisement> {
+    parse_records(packet).map(|records| advertisements(&records)).unwrap_or_default()
+}
+
+/// A resource record relevant to service discovery.
+enum Record {
+    Ptr { name: String, target: String },
+    Srv { name: String, port: u16, target: String },
+    Address { name: String, address: IpAddr },
+}
+
+fn parse_records(packet: &[u8]) -> Option<Vec<Record>> {
+    if get_u16(packet, 2)? & 0x8000 == 0 {
+        return None;
+    }
+    let questions = get_u16(packet, 4)?;
+    let records = usize::from(get_u16(packet, 6)?) + usize::from(get_u16(packet, 8)?) + usize::from(get_u16(packet, 10)?);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(packet, offset)?.1 + 4;
+    }
+    let mut parsed = Vec::new();
+    for _ in 0..records {
+        let (name, next) = read_name(packet, offset)?;
+        let kind = get_u16(packet, next)?;
+        let length = usize::from(get_u16(packet, next + 8)?);
+        let rdata = next + 10;
+        let end = rdata + length;
+        let data = packet.get(rdata..end)?;
+        match kind {
+            TYPE_PTR => parsed.push(Record::Ptr { name, target: read_name(packet, rdata)?.0 }),
+            TYPE_SRV => parsed.push(Record::Srv { name, port: get_u16(packet, rdata + 4)?, target: read_name(packet, rdata + 6)?.0 }),
+            TYPE_A => {
+                let octets: [u8; 4] = data.try_into().ok()?;
+                parsed.push(Record::Address { name, address: IpAddr::V4(Ipv4Addr::from(octets)) });
+            }
+            TYPE_AAAA => {
+                let octets: [u8; 16] = data.try_into().ok()?;
+                parsed.push(Record::Address { name, address: IpAddr::V6(Ipv6Addr::from(octets)) });
+            }
+            _ => {}
+        }
+        offset = end;
+    }
+    Some(parsed)
+}
+
+fn advertisements(records: &[Record]) -> Vec<Advertisement> {
+    let mut found = Vec::new();
+    for record in records {
+        let instance = match record {
+            Record::Ptr { name, target } if name.eq_ignore_ascii_case(SERVICE) => target,
+            _ => continue,
+        };
+        let service = records.iter().find_map(|record| match record {
+            Record::Srv { name, port, target } if name.eq_ignore_ascii_case(instance) => Some((*port, target)),
+            _ => None,
+        });
+        if let Some((port, host)) = service {
+            let addresses = records
+                .iter()
+                .filter_map(|record| match record {
+                    Record::Address { name, address } if name.eq_ignore_ascii_case(host) => Some(*address),
+                    _ => None,
+                })
+                .collect();
+            found.push(Advertisement { instance: instance.clone(), host: host.clone(), port, addresses });
+        }
+    }
+    found
+}
+
+fn header(id: u16, flags: u16, questions: u16, answers: u16, additional: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(512);
+    for value in [id, flags, questions, answers, 0, additional] {
+        put_u16(&mut packet, value);
+    }
+    packet
+}
+
+/// Appends a resource record whose data is written by `rdata`.
+fn record(packet: &mut Vec<u8>, name: &str, kind: u16, class: u16, rdata: impl FnOnce(&mut Vec<u8>)) {
+    write_name(packet, name);
+    put_u16(packet, kind);
+    put_u16(packet, class);
+    packet.extend_from_slice(&RECORD_TTL.to_be_bytes());
+    let mut data = Vec::new();
+    rdata(&mut data);
+    put_u16(packet, data.len() as u16);
+    packet.extend_from_slice(&data);
+}
+
+/// Appends `name` as uncompressed labels; labels longer than 63 bytes are truncated.
+fn write_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label);
+    }
+    packet.push(0);
+}
+
+/// Reads a possibly compressed name at `offset`.
+///
+/// # Returns
+/// - The dotted name and the offset just past it in the original position.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Every pointer must go backwards, which bounds the number of jumps and rules out loops
+    let mut limit = offset;
+    loop {
+        let length = *packet.get(offset)?;
+        match length {
+            0 => break,
+            length if length & 0xc0 == 0xc0 => {
+                let target = usize::from(get_u16(packet, offset)? & 0x3fff);
+                if target >= limit {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                limit = target;
+                offset = target;
+            }
+            length => {
+                let label = packet.get(offset + 1..offset + 1 + usize::from(length))?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + usize::from(length);
+            }
+        }
+    }
+    Some((labels.join("."), end.unwrap_or(offset + 1)))
+}
+
+fn put_u16(packet: &mut Vec<u8>, value: u16) {
+    packet.extend_from_slice(&value.to_be_bytes());
+}
+
+fn get_u16(packet: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(packet.get(offset..offset + 2)?.try_into().ok()?))
+}
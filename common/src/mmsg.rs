@@ -0,0 +1,292 @@
+//! Batched datagram I/O: `sendmmsg` and `recvmmsg` on Linux move a whole packet train in one
+//! system call, with a loop over `send_to` and `recv_from` elsewhere. UDP segmentation offload
+//! (GSO) and receive coalescing (GRO) go further, handing the kernel one buffer for many packets.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::SystemTime;
+
+/// Largest datagram a batch receives whole without GRO; longer ones are truncated.
+pub const MAX_DATAGRAM_LEN: usize = 2048;
+/// Largest buffer GRO coalesces datagrams into.
+pub const MAX_GRO_LEN: usize = 65535;
+/// Most segments the kernel splits one GSO buffer into.
+#[cfg(target_os = "linux")]
+const MAX_GSO_SEGMENTS: usize = 64;
+/// Largest UDP payload, which bounds a GSO buffer.
+#[cfg(target_os = "linux")]
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// A datagram received into a `Batch`.
+#[derive(Clone, Copy, Debug)]
+pub struct Datagram {
+    pub len: usize,
+    pub sender: SocketAddr,
+    /// When the kernel received the datagram, if timestamps are enabled on the socket.
+    pub received: Option<SystemTime>,
+    /// Length of the datagrams GRO coalesced into this one, which share its arrival time.
+    pub segment_len: Option<usize>,
+}
+
+impl Datagram {
+    /// Whether GRO merged several datagrams into this one.
+    pub fn is_coalesced(&self) -> bool {
+        self.segment_len.is_some_and(|segment_len| self.len > segment_len)
+    }
+}
+
+/// Buffers for receiving up to `capacity` datagrams in one call.
+pub struct Batch {
+    buffers: Vec<Vec<u8>>,
+    /// The datagrams of the last receive, with the buffer each was received into.
+    datagrams: Vec<(usize, Datagram)>,
+}
+
+impl Batch {
+    /// # Arguments
+    /// - `datagram_len`: Size of each buffer: `MAX_DATAGRAM_LEN`, or `MAX_GRO_LEN` on a socket with
+    ///   GRO enabled.
+    pub fn new(capacity: usize, datagram_len: usize) -> Self {
+        Batch { buffers: vec![vec![0u8; datagram_len]; capacity.max(1)], datagrams: Vec::with_capacity(capacity) }
+    }
+
+    /// Waits for at least one datagram, subject to the socket's read timeout, then takes whatever
+    /// else is already queued, up to the capacity of the batch.
+    ///
+    /// # Returns
+    /// - The number of datagrams received, which `iter` then yields.
+    pub fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        self.datagrams.clear();
+        recv(socket, &mut self.buffers, &mut self.datagrams)?;
+        Ok(self.datagrams.len())
+    }
+
+    /// The datagrams of the last `recv` with their payloads, in the order they arrived. Datagrams
+    /// GRO coalesced are split up again, each yielded with the coalesced one.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &Datagram)> {
+        self.datagrams.iter().flat_map(move |(slot, datagram)| {
+            let payload = &self.buffers[*slot][..datagram.len];
+            payload.chunks(datagram.segment_len.unwrap_or(datagram.len).max(1)).map(move |segment| (segment, datagram))
+        })
+    }
+}
+
+/// Has the kernel stamp every datagram `socket` receives with its arrival time, which a `Batch`
+/// reports in `Datagram::received`. Unlike a clock read after the receive call, the stamp does not
+/// depend on how many datagrams one call returns.
+#[cfg(target_os = "linux")]
+pub fn enable_timestamps(socket: &UdpSocket) -> io::Result<()> {
+    set_int_option(socket, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, 1)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_timestamps(_socket: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Receive timestamps are only supported on Linux"))
+}
+
+/// Lets the kernel coalesce datagrams of one flow that arrive together into a single buffer (UDP
+/// GRO), which a `Batch` splits up again. Coalesced datagrams share one arrival time.
+#[cfg(target_os = "linux")]
+pub fn enable_gro(socket: &UdpSocket) -> io::Result<()> {
+    set_int_option(socket, libc::SOL_UDP, libc::UDP_GRO, 1)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_gro(_socket: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "UDP GRO is only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+fn set_int_option(socket: &UdpSocket, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sends packets of equal length with UDP segmentation offload: they are copied into as few
+/// buffers as the kernel accepts, which it splits into the packets again, in the NIC where it
+/// supports UDP segmentation. Only the last packet may be shorter.
+///
+/// # Arguments
+/// - `target`: Where to send the packets, or `None` for the connected peer.
+#[cfg(target_os = "linux")]
+pub fn send_segmented<P: AsRef<[u8]>>(socket: &UdpSocket, packets: &[P], target: Option<SocketAddr>) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    use socket2::SockAddr;
+
+    let segment_len = match packets.first() {
+        Some(packet) => packet.as_ref().len(),
+        None => return Ok(()),
+    };
+    if segment_len == 0 || packets.iter().rev().skip(1).any(|packet| packet.as_ref().len() != segment_len) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "segmented packets must be of equal length"));
+    }
+    let target = target.map(SockAddr::from);
+    let per_buffer = (MAX_UDP_PAYLOAD / segment_len).clamp(1, MAX_GSO_SEGMENTS);
+    for group in packets.chunks(per_buffer) {
+        let mut buffer: Vec<u8> = group.iter().flat_map(|packet| packet.as_ref().iter().copied()).collect();
+        let mut iovec = libc::iovec { iov_base: buffer.as_mut_ptr() as *mut libc::c_void, iov_len: buffer.len() };
+        let mut control = [0u64; 4];
+        let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+        if let Some(target) = &target {
+            header.msg_name = target.as_ptr() as *mut libc::c_void;
+            header.msg_namelen = target.len();
+        }
+        header.msg_iov = &mut iovec;
+        header.msg_iovlen = 1 as _;
+        header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        header.msg_controllen = unsafe { libc::CMSG_SPACE(std::mem::size_of::<u16>() as libc::c_uint) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&header);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<u16>() as libc::c_uint) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_len as u16);
+        }
+        loop {
+            if unsafe { libc::sendmsg(socket.as_raw_fd(), &header, 0) } >= 0 {
+                break;
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_segmented<P: AsRef<[u8]>>(_socket: &UdpSocket, _packets: &[P], _target: Option<SocketAddr>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "UDP segmentation offload is only supported on Linux"))
+}
+
+/// Sends every packet, in order, to `target`, or to the connected peer if `target` is `None`.
+#[cfg(target_os = "linux")]
+pub fn send_all<P: AsRef<[u8]>>(socket: &UdpSocket, packets: &[P], target: Option<SocketAddr>) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    use socket2::SockAddr;
+
+    let target = target.map(SockAddr::from);
+    let mut iovecs: Vec<libc::iovec> =
+        packets.iter().map(|packet| libc::iovec { iov_base: packet.as_ref().as_ptr() as *mut libc::c_void, iov_len: packet.as_ref().len() }).collect();
+    let mut headers: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .map(|iovec| {
+            let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            if let Some(target) = &target {
+                header.msg_hdr.msg_name = target.as_ptr() as *mut libc::c_void;
+                header.msg_hdr.msg_namelen = target.len();
+            }
+            header.msg_hdr.msg_iov = iovec;
+            header.msg_hdr.msg_iovlen = 1 as _;
+            header
+        })
+        .collect();
+    // The kernel may send fewer than asked for, e.g. when the send buffer fills up
+    let mut sent = 0;
+    while sent < headers.len() {
+        let remaining = &mut headers[sent..];
+        let count = unsafe { libc::sendmmsg(socket.as_raw_fd(), remaining.as_mut_ptr(), remaining.len() as libc::c_uint, 0) };
+        if count < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        sent += count as usize;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_all<P: AsRef<[u8]>>(socket: &UdpSocket, packets: &[P], target: Option<SocketAddr>) -> io::Result<()> {
+    for packet in packets {
+        match target {
+            Some(target) => socket.send_to(packet.as_ref(), target)?,
+            None => socket.send(packet.as_ref())?,
+        };
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn recv(socket: &UdpSocket, buffers: &mut [Vec<u8>], datagrams: &mut Vec<(usize, Datagram)>) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    use std::time::{Duration, UNIX_EPOCH};
+    use socket2::SockAddr;
+
+    // Room for an SCM_TIMESTAMPNS and a UDP_GRO message, with the header alignment to spare
+    const CONTROL_LEN: usize = 128;
+    let mut addresses: Vec<libc::sockaddr_storage> = vec![unsafe { std::mem::zeroed() }; buffers.len()];
+    let mut controls = vec![[0u64; CONTROL_LEN / 8]; buffers.len()];
+    let mut iovecs: Vec<libc::iovec> = buffers.iter_mut().map(|buffer| libc::iovec { iov_base: buffer.as_mut_ptr() as *mut libc::c_void, iov_len: buffer.len() }).collect();
+    let mut headers: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(addresses.iter_mut().zip(controls.iter_mut()))
+        .map(|(iovec, (address, control))| {
+            let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            header.msg_hdr.msg_name = address as *mut libc::sockaddr_storage as *mut libc::c_void;
+            header.msg_hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            header.msg_hdr.msg_iov = iovec;
+            header.msg_hdr.msg_iovlen = 1 as _;
+            header.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            header.msg_hdr.msg_controllen = CONTROL_LEN as _;
+            header
+        })
+        .collect();
+    let count = loop {
+        let count = unsafe { libc::recvmmsg(socket.as_raw_fd(), headers.as_mut_ptr(), headers.len() as libc::c_uint, libc::MSG_WAITFORONE, std::ptr::null_mut()) };
+        if count >= 0 {
+            break count as usize;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    };
+
+    for (slot, (header, address)) in headers[..count].iter().zip(&addresses).enumerate() {
+        let sender = match unsafe { SockAddr::new(*address, header.msg_hdr.msg_namelen) }.as_socket() {
+            Some(sender) => sender,
+            None => continue,
+        };
+        let mut received = None;
+        let mut segment_len = None;
+        let mut message = unsafe { libc::CMSG_FIRSTHDR(&header.msg_hdr) };
+        while !message.is_null() {
+            let cmsg = unsafe { &*message };
+            if cmsg.cmsg_level == libc::SOL_SOCKET && cmsg.cmsg_type == libc::SCM_TIMESTAMPNS {
+                let stamp: libc::timespec = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(message) as *const libc::timespec) };
+                received = Some(UNIX_EPOCH + Duration::new(stamp.tv_sec as u64, stamp.tv_nsec as u32));
+            } else if cmsg.cmsg_level == libc::SOL_UDP && cmsg.cmsg_type == libc::UDP_GRO {
+                let len: libc::c_int = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(message) as *const libc::c_int) };
+                segment_len = Some(len as usize);
+            }
+            message = unsafe { libc::CMSG_NXTHDR(&header.msg_hdr, message) };
+        }
+        let len = (header.msg_len as usize).min(buffers[slot].len());
+        datagrams.push((slot, Datagram { len, sender, received, segment_len }));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn recv(socket: &UdpSocket, buffers: &mut [Vec<u8>], datagrams: &mut Vec<(usize, Datagram)>) -> io::Result<()> {
+    let (len, sender) = socket.recv_from(&mut buffers[0])?;
+    datagrams.push((0, Datagram { len, sender, received: None, segment_len: None }));
+    Ok(())
+}
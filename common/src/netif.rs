@@ -0,0 +1,46 @@
+//! Network interfaces of the host.
+
+use std::io;
+use std::net::IpAddr;
+
+/// Finds the interface that has `address` assigned, i.e. the one a socket bound to it sends through.
+#[cfg(unix)]
+pub fn interface_with_address(address: IpAddr) -> io::Result<String> {
+    use std::ffi::CStr;
+
+    let mut addresses: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addresses) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut found = None;
+    let mut entry = addresses;
+    while !entry.is_null() {
+        let ifaddr = unsafe { &*entry };
+        entry = ifaddr.ifa_next;
+        if ifaddr.ifa_addr.is_null() {
+            continue;
+        }
+        let assigned = match i32::from(unsafe { (*ifaddr.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::from(u32::from_be(sin.sin_addr.s_addr).to_be_bytes())
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(ifaddr.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::from(sin6.sin6_addr.s6_addr)
+            }
+            _ => continue,
+        };
+        if assigned == address {
+            found = Some(unsafe { CStr::from_ptr(ifaddr.ifa_name) }.to_string_lossy().into_owned());
+            break;
+        }
+    }
+    unsafe { libc::freeifaddrs(addresses) };
+    found.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no interface has address {}", address)))
+}
+
+#[cfg(not(unix))]
+pub fn interface_with_address(_address: IpAddr) -> io::Result<String> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Interface lookup is not supported on this platform"))
+}
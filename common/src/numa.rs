@@ -0,0 +1,134 @@
+//! NUMA placement of the data plane, so a test on a multi-socket machine keeps its threads and
+//! buffers on the node the NIC is attached to.
+
+use std::fmt;
+use std::io;
+
+/// A NUMA node and the CPUs that belong to it.
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub id: u32,
+    pub cpus: Vec<usize>,
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "node {} (CPUs {})", self.id, cpu_list(&self.cpus))
+    }
+}
+
+/// Returns the NUMA node the device behind `interface` is attached to, or `None` for virtual
+/// interfaces (loopback, bridges, tunnels) and devices the firmware does not place.
+#[cfg(target_os = "linux")]
+pub fn node_of_interface(interface: &str) -> io::Result<Option<Node>> {
+    let path = format!("/sys/class/net/{}/device/numa_node", interface);
+    let id: i64 = match std::fs::read_to_string(&path) {
+        Ok(id) => id.trim().parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("malformed {}", path)))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // The kernel reports -1 when the platform does not tell which node the device is on
+    if id < 0 {
+        return Ok(None);
+    }
+    let id = id as u32;
+    let cpus = std::fs::read_to_string(format!("/sys/devices/system/node/node{}/cpulist", id))?;
+    Ok(Some(Node { id, cpus: parse_cpu_list(&cpus)? }))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn node_of_interface(_interface: &str) -> io::Result<Option<Node>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "NUMA placement is only supported on Linux"))
+}
+
+/// Counts the NUMA nodes of the machine.
+#[cfg(target_os = "linux")]
+pub fn node_count() -> io::Result<usize> {
+    let online = std::fs::read_to_string("/sys/devices/system/node/online")?;
+    Ok(parse_cpu_list(&online)?.len())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn node_count() -> io::Result<usize> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "NUMA placement is only supported on Linux"))
+}
+
+/// Restricts the calling thread to `cpus`, or lets it run on every online CPU again if `cpus` is
+/// `None`.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: Option<&[usize]>) -> io::Result<()> {
+    let online;
+    let cpus = match cpus {
+        Some(cpus) => cpus,
+        None => {
+            online = parse_cpu_list(&std::fs::read_to_string("/sys/devices/system/cpu/online")?)?;
+            &online
+        }
+    };
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: Option<&[usize]>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "NUMA placement is only supported on Linux"))
+}
+
+/// Asks the kernel to back `buffer` with memory of `node`. Only pages not yet touched are placed,
+/// so this is called right after the buffer is allocated.
+#[cfg(target_os = "linux")]
+pub fn prefer_node(buffer: &mut [u8], node: u32) -> io::Result<()> {
+    const MPOL_PREFERRED: libc::c_long = 1;
+    if node >= libc::c_ulong::BITS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("node {} is beyond the {} nodes a placement can name", node, libc::c_ulong::BITS)));
+    }
+    let mask: libc::c_ulong = 1 << node;
+    // The kernel reads one bit less of the mask than it is told
+    let max_node = libc::c_ulong::from(libc::c_ulong::BITS) + 1;
+    let result = unsafe { libc::syscall(libc::SYS_mbind, buffer.as_mut_ptr(), buffer.len(), MPOL_PREFERRED, &mask as *const libc::c_ulong, max_node, 0) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn prefer_node(_buffer: &mut [u8], _node: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "NUMA placement is only supported on Linux"))
+}
+
+/// Parses a kernel CPU or node list, e.g. `0-3,8-11`.
+#[cfg(target_os = "linux")]
+fn parse_cpu_list(list: &str) -> io::Result<Vec<usize>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, format!("malformed CPU list '{}'", list.trim()));
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first: usize = first.parse().map_err(|_| malformed())?;
+        let last: usize = last.parse().map_err(|_| malformed())?;
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+/// Formats CPUs as a kernel CPU list, collapsing consecutive CPUs into ranges.
+fn cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some(range) if range.1 + 1 == cpu => range.1 = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,15 @@
+/// Fills `buffer` with pseudo-random bytes derived from `seed`.
+///
+/// Uses SplitMix64, which is fixed here rather than taken from a crate so that the same seed
+/// produces the same bytes on every platform and in every version of server and client.
+pub fn fill_seeded(buffer: &mut [u8], seed: u64) {
+    let mut state = seed;
+    for block in buffer.chunks_mut(8) {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        block.copy_from_slice(&z.to_le_bytes()[..block.len()]);
+    }
+}
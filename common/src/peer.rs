@@ -0,0 +1,154 @@
+//! Datagrams of a peer-to-peer test between two clients: their rendezvous through the server, the
+//! hole punching that opens their NATs to each other, and the test traffic itself.
+
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Longest session name, in bytes.
+pub const MAX_SESSION_LEN: usize = 64;
+
+const MAGIC: [u8; 4] = *b"BWPP";
+const HEADER_LEN: usize = 5;
+
+/// A datagram of the peer-to-peer protocol.
+///
+/// Every datagram starts with a 4-byte magic and a byte for its kind, followed by its fields in
+/// network byte order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// A client asks the server's rendezvous port for the other client of `session`. The server
+    /// learns the client's public address from the datagram itself.
+    Register { session: String },
+    /// The server's answer: the public address the other client of the session registered from.
+    PeerAddress { address: SocketAddr },
+    /// Sent by each client to the other until both have heard from each other, which opens the
+    /// mappings of the NATs on the way.
+    Punch {
+        session: String,
+        /// Whether the client sends the test data, so the two can check they chose opposite ones.
+        sender: bool,
+        /// Whether the client has already heard a punch from the other.
+        heard: bool,
+    },
+    /// A packet of test data, padded to the packet size of the test.
+    Data { sequence: u64 },
+    /// The sender is done and sent this many packets, paced at `rate_bps` bits per second.
+    Done { packets: u64, rate_bps: u64 },
+    /// The receiver's account of the test.
+    Report {
+        packets: u64,
+        bytes: u64,
+        /// Time from the first packet received to the last, in nanoseconds.
+        receive_ns: u64,
+    },
+}
+
+impl Message {
+    /// Size of a data packet without its padding.
+    pub const DATA_HEADER_LEN: usize = HEADER_LEN + 8;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        match self {
+            Message::Register { session } => {
+                bytes.push(1);
+                encode_session(&mut bytes, session);
+            }
+            Message::PeerAddress { address } => {
+                bytes.push(2);
+                match address.ip() {
+                    IpAddr::V4(ip) => {
+                        bytes.push(4);
+                        bytes.extend_from_slice(&ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        bytes.push(6);
+                        bytes.extend_from_slice(&ip.octets());
+                    }
+                }
+                bytes.extend_from_slice(&address.port().to_be_bytes());
+            }
+            Message::Punch { session, sender, heard } => {
+                bytes.push(3);
+                bytes.push(u8::from(*sender) | u8::from(*heard) << 1);
+                encode_session(&mut bytes, session);
+            }
+            Message::Data { sequence } => {
+                bytes.push(4);
+                bytes.extend_from_slice(&sequence.to_be_bytes());
+            }
+            Message::Done { packets, rate_bps } => {
+                bytes.push(5);
+                bytes.extend_from_slice(&packets.to_be_bytes());
+                bytes.extend_from_slice(&rate_bps.to_be_bytes());
+            }
+            Message::Report { packets, bytes: received, receive_ns } => {
+                bytes.push(6);
+                bytes.extend_from_slice(&packets.to_be_bytes());
+                bytes.extend_from_slice(&received.to_be_bytes());
+                bytes.extend_from_slice(&receive_ns.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Encodes a data packet padded with zeros to `len` bytes, or unpadded if `len` is shorter.
+    pub fn encode_data(sequence: u64, len: usize) -> Vec<u8> {
+        let mut bytes = Message::Data { sequence }.encode();
+        bytes.resize(len.max(Self::DATA_HEADER_LEN), 0);
+        bytes
+    }
+
+    /// Decodes a received datagram, or returns `None` if it is not one of the protocol.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return None;
+        }
+        let body = &bytes[HEADER_LEN..];
+        let u64_at = |offset: usize| body.get(offset..offset + 8).map(|field| u64::from_be_bytes(field.try_into().unwrap()));
+        match bytes[4] {
+            1 => Some(Message::Register { session: decode_session(body)? }),
+            2 => {
+                let (ip, rest) = match *body.first()? {
+                    4 => {
+                        let octets: [u8; 4] = body.get(1..5)?.try_into().ok()?;
+                        (IpAddr::V4(Ipv4Addr::from(octets)), &body[5..])
+                    }
+                    6 => {
+                        let octets: [u8; 16] = body.get(1..17)?.try_into().ok()?;
+                        (IpAddr::V6(Ipv6Addr::from(octets)), &body[17..])
+                    }
+                    _ => return None,
+                };
+                let port = u16::from_be_bytes(rest.get(0..2)?.try_into().ok()?);
+                Some(Message::PeerAddress { address: SocketAddr::new(ip, port) })
+            }
+            3 => {
+                let flags = *body.first()?;
+                Some(Message::Punch { session: decode_session(&body[1..])?, sender: flags & 1 != 0, heard: flags & 2 != 0 })
+            }
+            4 => Some(Message::Data { sequence: u64_at(0)? }),
+            5 => Some(Message::Done { packets: u64_at(0)?, rate_bps: u64_at(8)? }),
+            6 => Some(Message::Report { packets: u64_at(0)?, bytes: u64_at(8)?, receive_ns: u64_at(16)? }),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the session name with a length byte in front, cut to `MAX_SESSION_LEN` bytes.
+fn encode_session(bytes: &mut Vec<u8>, session: &str) {
+    let mut len = session.len().min(MAX_SESSION_LEN);
+    while !session.is_char_boundary(len) {
+        len -= 1;
+    }
+    bytes.push(len as u8);
+    bytes.extend_from_slice(&session.as_bytes()[..len]);
+}
+
+fn decode_session(body: &[u8]) -> Option<String> {
+    let len = usize::from(*body.first()?);
+    if len > MAX_SESSION_LEN {
+        return None;
+    }
+    String::from_utf8(body.get(1..1 + len)?.to_vec()).ok()
+}
@@ -0,0 +1,375 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+/// Keyword that starts every test request line.
+const REQUEST_KEYWORD: &str = "BWTEST";
+
+/// Keywords that start the server's reply line.
+const ACCEPTED_KEYWORD: &str = "BWTEST-OK";
+const REJECTED_KEYWORD: &str = "BWTEST-REJECTED";
+const QUOTA_EXCEEDED_KEYWORD: &str = "BWTEST-QUOTA-EXCEEDED";
+
+/// Protocol version this build speaks.
+///
+/// Version 1 is the protocol from before versioning: requests carry no version, and the server
+/// sends neither a reply nor an end-of-test frame. Version 2 adds both. Version 3 adds the
+/// quota-exceeded reply.
+///
+/// A server serves every client version from `MIN_PROTOCOL_VERSION` up to its own, and clients
+/// newer than itself at its own version, which it names in its reply; newer clients downgrade to
+/// it. A request the server cannot serve is rejected with a reason instead of being misread.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Oldest client protocol version the server still serves.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// First protocol version with the server's reply and end-of-test frame.
+pub const REPLY_VERSION: u32 = 2;
+
+/// First protocol version with the quota-exceeded reply; older clients are rejected with a reason.
+pub const QUOTA_VERSION: u32 = 3;
+
+/// Upper bound on the length of a request or reply line, so a misbehaving peer cannot make us buffer forever.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// Default number of chunks in a test.
+pub const DEFAULT_CHUNK_COUNT: usize = 100;
+
+/// Default chunk size in bytes.
+pub const DEFAULT_CHUNK_SIZE: usize = 1_000_000;
+
+/// Alternating on/off send pattern used to generate bursty traffic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BurstPattern {
+    /// How long the server sends at line rate before pausing.
+    pub on: Duration,
+    /// How long the server stays idle between bursts.
+    pub off: Duration,
+}
+
+/// Token bucket the server shapes the data it sends with: the bucket fills at `rate_bps` up to
+/// `burst_bytes`, and data only leaves against what it holds, so after an idle period up to a
+/// bucket's worth goes out at line rate before the rate takes over, as on a cable modem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenBucket {
+    pub rate_bps: u64,
+    pub burst_bytes: u64,
+}
+
+/// Which way the test data flows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// The server sends chunks to the client.
+    #[default]
+    Download,
+    /// The client sends chunks to the server, which acknowledges each one.
+    Upload,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Direction::Download => "download",
+            Direction::Upload => "upload",
+        })
+    }
+}
+
+impl FromStr for Direction {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "download" => Ok(Direction::Download),
+            "upload" => Ok(Direction::Upload),
+            _ => Err(invalid_data(format!("Unknown direction '{}'", s))),
+        }
+    }
+}
+
+/// Parameters of a test, sent by the client as a single text line right after connecting.
+///
+/// The line has the form `BWTEST key=value key=value ...\n`. Unknown keys are rejected so that a
+/// mismatch between client and server is reported instead of silently ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestRequest {
+    /// Protocol version the client speaks; 1 for clients from before versioning, which send none.
+    pub version: u32,
+    pub direction: Direction,
+    /// Number of chunks to send; zero streams until the client disconnects.
+    pub chunk_count: usize,
+    pub chunk_size: usize,
+    pub burst: Option<BurstPattern>,
+    /// DSCP code point the server marks the data it sends with.
+    pub dscp: Option<u8>,
+    /// TTL (IPv4) or hop limit (IPv6) for the data the server sends.
+    pub ttl: Option<u8>,
+    /// Fill in the checksum of every `frame::ChunkHeader`.
+    pub checksum: bool,
+    /// Seed of the pseudo-random payload (see `payload::fill_seeded`); zeros are sent without one.
+    pub payload_seed: Option<u64>,
+    /// File under the server's file root to stream instead of synthetic chunks. The transfer ends
+    /// at the end of the file, so the chunk count is ignored.
+    pub file: Option<String>,
+    /// Sequence number of the first chunk, nonzero when a test resumes on a new connection.
+    pub first_sequence: u64,
+    /// Test server (`host:port`) the receiving server should relay the test to instead of serving it.
+    pub relay: Option<String>,
+    /// Interval of the heartbeats both sides send while a direction of the connection is idle
+    /// (see `heartbeat`); none are sent without one.
+    pub heartbeat: Option<Duration>,
+    /// Token bucket the server shapes the data it sends with; it sends at line rate without one.
+    pub shape: Option<TokenBucket>,
+}
+
+impl Default for TestRequest {
+    fn default() -> Self {
+        TestRequest {
+            version: PROTOCOL_VERSION,
+            direction: Direction::Download,
+            chunk_count: DEFAULT_CHUNK_COUNT,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            burst: None,
+            dscp: None,
+            ttl: None,
+            checksum: false,
+            payload_seed: None,
+            file: None,
+            first_sequence: 0,
+            relay: None,
+            heartbeat: None,
+            shape: None,
+        }
+    }
+}
+
+impl fmt::Display for TestRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", REQUEST_KEYWORD)?;
+        // Relayed requests of unversioned clients stay unversioned
+        if self.version > 1 {
+            write!(f, " version={}", self.version)?;
+        }
+        write!(f, " chunks={} chunk_size={}", self.chunk_count, self.chunk_size)?;
+        if self.direction != Direction::Download {
+            write!(f, " direction={}", self.direction)?;
+        }
+        if let Some(burst) = &self.burst {
+            write!(f, " burst_on_ms={} burst_off_ms={}", burst.on.as_millis(), burst.off.as_millis())?;
+        }
+        if let Some(dscp) = self.dscp {
+            write!(f, " dscp={}", dscp)?;
+        }
+        if let Some(ttl) = self.ttl {
+            write!(f, " ttl={}", ttl)?;
+        }
+        if self.checksum {
+            write!(f, " checksum=1")?;
+        }
+        if let Some(seed) = self.payload_seed {
+            write!(f, " seed={}", seed)?;
+        }
+        if let Some(file) = &self.file {
+            write!(f, " file={}", file)?;
+        }
+        if self.first_sequence != 0 {
+            write!(f, " first_sequence={}", self.first_sequence)?;
+        }
+        if let Some(relay) = &self.relay {
+            write!(f, " relay={}", relay)?;
+        }
+        if let Some(heartbeat) = self.heartbeat {
+            write!(f, " heartbeat_ms={}", heartbeat.as_millis())?;
+        }
+        if let Some(shape) = &self.shape {
+            write!(f, " shape_rate_bps={} shape_burst={}", shape.rate_bps, shape.burst_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl TestRequest {
+    /// Sends the request line to the server.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(format!("{}\n", self).as_bytes())
+    }
+
+    /// Reads and parses a request line sent by the client.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Self::parse(&read_line(reader)?)
+    }
+
+    /// Returns the protocol version a request line asks for, even if the rest of it does not parse,
+    /// so that a rejection can still be answered in a way the client understands.
+    pub fn version_of(line: &str) -> u32 {
+        line.split_whitespace()
+            .find_map(|word| word.strip_prefix("version="))
+            .and_then(|version| version.parse().ok())
+            .unwrap_or(1)
+    }
+
+    /// Parses a request line without the trailing newline.
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let mut words = line.split_whitespace();
+        if words.next() != Some(REQUEST_KEYWORD) {
+            return Err(invalid_data("Not a bandwidth test request"));
+        }
+
+        // Requests without a version come from clients that predate versioning
+        let mut request = TestRequest { version: 1, ..TestRequest::default() };
+        let mut burst_on_ms = None;
+        let mut burst_off_ms = None;
+        let mut shape_rate_bps = None;
+        let mut shape_burst = None;
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| invalid_data(format!("Malformed request field '{}'", word)))?;
+            match key {
+                "version" => request.version = parse_value(key, value)?,
+                "direction" => request.direction = value.parse()?,
+                "chunks" => request.chunk_count = parse_value(key, value)?,
+                "chunk_size" => request.chunk_size = parse_value(key, value)?,
+                "burst_on_ms" => burst_on_ms = Some(parse_value(key, value)?),
+                "burst_off_ms" => burst_off_ms = Some(parse_value(key, value)?),
+                "dscp" => request.dscp = Some(parse_value(key, value)?),
+                "ttl" => request.ttl = Some(parse_value(key, value)?),
+                "checksum" => request.checksum = parse_value::<u8>(key, value)? != 0,
+                "seed" => request.payload_seed = Some(parse_value(key, value)?),
+                "file" => request.file = Some(value.to_string()),
+                "first_sequence" => request.first_sequence = parse_value(key, value)?,
+                "relay" => request.relay = Some(value.to_string()),
+                "heartbeat_ms" => match parse_value(key, value)? {
+                    0 => return Err(invalid_data("heartbeat_ms must be at least 1")),
+                    ms => request.heartbeat = Some(Duration::from_millis(ms)),
+                },
+                "shape_rate_bps" => shape_rate_bps = Some(parse_value(key, value)?),
+                "shape_burst" => shape_burst = Some(parse_value(key, value)?),
+                _ => return Err(invalid_data(format!("Unknown request field '{}'", key))),
+            }
+        }
+
+        request.burst = match (burst_on_ms, burst_off_ms) {
+            (Some(on), Some(off)) => Some(BurstPattern { on: Duration::from_millis(on), off: Duration::from_millis(off) }),
+            (None, None) => None,
+            _ => return Err(invalid_data("burst_on_ms and burst_off_ms must be given together")),
+        };
+        request.shape = match (shape_rate_bps, shape_burst) {
+            (Some(0), _) | (_, Some(0)) => return Err(invalid_data("shape_rate_bps and shape_burst must be at least 1")),
+            (Some(rate_bps), Some(burst_bytes)) => Some(TokenBucket { rate_bps, burst_bytes }),
+            (None, None) => None,
+            _ => return Err(invalid_data("shape_rate_bps and shape_burst must be given together")),
+        };
+
+        Ok(request)
+    }
+}
+
+/// The server's answer to a request of protocol version 2 or later, a single text line sent before
+/// any test data.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    /// The test starts, spoken at `version`.
+    Accepted { version: u32 },
+    /// The server will not run the test, for the given reason.
+    Rejected { reason: String },
+    /// The client's address has used up its daily byte quota on the server, or has too little of
+    /// it left for the test.
+    QuotaExceeded {
+        /// Bytes a client address may transfer per day.
+        quota: u64,
+        /// Bytes of the quota still left today.
+        remaining: u64,
+        /// Time until the quota resets, at midnight UTC.
+        reset_in: Duration,
+    },
+}
+
+impl Reply {
+    /// Builds the reply to a request from a client speaking `client_version`.
+    pub fn negotiate(client_version: u32) -> Self {
+        if client_version < MIN_PROTOCOL_VERSION {
+            return Reply::Rejected {
+                reason: format!(
+                    "protocol version {} is no longer supported; this server speaks versions {} to {}",
+                    client_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+                ),
+            };
+        }
+        Reply::Accepted { version: client_version.min(PROTOCOL_VERSION) }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let line = match self {
+            Reply::Accepted { version } => format!("{} version={}\n", ACCEPTED_KEYWORD, version),
+            // The reason must stay on one line
+            Reply::Rejected { reason } => format!("{} {}\n", REJECTED_KEYWORD, reason.replace('\n', " ")),
+            Reply::QuotaExceeded { quota, remaining, reset_in } => {
+                format!("{} quota={} remaining={} reset_in_s={}\n", QUOTA_EXCEEDED_KEYWORD, quota, remaining, reset_in.as_secs())
+            }
+        };
+        writer.write_all(line.as_bytes())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let line = read_line(reader)?;
+        let (keyword, rest) = line.split_once(' ').unwrap_or((&line, ""));
+        match keyword {
+            ACCEPTED_KEYWORD => match rest.strip_prefix("version=") {
+                Some(version) => Ok(Reply::Accepted { version: parse_value("version", version)? }),
+                None => Err(invalid_data(format!("Malformed reply '{}'", line))),
+            },
+            REJECTED_KEYWORD => Ok(Reply::Rejected { reason: rest.to_string() }),
+            QUOTA_EXCEEDED_KEYWORD => {
+                let (mut quota, mut remaining, mut reset_in) = (None, None, None);
+                for word in rest.split_whitespace() {
+                    match word.split_once('=') {
+                        Some(("quota", value)) => quota = Some(parse_value("quota", value)?),
+                        Some(("remaining", value)) => remaining = Some(parse_value("remaining", value)?),
+                        Some(("reset_in_s", value)) => reset_in = Some(Duration::from_secs(parse_value("reset_in_s", value)?)),
+                        // Fields of later versions
+                        _ => {}
+                    }
+                }
+                match (quota, remaining, reset_in) {
+                    (Some(quota), Some(remaining), Some(reset_in)) => Ok(Reply::QuotaExceeded { quota, remaining, reset_in }),
+                    _ => Err(invalid_data(format!("Malformed reply '{}'", line))),
+                }
+            }
+            _ => Err(invalid_data("Not a bandwidth test reply")),
+        }
+    }
+}
+
+/// Reads a line without its line ending.
+///
+/// The line is read byte by byte so that nothing past the newline is consumed from the stream.
+pub fn read_line<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == b'\n' {
+            break;
+        }
+        if line.len() == MAX_REQUEST_LEN {
+            return Err(invalid_data("Line too long"));
+        }
+        line.push(byte[0]);
+    }
+
+    let line = String::from_utf8(line).map_err(|_| invalid_data("Line is not valid UTF-8"))?;
+    Ok(line.trim_end().to_string())
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| invalid_data(format!("Invalid value '{}' for request field '{}'", value, key)))
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use socket2::{Socket, TcpKeepalive};
+
+/// Largest valid DSCP value (6 bits).
+pub const MAX_DSCP: u8 = 63;
+
+/// Marks outgoing packets of `socket` with the given DSCP code point.
+///
+/// The DSCP occupies the upper six bits of the IPv4 TOS byte and of the IPv6 traffic class.
+///
+/// # Arguments
+/// - `socket`: The socket to mark.
+/// - `dscp`: DSCP value between 0 and 63, e.g. 46 for Expedited Forwarding.
+pub fn set_dscp(socket: &Socket, dscp: u8) -> io::Result<()> {
+    if dscp > MAX_DSCP {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("DSCP value {} is out of range 0-{}", dscp, MAX_DSCP)));
+    }
+    let tos = u32::from(dscp) << 2;
+
+    if is_ipv6(socket)? {
+        set_tclass_v6(socket, tos)
+    } else {
+        socket.set_tos(tos)
+    }
+}
+
+/// Limits how many hops the packets sent on `socket` may travel (IPv4 TTL or IPv6 unicast hop limit).
+///
+/// # Arguments
+/// - `socket`: The socket to configure.
+/// - `ttl`: Maximum number of hops, between 1 and 255.
+pub fn set_ttl(socket: &Socket, ttl: u8) -> io::Result<()> {
+    if ttl == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "TTL must be at least 1"));
+    }
+
+    if is_ipv6(socket)? {
+        socket.set_unicast_hops_v6(u32::from(ttl))
+    } else {
+        socket.set_ttl(u32::from(ttl))
+    }
+}
+
+/// TCP keepalive settings; fields left unset keep the OS defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Keepalive {
+    /// Idle time before the first probe is sent.
+    pub idle: Option<Duration>,
+    /// Time between unanswered probes.
+    pub interval: Option<Duration>,
+    /// Unanswered probes after which the connection is dropped.
+    pub count: Option<u32>,
+}
+
+impl fmt::Display for Keepalive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = |value: Option<Duration>| value.map_or("OS default".to_string(), |value| format!("{}s", value.as_secs()));
+        write!(f, "idle {}, interval {}, ", seconds(self.idle), seconds(self.interval))?;
+        match self.count {
+            Some(count) => write!(f, "{} probes", count),
+            None => write!(f, "OS default probes"),
+        }
+    }
+}
+
+/// Enables TCP keepalive on `socket`, so idle connections survive NAT and firewall idle timeouts
+/// and a vanished peer is detected.
+pub fn set_keepalive(socket: &Socket, keepalive: &Keepalive) -> io::Result<()> {
+    let mut params = TcpKeepalive::new();
+    if let Some(idle) = keepalive.idle {
+        params = params.with_time(idle);
+    }
+    if let Some(interval) = keepalive.interval {
+        params = params.with_interval(interval);
+    }
+    if let Some(count) = keepalive.count {
+        params = with_retries(params, count)?;
+    }
+    socket.set_tcp_keepalive(&params)?;
+    // socket2 sets the idle time and interval through SIO_KEEPALIVE_VALS, which has no probe count
+    #[cfg(windows)]
+    if let Some(count) = keepalive.count {
+        crate::winsock::setsockopt_u32(socket, crate::winsock::IPPROTO_TCP, crate::winsock::TCP_KEEPCNT, count)?;
+    }
+    Ok(())
+}
+
+/// Returns true if `e` is the kernel dropping a connection whose keepalive probes went unanswered.
+///
+/// Only meaningful with keepalive enabled. On Unix, receive and send timeouts are reported as
+/// `WouldBlock`, so a `TimedOut` error comes from the kernel giving up on the connection itself.
+/// Windows reports both as `TimedOut`, but a connection dropped by keepalive fails with
+/// WSAENETRESET (10052) instead.
+pub fn is_keepalive_timeout(e: &io::Error) -> bool {
+    if cfg!(windows) {
+        e.raw_os_error() == Some(10052)
+    } else {
+        cfg!(unix) && e.kind() == io::ErrorKind::TimedOut
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "ios", target_os = "linux", target_os = "macos", target_os = "netbsd"))]
+fn with_retries(params: TcpKeepalive, count: u32) -> io::Result<TcpKeepalive> {
+    Ok(params.with_retries(count))
+}
+
+/// Windows sets the count separately, after the other keepalive parameters, in `set_keepalive`.
+#[cfg(windows)]
+fn with_retries(params: TcpKeepalive, _count: u32) -> io::Result<TcpKeepalive> {
+    Ok(params)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    windows
+)))]
+fn with_retries(_params: TcpKeepalive, _count: u32) -> io::Result<TcpKeepalive> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Setting the keepalive probe count is not supported on this platform"))
+}
+
+/// Lets clients that hold a Fast Open cookie for this server put their first data in the SYN, so a
+/// request is served a round trip earlier (RFC 7413).
+///
+/// # Arguments
+/// - `socket`: The listening socket.
+/// - `queue`: Connections with SYN data that may wait for `accept` at a time; beyond that, SYN data
+///   is ignored and the handshake completes the usual way.
+#[cfg(target_os = "linux")]
+pub fn set_fast_open(socket: &Socket, queue: u32) -> io::Result<()> {
+    setsockopt_int(socket, libc::IPPROTO_TCP, libc::TCP_FASTOPEN, queue as libc::c_int)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_fast_open(_socket: &Socket, _queue: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "TCP Fast Open is only supported on Linux"))
+}
+
+/// Makes `connect` on `socket` wait for the first write and send it in the SYN with the server's
+/// Fast Open cookie. Without a cookie yet, the SYN asks for one and the data follows the handshake.
+#[cfg(target_os = "linux")]
+pub fn set_fast_open_connect(socket: &Socket) -> io::Result<()> {
+    setsockopt_int(socket, libc::IPPROTO_TCP, libc::TCP_FASTOPEN_CONNECT, 1)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_fast_open_connect(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "TCP Fast Open is only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+fn setsockopt_int(socket: &Socket, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+fn is_ipv6(socket: &Socket) -> io::Result<bool> {
+    Ok(socket.local_addr()?.as_socket().is_some_and(|addr| addr.is_ipv6()))
+}
+
+#[cfg(unix)]
+fn set_tclass_v6(socket: &Socket, tclass: u32) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let value = tclass as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(windows)]
+fn set_tclass_v6(socket: &Socket, tclass: u32) -> io::Result<()> {
+    crate::winsock::setsockopt_u32(socket, crate::winsock::IPPROTO_IPV6, crate::winsock::IPV6_TCLASS, tclass)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_tclass_v6(_socket: &Socket, _tclass: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "IPv6 traffic class marking is not supported on this platform"))
+}
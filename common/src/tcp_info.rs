@@ -0,0 +1,360 @@
+use std::fmt;
+use std::io;
+use std::net::TcpStream;
+use serde::{Deserialize, Serialize};
+
+/// Snapshot of the kernel's view of a TCP connection (Linux `TCP_INFO`, Windows `SIO_TCP_INFO`).
+///
+/// Times are in microseconds unless the field name says otherwise. Fields the running kernel does
+/// not report are left at zero.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct TcpInfo {
+    /// Connection state, see `state_name`.
+    pub state: u8,
+    /// Congestion avoidance state (0 = open, 1 = disorder, 2 = CWR, 3 = recovery, 4 = loss).
+    pub ca_state: u8,
+    /// Number of unrecovered RTO timeouts.
+    pub retransmits: u8,
+    /// Unanswered zero-window probes.
+    pub probes: u8,
+    /// Exponential backoff of the retransmission timer.
+    pub backoff: u8,
+    /// Options negotiated on the handshake, a combination of the `OPTION_*` flags.
+    pub options: u8,
+    /// Window scale shift advertised by the peer.
+    pub snd_wscale: u8,
+    /// Window scale shift we advertised.
+    pub rcv_wscale: u8,
+    pub rto: u32,
+    pub snd_mss: u32,
+    pub rcv_mss: u32,
+    /// Segments sent but not yet acknowledged.
+    pub unacked: u32,
+    pub sacked: u32,
+    pub lost: u32,
+    pub retrans: u32,
+    pub last_data_sent_ms: u32,
+    pub last_data_recv_ms: u32,
+    pub last_ack_recv_ms: u32,
+    pub pmtu: u32,
+    /// Smoothed round-trip time.
+    pub rtt: u32,
+    pub rttvar: u32,
+    pub snd_ssthresh: u32,
+    /// Congestion window, in segments.
+    pub snd_cwnd: u32,
+    /// Receiver-side RTT estimate.
+    pub rcv_rtt: u32,
+    /// Receive buffer space the kernel auto-tuned for this connection, in bytes.
+    pub rcv_space: u32,
+    /// Total retransmitted segments over the lifetime of the connection.
+    pub total_retrans: u32,
+    pub pacing_rate: u64,
+    pub bytes_acked: u64,
+    pub bytes_received: u64,
+    /// Bytes written by the application but not yet sent.
+    pub notsent_bytes: u32,
+    pub min_rtt: u32,
+    /// Most recent delivery rate estimate, in bytes per second.
+    pub delivery_rate: u64,
+    /// Time spent with data in flight.
+    pub busy_time: u64,
+    /// Time the sender was limited by the receive window.
+    pub rwnd_limited: u64,
+    /// Time the sender was limited by the send buffer.
+    pub sndbuf_limited: u64,
+    pub bytes_sent: u64,
+    pub bytes_retrans: u64,
+    /// Peer's advertised receive window, in bytes.
+    pub snd_wnd: u32,
+}
+
+impl TcpInfo {
+    /// Timestamps (RFC 7323) were negotiated.
+    pub const OPTION_TIMESTAMPS: u8 = 1;
+    /// Selective acknowledgements were negotiated.
+    pub const OPTION_SACK: u8 = 2;
+    /// Window scaling was negotiated.
+    pub const OPTION_WSCALE: u8 = 4;
+    /// Explicit congestion notification was negotiated.
+    pub const OPTION_ECN: u8 = 8;
+    /// The data in the SYN was acknowledged by the SYN-ACK (TCP Fast Open).
+    pub const OPTION_SYN_DATA: u8 = 32;
+
+    /// Returns the name of the TCP state, as shown by `ss`.
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            1 => "ESTABLISHED",
+            2 => "SYN-SENT",
+            3 => "SYN-RECV",
+            4 => "FIN-WAIT-1",
+            5 => "FIN-WAIT-2",
+            6 => "TIME-WAIT",
+            7 => "CLOSE",
+            8 => "CLOSE-WAIT",
+            9 => "LAST-ACK",
+            10 => "LISTEN",
+            11 => "CLOSING",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for TcpInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "state {}, rtt {:.3}/{:.3} ms, cwnd {}, unacked {}, retransmits {} (total {}), backoff {}, last data received {} ms ago, bytes received {}",
+            self.state_name(),
+            self.rtt as f64 / 1000.0,
+            self.rttvar as f64 / 1000.0,
+            self.snd_cwnd,
+            self.unacked,
+            self.retransmits,
+            self.total_retrans,
+            self.backoff,
+            self.last_data_recv_ms,
+            self.bytes_received
+        )
+    }
+}
+
+/// Kernel `struct tcp_info` layout (include/uapi/linux/tcp.h), up to `tcpi_snd_wnd`.
+///
+/// Older kernels fill in a prefix of the structure; the rest stays zeroed.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct RawTcpInfo {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    snd_rcv_wscale: u8,
+    app_limited_fastopen: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32,
+    pacing_rate: u64,
+    max_pacing_rate: u64,
+    bytes_acked: u64,
+    bytes_received: u64,
+    segs_out: u32,
+    segs_in: u32,
+    notsent_bytes: u32,
+    min_rtt: u32,
+    data_segs_in: u32,
+    data_segs_out: u32,
+    delivery_rate: u64,
+    busy_time: u64,
+    rwnd_limited: u64,
+    sndbuf_limited: u64,
+    delivered: u32,
+    delivered_ce: u32,
+    bytes_sent: u64,
+    bytes_retrans: u64,
+    dsack_dups: u32,
+    reord_seen: u32,
+    rcv_ooopack: u32,
+    snd_wnd: u32,
+}
+
+/// Reads the kernel's TCP state for `stream`.
+///
+/// # Returns
+/// - The snapshot, or an `Unsupported` error on platforms other than Linux and Windows.
+#[cfg(target_os = "linux")]
+pub fn tcp_info(stream: &TcpStream) -> io::Result<TcpInfo> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut raw = RawTcpInfo::default();
+    let mut len = std::mem::size_of::<RawTcpInfo>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut raw as *mut RawTcpInfo as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(TcpInfo::from(raw))
+}
+
+/// Decodes a `struct tcp_info` the kernel reported some other way, e.g. in a sock_diag reply.
+///
+/// A shorter structure, from an older kernel, leaves the fields it does not have at zero.
+#[cfg(target_os = "linux")]
+pub fn from_bytes(bytes: &[u8]) -> TcpInfo {
+    let mut raw = RawTcpInfo::default();
+    let len = bytes.len().min(std::mem::size_of::<RawTcpInfo>());
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), &mut raw as *mut RawTcpInfo as *mut u8, len) };
+    TcpInfo::from(raw)
+}
+
+#[cfg(target_os = "linux")]
+impl From<RawTcpInfo> for TcpInfo {
+    fn from(raw: RawTcpInfo) -> Self {
+        TcpInfo {
+            state: raw.state,
+            ca_state: raw.ca_state,
+            retransmits: raw.retransmits,
+            probes: raw.probes,
+            backoff: raw.backoff,
+            options: raw.options,
+            snd_wscale: raw.snd_rcv_wscale & 0x0f,
+            rcv_wscale: raw.snd_rcv_wscale >> 4,
+            rto: raw.rto,
+            snd_mss: raw.snd_mss,
+            rcv_mss: raw.rcv_mss,
+            unacked: raw.unacked,
+            sacked: raw.sacked,
+            lost: raw.lost,
+            retrans: raw.retrans,
+            last_data_sent_ms: raw.last_data_sent,
+            last_data_recv_ms: raw.last_data_recv,
+            last_ack_recv_ms: raw.last_ack_recv,
+            pmtu: raw.pmtu,
+            rtt: raw.rtt,
+            rttvar: raw.rttvar,
+            snd_ssthresh: raw.snd_ssthresh,
+            snd_cwnd: raw.snd_cwnd,
+            rcv_rtt: raw.rcv_rtt,
+            rcv_space: raw.rcv_space,
+            total_retrans: raw.total_retrans,
+            pacing_rate: raw.pacing_rate,
+            bytes_acked: raw.bytes_acked,
+            bytes_received: raw.bytes_received,
+            notsent_bytes: raw.notsent_bytes,
+            min_rtt: raw.min_rtt,
+            delivery_rate: raw.delivery_rate,
+            busy_time: raw.busy_time,
+            rwnd_limited: raw.rwnd_limited,
+            sndbuf_limited: raw.sndbuf_limited,
+            bytes_sent: raw.bytes_sent,
+            bytes_retrans: raw.bytes_retrans,
+            snd_wnd: raw.snd_wnd,
+        }
+    }
+}
+
+/// `TCP_INFO_v0` (mstcpip.h), returned by the `SIO_TCP_INFO` ioctl.
+#[cfg(windows)]
+#[repr(C)]
+#[derive(Default)]
+struct RawTcpInfoV0 {
+    state: u32,
+    mss: u32,
+    connection_time_ms: u64,
+    timestamps_enabled: u8,
+    rtt_us: u32,
+    min_rtt_us: u32,
+    bytes_in_flight: u32,
+    cwnd: u32,
+    snd_wnd: u32,
+    rcv_wnd: u32,
+    rcv_buf: u32,
+    bytes_out: u64,
+    bytes_in: u64,
+    bytes_reordered: u32,
+    bytes_retrans: u32,
+    fast_retrans: u32,
+    dup_acks_in: u32,
+    timeout_episodes: u32,
+    syn_retrans: u8,
+}
+
+/// Reads the TCP state for `stream` with `SIO_TCP_INFO` (Windows 10 1703 and later), adding the
+/// extended statistics of `GetPerTcpConnectionEStats` when they can be collected.
+///
+/// Windows reports fewer fields than Linux: the congestion window, slow start threshold, and bytes
+/// in flight are converted to segments, the receive buffer stands in for `rcv_space`, and the
+/// window scale shifts are not reported at all. Collecting the extended statistics takes
+/// administrator rights; without them the slow start threshold, RTT variance, RTO, and the
+/// limited times stay at zero, and retransmissions are counted from the fast retransmits and
+/// timeouts instead.
+#[cfg(windows)]
+pub fn tcp_info(stream: &TcpStream) -> io::Result<TcpInfo> {
+    use std::convert::TryFrom;
+    use crate::{estats, winsock};
+
+    let version = 0u32;
+    let mut raw = RawTcpInfoV0::default();
+    winsock::ioctl(stream, winsock::SIO_TCP_INFO, &version, &mut raw)?;
+
+    // TCPSTATE_CLOSED (0) to TCPSTATE_TIME_WAIT (10), renumbered as Linux numbers them
+    let state = match raw.state {
+        1 => 10,
+        2 => 2,
+        3 => 3,
+        4 => 1,
+        5 => 4,
+        6 => 5,
+        7 => 8,
+        8 => 11,
+        9 => 9,
+        10 => 6,
+        _ => 7,
+    };
+    let segments = |bytes: u32| bytes.checked_div(raw.mss).unwrap_or_default();
+    let mut info = TcpInfo {
+        state,
+        options: if raw.timestamps_enabled != 0 { TcpInfo::OPTION_TIMESTAMPS } else { 0 },
+        snd_mss: raw.mss,
+        rcv_mss: raw.mss,
+        unacked: segments(raw.bytes_in_flight),
+        rtt: raw.rtt_us,
+        snd_cwnd: segments(raw.cwnd),
+        rcv_space: raw.rcv_buf,
+        total_retrans: raw.fast_retrans + raw.timeout_episodes,
+        bytes_acked: raw.bytes_out.saturating_sub(u64::from(raw.bytes_in_flight)),
+        bytes_received: raw.bytes_in,
+        min_rtt: raw.min_rtt_us,
+        bytes_sent: raw.bytes_out,
+        bytes_retrans: u64::from(raw.bytes_retrans),
+        snd_wnd: raw.snd_wnd,
+        ..TcpInfo::default()
+    };
+    if let Ok(extended) = estats::estats(stream.local_addr()?, stream.peer_addr()?) {
+        info.snd_ssthresh = segments(extended.ssthresh);
+        info.rttvar = extended.rttvar;
+        info.rto = extended.rto;
+        info.retransmits = u8::try_from(extended.timeouts).unwrap_or(u8::MAX);
+        info.total_retrans = extended.retransmitted;
+        info.busy_time = extended.rwnd_limited + extended.cwnd_limited + extended.sndbuf_limited;
+        info.rwnd_limited = extended.rwnd_limited;
+        info.sndbuf_limited = extended.sndbuf_limited;
+    }
+    Ok(info)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn tcp_info(_stream: &TcpStream) -> io::Result<TcpInfo> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "TCP_INFO is only available on Linux and Windows"))
+}
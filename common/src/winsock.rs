@@ -0,0 +1,62 @@
+//! The few Winsock calls the socket options need that socket2 does not wrap.
+
+use std::io;
+use std::os::windows::io::AsRawSocket;
+
+pub const IPPROTO_TCP: i32 = 6;
+pub const IPPROTO_IPV6: i32 = 41;
+/// IPv6 traffic class (ws2ipdef.h).
+pub const IPV6_TCLASS: i32 = 39;
+/// Keepalive probe count (mstcpip.h), Windows 10 1703 and later.
+pub const TCP_KEEPCNT: i32 = 16;
+/// `_WSAIORW(IOC_VENDOR, 39)`: reads `TCP_INFO_v0` for a connected socket, Windows 10 1703 and later.
+pub const SIO_TCP_INFO: u32 = 0xD800_0027;
+
+#[link(name = "ws2_32")]
+extern "system" {
+    #[link_name = "setsockopt"]
+    fn ws_setsockopt(socket: usize, level: i32, name: i32, value: *const u8, len: i32) -> i32;
+    #[link_name = "WSAIoctl"]
+    fn ws_ioctl(
+        socket: usize,
+        code: u32,
+        input: *const u8,
+        input_len: u32,
+        output: *mut u8,
+        output_len: u32,
+        returned: *mut u32,
+        overlapped: *mut u8,
+        completion: *mut u8,
+    ) -> i32;
+}
+
+/// Sets an integer socket option.
+pub fn setsockopt_u32<S: AsRawSocket>(socket: &S, level: i32, name: i32, value: u32) -> io::Result<()> {
+    let result = unsafe {
+        ws_setsockopt(socket.as_raw_socket() as usize, level, name, &value as *const u32 as *const u8, std::mem::size_of::<u32>() as i32)
+    };
+    // Winsock reports its error codes through GetLastError as well
+    if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+/// Issues a synchronous `WSAIoctl` with `input`, filling `output`.
+///
+/// # Returns
+/// - The number of bytes written to `output`.
+pub fn ioctl<S: AsRawSocket, I, O>(socket: &S, code: u32, input: &I, output: &mut O) -> io::Result<usize> {
+    let mut returned = 0u32;
+    let result = unsafe {
+        ws_ioctl(
+            socket.as_raw_socket() as usize,
+            code,
+            input as *const I as *const u8,
+            std::mem::size_of::<I>() as u32,
+            output as *mut O as *mut u8,
+            std::mem::size_of::<O>() as u32,
+            &mut returned,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if result == 0 { Ok(returned as usize) } else { Err(io::Error::last_os_error()) }
+}
@@ -1,42 +1,1414 @@
-use std::io::{Write, Read};
-use std::net::{TcpListener, TcpStream};
-use std::thread;
-use socket2::{Socket, Domain, Type, Protocol};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::io::ErrorKind;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use clap::Parser;
+use common::buffer::AlignedBuffer;
+use common::echo;
+use common::frame::{self, ChunkHeader, EndOfTest, Heartbeat};
+use common::heartbeat;
+use common::mdns;
+use common::mmsg::{self, Batch};
+use common::netif;
+use common::numa;
+use common::payload;
+use common::peer;
+use common::protocol::{self, Direction, Reply, TestRequest, TokenBucket, QUOTA_VERSION, REPLY_VERSION};
+use common::sockopt::{self, Keepalive};
+use common::tcp_info;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 
-fn handle_client(mut stream: TcpStream) {
+mod sessions;
+
+/// Largest chunk size a client may request, to bound the server's memory use per connection.
+const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+/// Fast Open connections that may wait for `accept` at a time.
+const FAST_OPEN_QUEUE: u32 = 256;
+
+/// Bandwidth test server: streams fixed-size chunks of data to a connecting client.
+#[derive(Parser)]
+struct Args {
+    /// Back the send buffer with hugepages where the OS supports it.
+    #[arg(long)]
+    hugepages: bool,
+
+    /// Serve each client on the NUMA node of the NIC it arrives through (Linux): the serving
+    /// thread is pinned to the node's CPUs and its buffers are allocated from the node's memory.
+    #[arg(long)]
+    numa: bool,
+
+    /// Exit after handling a single client instead of serving clients one after another.
+    #[arg(long)]
+    once: bool,
+
+    /// Address and port to accept test connections on; repeat to listen on several, e.g. an extra
+    /// port that passes restrictive firewalls or an IPv6 address.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:7878")]
+    listen: Vec<SocketAddr>,
+
+    /// Accept on this many threads per listening address, each with its own socket sharing the
+    /// port via SO_REUSEPORT (Unix), so the kernel spreads clients across cores. Each thread serves
+    /// its clients one at a time, so up to this many tests run at once.
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "once")]
+    acceptors: u32,
+
+    /// Advertise the server on the local network via mDNS/DNS-SD as a _bwtest._tcp service, so
+    /// clients can find it with --discover.
+    #[arg(long)]
+    advertise: bool,
+
+    /// Relay tests to this server on request, so clients can measure the path through this host;
+    /// repeat to allow several. The target must be given as clients name it in their --server, and
+    /// any other target is refused, so the server is no open proxy.
+    #[arg(long, value_name = "HOST:PORT")]
+    allow_relay: Vec<String>,
+
+    /// Enable TCP keepalive on client connections, so idle sessions survive NAT and firewall idle
+    /// timeouts and vanished clients are detected.
+    #[arg(long)]
+    keepalive: bool,
+
+    /// Idle time before the first keepalive probe (OS default if not given).
+    #[arg(long, value_name = "SECONDS", requires = "keepalive")]
+    keepalive_idle: Option<u64>,
+
+    /// Time between unanswered keepalive probes (OS default if not given).
+    #[arg(long, value_name = "SECONDS", requires = "keepalive")]
+    keepalive_interval: Option<u64>,
+
+    /// Unanswered keepalive probes before the connection is dropped (OS default if not given).
+    #[arg(long, value_name = "N", requires = "keepalive")]
+    keepalive_count: Option<u32>,
+
+    /// Send buffer size of each test connection (SO_SNDBUF), which bounds the server's send window.
+    #[arg(long, value_name = "BYTES", default_value_t = 1_000_000)]
+    send_buffer: usize,
+
+    /// Disable Nagle's algorithm (TCP_NODELAY) on test connections, so small writes such as upload
+    /// acknowledgements go out immediately.
+    #[arg(long)]
+    nodelay: bool,
+
+    /// Accept TCP Fast Open (Linux), so clients holding a cookie from an earlier connection send
+    /// their test request in the SYN (client --fast-open).
+    #[arg(long)]
+    fast_open: bool,
+
+    /// Directory clients may request files from for a real file transfer (--file on the client);
+    /// file requests are refused without it.
+    #[arg(long, value_name = "DIR")]
+    file_root: Option<PathBuf>,
+
+    /// Abort a transfer if the client accepts no data for this many seconds (0 waits forever).
+    #[arg(long, visible_alias = "io-timeout", value_name = "SECONDS", default_value_t = 30.0, value_parser = seconds)]
+    stall_timeout: f64,
+
+    /// Also echo UDP latency probes on this address and port (client --udp-echo), and serve the
+    /// packet trains of client --dispersion, independently of the test connections, so clients can
+    /// probe while a test runs.
+    #[arg(long, value_name = "ADDR")]
+    udp_echo: Option<SocketAddr>,
+
+    /// Introduce clients behind NATs to each other on this UDP address and port (client --peer), so
+    /// two sites can test the path between them directly. Any client that knows a session name can
+    /// join that session.
+    #[arg(long, value_name = "ADDR")]
+    rendezvous: Option<SocketAddr>,
+
+    /// Let every client address transfer at most this many bytes per day (UTC), in both directions
+    /// together, so a public server cannot be used as a free traffic generator. Tests the rest of
+    /// the quota cannot cover are refused; open-ended ones end where the quota does. IPv6 clients
+    /// are counted per /64.
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    daily_quota: Option<u64>,
+
+    /// Bytes each client address has transferred today, against --daily-quota.
+    #[arg(skip)]
+    quotas: Quotas,
+
+    /// Append every test the server serves to FILE, one JSON record per line: the client's
+    /// address, start, direction, bytes, duration, and data rate as the server saw it.
+    #[arg(long, value_name = "FILE")]
+    sessions: Option<PathBuf>,
+
+    /// Serve the recorded sessions and statistics across all clients (data rate distribution,
+    /// per-subnet medians, busiest hours) as JSON over HTTP on this address.
+    #[arg(long, value_name = "HOST:PORT", requires = "sessions")]
+    api: Option<SocketAddr>,
+
+    /// The opened --sessions file.
+    #[arg(skip)]
+    store: Option<Arc<sessions::Store>>,
+}
+
+/// Parses a time in seconds from the command line, which must be one a `Duration` can hold: finite
+/// and not negative.
+fn seconds(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value.parse().map_err(|_| format!("'{}' is not a number of seconds", value))?;
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(_) => Ok(seconds),
+        Err(_) => Err(format!("'{}' is out of range; seconds must be finite and not negative", value)),
+    }
+}
+
+fn handle_client(mut stream: TcpStream, args: &Args) {
+    let node = if args.numa { place_on_numa_node(&stream) } else { None };
     // Use socket2 to set the buffer size for the TCP socket
     let socket = Socket::from(stream.try_clone().expect("Failed to clone TcpStream"));
-    let buffer_size = 1_000_000; // 1 MB buffer size for TCP window
-    socket.set_send_buffer_size(buffer_size).expect("Failed to set send buffer size");
+    if let Err(e) = socket.set_send_buffer_size(args.send_buffer) {
+        eprintln!("Failed to set the send buffer to {} bytes: {}", args.send_buffer, e);
+        return;
+    }
+    if let Err(e) = socket.set_nodelay(args.nodelay) {
+        eprintln!("Failed to set TCP_NODELAY: {}", e);
+        return;
+    }
+    if args.keepalive {
+        let keepalive = Keepalive {
+            idle: args.keepalive_idle.map(Duration::from_secs),
+            interval: args.keepalive_interval.map(Duration::from_secs),
+            count: args.keepalive_count,
+        };
+        if let Err(e) = sockopt::set_keepalive(&socket, &keepalive) {
+            eprintln!("Failed to enable keepalive ({}): {}", keepalive, e);
+            return;
+        }
+    }
+
+    let stall_timeout = if args.stall_timeout > 0.0 { Some(Duration::from_secs_f64(args.stall_timeout)) } else { None };
+    // The same timeout bounds how long we wait for the request, so a silent client cannot hold the server
+    if let Err(e) = stream.set_read_timeout(stall_timeout).and_then(|_| stream.set_write_timeout(stall_timeout)) {
+        eprintln!("Failed to set socket timeouts: {}", e);
+        return;
+    }
+
+    let line = match protocol::read_line(&mut stream) {
+        Ok(line) => line,
+        // Clients probing how quickly the server can be reached close the connection right away
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+            println!("Client disconnected without a test request");
+            return;
+        }
+        Err(e) => {
+            eprintln!("Invalid test request: {}", e);
+            return;
+        }
+    };
+    let request = match TestRequest::parse(&line) {
+        Ok(request) => request,
+        Err(e) => {
+            reject(&mut stream, TestRequest::version_of(&line), format!("invalid test request: {}", e));
+            return;
+        }
+    };
+    if request.chunk_size == 0 || request.chunk_size > MAX_CHUNK_SIZE {
+        reject(&mut stream, request.version, format!("chunk size of {} bytes is not between 1 and {}", request.chunk_size, MAX_CHUNK_SIZE));
+        return;
+    }
+    if request.shape.is_some() && request.direction == Direction::Upload {
+        reject(&mut stream, request.version, "shaping applies to the data the server sends, and an upload has none".to_string());
+        return;
+    }
+    println!("Client requested: {}", request);
+
+    if let Some(target) = request.relay.clone() {
+        if !args.allow_relay.iter().any(|allowed| allowed.eq_ignore_ascii_case(&target)) {
+            reject(&mut stream, request.version, format!("relaying to {} is not allowed on this server (--allow-relay)", target));
+            return;
+        }
+        let reservation = match reserve(&mut stream, &request, None, args) {
+            Some(reservation) => reservation,
+            None => return,
+        };
+        // The target answers the client through the relay
+        relay(stream, TestRequest { relay: None, ..request }, &target, stall_timeout, reservation.bytes);
+        return;
+    }
+
+    // From here on the request is served at the version both sides speak
+    let request = match Reply::negotiate(request.version) {
+        Reply::Accepted { version } => TestRequest { version, ..request },
+        Reply::Rejected { reason } => {
+            reject(&mut stream, request.version, reason);
+            return;
+        }
+        Reply::QuotaExceeded { .. } => unreachable!("negotiation does not look at quotas"),
+    };
+    if let Some(dscp) = request.dscp {
+        if let Err(e) = sockopt::set_dscp(&socket, dscp) {
+            reject(&mut stream, request.version, format!("failed to set DSCP {}: {}", dscp, e));
+            return;
+        }
+    }
+    if let Some(ttl) = request.ttl {
+        if let Err(e) = sockopt::set_ttl(&socket, ttl) {
+            reject(&mut stream, request.version, format!("failed to set TTL {}: {}", ttl, e));
+            return;
+        }
+    }
+
+    // Known only while the client is connected
+    let client = stream.peer_addr().ok().map(|peer| peer.ip().to_canonical());
+    let started_at = SystemTime::now();
+    let start = Instant::now();
+    let outcome = match (&request.file, &args.file_root, request.direction) {
+        (Some(name), Some(root), Direction::Download) => send_file(&mut stream, &request, args, node, root, name),
+        (Some(name), Some(_), Direction::Upload) => {
+            reject(&mut stream, request.version, format!("file {} requested for upload; files can only be downloaded", name));
+            Outcome::Refused
+        }
+        (Some(name), None, _) => {
+            reject(&mut stream, request.version, format!("file {} requested, but file transfers are not enabled on this server (--file-root)", name));
+            Outcome::Refused
+        }
+        (None, _, _) => serve_chunks(&mut stream, request.clone(), args, node),
+    };
+    if let Some(store) = &args.store {
+        record(store, client, &request, started_at, start.elapsed(), outcome);
+    }
+}
+
+/// Serves a test of synthetic chunks, within what is left of the client's daily quota.
+fn serve_chunks(stream: &mut TcpStream, request: TestRequest, args: &Args, node: Option<u32>) -> Outcome {
+    let reservation = match reserve(stream, &request, None, args) {
+        Some(reservation) => reservation,
+        None => return Outcome::Refused,
+    };
+    // An open-ended test ends where the quota does
+    let request = match reservation.account {
+        Some(_) if request.chunk_count == 0 => TestRequest { chunk_count: (reservation.bytes / request.chunk_size as u64) as usize, ..request },
+        _ => request,
+    };
+    if !accept(stream, &request) {
+        return Outcome::Aborted(0);
+    }
+    match request.direction {
+        Direction::Download => send_chunks(stream, &request, args, node),
+        Direction::Upload => receive_chunks(stream, &request, args, node),
+    }
+}
+
+/// How a test session ended, with the payload bytes it transferred.
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    /// The test was refused before any data was transferred.
+    Refused,
+    Completed(u64),
+    /// The transfer broke off, or stalled, before its end.
+    Aborted(u64),
+}
+
+/// Appends a session the server served to the sessions file; refused tests are not recorded.
+fn record(store: &sessions::Store, client: Option<IpAddr>, request: &TestRequest, started_at: SystemTime, duration: Duration, outcome: Outcome) {
+    let (bytes, completed) = match outcome {
+        Outcome::Refused => return,
+        Outcome::Completed(bytes) => (bytes, true),
+        Outcome::Aborted(bytes) => (bytes, false),
+    };
+    let client = match client {
+        Some(client) => client,
+        None => {
+            eprintln!("Failed to record the session: the client's address is unknown");
+            return;
+        }
+    };
+    let session = sessions::Session {
+        client,
+        started_at: humantime::format_rfc3339_millis(started_at).to_string(),
+        direction: request.direction,
+        bytes,
+        duration_seconds: duration.as_secs_f64(),
+        throughput_bps: if duration.is_zero() { 0.0 } else { bytes as f64 * 8.0 / duration.as_secs_f64() },
+        completed,
+    };
+    if let Err(e) = store.append(&session) {
+        eprintln!("Failed to record the session: {}", e);
+    }
+}
+
+/// Refuses a test, telling the client why if its protocol version has a reply.
+fn reject(stream: &mut TcpStream, version: u32, reason: String) {
+    eprintln!("Rejecting test: {}", reason);
+    if version >= REPLY_VERSION {
+        let _ = Reply::Rejected { reason }.write_to(stream);
+    }
+}
+
+/// Bytes each client address has transferred on the current day (UTC), against the daily quota.
+#[derive(Default)]
+struct Quotas {
+    usage: Mutex<HashMap<IpAddr, Usage>>,
+}
+
+struct Usage {
+    /// Days since the Unix epoch.
+    day: u64,
+    bytes: u64,
+}
+
+impl Quotas {
+    /// Sets aside `bytes` of `client`'s quota for a session, or, if `open_ended`, all that is left
+    /// of it as long as that is at least `bytes`.
+    ///
+    /// # Returns
+    /// - The bytes set aside, or the quota-exceeded reply if too little of the quota is left.
+    fn reserve(&self, client: IpAddr, bytes: u64, open_ended: bool, quota: u64) -> Result<u64, Reply> {
+        let (day, reset_in) = today();
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+        // Yesterday's usage goes with the first session of a new day
+        usage.retain(|_, usage| usage.day == day);
+        let used = usage.entry(client).or_insert(Usage { day, bytes: 0 });
+        let remaining = quota.saturating_sub(used.bytes);
+        if remaining == 0 || bytes > remaining {
+            return Err(Reply::QuotaExceeded { quota, remaining, reset_in });
+        }
+        let reserved = if open_ended { remaining } else { bytes };
+        used.bytes += reserved;
+        Ok(reserved)
+    }
+
+    /// Charges `client` for what a session actually transferred instead of what was set aside for
+    /// it. Sessions that span midnight are charged to the day they started.
+    fn settle(&self, client: IpAddr, reserved: u64, transferred: u64) {
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(used) = usage.get_mut(&client) {
+            used.bytes = used.bytes.saturating_sub(reserved) + transferred;
+        }
+    }
+}
+
+/// Returns the current day in days since the Unix epoch, and the time until the next one starts.
+fn today() -> (u64, Duration) {
+    const DAY: u64 = 24 * 60 * 60;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    (now / DAY, Duration::from_secs(DAY - now % DAY))
+}
+
+/// The address a client is charged under: its IPv4 address, or the /64 of its IPv6 address, as
+/// one subscriber is usually handed a whole /64.
+fn quota_account(address: IpAddr) -> IpAddr {
+    match address.to_canonical() {
+        IpAddr::V6(address) => IpAddr::V6(Ipv6Addr::from(u128::from(address) & !u128::from(u64::MAX))),
+        address => address,
+    }
+}
+
+/// Part of a client's daily quota set aside for one session. Dropping it at the end of the session
+/// charges the client for what the session actually transferred.
+struct Reservation<'a> {
+    /// Bytes the session may transfer; unlimited without a quota.
+    bytes: u64,
+    /// The quotas, the address charged, and the client's connection, whose kernel byte counters
+    /// tell what the session transferred.
+    account: Option<(&'a Quotas, IpAddr, TcpStream)>,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some((quotas, client, stream)) = &self.account {
+            // Without the kernel's counters the whole reservation counts as used
+            if let Some((sent, received)) = transferred(stream) {
+                quotas.settle(*client, self.bytes, sent + received);
+            }
+        }
+    }
+}
+
+/// Sets aside the client's daily quota for the session of `request`, and refuses the test if too
+/// little of it is left.
+///
+/// # Arguments
+/// - `file_len`: Bytes left to send of a requested file, once known. Relayed file transfers and
+///   open-ended tests may use the rest of the quota, as long as it covers one chunk.
+///
+/// # Returns
+/// - The reservation to hold until the session ends, or `None` if the test was refused.
+fn reserve<'a>(stream: &mut TcpStream, request: &TestRequest, file_len: Option<u64>, args: &'a Args) -> Option<Reservation<'a>> {
+    let (quota, client) = match (args.daily_quota, stream.peer_addr()) {
+        (Some(quota), Ok(peer)) => (quota, quota_account(peer.ip())),
+        (Some(_), Err(e)) => {
+            reject(stream, request.version, format!("the client's address, which the daily quota is kept by, is unknown: {}", e));
+            return None;
+        }
+        (None, _) => return Some(Reservation { bytes: u64::MAX, account: None }),
+    };
+    let chunk_size = request.chunk_size as u64;
+    let (bytes, open_ended) = match file_len {
+        Some(len) => (len, false),
+        None if request.file.is_some() || request.chunk_count == 0 => (chunk_size, true),
+        None => ((request.chunk_count as u64).saturating_mul(chunk_size), false),
+    };
+    let account = match stream.try_clone() {
+        Ok(account) => account,
+        Err(e) => {
+            reject(stream, request.version, format!("failed to clone the connection to keep the daily quota: {}", e));
+            return None;
+        }
+    };
+    match args.quotas.reserve(client, bytes, open_ended, quota) {
+        Ok(reserved) => Some(Reservation { bytes: reserved, account: Some((&args.quotas, client, account)) }),
+        Err(reply) => {
+            let reason = format!("the daily quota of {} bytes of {} cannot cover the test", quota, client);
+            if request.version >= QUOTA_VERSION {
+                eprintln!("Rejecting test: {}", reason);
+                let _ = reply.write_to(stream);
+            } else {
+                reject(stream, request.version, reason);
+            }
+            None
+        }
+    }
+}
+
+/// Tells the client that the test starts, if its protocol version has a reply.
+///
+/// # Returns
+/// - False if the reply could not be sent.
+fn accept(stream: &mut TcpStream, request: &TestRequest) -> bool {
+    if request.version < REPLY_VERSION {
+        return true;
+    }
+    match (Reply::Accepted { version: request.version }).write_to(stream) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Failed to answer the test request: {}", e);
+            false
+        }
+    }
+}
+
+/// Allocates a data buffer of `len` bytes, backed by hugepages if requested and placed on `node`.
+fn allocate(len: usize, args: &Args, node: Option<u32>) -> AlignedBuffer {
+    let mut buffer = AlignedBuffer::new(len, args.hugepages);
+    if args.hugepages && !buffer.is_hugepage_backed() {
+        eprintln!("Hugepages unavailable, falling back to regular pages");
+    }
+    if let Some(node) = node {
+        if let Err(e) = numa::prefer_node(&mut buffer, node) {
+            eprintln!("Failed to allocate the buffer on NUMA node {}: {}", node, e);
+        }
+    }
+    buffer
+}
+
+/// Pins the calling thread to the NUMA node of the interface the client connected through, and
+/// reports the placement. Clients of interfaces without a node, such as loopback, are served on
+/// any CPU.
+///
+/// # Returns
+/// - The node the thread was pinned to, on which the client's buffers are to be allocated.
+fn place_on_numa_node(stream: &TcpStream) -> Option<u32> {
+    let interface = match stream.local_addr().and_then(|local| netif::interface_with_address(local.ip())) {
+        Ok(interface) => interface,
+        Err(e) => {
+            eprintln!("NUMA placement unavailable, continuing without it: {}", e);
+            return None;
+        }
+    };
+    let node = match numa::node_of_interface(&interface) {
+        Ok(node) => node,
+        Err(e) => {
+            eprintln!("NUMA placement unavailable, continuing without it: {}", e);
+            return None;
+        }
+    };
+    // A thread pinned for an earlier client is released again
+    if let Err(e) = numa::pin_current_thread(node.as_ref().map(|node| &node.cpus[..])) {
+        eprintln!("NUMA placement unavailable, continuing without it: {}", e);
+        return None;
+    }
+    match node {
+        Some(node) => {
+            println!("NUMA: client arrived through {} on {}; serving it there", interface, node);
+            Some(node.id)
+        }
+        None => {
+            println!("NUMA: client arrived through {}, which is not attached to a node; serving it on any CPU", interface);
+            None
+        }
+    }
+}
+
+/// Streams the requested chunks to the client.
+fn send_chunks(stream: &mut TcpStream, request: &TestRequest, args: &Args, node: Option<u32>) -> Outcome {
+    // Allocate a single page-aligned chunk of zeroed data and reuse it for every send
+    let mut chunk = allocate(request.chunk_size, args, node);
+    if let Some(seed) = request.payload_seed {
+        payload::fill_seeded(&mut chunk, seed);
+    }
+    // The payload never changes, so its checksum is computed once
+    let checksum = if request.checksum { ChunkHeader::checksum_of(&chunk) } else { 0 };
+    let watch = match HeartbeatWatch::start(stream, request) {
+        Ok(watch) => watch,
+        Err(e) => {
+            eprintln!("Failed to watch the client's heartbeats: {}", e);
+            return Outcome::Aborted(0);
+        }
+    };
+
+    // A chunk count of zero asks for an unbounded transfer that ends when the client disconnects
+    let unbounded = request.chunk_count == 0;
+    let mut shaper = request.shape.map(Shaper::new);
+    let mut burst_start = Instant::now();
+    let mut sent = 0;
+    while unbounded || sent < request.chunk_count {
+        // In burst mode, pause once the current burst has used up its on-time
+        if let Some(burst) = &request.burst {
+            if burst_start.elapsed() >= burst.on {
+                match request.heartbeat {
+                    Some(interval) => {
+                        if let Err(e) = heartbeat::sleep(burst.off, interval, |frame| stream.write_all(frame)) {
+                            eprintln!("Failed to send a heartbeat: {}", e);
+                            return Outcome::Aborted((sent * chunk.len()) as u64);
+                        }
+                    }
+                    None => thread::sleep(burst.off),
+                }
+                burst_start = Instant::now();
+            }
+        }
+
+        // Send the chunk to the client, framed by its header
+        let header = ChunkHeader {
+            sequence: request.first_sequence + sent as u64,
+            length: chunk.len() as u32,
+            send_time_ns: frame::unix_time_ns(),
+            checksum,
+        };
+        if let Err(e) = send_chunk(stream, &header, &chunk, shaper.as_mut()) {
+            let bytes = (sent * chunk.len()) as u64;
+            if watch.as_ref().is_some_and(HeartbeatWatch::lost) {
+                report_lost_heartbeats(request, sent);
+            } else if args.keepalive && sockopt::is_keepalive_timeout(&e) {
+                eprintln!("Client stopped answering keepalive probes after {} chunks", sent);
+            } else if is_timeout(&e) {
+                report_stall(stream, sent, "accepted", args);
+            } else if unbounded {
+                println!("Client ended the transfer after {} chunks", sent);
+                return Outcome::Completed(bytes);
+            } else {
+                eprintln!("Failed to send data chunk: {}", e);
+            }
+            return Outcome::Aborted(bytes);
+        }
+        sent += 1;
+        println!("Sent {} byte chunk to client", chunk.len());
+    }
+
+    let end = EndOfTest { next_sequence: request.first_sequence + sent as u64, bytes: (sent * chunk.len()) as u64, finish_time_ns: frame::unix_time_ns() };
+    end_test(stream, request, &end);
+    if let Some(watch) = watch {
+        watch.finish();
+    }
+    println!("Completed {} chunks transfer to client", sent);
+    Outcome::Completed(end.bytes)
+}
+
+/// Sends a chunk framed by its header, through the token bucket if the client asked for shaping.
+fn send_chunk(stream: &mut TcpStream, header: &ChunkHeader, payload: &[u8], shaper: Option<&mut Shaper>) -> std::io::Result<()> {
+    match shaper {
+        Some(shaper) => shaper.write_all(stream, &header.encode()).and_then(|_| shaper.write_all(stream, payload)),
+        None => stream.write_all(&header.encode()).and_then(|_| stream.write_all(payload)),
+    }
+}
+
+/// Shapes the data the server sends with a token bucket. The bucket starts full, as a link that
+/// was idle before the test.
+struct Shaper {
+    /// Fill rate, in bytes per second.
+    rate: f64,
+    burst: usize,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Shaper {
+    fn new(bucket: TokenBucket) -> Self {
+        let burst = usize::try_from(bucket.burst_bytes).unwrap_or(usize::MAX);
+        Shaper { rate: bucket.rate_bps as f64 / 8.0, burst, tokens: burst as f64, refilled: Instant::now() }
+    }
+
+    /// Writes `data` in pieces of at most the bucket size, each once the bucket holds enough
+    /// tokens for it.
+    fn write_all(&mut self, stream: &mut TcpStream, data: &[u8]) -> std::io::Result<()> {
+        for piece in data.chunks(self.burst) {
+            self.refill();
+            let missing = piece.len() as f64 - self.tokens;
+            if missing > 0.0 {
+                thread::sleep(Duration::from_secs_f64(missing / self.rate));
+                self.refill();
+            }
+            self.tokens -= piece.len() as f64;
+            stream.write_all(piece)?;
+        }
+        Ok(())
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.refilled).as_secs_f64() * self.rate).min(self.burst as f64);
+        self.refilled = now;
+    }
+}
+
+/// Watches the heartbeats a downloading client sends from a separate thread, and shuts the
+/// connection down once they stop, so a send blocked on a client that is gone fails.
+struct HeartbeatWatch {
+    lost: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl HeartbeatWatch {
+    /// Starts watching, if the client asked for heartbeats.
+    fn start(stream: &TcpStream, request: &TestRequest) -> std::io::Result<Option<Self>> {
+        let timeout = match request.heartbeat {
+            Some(interval) => heartbeat::timeout(interval),
+            None => return Ok(None),
+        };
+        // The download never reads from the connection otherwise, so the read timeout is free to use
+        let mut reader = stream.try_clone()?;
+        reader.set_read_timeout(Some(timeout))?;
+        let lost = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&lost);
+        let handle = thread::spawn(move || {
+            let mut frame = [0u8; ChunkHeader::LEN];
+            loop {
+                match reader.read_exact(&mut frame) {
+                    Ok(()) => {}
+                    Err(e) if is_timeout(&e) => {
+                        flag.store(true, Ordering::Relaxed);
+                        let _ = reader.shutdown(Shutdown::Both);
+                        return;
+                    }
+                    // The client closed the connection, or the transfer is over
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(Some(HeartbeatWatch { lost, handle }))
+    }
+
+    /// Whether the connection was shut down because the heartbeats stopped.
+    fn lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the client to close its side at the end of the transfer, so no heartbeat is left
+    /// unread when the connection closes, which would reset it.
+    fn finish(self) {
+        let _ = self.handle.join();
+    }
+}
+
+fn report_lost_heartbeats(request: &TestRequest, chunks: usize) {
+    let timeout = request.heartbeat.map(heartbeat::timeout).unwrap_or_default();
+    eprintln!(
+        "Client sent neither data nor heartbeats for {:.1}s after {} chunks; assuming it is gone and ending the session",
+        timeout.as_secs_f64(),
+        chunks
+    );
+}
+
+/// Tells the client the transfer is complete, if its protocol version has an end-of-test frame;
+/// there is nothing left to do if that fails.
+fn end_test(stream: &mut TcpStream, request: &TestRequest, end: &EndOfTest) {
+    if request.version < REPLY_VERSION {
+        return;
+    }
+    if let Err(e) = stream.write_all(&end.encode()) {
+        eprintln!("Failed to send the end of the test: {}", e);
+    }
+}
+
+/// Streams a file from under `root` to the client in chunks of the requested size, followed by the
+/// end-of-test frame at the end of the file.
+///
+/// Reading the file and sending it are timed separately, so a slow disk can be told apart from a
+/// slow network.
+fn send_file(stream: &mut TcpStream, request: &TestRequest, args: &Args, node: Option<u32>, root: &Path, name: &str) -> Outcome {
+    // Only plain relative names, so clients cannot escape the file root
+    let relative = Path::new(name);
+    if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        reject(stream, request.version, format!("file name {} is not a plain relative path", name));
+        return Outcome::Refused;
+    }
+    let path = root.join(relative);
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            reject(stream, request.version, format!("failed to open file {}: {}", name, e));
+            return Outcome::Refused;
+        }
+    };
+    let size = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            reject(stream, request.version, format!("failed to read the size of file {}: {}", name, e));
+            return Outcome::Refused;
+        }
+    };
+    // A resumed transfer continues where the previous connection left off
+    let offset = match request.first_sequence.checked_mul(request.chunk_size as u64) {
+        Some(offset) if offset <= size => offset,
+        _ => {
+            reject(stream, request.version, format!("chunk {} starts past the end of file {} ({} bytes)", request.first_sequence, name, size));
+            return Outcome::Refused;
+        }
+    };
+    if let Err(e) = file.seek(SeekFrom::Start(offset)) {
+        reject(stream, request.version, format!("failed to seek to byte {} of file {}: {}", offset, name, e));
+        return Outcome::Refused;
+    }
+    let len = size - offset;
+    let _reservation = match reserve(stream, request, Some(len), args) {
+        Some(reservation) => reservation,
+        None => return Outcome::Refused,
+    };
+    if !accept(stream, request) {
+        return Outcome::Aborted(0);
+    }
+    let mut chunk = allocate(request.chunk_size, args, node);
+    println!("Sending {} from byte {}", path.display(), offset);
+    let mut shaper = request.shape.map(Shaper::new);
+    let watch = match HeartbeatWatch::start(stream, request) {
+        Ok(watch) => watch,
+        Err(e) => {
+            eprintln!("Failed to watch the client's heartbeats: {}", e);
+            return Outcome::Aborted(0);
+        }
+    };
+
+    let mut sent = 0;
+    let mut bytes = 0;
+    let mut disk_time = Duration::ZERO;
+    let mut network_time = Duration::ZERO;
+    loop {
+        let read_start = Instant::now();
+        let length = match read_full(&mut file, &mut chunk) {
+            Ok(length) => length,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                return Outcome::Aborted(bytes as u64);
+            }
+        };
+        disk_time += read_start.elapsed();
+        if length == 0 {
+            break;
+        }
+
+        let payload = &chunk[..length];
+        let header = ChunkHeader {
+            sequence: request.first_sequence + sent,
+            length: length as u32,
+            send_time_ns: frame::unix_time_ns(),
+            checksum: if request.checksum { ChunkHeader::checksum_of(payload) } else { 0 },
+        };
+        let send_start = Instant::now();
+        if let Err(e) = send_chunk(stream, &header, payload, shaper.as_mut()) {
+            if watch.as_ref().is_some_and(HeartbeatWatch::lost) {
+                report_lost_heartbeats(request, sent as usize);
+            } else if is_timeout(&e) {
+                report_stall(stream, sent as usize, "accepted", args);
+            } else {
+                eprintln!("Failed to send file chunk: {}", e);
+            }
+            return Outcome::Aborted(bytes as u64);
+        }
+        network_time += send_start.elapsed();
+        sent += 1;
+        bytes += length;
+    }
+    end_test(stream, request, &EndOfTest { next_sequence: request.first_sequence + sent, bytes: bytes as u64, finish_time_ns: frame::unix_time_ns() });
+    if let Some(watch) = watch {
+        watch.finish();
+    }
+
+    // A transfer resumed at the end of the file sends nothing, in no time
+    let rate = |time: Duration| if time.is_zero() { 0.0 } else { bytes as f64 * 8.0 / time.as_secs_f64() };
+    println!(
+        "Completed file transfer to client: {} bytes in {} chunks, disk read {:.3}s ({:.2} bps), network send {:.3}s ({:.2} bps)",
+        bytes,
+        sent,
+        disk_time.as_secs_f64(),
+        rate(disk_time),
+        network_time.as_secs_f64(),
+        rate(network_time)
+    );
+    Outcome::Completed(bytes as u64)
+}
+
+/// Reads until `buffer` is full or the end of the file is reached.
+///
+/// # Returns
+/// - The number of bytes read, which is only less than the buffer size at the end of the file.
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Reads a chunk header, noting the wall-clock time its first bytes arrived.
+///
+/// The client stamps a download chunk the same way when it reads its header, so the one-way delays
+/// of the two directions measure the same span.
+///
+/// # Returns
+/// - The arrival time in nanoseconds since the Unix epoch.
+fn read_header<R: Read>(reader: &mut R, header: &mut [u8; ChunkHeader::LEN]) -> std::io::Result<u64> {
+    let received = loop {
+        match reader.read(header) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => break n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    };
+    let arrival_ns = frame::unix_time_ns();
+    reader.read_exact(&mut header[received..])?;
+    Ok(arrival_ns)
+}
+
+/// Receives the chunks uploaded by the client, acknowledging each one.
+///
+/// The acknowledgement is a chunk header echoing the sequence number and the number of bytes
+/// received, with the time the chunk started to arrive and, if requested, the checksum of the
+/// received payload, so the client can derive upload timing and integrity.
+fn receive_chunks(stream: &mut TcpStream, request: &TestRequest, args: &Args, node: Option<u32>) -> Outcome {
+    let mut buffer = allocate(request.chunk_size, args, node);
+    let expected_payload = request.payload_seed.map(|seed| {
+        let mut expected = vec![0u8; request.chunk_size];
+        payload::fill_seeded(&mut expected, seed);
+        expected
+    });
+
+    // A chunk count of zero lets the client upload until it disconnects
+    let unbounded = request.chunk_count == 0;
+    let mut received = 0;
+    let mut bytes = 0;
+    let mut corrupted = 0;
+    // Heartbeats share the direction with the acknowledgements, so every frame is written whole under the lock
+    let writer = match stream.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(writer)),
+        Err(e) => {
+            eprintln!("Failed to clone the connection: {}", e);
+            return Outcome::Aborted(bytes as u64);
+        }
+    };
+    let heartbeats = match request.heartbeat {
+        Some(interval) => {
+            // The client sends heartbeats between bursts, so silence means it is gone
+            if let Err(e) = stream.set_read_timeout(Some(heartbeat::timeout(interval))) {
+                eprintln!("Failed to set the heartbeat timeout: {}", e);
+                return Outcome::Aborted(bytes as u64);
+            }
+            let writer = Arc::clone(&writer);
+            Some(heartbeat::Sender::start(interval, move |frame| writer.lock().unwrap_or_else(PoisonError::into_inner).write_all(frame)))
+        }
+        None => None,
+    };
+    while unbounded || received < request.chunk_count {
+        let mut header = [0u8; ChunkHeader::LEN];
+        let receive_time_ns = match read_header(stream, &mut header) {
+            Ok(arrival_ns) => arrival_ns,
+            Err(e) => {
+                if args.keepalive && sockopt::is_keepalive_timeout(&e) {
+                    eprintln!("Client stopped answering keepalive probes after {} chunks", received);
+                } else if request.heartbeat.is_some() && is_timeout(&e) {
+                    report_lost_heartbeats(request, received);
+                } else if is_timeout(&e) {
+                    report_stall(stream, received, "sent", args);
+                } else if unbounded && e.kind() == ErrorKind::UnexpectedEof {
+                    println!("Client ended the upload after {} chunks", received);
+                    return Outcome::Completed(bytes as u64);
+                } else {
+                    eprintln!("Failed to receive chunk header: {}", e);
+                }
+                return Outcome::Aborted(bytes as u64);
+            }
+        };
+        let header = ChunkHeader::decode(&header);
+        if Heartbeat::from_header(&header).is_some() {
+            continue;
+        }
+        let length = header.length as usize;
+        if length > buffer.len() {
+            eprintln!("Rejecting chunk {} of {} bytes, more than the requested {}", header.sequence, length, buffer.len());
+            return Outcome::Aborted(bytes as u64);
+        }
+        if let Err(e) = stream.read_exact(&mut buffer[..length]) {
+            if request.heartbeat.is_some() && is_timeout(&e) {
+                report_lost_heartbeats(request, received);
+            } else if is_timeout(&e) {
+                report_stall(stream, received, "sent", args);
+            } else if unbounded && e.kind() == ErrorKind::UnexpectedEof {
+                // The client stops a soak upload wherever its deadline falls, usually mid-chunk
+                println!("Client ended the upload after {} chunks", received);
+                return Outcome::Completed(bytes as u64);
+            } else {
+                eprintln!("Failed to receive data chunk: {}", e);
+            }
+            return Outcome::Aborted(bytes as u64);
+        }
+
+        let payload = &buffer[..length];
+        if expected_payload.as_ref().is_some_and(|expected| payload != &expected[..length]) {
+            eprintln!("Chunk {}: payload differs from the seeded content", header.sequence);
+            corrupted += 1;
+        }
+        let ack = ChunkHeader {
+            sequence: header.sequence,
+            length: header.length,
+            send_time_ns: receive_time_ns,
+            checksum: if request.checksum { ChunkHeader::checksum_of(payload) } else { 0 },
+        };
+        if let Err(e) = writer.lock().unwrap_or_else(PoisonError::into_inner).write_all(&ack.encode()) {
+            eprintln!("Failed to acknowledge chunk: {}", e);
+            return Outcome::Aborted(bytes as u64);
+        }
+        received += 1;
+        bytes += length;
+        println!("Received {} byte chunk from client", length);
+    }
+    if let Some(heartbeats) = heartbeats {
+        let _ = heartbeats.stop();
+    }
+    end_test(stream, request, &EndOfTest { next_sequence: request.first_sequence + received as u64, bytes: bytes as u64, finish_time_ns: frame::unix_time_ns() });
 
-    // Create a 1 MB chunk of dummy data to send to the client
-    let chunk = vec![0u8; 1_000_000]; // 1 MB of zeroed bytes
+    if expected_payload.is_some() {
+        println!("Completed {} chunks upload from client, {} corrupted", received, corrupted);
+    } else {
+        println!("Completed {} chunks upload from client", received);
+    }
+    Outcome::Completed(bytes as u64)
+}
 
-    for _ in 0..100 {
-        // Send the 1 MB chunk to the client
-        if let Err(e) = stream.write_all(&chunk) {
-            eprintln!("Failed to send data chunk: {}", e);
+/// Forwards a test to `target` and relays the traffic in both directions until either side is done,
+/// or `limit` bytes have been relayed in a direction.
+///
+/// Neither the data nor the acknowledgements are interpreted, so the client measures the path
+/// through this host as if it were talking to the target directly.
+fn relay(client: TcpStream, request: TestRequest, target: &str, stall_timeout: Option<Duration>, limit: u64) {
+    let upstream = match connect_target(target, stall_timeout) {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            eprintln!("Failed to connect to relay target {}: {}", target, e);
             return;
         }
-        println!("Sent 1 MB chunk to client");
+    };
+    // Only writes can stall: one direction of every test is legitimately idle, so reads wait
+    let setup = client
+        .set_read_timeout(None)
+        .and_then(|_| upstream.set_write_timeout(stall_timeout))
+        .and_then(|_| request.write_to(&mut &upstream));
+    if let Err(e) = setup {
+        eprintln!("Failed to forward the test request to {}: {}", target, e);
+        return;
+    }
+    let (mut client_reader, mut upstream_writer) = match (client.try_clone(), upstream.try_clone()) {
+        (Ok(client_reader), Ok(upstream_writer)) => (client_reader, upstream_writer),
+        _ => {
+            eprintln!("Failed to clone the relayed connections");
+            return;
+        }
+    };
+    println!("Relaying test to {}", target);
+
+    let to_target = thread::spawn(move || {
+        let copied = std::io::copy(&mut (&mut client_reader).take(limit), &mut upstream_writer);
+        let _ = upstream_writer.shutdown(Shutdown::Write);
+        copied
+    });
+    let (mut upstream_reader, mut client_writer) = (upstream, client);
+    let to_client = std::io::copy(&mut (&mut upstream_reader).take(limit), &mut client_writer);
+    let _ = client_writer.shutdown(Shutdown::Write);
+    // Unblocks the other direction if the target finished first
+    let _ = client_writer.shutdown(Shutdown::Read);
+    let to_target = to_target.join().unwrap_or_else(|_| Err(std::io::Error::other("relay thread panicked")));
+
+    match (to_client, to_target) {
+        (Ok(down), Ok(up)) => println!("Relay to {} finished: {} bytes to the client, {} bytes to the target", target, down, up),
+        (Err(e), _) | (_, Err(e)) => eprintln!("Relay to {} ended with an error: {}", target, e),
+    }
+}
+
+/// Connects to a relay target, giving up on each of its addresses after `timeout`, if one is set.
+fn connect_target(target: &str, timeout: Option<Duration>) -> std::io::Result<TcpStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect(target),
+    };
+    let mut last_error = std::io::Error::new(ErrorKind::NotFound, "the name resolved to no address");
+    for address in target.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Reports a client that made no progress within the stall timeout, with the kernel's TCP state.
+fn report_stall(stream: &TcpStream, chunks: usize, activity: &str, args: &Args) {
+    eprintln!("Client stalled after {} chunks: no data {} for {:.1}s", chunks, activity, args.stall_timeout);
+    if let Ok(info) = tcp_info::tcp_info(stream) {
+        eprintln!("Last TCP state: {}", info);
+    }
+}
+
+/// Returns UDP latency probes to their senders on a background thread, and serves the packet
+/// trains of dispersion estimates: an upload train's packets are answered with their arrival time,
+/// a train request with the packets of a download train, sent back-to-back.
+///
+/// The echo is not serialized with the test connections, so a client can probe continuously while
+/// its own test runs.
+fn serve_udp_echo(address: SocketAddr) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(address)?;
+    // Kernel timestamps keep the arrival stamps of a train apart however many packets one receive
+    // call returns; without them arrivals are timed when the call returns
+    let kernel_timestamps = mmsg::enable_timestamps(&socket).is_ok();
+    let started = Instant::now();
+    thread::spawn(move || {
+        let mut batch = Batch::new(usize::from(echo::MAX_TRAIN_PACKETS), mmsg::MAX_DATAGRAM_LEN);
+        loop {
+            if let Err(e) = batch.recv(&socket) {
+                eprintln!("UDP echo stopped: {}", e);
+                return;
+            }
+            let returned_ns = started.elapsed().as_nanos() as u64;
+            for (received, datagram) in batch.iter() {
+                let sender = datagram.sender;
+                // Lost replies are just lost probes or incomplete trains to the client
+                if echo::Probe::decode(received).is_some() {
+                    let _ = socket.send_to(received, sender);
+                } else if let Some(train) = echo::TrainPacket::decode(received) {
+                    let received_ns = match datagram.received.filter(|_| kernel_timestamps) {
+                        Some(at) => at.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64,
+                        None => returned_ns,
+                    };
+                    let stamp = echo::TrainStamp { train: train.train, index: train.index, received_ns };
+                    let _ = socket.send_to(&stamp.encode(), sender);
+                } else if let Some(request) = echo::TrainRequest::decode(received) {
+                    let packets = request.packets.min(echo::MAX_TRAIN_PACKETS);
+                    let len = usize::from(request.len).clamp(echo::TrainPacket::HEADER_LEN, echo::MAX_TRAIN_PACKET_LEN);
+                    let train: Vec<Vec<u8>> = (0..packets).map(|index| echo::TrainPacket { train: request.train, index, packets }.encode(len)).collect();
+                    let _ = mmsg::send_all(&socket, &train, Some(sender));
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Sessions the rendezvous port keeps at most, so registrations cannot grow without bound.
+const MAX_RENDEZVOUS_SESSIONS: usize = 1024;
+/// How long a registration waits for the other client of its session.
+const RENDEZVOUS_EXPIRY: Duration = Duration::from_secs(120);
+
+/// Introduces the two clients of a peer-to-peer session to each other on a background thread.
+///
+/// Each client registers from the socket it tests with, so the source address of its registration
+/// is its address on the far side of its NAT. Once a second client registers for the same session,
+/// each is sent the other's address, again on every repeated registration in case an answer is lost.
+fn serve_rendezvous(address: SocketAddr) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(address)?;
+    thread::spawn(move || {
+        let mut sessions: HashMap<String, Vec<(SocketAddr, Instant)>> = HashMap::new();
+        let mut packet = [0u8; 1500];
+        loop {
+            let (len, sender) = match socket.recv_from(&mut packet) {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("Rendezvous stopped: {}", e);
+                    return;
+                }
+            };
+            let session = match peer::Message::decode(&packet[..len]) {
+                Some(peer::Message::Register { session }) => session,
+                _ => continue,
+            };
+            let now = Instant::now();
+            sessions.retain(|_, clients| {
+                clients.retain(|&(_, registered)| now.duration_since(registered) < RENDEZVOUS_EXPIRY);
+                !clients.is_empty()
+            });
+            if !sessions.contains_key(&session) && sessions.len() >= MAX_RENDEZVOUS_SESSIONS {
+                continue;
+            }
+            let clients = sessions.entry(session).or_default();
+            match clients.iter().position(|&(client, _)| client == sender) {
+                Some(index) => clients[index].1 = now,
+                // A third client of a session replaces the oldest, e.g. one that restarted
+                None if clients.len() == 2 => clients[0] = (sender, now),
+                None => clients.push((sender, now)),
+            }
+            if let [(first, _), (second, _)] = clients[..] {
+                let _ = socket.send_to(&peer::Message::PeerAddress { address: second }.encode(), first);
+                let _ = socket.send_to(&peer::Message::PeerAddress { address: first }.encode(), second);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answers mDNS queries for test servers on a background thread.
+///
+/// # Returns
+/// - The instance name the server is advertised under (the hostname).
+fn advertise(listen: SocketAddr) -> std::io::Result<String> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // Share the port with any mDNS responder already running on the host
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, mdns::PORT)).into())?;
+    socket.join_multicast_v4(&mdns::GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    let socket = UdpSocket::from(socket);
+    let name = hostname()?;
+
+    let advertised = name.clone();
+    thread::spawn(move || {
+        let mut packet = [0u8; 9000];
+        loop {
+            let (len, querier) = match socket.recv_from(&mut packet) {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("mDNS responder stopped: {}", e);
+                    return;
+                }
+            };
+            let id = match mdns::service_query_id(&packet[..len]) {
+                Some(id) => id,
+                None => continue,
+            };
+            // Advertise the address the querier reaches us at, unless the listener is bound to one
+            let address = if listen.ip().is_unspecified() { local_address_towards(querier.ip()) } else { Some(listen.ip()) };
+            if let Some(address) = address {
+                let response = mdns::response(id, &advertised, listen.port(), address);
+                if let Err(e) = socket.send_to(&response, querier) {
+                    eprintln!("Failed to answer mDNS query from {}: {}", querier, e);
+                }
+            }
+        }
+    });
+    Ok(name)
+}
+
+/// Returns the local address the OS would send from to reach `peer`.
+fn local_address_towards(peer: IpAddr) -> Option<IpAddr> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)).ok()?;
+    socket.connect(SocketAddr::new(peer, mdns::PORT)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+fn hostname() -> std::io::Result<String> {
+    // Only the first label, as the service is advertised in .local
+    Ok(system_hostname()?.split('.').next().unwrap_or_default().to_string())
+}
+
+#[cfg(unix)]
+fn system_hostname() -> std::io::Result<String> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Ok(String::from_utf8_lossy(&name[..len]).into_owned())
+}
+
+/// The NetBIOS name of the computer, which Windows keeps in the environment of every process.
+#[cfg(not(unix))]
+fn system_hostname() -> std::io::Result<String> {
+    std::env::var("COMPUTERNAME").map_err(|_| std::io::Error::new(std::io::ErrorKind::NotFound, "COMPUTERNAME is not set"))
+}
+
+/// Clients served and bytes moved through one listening address.
+#[derive(Default)]
+struct ListenerStats {
+    clients: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl ListenerStats {
+    /// Adds a finished client, with its byte counts from the kernel where it reports them.
+    fn record(&mut self, stream: Option<&TcpStream>) {
+        self.clients += 1;
+        if let Some((sent, received)) = stream.and_then(transferred) {
+            self.bytes_sent += sent;
+            self.bytes_received += received;
+        }
+    }
+}
+
+/// Returns the bytes sent and received on a connection so far, from the kernel where it reports
+/// them.
+fn transferred(stream: &TcpStream) -> Option<(u64, u64)> {
+    let info = tcp_info::tcp_info(stream).ok()?;
+    // Data still queued has been written by us, so it counts as sent
+    Some((info.bytes_sent.saturating_sub(info.bytes_retrans) + u64::from(info.notsent_bytes), info.bytes_received))
+}
+
+/// Binds a listening socket to `address`. With `reuse_port`, other sockets may bind the same
+/// address too, and the kernel balances new connections across them.
+fn bind(address: SocketAddr, reuse_port: bool) -> std::io::Result<TcpListener> {
+    if !reuse_port {
+        return TcpListener::bind(address);
+    }
+    if cfg!(not(unix)) {
+        return Err(std::io::Error::new(ErrorKind::Unsupported, "SO_REUSEPORT is not available on this platform; use a single acceptor"));
+    }
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+/// Serves the clients one acceptor thread takes off its share of the port, one at a time, and
+/// prints what the thread has handled so far after each.
+fn serve_acceptor(listener: TcpListener, address: SocketAddr, acceptor: u32, args: &Args) {
+    let mut stats = ListenerStats::default();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let finished = stream.try_clone();
+                handle_client(stream, args);
+                stats.record(finished.as_ref().ok());
+                println!(
+                    "Acceptor {} on {}: {} clients, {} bytes sent, {} bytes received",
+                    acceptor, address, stats.clients, stats.bytes_sent, stats.bytes_received
+                );
+            }
+            Err(e) => eprintln!("Connection failed on acceptor {}: {}", acceptor, e),
+        }
     }
+}
 
-    println!("Completed 100 chunks transfer to client");
+/// Reads `net.ipv4.tcp_fastopen`: bit 1 enables Fast Open for clients, bit 2 for servers.
+fn fast_open_sysctl() -> Option<u32> {
+    std::fs::read_to_string("/proc/sys/net/ipv4/tcp_fastopen").ok()?.trim().parse().ok()
 }
 
 fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:7878")?;
-    println!("Server listening on port 7878...");
+    let mut args = Args::parse();
+    if let Some(path) = &args.sessions {
+        args.store = Some(Arc::new(sessions::Store::open(path)?));
+    }
+    let args = Arc::new(args);
+
+    // Every listener accepts on its own thread and hands its clients to the loop below, unless
+    // several acceptors share the port and serve their clients themselves
+    let (accepted, clients) = mpsc::channel();
+    let mut acceptors = Vec::new();
+    for (index, &address) in args.listen.iter().enumerate() {
+        let listeners = (0..args.acceptors).map(|_| bind(address, args.acceptors > 1)).collect::<std::io::Result<Vec<_>>>()?;
+        if args.acceptors > 1 {
+            println!("Server listening on {} with {} acceptors...", address, args.acceptors);
+        } else {
+            println!("Server listening on {}...", address);
+        }
+        if args.fast_open {
+            match listeners.iter().try_for_each(|listener| sockopt::set_fast_open(&SockRef::from(listener), FAST_OPEN_QUEUE)) {
+                // Bit 2 of the sysctl enables the server side; without it the option is accepted but unused
+                Ok(()) if fast_open_sysctl().is_some_and(|flags| flags & 2 == 0) => {
+                    eprintln!("TCP Fast Open is disabled for servers; enable it with sysctl -w net.ipv4.tcp_fastopen=3")
+                }
+                Ok(()) => println!("TCP Fast Open enabled on {}", address),
+                Err(e) => eprintln!("Failed to enable TCP Fast Open on {}: {}", address, e),
+            }
+        }
+        for (acceptor, listener) in (1..).zip(listeners) {
+            if args.acceptors > 1 {
+                let args = Arc::clone(&args);
+                acceptors.push(thread::spawn(move || serve_acceptor(listener, address, acceptor, &args)));
+                continue;
+            }
+            let accepted = accepted.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if accepted.send((index, stream)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    }
+    drop(accepted);
+    if args.numa {
+        match numa::node_count() {
+            Ok(nodes) => println!("NUMA topology: {} nodes; clients are served on the node of their interface", nodes),
+            Err(e) => eprintln!("NUMA placement unavailable, continuing without it: {}", e),
+        }
+    }
+    if let (Some(address), Some(store)) = (args.api, &args.store) {
+        sessions::serve_api(address, Arc::clone(store))?;
+    }
+    if let Some(address) = args.udp_echo {
+        serve_udp_echo(address)?;
+        println!("UDP echo listening on {}", address);
+    }
+    if let Some(address) = args.rendezvous {
+        serve_rendezvous(address)?;
+        println!("Rendezvous listening on {}", address);
+    }
+    if args.advertise {
+        // mDNS is answered over IPv4 only
+        match args.listen.iter().find(|address| address.is_ipv4()) {
+            Some(&listen) => match advertise(listen) {
+                Ok(name) => println!("Advertising {} as {}.{}", listen, name, mdns::SERVICE),
+                Err(e) => eprintln!("Failed to advertise via mDNS: {}", e),
+            },
+            None => eprintln!("Failed to advertise via mDNS: no IPv4 listener"),
+        }
+    }
 
-    if let Some(stream) = listener.incoming().next() {
+    // Clients are served one at a time, whichever listener they came through, so concurrent tests
+    // do not skew each other's results
+    let mut stats: Vec<ListenerStats> = args.listen.iter().map(|_| ListenerStats::default()).collect();
+    for (index, stream) in clients {
         match stream {
             Ok(stream) => {
-                handle_client(stream);
-                println!("Server exiting after handling one client.");
+                let finished = stream.try_clone();
+                handle_client(stream, &args);
+                if args.listen.len() > 1 {
+                    let listener = &mut stats[index];
+                    listener.record(finished.as_ref().ok());
+                    println!(
+                        "Listener {}: {} clients, {} bytes sent, {} bytes received",
+                        args.listen[index], listener.clients, listener.bytes_sent, listener.bytes_received
+                    );
+                }
+                if args.once {
+                    println!("Server exiting after handling one client.");
+                    break;
+                }
             }
             Err(e) => eprintln!("Connection failed: {}", e),
         }
     }
+    for acceptor in acceptors {
+        let _ = acceptor.join();
+    }
 
     Ok(())
 }
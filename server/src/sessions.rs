@@ -0,0 +1,293 @@
+//! The sessions file, which keeps every test the server served, and the HTTP API that serves the
+//! sessions and statistics across all clients from it.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use common::protocol::Direction;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Sessions `GET /sessions` returns unless asked for another number.
+const DEFAULT_LIMIT: usize = 100;
+/// Subnets `GET /stats` lists, those with the most sessions first.
+const MAX_SUBNETS: usize = 100;
+
+/// One test session the server served, as kept in the sessions file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub client: IpAddr,
+    pub started_at: String,
+    pub direction: Direction,
+    /// Payload bytes sent or received by the server.
+    pub bytes: u64,
+    pub duration_seconds: f64,
+    /// Payload data rate as the server saw it. Downloads count data once it is handed to the
+    /// kernel, so short ones read high by up to the send buffer.
+    pub throughput_bps: f64,
+    /// Whether the session ran to its end; open-ended tests end when the client disconnects.
+    pub completed: bool,
+}
+
+/// The sessions file, appended to by every thread serving clients.
+pub struct Store {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Store {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to open sessions file {}: {}", path.display(), e)))?;
+        Ok(Store { path: path.to_path_buf(), file: Mutex::new(file) })
+    }
+
+    pub fn append(&self, session: &Session) -> io::Result<()> {
+        let line = format!("{}\n", serde_json::to_string(session)?);
+        self.file.lock().unwrap_or_else(PoisonError::into_inner).write_all(line.as_bytes())
+    }
+
+    /// Reads the sessions, newest first, skipping lines that are not sessions (e.g. one cut short
+    /// by a crash).
+    fn read(&self) -> io::Result<Vec<Session>> {
+        let mut sessions = Vec::new();
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            if let Ok(session) = serde_json::from_str(&line?) {
+                sessions.push(session);
+            }
+        }
+        sessions.reverse();
+        Ok(sessions)
+    }
+}
+
+/// Statistics across the sessions of all clients.
+#[derive(Serialize)]
+struct Stats {
+    sessions: usize,
+    completed: usize,
+    bytes: u64,
+    /// Data rates of the completed downloads and uploads.
+    download: Option<Distribution>,
+    upload: Option<Distribution>,
+    /// Median data rates per /24 (IPv4) or /48 (IPv6), those with the most sessions first.
+    subnets: Vec<Subnet>,
+    /// Sessions and bytes per hour of the day (UTC), the busiest first.
+    busiest_hours: Vec<Hour>,
+}
+
+/// How the data rates of many sessions are spread.
+#[derive(Serialize)]
+struct Distribution {
+    sessions: usize,
+    p10_bps: f64,
+    p25_bps: f64,
+    median_bps: f64,
+    p75_bps: f64,
+    p90_bps: f64,
+    /// Sessions per decade of data rate, from below 1 Mbit/s to 100 Gbit/s and above.
+    histogram: Vec<Bin>,
+}
+
+#[derive(Serialize)]
+struct Bin {
+    from_bps: f64,
+    /// Upper bound, exclusive; absent for the last bin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_bps: Option<f64>,
+    sessions: usize,
+}
+
+#[derive(Serialize)]
+struct Subnet {
+    subnet: String,
+    sessions: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    median_download_bps: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    median_upload_bps: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Hour {
+    hour: u64,
+    sessions: usize,
+    bytes: u64,
+}
+
+impl Stats {
+    fn of(sessions: &[Session]) -> Self {
+        let completed: Vec<&Session> = sessions.iter().filter(|session| session.completed).collect();
+        let rates = |sessions: &[&Session], direction| -> Vec<f64> {
+            sessions.iter().filter(|session| session.direction == direction).map(|session| session.throughput_bps).collect()
+        };
+
+        let mut subnets: BTreeMap<String, Vec<&Session>> = BTreeMap::new();
+        for session in sessions {
+            subnets.entry(subnet_of(session.client)).or_default().push(session);
+        }
+        let mut subnets: Vec<Subnet> = subnets
+            .into_iter()
+            .map(|(subnet, sessions)| {
+                let completed: Vec<&Session> = sessions.iter().copied().filter(|session| session.completed).collect();
+                Subnet {
+                    subnet,
+                    sessions: sessions.len(),
+                    median_download_bps: median(rates(&completed, Direction::Download)),
+                    median_upload_bps: median(rates(&completed, Direction::Upload)),
+                }
+            })
+            .collect();
+        // Stable, so subnets with as many sessions stay in address order
+        subnets.sort_by_key(|subnet| Reverse(subnet.sessions));
+        subnets.truncate(MAX_SUBNETS);
+
+        let mut hours: Vec<Hour> = (0..24).map(|hour| Hour { hour, sessions: 0, bytes: 0 }).collect();
+        for session in sessions {
+            if let Some(started_at) = started_at(session) {
+                let hour = &mut hours[(started_at.as_secs() % 86_400 / 3600) as usize];
+                hour.sessions += 1;
+                hour.bytes += session.bytes;
+            }
+        }
+        hours.sort_by_key(|hour| Reverse((hour.sessions, hour.bytes)));
+
+        Stats {
+            sessions: sessions.len(),
+            completed: completed.len(),
+            bytes: sessions.iter().map(|session| session.bytes).sum(),
+            download: Distribution::of(rates(&completed, Direction::Download)),
+            upload: Distribution::of(rates(&completed, Direction::Upload)),
+            subnets,
+            busiest_hours: hours,
+        }
+    }
+}
+
+impl Distribution {
+    fn of(mut rates: Vec<f64>) -> Option<Self> {
+        if rates.is_empty() {
+            return None;
+        }
+        rates.sort_by(f64::total_cmp);
+        let mut histogram = vec![Bin { from_bps: 0.0, to_bps: Some(1e6), sessions: 0 }];
+        histogram.extend((6..11).map(|decade| Bin { from_bps: 10f64.powi(decade), to_bps: Some(10f64.powi(decade + 1)), sessions: 0 }));
+        histogram.push(Bin { from_bps: 1e11, to_bps: None, sessions: 0 });
+        for &rate in &rates {
+            if let Some(bin) = histogram.iter_mut().find(|bin| bin.to_bps.is_none_or(|to| rate < to)) {
+                bin.sessions += 1;
+            }
+        }
+        Some(Distribution {
+            sessions: rates.len(),
+            p10_bps: percentile(&rates, 10.0),
+            p25_bps: percentile(&rates, 25.0),
+            median_bps: percentile(&rates, 50.0),
+            p75_bps: percentile(&rates, 75.0),
+            p90_bps: percentile(&rates, 90.0),
+            histogram,
+        })
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty values.
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    Some(percentile(&values, 50.0))
+}
+
+/// The subnet a client's sessions are grouped under: its /24 (IPv4) or /48 (IPv6).
+fn subnet_of(client: IpAddr) -> String {
+    match client.to_canonical() {
+        IpAddr::V4(address) => format!("{}/24", Ipv4Addr::from(u32::from(address) & 0xffff_ff00)),
+        IpAddr::V6(address) => format!("{}/48", Ipv6Addr::from(u128::from(address) & !((1u128 << 80) - 1))),
+    }
+}
+
+/// When a session started, since the Unix epoch.
+fn started_at(session: &Session) -> Option<Duration> {
+    humantime::parse_rfc3339(&session.started_at).ok()?.duration_since(UNIX_EPOCH).ok()
+}
+
+/// Serves the sessions and statistics across them as JSON over HTTP on a background thread:
+///
+/// - `GET /sessions`: the sessions, newest first; `?limit=N` sets the number returned (default 100).
+/// - `GET /stats`: totals, the distribution of client data rates per direction, median rates per
+///   subnet, and the busiest hours of the day; `?days=N` only counts the last `N` days.
+pub fn serve_api(address: SocketAddr, store: Arc<Store>) -> io::Result<()> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to listen for API requests on {}: {}", address, e)))?;
+    println!("Sessions API listening on http://{}", address);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let store = Arc::clone(&store);
+            thread::spawn(move || {
+                if let Err(e) = answer(stream, &store) {
+                    eprintln!("API request failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn answer(stream: TcpStream, store: &Store) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are read and ignored, so the client is not reset when the connection closes
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut fields = request_line.split_whitespace();
+    let (method, target) = (fields.next().unwrap_or_default(), fields.next().unwrap_or_default());
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let parameter = |name: &str| query.split('&').filter_map(|pair| pair.split_once('=')).find(|(key, _)| *key == name).map(|(_, value)| value);
+    let (status, body) = match (method, path) {
+        ("GET", "/sessions") => match parameter("limit").map_or(Ok(DEFAULT_LIMIT), str::parse) {
+            Ok(limit) => ("200 OK", json!(store.read()?.into_iter().take(limit).collect::<Vec<_>>())),
+            Err(_) => ("400 Bad Request", json!({ "error": "limit is not a number" })),
+        },
+        ("GET", "/stats") => match parameter("days").map(str::parse::<u64>).transpose() {
+            Ok(days) => {
+                let since = days.map(|days| SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().saturating_sub(Duration::from_secs(days * 86_400)));
+                let sessions: Vec<Session> =
+                    store.read()?.into_iter().filter(|session| since.is_none_or(|since| started_at(session).is_some_and(|at| at >= since))).collect();
+                ("200 OK", json!(Stats::of(&sessions)))
+            }
+            Err(_) => ("400 Bad Request", json!({ "error": "days is not a number" })),
+        },
+        (_, "/sessions" | "/stats") => ("405 Method Not Allowed", json!({ "error": "only GET is supported" })),
+        _ => ("404 Not Found", json!({ "error": "unknown path; try /sessions or /stats" })),
+    };
+
+    let body = serde_json::to_string_pretty(&body)?;
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}